@@ -32,6 +32,7 @@ fn main() {
         .newtype_enum("exr_tile_level_mode_t")
         .newtype_enum("exr_tile_round_mode_t")
         .newtype_enum("exr_pixel_type_t")
+        .newtype_enum("exr_attribute_type_t")
         .rustfmt_bindings(true)
         .generate()
         .expect("bindgen failed");
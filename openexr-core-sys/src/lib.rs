@@ -1,15 +1,21 @@
 #![allow(non_snake_case, non_camel_case_types, non_upper_case_globals)]
 type size_t = usize;
 
+use std::collections::{HashMap, HashSet};
 use std::marker::PhantomData;
+use std::sync::{Mutex, RwLock};
+use std::thread::ThreadId;
 
 include!(concat!(env!("OUT_DIR"), "/openexr_wrapper.rs"));
 
 #[repr(transparent)]
 pub struct exr_result_t(i32);
 
-#[derive(Debug, Clone, PartialEq, thiserror::Error)]
-pub enum Error {
+/// The kinds of error the C core can return
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
+#[non_exhaustive]
+pub enum ErrorKind {
     #[error("Unable to allocate memory")]
     OutOfMemory,
     #[error("Context argument to function is not valid")]
@@ -76,97 +82,467 @@ pub enum Error {
     InvalidSampleData,
     #[error("Feature not yet implemented, please use C++ library")]
     FeatureNotImplemented,
+    /// Not returned by the C core, but by operations that a progress
+    /// callback asked to stop
+    #[error("Operation cancelled")]
+    Cancelled,
     #[error("Unknown error code")]
     Unknown,
 }
 
 impl exr_result_t {
+    /// Wrap one of the [`exr_error_code_t`] codes, for returning from
+    /// callbacks the C core calls
+    ///
+    pub const fn from_code(code: exr_error_code_t::Type) -> exr_result_t {
+        exr_result_t(code as i32)
+    }
+
+    /// Convert to a `Result`, holding `val` on success
+    ///
+    /// The error has no message, as the message the C core reports goes to
+    /// the context's error handler, so use [`ok_in`](Self::ok_in) for the
+    /// results of calls on a context.
+    ///
     pub fn ok<T>(&self, val: T) -> Result<T, Error> {
-        match self.0 as u32 {
-            exr_error_code_t::EXR_ERR_SUCCESS => Ok(val),
-            exr_error_code_t::EXR_ERR_OUT_OF_MEMORY => Err(Error::OutOfMemory),
+        let kind = match self.0 as u32 {
+            exr_error_code_t::EXR_ERR_SUCCESS => return Ok(val),
+            exr_error_code_t::EXR_ERR_OUT_OF_MEMORY => ErrorKind::OutOfMemory,
             exr_error_code_t::EXR_ERR_MISSING_CONTEXT_ARG => {
-                Err(Error::MissingContextArg)
+                ErrorKind::MissingContextArg
             }
             exr_error_code_t::EXR_ERR_INVALID_ARGUMENT => {
-                Err(Error::InvalidArgument)
+                ErrorKind::InvalidArgument
             }
             exr_error_code_t::EXR_ERR_ARGUMENT_OUT_OF_RANGE => {
-                Err(Error::ArgumentOutOfRange)
+                ErrorKind::ArgumentOutOfRange
             }
-            exr_error_code_t::EXR_ERR_FILE_ACCESS => Err(Error::FileAccess),
+            exr_error_code_t::EXR_ERR_FILE_ACCESS => ErrorKind::FileAccess,
             exr_error_code_t::EXR_ERR_FILE_BAD_HEADER => {
-                Err(Error::FileBadHeader)
-            }
-            exr_error_code_t::EXR_ERR_NOT_OPEN_READ => Err(Error::NotOpenRead),
-            exr_error_code_t::EXR_ERR_NOT_OPEN_WRITE => {
-                Err(Error::NotOpenWrite)
+                ErrorKind::FileBadHeader
             }
+            exr_error_code_t::EXR_ERR_NOT_OPEN_READ => ErrorKind::NotOpenRead,
+            exr_error_code_t::EXR_ERR_NOT_OPEN_WRITE => ErrorKind::NotOpenWrite,
             exr_error_code_t::EXR_ERR_HEADER_NOT_WRITTEN => {
-                Err(Error::HeaderNotWritten)
+                ErrorKind::HeaderNotWritten
             }
-            exr_error_code_t::EXR_ERR_READ_IO => Err(Error::ReadIo),
-            exr_error_code_t::EXR_ERR_WRITE_IO => Err(Error::WriteIo),
-            exr_error_code_t::EXR_ERR_NAME_TOO_LONG => Err(Error::NameTooLong),
+            exr_error_code_t::EXR_ERR_READ_IO => ErrorKind::ReadIo,
+            exr_error_code_t::EXR_ERR_WRITE_IO => ErrorKind::WriteIo,
+            exr_error_code_t::EXR_ERR_NAME_TOO_LONG => ErrorKind::NameTooLong,
             exr_error_code_t::EXR_ERR_MISSING_REQ_ATTR => {
-                Err(Error::MissingReqAttr)
+                ErrorKind::MissingReqAttr
             }
-            exr_error_code_t::EXR_ERR_INVALID_ATTR => Err(Error::InvalidAttr),
+            exr_error_code_t::EXR_ERR_INVALID_ATTR => ErrorKind::InvalidAttr,
             exr_error_code_t::EXR_ERR_NO_ATTR_BY_NAME => {
-                Err(Error::NoAttrByName)
+                ErrorKind::NoAttrByName
             }
             exr_error_code_t::EXR_ERR_ATTR_TYPE_MISMATCH => {
-                Err(Error::AttrTypeMismatch)
+                ErrorKind::AttrTypeMismatch
             }
             exr_error_code_t::EXR_ERR_ATTR_SIZE_MISMATCH => {
-                Err(Error::AttrSizeMismatch)
+                ErrorKind::AttrSizeMismatch
             }
             exr_error_code_t::EXR_ERR_SCAN_TILE_MIXEDAPI => {
-                Err(Error::ScanTileMixedApi)
+                ErrorKind::ScanTileMixedApi
             }
             exr_error_code_t::EXR_ERR_TILE_SCAN_MIXEDAPI => {
-                Err(Error::TileScanMixedApi)
+                ErrorKind::TileScanMixedApi
             }
             exr_error_code_t::EXR_ERR_MODIFY_SIZE_CHANGE => {
-                Err(Error::ModifySizeChange)
+                ErrorKind::ModifySizeChange
             }
             exr_error_code_t::EXR_ERR_ALREADY_WROTE_ATTRS => {
-                Err(Error::AlreadyWroteAttrs)
+                ErrorKind::AlreadyWroteAttrs
             }
             exr_error_code_t::EXR_ERR_BAD_CHUNK_LEADER => {
-                Err(Error::BadChunkLeader)
+                ErrorKind::BadChunkLeader
             }
-            exr_error_code_t::EXR_ERR_CORRUPT_CHUNK => Err(Error::CorruptChunk),
+            exr_error_code_t::EXR_ERR_CORRUPT_CHUNK => ErrorKind::CorruptChunk,
             exr_error_code_t::EXR_ERR_INCORRECT_PART => {
-                Err(Error::IncorrectPart)
+                ErrorKind::IncorrectPart
             }
             exr_error_code_t::EXR_ERR_INCORRECT_CHUNK => {
-                Err(Error::IncorrectChunk)
+                ErrorKind::IncorrectChunk
             }
             exr_error_code_t::EXR_ERR_USE_SCAN_DEEP_WRITE => {
-                Err(Error::UseScanDeepWrite)
+                ErrorKind::UseScanDeepWrite
             }
             exr_error_code_t::EXR_ERR_USE_TILE_DEEP_WRITE => {
-                Err(Error::UseTileDeepWrite)
+                ErrorKind::UseTileDeepWrite
             }
             exr_error_code_t::EXR_ERR_USE_SCAN_NONDEEP_WRITE => {
-                Err(Error::UseScanNonDeepWrite)
+                ErrorKind::UseScanNonDeepWrite
             }
             exr_error_code_t::EXR_ERR_USE_TILE_NONDEEP_WRITE => {
-                Err(Error::UseTileNonDeepWrite)
+                ErrorKind::UseTileNonDeepWrite
             }
             exr_error_code_t::EXR_ERR_INVALID_SAMPLE_DATA => {
-                Err(Error::InvalidSampleData)
+                ErrorKind::InvalidSampleData
             }
             exr_error_code_t::EXR_ERR_FEATURE_NOT_IMPLEMENTED => {
-                Err(Error::FeatureNotImplemented)
+                ErrorKind::FeatureNotImplemented
+            }
+            exr_error_code_t::EXR_ERR_UNKNOWN => ErrorKind::Unknown,
+            // the raw code is kept, so codes added to the C core later can
+            // still be told apart
+            _ => ErrorKind::Unknown,
+        };
+
+        Err(Error {
+            code: Some(self.0),
+            ..Error::from_kind(kind)
+        })
+    }
+
+    /// Convert the result of a call on the context `ctxt` as
+    /// [`ok`](Self::ok) does, attaching the message the context's error
+    /// handler was given for the error
+    ///
+    /// A message given during a call that succeeded, or for a different
+    /// error, is passed to the warning handler instead.
+    ///
+    pub fn ok_in<T>(
+        &self,
+        ctxt: exr_const_context_t,
+        val: T,
+    ) -> Result<T, Error> {
+        let message = with_messages(|m| m.take(ctxt as usize));
+        self.with_message(message, val)
+    }
+
+    /// Convert the result of the `exr_start_*` call that created the context
+    /// `ctxt`, or failed to, as [`ok_in`](Self::ok_in) does
+    ///
+    /// The context only becomes known to the error handlers once it has
+    /// been created, so messages given while creating it are taken from
+    /// those kept for no context.
+    ///
+    pub fn ok_started<T>(
+        &self,
+        ctxt: exr_const_context_t,
+        val: T,
+    ) -> Result<T, Error> {
+        let message = with_messages(|m| {
+            if self.0 == 0 && !ctxt.is_null() {
+                m.contexts.insert(ctxt as usize);
             }
-            exr_error_code_t::EXR_ERR_UNKNOWN => Err(Error::Unknown),
-            _ => panic!(
-                "{}",
-                format!("Unhandled value for exr_error_code_t: {}", self.0)
-            ),
+            m.take(0)
+        });
+        self.with_message(message, val)
+    }
+
+    /// Convert the result of the `exr_finish` call that freed the context
+    /// `ctxt` as [`ok_in`](Self::ok_in) does, after which the context is no
+    /// longer known to the error handlers
+    ///
+    pub fn ok_finished<T>(
+        &self,
+        ctxt: exr_const_context_t,
+        val: T,
+    ) -> Result<T, Error> {
+        let message = with_messages(|m| {
+            let message = m.take(ctxt as usize);
+            m.contexts.remove(&(ctxt as usize));
+            message
+        });
+        self.with_message(message, val)
+    }
+
+    fn with_message<T>(
+        &self,
+        message: Option<(i32, String)>,
+        val: T,
+    ) -> Result<T, Error> {
+        match (self.ok(val), message) {
+            (Err(e), Some((code, msg))) if code == self.0 => Err(Error {
+                message: Some(msg),
+                ..e
+            }),
+            (result, Some((_, msg))) => {
+                warn(&msg);
+                result
+            }
+            (result, None) => result,
+        }
+    }
+}
+
+/// An error returned by the C core, along with the message it reported and
+/// which part, attribute and chunk it concerns, where those are known
+///
+/// Errors compare equal only if everything they carry is the same, so the
+/// error constants can be used as patterns, where they match an error of that
+/// kind with nothing attached. To check an error's kind whatever else it
+/// carries, compare it with an [`ErrorKind`] or use [`Error::kind`].
+///
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Error {
+    kind: ErrorKind,
+    code: Option<i32>,
+    message: Option<String>,
+    part_index: Option<usize>,
+    attribute: Option<String>,
+    chunk_index: Option<usize>,
+}
+
+macro_rules! error_kinds {
+    ($($kind:ident),*) => {
+        impl Error {
+            $(
+                pub const $kind: Error = Error::from_kind(ErrorKind::$kind);
+            )*
+        }
+    };
+}
+
+// An error of each kind with nothing else attached, so errors can be created
+// as `Error::ReadIo` etc.
+error_kinds!(
+    OutOfMemory,
+    MissingContextArg,
+    InvalidArgument,
+    ArgumentOutOfRange,
+    FileAccess,
+    FileBadHeader,
+    NotOpenRead,
+    NotOpenWrite,
+    HeaderNotWritten,
+    ReadIo,
+    WriteIo,
+    NameTooLong,
+    MissingReqAttr,
+    InvalidAttr,
+    NoAttrByName,
+    AttrTypeMismatch,
+    AttrSizeMismatch,
+    ScanTileMixedApi,
+    TileScanMixedApi,
+    ModifySizeChange,
+    AlreadyWroteAttrs,
+    BadChunkLeader,
+    CorruptChunk,
+    IncorrectPart,
+    IncorrectChunk,
+    UseScanDeepWrite,
+    UseTileDeepWrite,
+    UseScanNonDeepWrite,
+    UseTileNonDeepWrite,
+    InvalidSampleData,
+    FeatureNotImplemented,
+    Cancelled,
+    Unknown
+);
+
+impl Error {
+    pub fn new(kind: ErrorKind, message: Option<String>) -> Error {
+        Error {
+            message,
+            ..Error::from_kind(kind)
+        }
+    }
+
+    const fn from_kind(kind: ErrorKind) -> Error {
+        Error {
+            kind,
+            code: None,
+            message: None,
+            part_index: None,
+            attribute: None,
+            chunk_index: None,
+        }
+    }
+
+    pub fn kind(&self) -> ErrorKind {
+        self.kind
+    }
+
+    /// The `exr_result_t` the C core returned, if the error came from it
+    ///
+    pub fn raw_code(&self) -> Option<i32> {
+        self.code
+    }
+
+    /// The message the C core reported with the error, such as which
+    /// attribute was wrong and why
+    ///
+    pub fn message(&self) -> Option<&str> {
+        self.message.as_deref()
+    }
+
+    /// The index of the part the error concerns
+    ///
+    pub fn part_index(&self) -> Option<usize> {
+        self.part_index
+    }
+
+    /// The name of the attribute the error concerns
+    ///
+    pub fn attribute(&self) -> Option<&str> {
+        self.attribute.as_deref()
+    }
+
+    /// The index of the chunk the error concerns
+    ///
+    pub fn chunk_index(&self) -> Option<usize> {
+        self.chunk_index
+    }
+
+    /// Record that the error concerns part `part_index`
+    ///
+    pub fn with_part(mut self, part_index: usize) -> Error {
+        self.part_index = Some(part_index);
+        self
+    }
+
+    /// Record that the error concerns the attribute `name`
+    ///
+    pub fn with_attribute<S: Into<String>>(mut self, name: S) -> Error {
+        self.attribute = Some(name.into());
+        self
+    }
+
+    /// Record that the error concerns chunk `chunk_index`
+    ///
+    pub fn with_chunk(mut self, chunk_index: usize) -> Error {
+        self.chunk_index = Some(chunk_index);
+        self
+    }
+}
+
+impl From<ErrorKind> for Error {
+    fn from(kind: ErrorKind) -> Error {
+        Error::from_kind(kind)
+    }
+}
+
+impl PartialEq<ErrorKind> for Error {
+    fn eq(&self, other: &ErrorKind) -> bool {
+        self.kind == *other
+    }
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.kind)?;
+        if let Some(message) = &self.message {
+            write!(f, ": {}", message)?;
+        }
+
+        let mut context = Vec::new();
+        if let Some(part_index) = self.part_index {
+            context.push(format!("part {}", part_index));
+        }
+        if let Some(attribute) = &self.attribute {
+            context.push(format!("attribute '{}'", attribute));
         }
+        if let Some(chunk_index) = self.chunk_index {
+            context.push(format!("chunk {}", chunk_index));
+        }
+        if !context.is_empty() {
+            write!(f, " ({})", context.join(", "))?;
+        }
+
+        Ok(())
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// The messages the C core has given the error handlers of contexts that
+/// haven't yet been attached to an [`Error`]
+///
+/// Messages are kept for the context they were reported for and the thread
+/// that made the call, so calls made on one context from several threads at
+/// once don't take each other's. Messages for contexts that are still being
+/// created, or for no context, are kept under address 0.
+///
+#[derive(Default)]
+struct Messages {
+    /// The addresses of the contexts that have been created and not yet
+    /// finished
+    contexts: HashSet<usize>,
+    pending: HashMap<(usize, ThreadId), (i32, String)>,
+}
+
+impl Messages {
+    fn take(&mut self, address: usize) -> Option<(i32, String)> {
+        self.pending.remove(&(address, std::thread::current().id()))
+    }
+}
+
+static MESSAGES: Mutex<Option<Messages>> = Mutex::new(None);
+
+fn with_messages<R>(f: impl FnOnce(&mut Messages) -> Option<R>) -> Option<R> {
+    let mut messages = MESSAGES.lock().ok()?;
+    f(messages.get_or_insert_with(Messages::default))
+}
+
+/// An error handler for a context that keeps the message the C core reports
+/// with an error, so it can be attached to the [`Error`] the failing call
+/// returns, rather than printing it
+///
+/// # Safety
+/// `msg` must be null or a valid C string
+///
+pub unsafe extern "C" fn capture_error_message(
+    ctxt: exr_const_context_t,
+    code: exr_result_t,
+    msg: *const std::os::raw::c_char,
+) {
+    if !msg.is_null() {
+        let msg = std::ffi::CStr::from_ptr(msg).to_string_lossy();
+        record_error_message(ctxt, &code, &msg);
+    }
+}
+
+/// Keep `msg`, reported by the error handler of the context `ctxt`, to
+/// attach to the error with `code` that the call being made on this thread
+/// returns, when it is converted with [`exr_result_t::ok_in`]
+///
+/// If the message kept for the last error hasn't been taken yet, no error was
+/// returned for it, so it is passed to the warning handler.
+///
+pub fn record_error_message(
+    ctxt: exr_const_context_t,
+    code: &exr_result_t,
+    msg: &str,
+) {
+    let replaced = with_messages(|m| {
+        let address = match ctxt as usize {
+            address if m.contexts.contains(&address) => address,
+            _ => 0,
+        };
+        m.pending.insert(
+            (address, std::thread::current().id()),
+            (code.0, msg.to_string()),
+        )
+    });
+    if let Some((_, replaced)) = replaced {
+        warn(&replaced);
+    }
+}
+
+/// A function called with the messages the C core reports that aren't
+/// attached to an [`Error`], such as those for out-of-spec header values it
+/// tolerates
+///
+pub type WarningHandler = fn(&str);
+
+static WARNING_HANDLER: RwLock<Option<WarningHandler>> = RwLock::new(None);
+
+/// Set the function messages that aren't attached to an [`Error`] are passed
+/// to, or drop them if `handler` is `None`, which is the default
+///
+pub fn set_warning_handler(handler: Option<WarningHandler>) {
+    if let Ok(mut h) = WARNING_HANDLER.write() {
+        *h = handler;
+    }
+}
+
+fn warn(msg: &str) {
+    let handler = WARNING_HANDLER.read().ok().and_then(|h| *h);
+    if let Some(handler) = handler {
+        handler(msg);
     }
 }
 
@@ -219,6 +595,98 @@ pub struct exr_attr_v3d_t {
 mod tests {
     use crate as sys;
 
+    #[test]
+    fn warnings() {
+        use std::sync::Mutex;
+        static WARNINGS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+        sys::set_warning_handler(Some(|msg| {
+            WARNINGS.lock().unwrap().push(msg.to_string())
+        }));
+
+        let success = sys::exr_result_t::from_code(
+            sys::exr_error_code_t::EXR_ERR_SUCCESS,
+        );
+        let failure = sys::exr_result_t::from_code(
+            sys::exr_error_code_t::EXR_ERR_FILE_BAD_HEADER,
+        );
+
+        let ctxt = 0x100 as sys::exr_const_context_t;
+
+        // messages given while a context is created go with the error
+        // creating it
+        sys::record_error_message(ctxt, &failure, "bad header");
+        let err = failure.ok_started(std::ptr::null(), ()).unwrap_err();
+        assert_eq!(err.message(), Some("bad header"));
+
+        // a message for a call that succeeds is a warning
+        sys::record_error_message(ctxt, &failure, "tolerated");
+        assert!(success.ok_started(ctxt, ()).is_ok());
+        assert_eq!(*WARNINGS.lock().unwrap(), ["tolerated"]);
+
+        // as is one replaced by a later message before it was taken
+        sys::record_error_message(ctxt, &failure, "replaced");
+        sys::record_error_message(ctxt, &failure, "bad chunk");
+        let err = failure.ok_in(ctxt, ()).unwrap_err();
+        assert_eq!(err.message(), Some("bad chunk"));
+        assert_eq!(*WARNINGS.lock().unwrap(), ["tolerated", "replaced"]);
+
+        // converting a result has no effect on the messages kept, and the
+        // messages of calls on other threads are theirs
+        sys::record_error_message(ctxt, &failure, "kept");
+        assert!(success.ok(()).is_ok());
+        assert_eq!(failure.ok(()).unwrap_err().message(), None);
+        let address = ctxt as usize;
+        std::thread::spawn(move || {
+            let ctxt = address as sys::exr_const_context_t;
+            let failure = sys::exr_result_t::from_code(
+                sys::exr_error_code_t::EXR_ERR_FILE_BAD_HEADER,
+            );
+            sys::record_error_message(ctxt, &failure, "other thread");
+            let err = failure.ok_in(ctxt, ()).unwrap_err();
+            assert_eq!(err.message(), Some("other thread"));
+        })
+        .join()
+        .unwrap();
+        let err = failure.ok_in(ctxt, ()).unwrap_err();
+        assert_eq!(err.message(), Some("kept"));
+        assert!(failure.ok_in(ctxt, ()).unwrap_err().message().is_none());
+
+        sys::set_warning_handler(None);
+        sys::record_error_message(ctxt, &failure, "dropped");
+        assert!(success.ok_finished(ctxt, ()).is_ok());
+        assert_eq!(WARNINGS.lock().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn error_patterns() {
+        fn open(found: bool) -> Result<(), sys::Error> {
+            if found {
+                Ok(())
+            } else {
+                Err(sys::Error::FileAccess)
+            }
+        }
+
+        match open(false) {
+            Err(sys::Error::FileAccess) => (),
+            other => panic!("expected FileAccess, got {:?}", other),
+        }
+        assert!(matches!(open(true), Ok(())));
+
+        // errors carrying more than their kind only match by kind
+        let failure = sys::exr_result_t::from_code(
+            sys::exr_error_code_t::EXR_ERR_FILE_ACCESS,
+        );
+        let err = failure.ok(()).unwrap_err();
+        assert!(!matches!(err, sys::Error::FileAccess));
+        assert_eq!(err, sys::ErrorKind::FileAccess);
+        assert_ne!(err, sys::Error::FileAccess.with_part(0));
+        assert_eq!(
+            sys::Error::from(sys::ErrorKind::FileAccess),
+            sys::Error::FileAccess
+        );
+    }
+
     #[test]
     fn it_works() {
         let mut major = 0;
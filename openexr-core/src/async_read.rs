@@ -0,0 +1,675 @@
+//! Reading from async sources with tokio, enabled with the `async` feature.
+//!
+//! The C core reads synchronously, so [`AsyncReadContext`] keeps a cache of
+//! the parts of the stream that have been fetched. When the core asks for
+//! bytes that aren't in the cache the read fails, the missing range is fetched
+//! from the async reader, and the operation is retried. Decoding happens on
+//! tokio's blocking thread pool so it doesn't hold up the runtime.
+//!
+//! Once each operation has finished, the cache is trimmed to a fixed number of
+//! bytes, dropping the blocks that were fetched first, which are fetched
+//! again if they're needed later. Every block an operation fetches is kept
+//! until it finishes, however large, as the C core starts the operation again
+//! from the beginning each time it's retried. Parts are read a chunk at a
+//! time, so reading a whole image never holds much more of the stream than
+//! that in memory.
+//!
+use crate::attr::Storage;
+use crate::chunkio::ChunkInfo;
+use crate::coding::ChannelElement;
+use crate::context::ReadContext;
+use crate::error::Error;
+use crate::read::{num_tiles, ImageBuffer, Tile};
+use crate::stream::{stream, StreamCallbacks};
+use openexr_core_sys as sys;
+use std::collections::{BTreeMap, VecDeque};
+use std::ffi::c_void;
+use std::io::SeekFrom;
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncSeek, AsyncSeekExt};
+
+type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// The smallest number of bytes fetched from the reader at once, to avoid
+/// lots of tiny reads while the header and chunk table are parsed
+///
+const MIN_FETCH_SIZE: u64 = 64 * 1024;
+
+/// The number of bytes of the stream [`AsyncReadContext::new`] keeps cached
+///
+pub const DEFAULT_CACHE_LIMIT: usize = 64 * 1024 * 1024;
+
+/// The parts of the stream fetched most recently
+///
+#[derive(Default)]
+struct StreamCache {
+    size: u64,
+    /// The blocks fetched, by their offset in the stream
+    blocks: BTreeMap<u64, Vec<u8>>,
+    /// The offsets of the blocks, in the order they were fetched
+    fetched: VecDeque<u64>,
+    /// The number of bytes in all the blocks
+    cached: usize,
+    /// The number of bytes to trim the cache to, other than the last block
+    /// fetched
+    limit: usize,
+    /// The range of the last read that wasn't in the cache
+    miss: Option<(u64, u64)>,
+}
+
+impl StreamCache {
+    fn find(&self, start: u64, end: u64) -> Option<&[u8]> {
+        let (offset, data) = self.blocks.range(..=start).next_back()?;
+        if end <= offset + data.len() as u64 {
+            let from = (start - offset) as usize;
+            Some(&data[from..from + (end - start) as usize])
+        } else {
+            None
+        }
+    }
+
+    /// Add a block fetched from `offset`
+    ///
+    fn insert(&mut self, offset: u64, data: Vec<u8>) {
+        self.cached += data.len();
+        if let Some(old) = self.blocks.insert(offset, data) {
+            self.cached -= old.len();
+            self.fetched.retain(|o| *o != offset);
+        }
+        self.fetched.push_back(offset);
+    }
+
+    /// Take the range of the last read that missed, if any
+    ///
+    /// # Errors
+    /// * `[Error::ReadIo]` - If the read missed `fetched`, the range that was
+    /// fetched for the last miss, as fetching it again won't help
+    ///
+    fn take_miss(
+        &mut self,
+        fetched: Option<(u64, u64)>,
+    ) -> Result<Option<(u64, u64)>> {
+        let miss = self.miss.take();
+        if miss.is_some() && miss == fetched {
+            return Err(Error::ReadIo);
+        }
+        Ok(miss)
+    }
+
+    /// Drop the oldest blocks until the cache is back within its limit, once
+    /// an operation no longer needs them
+    ///
+    fn trim(&mut self) {
+        while self.cached > self.limit && self.fetched.len() > 1 {
+            if let Some(oldest) = self.fetched.pop_front() {
+                if let Some(old) = self.blocks.remove(&oldest) {
+                    self.cached -= old.len();
+                }
+            }
+        }
+    }
+}
+
+/// A single decoded chunk of a scanline part
+///
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScanlineChunk<T> {
+    /// The y coordinate of the first line in the chunk
+    pub start_y: i32,
+    /// Width of the chunk in pixels
+    pub width: usize,
+    /// Number of lines in the chunk
+    pub height: usize,
+    /// The requested channels, interleaved in the order they were requested,
+    /// a line at a time
+    pub pixels: Vec<T>,
+}
+
+/// Reads an EXR from an async source such as a network stream
+///
+pub struct AsyncReadContext<R> {
+    reader: R,
+    cache: Arc<Mutex<StreamCache>>,
+    ctx: Arc<ReadContext>,
+}
+
+impl<R: AsyncRead + AsyncSeek + Unpin + Send> AsyncReadContext<R> {
+    /// Create a context reading from `reader`, fetching as much as is needed
+    /// to read the header, and keeping up to [`DEFAULT_CACHE_LIMIT`] bytes of
+    /// the stream cached
+    ///
+    /// # Errors
+    /// * `[Error::ReadIo]` - If the reader fails
+    /// * `[Error::FileBadHeader]` - If the data does not hold a valid header
+    ///
+    pub async fn new(reader: R) -> Result<AsyncReadContext<R>> {
+        AsyncReadContext::with_cache_limit(reader, DEFAULT_CACHE_LIMIT).await
+    }
+
+    /// Create a context reading from `reader`, as for
+    /// [`AsyncReadContext::new`], keeping up to `cache_limit` bytes of the
+    /// stream cached
+    ///
+    /// The cache is only trimmed to `cache_limit` between operations, and
+    /// always keeps the last block fetched, so a limit of 0 keeps just the
+    /// blocks the operation in progress needs.
+    ///
+    /// # Errors
+    /// * `[Error::ReadIo]` - If the reader fails
+    /// * `[Error::FileBadHeader]` - If the data does not hold a valid header
+    ///
+    pub async fn with_cache_limit(
+        mut reader: R,
+        cache_limit: usize,
+    ) -> Result<AsyncReadContext<R>> {
+        let size = reader
+            .seek(SeekFrom::End(0))
+            .await
+            .map_err(|_| Error::ReadIo)?;
+        let cache = Arc::new(Mutex::new(StreamCache {
+            size,
+            limit: cache_limit,
+            ..StreamCache::default()
+        }));
+
+        let mut fetched = None;
+        loop {
+            let callbacks = StreamCallbacks {
+                read_fn: Some(cache_read),
+                size_fn: Some(cache_size),
+                ..StreamCallbacks::new(cache.clone(), None)
+            };
+
+            // Safety: the callbacks only ever treat the user data as an
+            // Arc<Mutex<StreamCache>>
+            let result =
+                unsafe { ReadContext::from_stream("<async>", callbacks) };
+            let miss = cache.lock().unwrap().take_miss(fetched)?;
+            match (result, miss) {
+                (Err(_), Some(miss)) => {
+                    fetch(&mut reader, &cache, miss).await?;
+                    fetched = Some(miss);
+                }
+                (result, _) => {
+                    cache.lock().unwrap().trim();
+                    return Ok(AsyncReadContext {
+                        reader,
+                        cache,
+                        ctx: Arc::new(result?),
+                    });
+                }
+            }
+        }
+    }
+
+    /// The underlying context, for querying the header
+    ///
+    /// Reading pixels through it directly fails for any chunk that hasn't
+    /// been fetched yet.
+    ///
+    pub fn context(&self) -> &ReadContext {
+        &self.ctx
+    }
+
+    /// Decode the whole of a part into an interleaved buffer of the channels
+    /// named in `channels`, converted to `T`, as for
+    /// [`ReadContext::read_image`]
+    ///
+    /// The chunks of the part are fetched and decoded one at a time. For
+    /// tiled parts, this reads the highest-resolution level.
+    ///
+    /// # Errors
+    /// * `[Error::ReadIo]` - If the reader fails
+    /// * `[Error::FeatureNotImplemented]` - If the part holds deep data
+    ///
+    pub async fn read_image<T: ChannelElement + Send>(
+        &mut self,
+        part_index: usize,
+        channels: &[&str],
+    ) -> Result<ImageBuffer<T>> {
+        let ctx = self.context();
+        let dw = ctx.data_window::<[i32; 4]>(part_index)?;
+        let width = (dw[2] - dw[0] + 1).max(0) as usize;
+        let height = (dw[3] - dw[1] + 1).max(0) as usize;
+        let line_len = width * channels.len();
+
+        let mut image = ImageBuffer {
+            width,
+            height,
+            data_window: dw,
+            channels: owned(channels),
+            pixels: vec![T::default(); width * height * channels.len()],
+        };
+
+        match ctx.storage(part_index)? {
+            Storage::Scanline => {
+                let mut y = dw[1];
+                while y <= dw[3] {
+                    let chunk = self
+                        .read_scanline_chunk(part_index, y, channels)
+                        .await?;
+                    let start = (chunk.start_y - dw[1]) as usize * line_len;
+                    image.pixels[start..start + chunk.pixels.len()]
+                        .copy_from_slice(&chunk.pixels);
+                    y = chunk.start_y + (chunk.height as i32).max(1);
+                }
+            }
+            Storage::Tiled => {
+                let (tile_width, tile_height) =
+                    ctx.tile_sizes(part_index, 0, 0)?;
+                let tiles_x = num_tiles(width, tile_width);
+                let tiles_y = num_tiles(height, tile_height);
+                for tile_y in 0..tiles_y {
+                    for tile_x in 0..tiles_x {
+                        let tile = self
+                            .read_tile(
+                                part_index, tile_x, tile_y, 0, 0, channels,
+                            )
+                            .await?;
+                        let x = tile_x as usize * tile_width * channels.len();
+                        let tile_line_len =
+                            tile.width as usize * channels.len();
+                        if tile_line_len == 0 {
+                            continue;
+                        }
+                        for (row, src) in
+                            tile.pixels.chunks_exact(tile_line_len).enumerate()
+                        {
+                            let y = tile_y as usize * tile_height + row;
+                            let start = y * line_len + x;
+                            image.pixels[start..start + tile_line_len]
+                                .copy_from_slice(src);
+                        }
+                    }
+                }
+            }
+            Storage::DeepScanline | Storage::DeepTiled => {
+                return Err(Error::FeatureNotImplemented)
+            }
+        }
+
+        Ok(image)
+    }
+
+    /// Fetch and decode the chunk of a scanline part containing line `y`,
+    /// with the channels named in `channels` interleaved in that order and
+    /// converted to `T`
+    ///
+    /// Call this with the line after the end of each chunk to stream a part
+    /// a chunk at a time. Requested channels that do not exist in the part
+    /// are left at `T::default()`.
+    ///
+    /// # Errors
+    /// * `[Error::ReadIo]` - If the reader fails
+    /// * `[Error::TileScanMixedApi]` - If the part is not a scanline part
+    ///
+    pub async fn read_scanline_chunk<T: ChannelElement + Send>(
+        &mut self,
+        part_index: usize,
+        y: i32,
+        channels: &[&str],
+    ) -> Result<ScanlineChunk<T>> {
+        if self.context().storage(part_index)? != Storage::Scanline {
+            return Err(Error::TileScanMixedApi);
+        }
+
+        let chunk_info = self
+            .retry(|ctx| ctx.read_scanline_chunk_info(part_index, y))
+            .await?;
+        let start_y = chunk_info.start_y;
+        let (width, height, pixels) =
+            self.decode_chunk(part_index, chunk_info, channels).await?;
+
+        Ok(ScanlineChunk {
+            start_y,
+            width,
+            height,
+            pixels,
+        })
+    }
+
+    /// Fetch and decode the tile at (`tile_x`, `tile_y`) in level
+    /// (`level_x`, `level_y`) of a tiled part, with the channels named in
+    /// `channels` interleaved in that order and converted to `T`
+    ///
+    /// Requested channels that do not exist in the part are left at
+    /// `T::default()`.
+    ///
+    /// # Errors
+    /// * `[Error::ReadIo]` - If the reader fails
+    /// * `[Error::ScanTileMixedApi]` - If the part is not a tiled part
+    ///
+    pub async fn read_tile<T: ChannelElement + Send>(
+        &mut self,
+        part_index: usize,
+        tile_x: i32,
+        tile_y: i32,
+        level_x: i32,
+        level_y: i32,
+        channels: &[&str],
+    ) -> Result<Tile<T>> {
+        if self.context().storage(part_index)? != Storage::Tiled {
+            return Err(Error::ScanTileMixedApi);
+        }
+
+        let chunk_info = self
+            .retry(|ctx| {
+                ctx.read_tile_chunk_info(
+                    part_index, tile_x, tile_y, level_x, level_y,
+                )
+            })
+            .await?;
+        let (width, height, pixels) =
+            self.decode_chunk(part_index, chunk_info, channels).await?;
+
+        Ok(Tile {
+            x: tile_x,
+            y: tile_y,
+            width: width as i32,
+            height: height as i32,
+            pixels,
+        })
+    }
+
+    /// Fetch and decode a chunk on the blocking thread pool, returning its
+    /// width, height and interleaved pixels
+    ///
+    async fn decode_chunk<T: ChannelElement + Send>(
+        &mut self,
+        part_index: usize,
+        chunk_info: ChunkInfo,
+        channels: &[&str],
+    ) -> Result<(usize, usize, Vec<T>)> {
+        self.fetch_range(chunk_info.data_offset, chunk_info.packed_size)
+            .await?;
+
+        let channels = owned(channels);
+        self.run_blocking(move |ctx| {
+            let channels =
+                channels.iter().map(|c| c.as_str()).collect::<Vec<_>>();
+            let width = chunk_info.width.max(0) as usize;
+            let height = chunk_info.height.max(0) as usize;
+            let mut pixels =
+                vec![T::default(); width * height * channels.len()];
+
+            let mut ready = ctx.decoding_initialize(part_index, &chunk_info)?;
+            ready.select_interleaved(&channels, &mut pixels)?;
+            let mut runnable = ready.choose_default_routines()?;
+            runnable.run_checked(&mut pixels)?;
+
+            Ok((width, height, pixels))
+        })
+        .await
+    }
+
+    /// Call `f`, fetching whatever it was missing and calling it again until
+    /// it doesn't miss anything
+    ///
+    async fn retry<T, F>(&mut self, f: F) -> Result<T>
+    where
+        F: Fn(&ReadContext) -> Result<T>,
+    {
+        let mut fetched = None;
+        loop {
+            let result = f(&self.ctx);
+            let miss = self.cache.lock().unwrap().take_miss(fetched)?;
+            match (result, miss) {
+                (Err(_), Some(miss)) => {
+                    fetch(&mut self.reader, &self.cache, miss).await?;
+                    fetched = Some(miss);
+                }
+                (result, _) => {
+                    self.cache.lock().unwrap().trim();
+                    return result;
+                }
+            }
+        }
+    }
+
+    /// As [`retry`](AsyncReadContext::retry), but calling `f` on the blocking
+    /// thread pool
+    ///
+    async fn run_blocking<T, F>(&mut self, f: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: Fn(&ReadContext) -> Result<T> + Clone + Send + 'static,
+    {
+        let mut fetched = None;
+        loop {
+            let ctx = self.ctx.clone();
+            let task = f.clone();
+            let result =
+                match tokio::task::spawn_blocking(move || task(&ctx)).await {
+                    Ok(result) => result,
+                    Err(e) => std::panic::resume_unwind(e.into_panic()),
+                };
+
+            let miss = self.cache.lock().unwrap().take_miss(fetched)?;
+            match (result, miss) {
+                (Err(_), Some(miss)) => {
+                    fetch(&mut self.reader, &self.cache, miss).await?;
+                    fetched = Some(miss);
+                }
+                (result, _) => {
+                    self.cache.lock().unwrap().trim();
+                    return result;
+                }
+            }
+        }
+    }
+
+    async fn fetch_range(&mut self, offset: u64, size: u64) -> Result<()> {
+        let cached = self
+            .cache
+            .lock()
+            .unwrap()
+            .find(offset, offset + size)
+            .is_some();
+        if cached {
+            Ok(())
+        } else {
+            fetch(&mut self.reader, &self.cache, (offset, offset + size)).await
+        }
+    }
+}
+
+fn owned(channels: &[&str]) -> Vec<String> {
+    channels.iter().map(|c| c.to_string()).collect()
+}
+
+/// Read the bytes from `start` to `end` into the cache, along with some more
+/// after them if they're small
+///
+async fn fetch<R: AsyncRead + AsyncSeek + Unpin>(
+    reader: &mut R,
+    cache: &Mutex<StreamCache>,
+    (start, end): (u64, u64),
+) -> Result<()> {
+    let size = cache.lock().unwrap().size;
+    let end = end.max(start + MIN_FETCH_SIZE).min(size);
+    if start >= end {
+        return Err(Error::ReadIo);
+    }
+
+    let mut data = vec![0u8; (end - start) as usize];
+    reader
+        .seek(SeekFrom::Start(start))
+        .await
+        .map_err(|_| Error::ReadIo)?;
+    reader
+        .read_exact(&mut data)
+        .await
+        .map_err(|_| Error::ReadIo)?;
+
+    cache.lock().unwrap().insert(start, data);
+    Ok(())
+}
+
+unsafe extern "C" fn cache_read(
+    _ctxt: sys::exr_const_context_t,
+    userdata: *mut c_void,
+    buffer: *mut c_void,
+    sz: u64,
+    offset: u64,
+    _error_cb: sys::exr_stream_error_func_ptr_t,
+) -> i64 {
+    let cache = stream::<Arc<Mutex<StreamCache>>>(userdata);
+    let mut cache = match cache.lock() {
+        Ok(cache) => cache,
+        Err(_) => return -1,
+    };
+    if offset > cache.size {
+        return -1;
+    }
+
+    // reads past the end are short rather than failing, as for a file
+    let end = offset.saturating_add(sz).min(cache.size);
+    match cache.find(offset, end) {
+        Some(data) => {
+            std::ptr::copy_nonoverlapping(
+                data.as_ptr(),
+                buffer as *mut u8,
+                data.len(),
+            );
+            data.len() as i64
+        }
+        None => {
+            cache.miss = Some((offset, end));
+            -1
+        }
+    }
+}
+
+unsafe extern "C" fn cache_size(
+    _ctxt: sys::exr_const_context_t,
+    userdata: *mut c_void,
+) -> i64 {
+    let cache = stream::<Arc<Mutex<StreamCache>>>(userdata);
+    cache.lock().map(|c| c.size as i64).unwrap_or(-1)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate as exr;
+    use exr::async_read::AsyncReadContext;
+    use imath_traits::f16;
+    use std::path::Path;
+
+    #[tokio::test]
+    async fn async_read_image() -> Result<(), exr::Error> {
+        let path_ferris = Path::new(
+            &std::env::var("CARGO_MANIFEST_DIR")
+                .expect("CARGO_MANIFEST_DIR not set"),
+        )
+        .join("images")
+        .join("ferris.exr");
+
+        let bytes = std::fs::read(&path_ferris).unwrap();
+        let mut ctx =
+            AsyncReadContext::new(std::io::Cursor::new(bytes)).await?;
+        assert_eq!(
+            ctx.context().data_window::<[i32; 4]>(0)?,
+            [0, 0, 1199, 799]
+        );
+
+        let chunk = ctx.read_scanline_chunk::<f16>(0, 0, &["R"]).await?;
+        assert_eq!(chunk.start_y, 0);
+        assert_eq!(chunk.pixels.len(), 1200 * chunk.height);
+
+        let image = ctx.read_image::<f16>(0, &["R", "G", "B", "A"]).await?;
+        let file_ctx = exr::context::ReadContext::new(&path_ferris)?;
+        assert_eq!(image, file_ctx.read_image(0, &["R", "G", "B", "A"])?);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn async_read_tiles() -> Result<(), exr::Error> {
+        let path_tiled = Path::new(
+            &std::env::var("CARGO_MANIFEST_DIR")
+                .expect("CARGO_MANIFEST_DIR not set"),
+        )
+        .join("images")
+        .join("ferris-tiled.exr");
+        let file_ctx = exr::context::ReadContext::new(&path_tiled)?;
+
+        // with no room to spare, only the chunk being decoded is cached
+        let bytes = std::fs::read(&path_tiled).unwrap();
+        let mut ctx =
+            AsyncReadContext::with_cache_limit(std::io::Cursor::new(bytes), 0)
+                .await?;
+
+        let channels = ["R", "G", "B", "A"];
+        let tile = ctx.read_tile::<f16>(0, 1, 0, 0, 0, &channels).await?;
+        let expected = file_ctx
+            .tiles::<f16>(0, 0, 0, &channels)?
+            .nth(1)
+            .expect("no second tile")?;
+        assert_eq!(tile, expected);
+        assert_eq!(
+            ctx.read_scanline_chunk::<f16>(0, 0, &channels)
+                .await
+                .unwrap_err()
+                .kind(),
+            exr::ErrorKind::TileScanMixedApi
+        );
+
+        let image = ctx.read_image::<f16>(0, &channels).await?;
+        assert_eq!(image, file_ctx.read_image(0, &channels)?);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn async_read_large_header() -> Result<(), exr::Error> {
+        use exr::attr::PixelType;
+        use exr::context::{DefaultWriteMode, WriteHeaderContext};
+        use exr::write::ImageDesc;
+
+        let path_ferris = Path::new(
+            &std::env::var("CARGO_MANIFEST_DIR")
+                .expect("CARGO_MANIFEST_DIR not set"),
+        )
+        .join("images")
+        .join("ferris.exr");
+        let src = exr::context::ReadContext::new(&path_ferris)?;
+        let channels = ["A", "B", "G", "R"];
+        let image = src.read_image::<f16>(0, &channels)?;
+        let bytes = image
+            .pixels
+            .iter()
+            .flat_map(|p| p.to_le_bytes())
+            .collect::<Vec<_>>();
+        let desc = ImageDesc::new(
+            image.width,
+            image.height,
+            &channels,
+            PixelType::Half,
+        );
+
+        // an attribute big enough that the header spans several fetches
+        let path = std::env::temp_dir().join("async_read_large_header.exr");
+        let mut header = WriteHeaderContext::new(
+            &path,
+            DefaultWriteMode::WriteFileDirectly,
+        )?;
+        header.add_part_copy(&src, 0, None)?;
+        let weights = vec![0.5f32; 64 * 1024];
+        header.set_attribute(0, "weights", &&weights[..])?;
+        let mut ctx = header.write_header()?;
+        ctx.write_image(0, &desc, &bytes)?;
+        ctx.close()?;
+
+        let bytes = std::fs::read(&path).unwrap();
+        let mut ctx =
+            AsyncReadContext::with_cache_limit(std::io::Cursor::new(bytes), 0)
+                .await?;
+        assert_eq!(
+            ctx.context().get_attribute::<&[f32]>(0, "weights")?,
+            &weights[..]
+        );
+        assert_eq!(ctx.read_image::<f16>(0, &channels).await?, image);
+
+        Ok(())
+    }
+}
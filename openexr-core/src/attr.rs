@@ -1,31 +1,29 @@
 use std::borrow::Cow;
-use std::ffi::{CStr, CString};
+use std::ffi::{c_void, CStr, CString};
+use std::fmt;
 use std::marker::PhantomData;
 
-use std::convert::TryInto;
+use std::cmp::Ordering;
+use std::convert::{TryFrom, TryInto};
 use std::ops::Deref;
 
-use crate::context::{Context, ContextState, WriteHeaderContext};
+use crate::context::{
+    c_part_index, Context, ContextState, WritableHeaderState,
+};
 
-use imath_traits::Bound2;
+use imath_traits::{Bound2, Vec2};
 
 use crate::error::Error;
 type Result<T, E = Error> = std::result::Result<T, E>;
 
 use openexr_core_sys as sys;
 
-pub use sys::exr_attr_chromaticities_t as AttrChromaticities;
-pub use sys::exr_attr_keycode_t as AttrKeycode;
-
 pub use sys::exr_attr_m33d_t as AttrM33d;
 pub use sys::exr_attr_m33f_t as AttrM33f;
 
 pub use sys::exr_attr_m44d_t as AttrM44d;
 pub use sys::exr_attr_m44f_t as AttrM44f;
 
-pub use sys::exr_attr_rational_t as AttrRational;
-pub use sys::exr_attr_timecode_t as AttrTimecode;
-
 pub use sys::exr_attr_v2d_t as AttrV2d;
 pub use sys::exr_attr_v2f_t as AttrV2f;
 pub use sys::exr_attr_v2i_t as AttrV2i;
@@ -39,6 +37,7 @@ pub use sys::exr_attr_box2i_t as AttrBox2i;
 
 pub use sys::exr_attr_tiledesc_t as AttrTiledesc;
 
+#[repr(transparent)]
 pub struct Attribute(pub(crate) sys::exr_attribute_t);
 
 impl Attribute {
@@ -53,6 +52,161 @@ impl Attribute {
     pub fn set_name(&mut self, name: &CStr) {
         self.0.name = name.as_ptr();
     }
+
+    /// Name of the attribute's type as stored in the file, e.g. "box2i"
+    ///
+    pub fn type_name(&self) -> &str {
+        unsafe {
+            CStr::from_ptr(self.0.type_name)
+                .to_str()
+                .expect("Could not convert type name string")
+        }
+    }
+
+    /// The type of the attribute, for dispatching on without reading its
+    /// value
+    ///
+    pub fn kind(&self) -> AttrType {
+        self.0.type_.into()
+    }
+
+    /// Copy the attribute's value out of the header
+    ///
+    pub fn value(&self) -> AttributeValue {
+        AttributeValue::from(self)
+    }
+
+    /// The packed bytes of an attribute of a type the library does not know
+    /// how to interpret, as they appear in the file, or `None` if the
+    /// attribute is of a known type
+    ///
+    pub fn opaque_data(&self) -> Option<&[u8]> {
+        if self.kind() != AttrType::Opaque {
+            return None;
+        }
+        unsafe {
+            let o = self.0.__bindgen_anon_1.opaque;
+            if o.is_null() {
+                Some(&[])
+            } else {
+                Some(slice_or_empty(
+                    (*o).packed_data as *const u8,
+                    (*o).size.max(0) as usize,
+                ))
+            }
+        }
+    }
+}
+
+/// The types of attribute the library knows how to interpret
+///
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum AttrType {
+    Box2i,
+    Box2f,
+    Chlist,
+    Chromaticities,
+    Compression,
+    Double,
+    Envmap,
+    Float,
+    FloatVector,
+    Int,
+    Keycode,
+    LineOrder,
+    M33f,
+    M33d,
+    M44f,
+    M44d,
+    Preview,
+    Rational,
+    String,
+    StringVector,
+    Tiledesc,
+    Timecode,
+    V2i,
+    V2f,
+    V2d,
+    V3i,
+    V3f,
+    V3d,
+    /// Any other type, which the library keeps as packed bytes. Use
+    /// [`Attribute::type_name`] to find out what it is
+    Opaque,
+}
+
+impl From<sys::exr_attribute_type_t> for AttrType {
+    fn from(t: sys::exr_attribute_type_t) -> AttrType {
+        use sys::exr_attribute_type_t as T;
+        match t {
+            T::EXR_ATTR_BOX2I => AttrType::Box2i,
+            T::EXR_ATTR_BOX2F => AttrType::Box2f,
+            T::EXR_ATTR_CHLIST => AttrType::Chlist,
+            T::EXR_ATTR_CHROMATICITIES => AttrType::Chromaticities,
+            T::EXR_ATTR_COMPRESSION => AttrType::Compression,
+            T::EXR_ATTR_DOUBLE => AttrType::Double,
+            T::EXR_ATTR_ENVMAP => AttrType::Envmap,
+            T::EXR_ATTR_FLOAT => AttrType::Float,
+            T::EXR_ATTR_FLOAT_VECTOR => AttrType::FloatVector,
+            T::EXR_ATTR_INT => AttrType::Int,
+            T::EXR_ATTR_KEYCODE => AttrType::Keycode,
+            T::EXR_ATTR_LINEORDER => AttrType::LineOrder,
+            T::EXR_ATTR_M33F => AttrType::M33f,
+            T::EXR_ATTR_M33D => AttrType::M33d,
+            T::EXR_ATTR_M44F => AttrType::M44f,
+            T::EXR_ATTR_M44D => AttrType::M44d,
+            T::EXR_ATTR_PREVIEW => AttrType::Preview,
+            T::EXR_ATTR_RATIONAL => AttrType::Rational,
+            T::EXR_ATTR_STRING => AttrType::String,
+            T::EXR_ATTR_STRING_VECTOR => AttrType::StringVector,
+            T::EXR_ATTR_TILEDESC => AttrType::Tiledesc,
+            T::EXR_ATTR_TIMECODE => AttrType::Timecode,
+            T::EXR_ATTR_V2I => AttrType::V2i,
+            T::EXR_ATTR_V2F => AttrType::V2f,
+            T::EXR_ATTR_V2D => AttrType::V2d,
+            T::EXR_ATTR_V3I => AttrType::V3i,
+            T::EXR_ATTR_V3F => AttrType::V3f,
+            T::EXR_ATTR_V3D => AttrType::V3d,
+            _ => AttrType::Opaque,
+        }
+    }
+}
+
+impl From<AttrType> for sys::exr_attribute_type_t {
+    fn from(t: AttrType) -> sys::exr_attribute_type_t {
+        use sys::exr_attribute_type_t as T;
+        match t {
+            AttrType::Box2i => T::EXR_ATTR_BOX2I,
+            AttrType::Box2f => T::EXR_ATTR_BOX2F,
+            AttrType::Chlist => T::EXR_ATTR_CHLIST,
+            AttrType::Chromaticities => T::EXR_ATTR_CHROMATICITIES,
+            AttrType::Compression => T::EXR_ATTR_COMPRESSION,
+            AttrType::Double => T::EXR_ATTR_DOUBLE,
+            AttrType::Envmap => T::EXR_ATTR_ENVMAP,
+            AttrType::Float => T::EXR_ATTR_FLOAT,
+            AttrType::FloatVector => T::EXR_ATTR_FLOAT_VECTOR,
+            AttrType::Int => T::EXR_ATTR_INT,
+            AttrType::Keycode => T::EXR_ATTR_KEYCODE,
+            AttrType::LineOrder => T::EXR_ATTR_LINEORDER,
+            AttrType::M33f => T::EXR_ATTR_M33F,
+            AttrType::M33d => T::EXR_ATTR_M33D,
+            AttrType::M44f => T::EXR_ATTR_M44F,
+            AttrType::M44d => T::EXR_ATTR_M44D,
+            AttrType::Preview => T::EXR_ATTR_PREVIEW,
+            AttrType::Rational => T::EXR_ATTR_RATIONAL,
+            AttrType::String => T::EXR_ATTR_STRING,
+            AttrType::StringVector => T::EXR_ATTR_STRING_VECTOR,
+            AttrType::Tiledesc => T::EXR_ATTR_TILEDESC,
+            AttrType::Timecode => T::EXR_ATTR_TIMECODE,
+            AttrType::V2i => T::EXR_ATTR_V2I,
+            AttrType::V2f => T::EXR_ATTR_V2F,
+            AttrType::V2d => T::EXR_ATTR_V2D,
+            AttrType::V3i => T::EXR_ATTR_V3I,
+            AttrType::V3f => T::EXR_ATTR_V3F,
+            AttrType::V3d => T::EXR_ATTR_V3D,
+            AttrType::Opaque => T::EXR_ATTR_OPAQUE,
+        }
+    }
 }
 
 pub enum AttrString<'a> {
@@ -95,6 +249,7 @@ impl<'a> From<&'a sys::exr_attr_string_t> for AttrString<'a> {
 }
 
 #[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub enum Compression {
     None,
     Rle,
@@ -106,6 +261,22 @@ pub enum Compression {
     B44a,
     Dwaa,
     Dwab,
+    /// A compression type added in a newer version of OpenEXR than these
+    /// bindings know about (e.g. HTJ2K), holding the raw value from the file.
+    ///
+    /// Headers using it can still be read and written if the linked library
+    /// accepts the value, but decoding is refused with
+    /// [`Error::FeatureNotImplemented`].
+    Unknown(u32),
+}
+
+impl Compression {
+    /// Returns `true` if this is one of the compression types these bindings
+    /// know how to decode
+    ///
+    pub fn is_known(&self) -> bool {
+        !matches!(self, Compression::Unknown(_))
+    }
 }
 
 impl From<Compression> for sys::exr_compression_t {
@@ -121,6 +292,7 @@ impl From<Compression> for sys::exr_compression_t {
             Compression::B44a => sys::exr_compression_t::EXR_COMPRESSION_B44A,
             Compression::Dwaa => sys::exr_compression_t::EXR_COMPRESSION_DWAA,
             Compression::Dwab => sys::exr_compression_t::EXR_COMPRESSION_DWAB,
+            Compression::Unknown(c) => sys::exr_compression_t(c),
         }
     }
 }
@@ -138,12 +310,13 @@ impl From<sys::exr_compression_t> for Compression {
             sys::exr_compression_t::EXR_COMPRESSION_B44A => Compression::B44a,
             sys::exr_compression_t::EXR_COMPRESSION_DWAA => Compression::Dwaa,
             sys::exr_compression_t::EXR_COMPRESSION_DWAB => Compression::Dwab,
-            _ => panic!("Unhandled compression variant"),
+            sys::exr_compression_t(c) => Compression::Unknown(c),
         }
     }
 }
 
 #[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub enum Envmap {
     Latlong,
     Cube,
@@ -158,17 +331,20 @@ impl From<Envmap> for sys::exr_envmap_t {
     }
 }
 
-impl From<sys::exr_envmap_t> for Envmap {
-    fn from(e: sys::exr_envmap_t) -> Self {
+impl TryFrom<sys::exr_envmap_t> for Envmap {
+    type Error = Error;
+
+    fn try_from(e: sys::exr_envmap_t) -> Result<Envmap> {
         match e {
-            sys::exr_envmap_t::EXR_ENVMAP_LATLONG => Envmap::Latlong,
-            sys::exr_envmap_t::EXR_ENVMAP_CUBE => Envmap::Cube,
-            _ => panic!("Unhandled envmap variant"),
+            sys::exr_envmap_t::EXR_ENVMAP_LATLONG => Ok(Envmap::Latlong),
+            sys::exr_envmap_t::EXR_ENVMAP_CUBE => Ok(Envmap::Cube),
+            _ => Err(Error::Unknown),
         }
     }
 }
 
 #[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub enum LineOrder {
     IncreasingY,
     DecreasingY,
@@ -189,17 +365,21 @@ impl From<LineOrder> for sys::exr_lineorder_t {
     }
 }
 
-impl From<sys::exr_lineorder_t> for LineOrder {
-    fn from(e: sys::exr_lineorder_t) -> Self {
+impl TryFrom<sys::exr_lineorder_t> for LineOrder {
+    type Error = Error;
+
+    fn try_from(e: sys::exr_lineorder_t) -> Result<LineOrder> {
         match e {
             sys::exr_lineorder_t::EXR_LINEORDER_INCREASING_Y => {
-                LineOrder::IncreasingY
+                Ok(LineOrder::IncreasingY)
             }
             sys::exr_lineorder_t::EXR_LINEORDER_DECREASING_Y => {
-                LineOrder::DecreasingY
+                Ok(LineOrder::DecreasingY)
             }
-            sys::exr_lineorder_t::EXR_LINEORDER_RANDOM_Y => LineOrder::RandomY,
-            _ => panic!("Unhandled lineorder variant"),
+            sys::exr_lineorder_t::EXR_LINEORDER_RANDOM_Y => {
+                Ok(LineOrder::RandomY)
+            }
+            _ => Err(Error::Unknown),
         }
     }
 }
@@ -225,23 +405,26 @@ impl From<Storage> for sys::exr_storage_t {
     }
 }
 
-impl From<sys::exr_storage_t> for Storage {
-    fn from(s: sys::exr_storage_t) -> Storage {
+impl TryFrom<sys::exr_storage_t> for Storage {
+    type Error = Error;
+
+    fn try_from(s: sys::exr_storage_t) -> Result<Storage> {
         match s {
-            sys::exr_storage_t::EXR_STORAGE_SCANLINE => Storage::Scanline,
-            sys::exr_storage_t::EXR_STORAGE_TILED => Storage::Tiled,
+            sys::exr_storage_t::EXR_STORAGE_SCANLINE => Ok(Storage::Scanline),
+            sys::exr_storage_t::EXR_STORAGE_TILED => Ok(Storage::Tiled),
             sys::exr_storage_t::EXR_STORAGE_DEEP_SCANLINE => {
-                Storage::DeepScanline
+                Ok(Storage::DeepScanline)
             }
-            sys::exr_storage_t::EXR_STORAGE_DEEP_TILED => Storage::DeepTiled,
-            _ => {
-                panic!("unhandled exr_storage_t value")
+            sys::exr_storage_t::EXR_STORAGE_DEEP_TILED => {
+                Ok(Storage::DeepTiled)
             }
+            _ => Err(Error::Unknown),
         }
     }
 }
 
 #[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub enum LevelMode {
     OneLevel,
     MipmapLevels,
@@ -264,26 +447,27 @@ impl From<LevelMode> for sys::exr_tile_level_mode_t {
     }
 }
 
-impl From<sys::exr_tile_level_mode_t> for LevelMode {
-    fn from(s: sys::exr_tile_level_mode_t) -> LevelMode {
+impl TryFrom<sys::exr_tile_level_mode_t> for LevelMode {
+    type Error = Error;
+
+    fn try_from(s: sys::exr_tile_level_mode_t) -> Result<LevelMode> {
         match s {
             sys::exr_tile_level_mode_t::EXR_TILE_ONE_LEVEL => {
-                LevelMode::OneLevel
+                Ok(LevelMode::OneLevel)
             }
             sys::exr_tile_level_mode_t::EXR_TILE_MIPMAP_LEVELS => {
-                LevelMode::MipmapLevels
+                Ok(LevelMode::MipmapLevels)
             }
             sys::exr_tile_level_mode_t::EXR_TILE_RIPMAP_LEVELS => {
-                LevelMode::RipmapLevels
-            }
-            _ => {
-                panic!("unhandled exr_tile_level_mode_t value")
+                Ok(LevelMode::RipmapLevels)
             }
+            _ => Err(Error::Unknown),
         }
     }
 }
 
 #[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub enum TileRoundMode {
     RoundDown,
     RoundUp,
@@ -302,23 +486,66 @@ impl From<TileRoundMode> for sys::exr_tile_round_mode_t {
     }
 }
 
-impl From<sys::exr_tile_round_mode_t> for TileRoundMode {
-    fn from(e: sys::exr_tile_round_mode_t) -> Self {
+impl TryFrom<sys::exr_tile_round_mode_t> for TileRoundMode {
+    type Error = Error;
+
+    fn try_from(e: sys::exr_tile_round_mode_t) -> Result<TileRoundMode> {
         match e {
             sys::exr_tile_round_mode_t::EXR_TILE_ROUND_DOWN => {
-                TileRoundMode::RoundDown
+                Ok(TileRoundMode::RoundDown)
             }
             sys::exr_tile_round_mode_t::EXR_TILE_ROUND_UP => {
-                TileRoundMode::RoundUp
-            }
-            _ => {
-                panic!("unhandled exr_tile_round_mode_t value")
+                Ok(TileRoundMode::RoundUp)
             }
+            _ => Err(Error::Unknown),
+        }
+    }
+}
+
+/// The size of the tiles of a tiled part, and how its levels are laid out
+///
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct TileDescriptor {
+    pub x_size: u32,
+    pub y_size: u32,
+    pub level_mode: LevelMode,
+    pub round_mode: TileRoundMode,
+}
+
+impl TryFrom<&sys::exr_attr_tiledesc_t> for TileDescriptor {
+    type Error = Error;
+
+    fn try_from(td: &sys::exr_attr_tiledesc_t) -> Result<TileDescriptor> {
+        Ok(TileDescriptor {
+            x_size: td.x_size,
+            y_size: td.y_size,
+            level_mode: sys::exr_tile_level_mode_t(
+                (td.level_and_round & 0xF) as u32,
+            )
+            .try_into()?,
+            round_mode: sys::exr_tile_round_mode_t(
+                ((td.level_and_round >> 4) & 0xF) as u32,
+            )
+            .try_into()?,
+        })
+    }
+}
+
+impl From<TileDescriptor> for sys::exr_attr_tiledesc_t {
+    fn from(t: TileDescriptor) -> Self {
+        let level = sys::exr_tile_level_mode_t::from(t.level_mode).0 as u8;
+        let round = sys::exr_tile_round_mode_t::from(t.round_mode).0 as u8;
+        sys::exr_attr_tiledesc_t {
+            x_size: t.x_size,
+            y_size: t.y_size,
+            level_and_round: (round << 4) | level,
         }
     }
 }
 
 #[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub enum PixelType {
     Uint,
     Half,
@@ -335,13 +562,15 @@ impl From<PixelType> for sys::exr_pixel_type_t {
     }
 }
 
-impl From<sys::exr_pixel_type_t> for PixelType {
-    fn from(e: sys::exr_pixel_type_t) -> Self {
+impl TryFrom<sys::exr_pixel_type_t> for PixelType {
+    type Error = Error;
+
+    fn try_from(e: sys::exr_pixel_type_t) -> Result<PixelType> {
         match e {
-            sys::exr_pixel_type_t::EXR_PIXEL_UINT => PixelType::Uint,
-            sys::exr_pixel_type_t::EXR_PIXEL_HALF => PixelType::Half,
-            sys::exr_pixel_type_t::EXR_PIXEL_FLOAT => PixelType::Float,
-            _ => panic!("Unhandled exr_pixel_type_t value"),
+            sys::exr_pixel_type_t::EXR_PIXEL_UINT => Ok(PixelType::Uint),
+            sys::exr_pixel_type_t::EXR_PIXEL_HALF => Ok(PixelType::Half),
+            sys::exr_pixel_type_t::EXR_PIXEL_FLOAT => Ok(PixelType::Float),
+            _ => Err(Error::Unknown),
         }
     }
 }
@@ -358,8 +587,8 @@ impl Channel {
         }
     }
 
-    pub fn pixel_type(&self) -> PixelType {
-        self.0.pixel_type.into()
+    pub fn pixel_type(&self) -> Result<PixelType> {
+        self.0.pixel_type.try_into()
     }
 
     pub fn p_linear(&self) -> bool {
@@ -381,9 +610,9 @@ pub struct ChannelList(sys::exr_attr_chlist_t);
 impl ChannelList {
     pub fn as_slice(&self) -> &[Channel] {
         unsafe {
-            std::slice::from_raw_parts(
+            slice_or_empty(
                 self.0.entries as *const Channel,
-                self.0.num_channels.try_into().unwrap(),
+                self.0.num_channels.max(0) as usize,
             )
         }
     }
@@ -403,121 +632,3648 @@ impl Deref for ChannelList {
     }
 }
 
-pub trait AttributeRead: Sized {
-    fn get<S: ContextState>(
-        ctx: &Context<S>,
-        part_index: usize,
-        name: &str,
-    ) -> Result<Self>;
+/// An owned description of a channel
+///
+/// [`Channel`] and [`ChannelList`] are views into memory owned by a context,
+/// so can't outlive it or be constructed directly. `ChannelDesc` holds the same
+/// information by value so it can be stored, compared and used to declare
+/// channels when writing.
+///
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct ChannelDesc {
+    pub name: String,
+    pub pixel_type: PixelType,
+    /// Subsampling in x and y, respectively. (1, 1) for full resolution
+    pub sampling: (i32, i32),
+    /// Is the channel perceptually linear?
+    pub p_linear: bool,
 }
 
-pub trait AttributeWrite: Sized {
-    fn set(
-        ctx: &WriteHeaderContext,
-        part_index: usize,
-        name: &str,
-        value: &Self,
-    ) -> Result<()>;
+impl ChannelDesc {
+    /// Create a full-resolution, non-perceptually-linear channel description
+    ///
+    pub fn new<S: Into<String>>(name: S, pixel_type: PixelType) -> ChannelDesc {
+        ChannelDesc {
+            name: name.into(),
+            pixel_type,
+            sampling: (1, 1),
+            p_linear: false,
+        }
+    }
 }
 
-impl AttributeRead for f32 {
-    fn get<S: ContextState>(
-        ctx: &Context<S>,
-        part_index: usize,
+impl TryFrom<&Channel> for ChannelDesc {
+    type Error = Error;
+
+    fn try_from(c: &Channel) -> Result<ChannelDesc> {
+        Ok(ChannelDesc {
+            name: c.name().to_string(),
+            pixel_type: c.pixel_type()?,
+            sampling: (c.x_sampling(), c.y_sampling()),
+            p_linear: c.p_linear(),
+        })
+    }
+}
+
+impl ChannelList {
+    /// Copy the channel list into a `Vec` of owned [`ChannelDesc`]
+    ///
+    /// # Errors
+    /// * `[Error::Unknown]` - If a channel has a pixel type these bindings
+    /// do not know about
+    ///
+    pub fn descs(&self) -> Result<Vec<ChannelDesc>> {
+        self.iter().map(ChannelDesc::try_from).collect()
+    }
+}
+
+/// The longest channel name a file can hold, in bytes. Names longer than 31
+/// bytes also need long name support, which contexts enable automatically
+/// when they are allowed to.
+///
+const MAX_NAME_LENGTH: usize = 255;
+
+/// Builds up a list of channels in Rust, checking each as it is added, so it
+/// can be set on a part being written in one go with
+/// [`set_channels`](crate::context::WriteHeaderContext::set_channels)
+///
+/// A builder can also be made from an existing [`ChannelList`] to copy a
+/// part's channels with some changes.
+///
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ChannelListBuilder {
+    channels: Vec<ChannelDesc>,
+}
+
+impl ChannelListBuilder {
+    pub fn new() -> ChannelListBuilder {
+        ChannelListBuilder::default()
+    }
+
+    /// Add `channel` to the list
+    ///
+    /// # Errors
+    /// * `[Error::InvalidArgument]` - If the name is empty, contains null
+    /// bytes or is already in the list, or either sampling is less than 1
+    /// * `[Error::NameTooLong]` - If the name is longer than 255 bytes
+    ///
+    pub fn add(&mut self, channel: ChannelDesc) -> Result<&mut Self> {
+        if channel.name.is_empty()
+            || channel.name.contains('\0')
+            || channel.sampling.0 < 1
+            || channel.sampling.1 < 1
+            || self.get(&channel.name).is_some()
+        {
+            return Err(Error::InvalidArgument);
+        }
+        if channel.name.len() > MAX_NAME_LENGTH {
+            return Err(Error::NameTooLong);
+        }
+
+        self.channels.push(channel);
+        Ok(self)
+    }
+
+    /// Add a full-resolution, non-perceptually-linear channel called `name`
+    /// to the list
+    ///
+    /// # Errors
+    /// * `[Error::InvalidArgument]` - If `name` is empty, contains null bytes
+    /// or is already in the list
+    /// * `[Error::NameTooLong]` - If `name` is longer than 255 bytes
+    ///
+    pub fn channel(
+        &mut self,
         name: &str,
-    ) -> Result<Self> {
-        let mut result = Default::default();
-        unsafe {
-            let c_name = CString::new(name).unwrap();
-            sys::exr_attr_get_float(
-                ctx.inner,
-                part_index.try_into().unwrap(),
-                c_name.as_ptr(),
-                &mut result,
-            )
-            .ok(result)
+        pixel_type: PixelType,
+    ) -> Result<&mut Self> {
+        self.add(ChannelDesc::new(name, pixel_type))
+    }
+
+    /// Remove the channel called `name` from the list, returning it if it
+    /// was there
+    ///
+    pub fn remove(&mut self, name: &str) -> Option<ChannelDesc> {
+        let index = self.channels.iter().position(|c| c.name == name)?;
+        Some(self.channels.remove(index))
+    }
+
+    /// Get the channel called `name`
+    ///
+    pub fn get(&self, name: &str) -> Option<&ChannelDesc> {
+        self.channels.iter().find(|c| c.name == name)
+    }
+
+    /// The channels in the order they were added
+    ///
+    pub fn channels(&self) -> &[ChannelDesc] {
+        &self.channels
+    }
+
+    /// Do any of the names need long name support, i.e. are longer than 31
+    /// bytes?
+    ///
+    pub fn needs_long_names(&self) -> bool {
+        self.channels.iter().any(|c| c.name.len() > 31)
+    }
+}
+
+impl TryFrom<&ChannelList> for ChannelListBuilder {
+    type Error = Error;
+
+    fn try_from(list: &ChannelList) -> Result<ChannelListBuilder> {
+        Ok(ChannelListBuilder {
+            channels: list.descs()?,
+        })
+    }
+}
+
+/// Split a channel name into its layer and base name, e.g. "diffuse.R" into
+/// `(Some("diffuse"), "R")`
+///
+pub(crate) fn split_layer(name: &str) -> (Option<&str>, &str) {
+    match name.rfind('.') {
+        Some(i) => (Some(&name[..i]), &name[i + 1..]),
+        None => (None, name),
+    }
+}
+
+/// A group of channels sharing a layer name, following the `layer.channel`
+/// naming convention, e.g. "diffuse.R", "diffuse.G" and "diffuse.B"
+///
+/// Nested layers such as "light1.specular.R" are grouped by everything before
+/// the last '.', i.e. "light1.specular".
+///
+#[derive(Clone)]
+pub struct Layer<'a> {
+    name: Option<&'a str>,
+    channels: Vec<&'a Channel>,
+}
+
+impl<'a> Layer<'a> {
+    /// The name of the layer, or `None` for channels with no layer prefix
+    ///
+    pub fn name(&self) -> Option<&'a str> {
+        self.name
+    }
+
+    /// The channels in the layer, in the order they appear in the channel
+    /// list
+    ///
+    pub fn channels(&self) -> &[&'a Channel] {
+        &self.channels
+    }
+
+    /// The names of the channels in the layer with the layer prefix removed,
+    /// e.g. "R" for "diffuse.R"
+    ///
+    pub fn base_names(&self) -> impl Iterator<Item = &'a str> + '_ {
+        self.channels.iter().map(|c| split_layer(c.name()).1)
+    }
+
+    /// Get the channel in the layer with base name `base`, e.g. "R"
+    ///
+    pub fn channel(&self, base: &str) -> Option<&'a Channel> {
+        self.channels
+            .iter()
+            .find(|c| split_layer(c.name()).1 == base)
+            .copied()
+    }
+}
+
+impl ChannelList {
+    /// Group the channels by layer, in the order each layer first appears in
+    /// the channel list. Channels with no layer prefix are grouped in a layer
+    /// whose name is `None`
+    ///
+    pub fn layers(&self) -> Vec<Layer<'_>> {
+        let mut layers: Vec<Layer> = Vec::new();
+        for channel in self.iter() {
+            let (name, _) = split_layer(channel.name());
+            match layers.iter_mut().find(|l| l.name == name) {
+                Some(layer) => layer.channels.push(channel),
+                None => layers.push(Layer {
+                    name,
+                    channels: vec![channel],
+                }),
+            }
         }
+        layers
+    }
+
+    /// The names of all the layers in the list, not including the unprefixed
+    /// channels
+    ///
+    pub fn layer_names(&self) -> Vec<&str> {
+        self.layers().iter().filter_map(|l| l.name()).collect()
+    }
+
+    /// Get the channels of the layer `name`, e.g. "diffuse", or `None` if
+    /// there are no channels in that layer
+    ///
+    pub fn layer(&self, name: &str) -> Option<Layer<'_>> {
+        self.layers().into_iter().find(|l| l.name == Some(name))
     }
 }
 
-impl AttributeRead for i32 {
-    fn get<S: ContextState>(
-        ctx: &Context<S>,
-        part_index: usize,
-        name: &str,
-    ) -> Result<Self> {
-        let mut result = Default::default();
-        unsafe {
-            let c_name = CString::new(name).unwrap();
-            sys::exr_attr_get_int(
-                ctx.inner,
-                part_index.try_into().unwrap(),
-                c_name.as_ptr(),
-                &mut result,
-            )
-            .ok(result)
+/// An owned copy of an attribute's value
+///
+/// Unlike [`Attribute`], which borrows from the context it was read from, an
+/// `AttributeValue` can be stored and compared after the context is gone.
+///
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub enum AttributeValue {
+    Box2i([i32; 4]),
+    Box2f([f32; 4]),
+    Chlist(Vec<ChannelDesc>),
+    Chromaticities(AttrChromaticities),
+    Compression(Compression),
+    Double(f64),
+    Envmap(Envmap),
+    Float(f32),
+    FloatVector(Vec<f32>),
+    Int(i32),
+    Keycode(AttrKeycode),
+    LineOrder(LineOrder),
+    M33f([f32; 9]),
+    M33d([f64; 9]),
+    M44f([f32; 16]),
+    M44d([f64; 16]),
+    Preview(PreviewBuf),
+    Rational(AttrRational),
+    String(String),
+    StringVector(Vec<String>),
+    Tiledesc(TileDescriptor),
+    Timecode(AttrTimecode),
+    V2i([i32; 2]),
+    V2f([f32; 2]),
+    V2d([f64; 2]),
+    V3i([i32; 3]),
+    V3f([f32; 3]),
+    V3d([f64; 3]),
+    /// An attribute of a type the library does not know how to interpret,
+    /// holding its packed bytes as they appear in the file
+    Opaque {
+        type_name: String,
+        data: Vec<u8>,
+    },
+}
+
+impl AttributeValue {
+    /// The type of the value
+    ///
+    pub fn kind(&self) -> AttrType {
+        match self {
+            AttributeValue::Box2i(_) => AttrType::Box2i,
+            AttributeValue::Box2f(_) => AttrType::Box2f,
+            AttributeValue::Chlist(_) => AttrType::Chlist,
+            AttributeValue::Chromaticities(_) => AttrType::Chromaticities,
+            AttributeValue::Compression(_) => AttrType::Compression,
+            AttributeValue::Double(_) => AttrType::Double,
+            AttributeValue::Envmap(_) => AttrType::Envmap,
+            AttributeValue::Float(_) => AttrType::Float,
+            AttributeValue::FloatVector(_) => AttrType::FloatVector,
+            AttributeValue::Int(_) => AttrType::Int,
+            AttributeValue::Keycode(_) => AttrType::Keycode,
+            AttributeValue::LineOrder(_) => AttrType::LineOrder,
+            AttributeValue::M33f(_) => AttrType::M33f,
+            AttributeValue::M33d(_) => AttrType::M33d,
+            AttributeValue::M44f(_) => AttrType::M44f,
+            AttributeValue::M44d(_) => AttrType::M44d,
+            AttributeValue::Preview(_) => AttrType::Preview,
+            AttributeValue::Rational(_) => AttrType::Rational,
+            AttributeValue::String(_) => AttrType::String,
+            AttributeValue::StringVector(_) => AttrType::StringVector,
+            AttributeValue::Tiledesc(_) => AttrType::Tiledesc,
+            AttributeValue::Timecode(_) => AttrType::Timecode,
+            AttributeValue::V2i(_) => AttrType::V2i,
+            AttributeValue::V2f(_) => AttrType::V2f,
+            AttributeValue::V2d(_) => AttrType::V2d,
+            AttributeValue::V3i(_) => AttrType::V3i,
+            AttributeValue::V3f(_) => AttrType::V3f,
+            AttributeValue::V3d(_) => AttrType::V3d,
+            AttributeValue::Opaque { .. } => AttrType::Opaque,
         }
     }
 }
 
-impl AttributeRead for &[f32] {
-    fn get<S: ContextState>(
-        ctx: &Context<S>,
-        part_index: usize,
-        name: &str,
-    ) -> Result<Self> {
-        unsafe {
-            let c_name = CString::new(name).unwrap();
-            let mut sz = 0;
-            let mut ptr = std::ptr::null();
-            sys::exr_attr_get_float_vector(
-                ctx.inner,
-                part_index.try_into().unwrap(),
-                c_name.as_ptr(),
-                &mut sz,
-                &mut ptr,
-            )
-            .ok(std::slice::from_raw_parts(ptr, sz as usize))
+impl fmt::Display for AttributeValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AttributeValue::Box2i(b) => {
+                write!(f, "({}, {}) - ({}, {})", b[0], b[1], b[2], b[3])
+            }
+            AttributeValue::Box2f(b) => {
+                write!(f, "({}, {}) - ({}, {})", b[0], b[1], b[2], b[3])
+            }
+            AttributeValue::Chlist(channels) => {
+                for (i, c) in channels.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{} ({:?})", c.name, c.pixel_type)?;
+                }
+                Ok(())
+            }
+            AttributeValue::Chromaticities(c) => {
+                let (r, g, b, w): ([f32; 2], [f32; 2], [f32; 2], [f32; 2]) =
+                    (c.red(), c.green(), c.blue(), c.white());
+                write!(
+                    f,
+                    "red {:?}, green {:?}, blue {:?}, white {:?}",
+                    r, g, b, w
+                )
+            }
+            AttributeValue::Compression(c) => write!(f, "{:?}", c),
+            AttributeValue::Double(d) => write!(f, "{}", d),
+            AttributeValue::Envmap(e) => write!(f, "{:?}", e),
+            AttributeValue::Float(x) => write!(f, "{}", x),
+            AttributeValue::FloatVector(v) => write!(f, "{:?}", v),
+            AttributeValue::Int(i) => write!(f, "{}", i),
+            AttributeValue::Keycode(k) => write!(f, "{}", k),
+            AttributeValue::LineOrder(l) => write!(f, "{:?}", l),
+            AttributeValue::M33f(m) => write!(f, "{:?}", m),
+            AttributeValue::M33d(m) => write!(f, "{:?}", m),
+            AttributeValue::M44f(m) => write!(f, "{:?}", m),
+            AttributeValue::M44d(m) => write!(f, "{:?}", m),
+            AttributeValue::Preview(p) => {
+                write!(f, "{}x{} preview", p.width, p.height)
+            }
+            AttributeValue::Rational(r) => write!(f, "{}", r),
+            AttributeValue::String(s) => write!(f, "{:?}", s),
+            AttributeValue::StringVector(v) => write!(f, "{:?}", v),
+            AttributeValue::Tiledesc(t) => write!(
+                f,
+                "{}x{} tiles, {:?}, {:?}",
+                t.x_size, t.y_size, t.level_mode, t.round_mode
+            ),
+            AttributeValue::Timecode(t) => write!(f, "{}", t),
+            AttributeValue::V2i(v) => write!(f, "({}, {})", v[0], v[1]),
+            AttributeValue::V2f(v) => write!(f, "({}, {})", v[0], v[1]),
+            AttributeValue::V2d(v) => write!(f, "({}, {})", v[0], v[1]),
+            AttributeValue::V3i(v) => {
+                write!(f, "({}, {}, {})", v[0], v[1], v[2])
+            }
+            AttributeValue::V3f(v) => {
+                write!(f, "({}, {}, {})", v[0], v[1], v[2])
+            }
+            AttributeValue::V3d(v) => {
+                write!(f, "({}, {}, {})", v[0], v[1], v[2])
+            }
+            AttributeValue::Opaque { type_name, data } => {
+                write!(f, "<{} bytes of {}>", data.len(), type_name)
+            }
         }
     }
 }
 
-impl AttributeRead for Compression {
-    fn get<S: ContextState>(
-        ctx: &Context<S>,
-        part_index: usize,
-        name: &str,
-    ) -> Result<Self> {
-        let mut result = sys::exr_compression_t::EXR_COMPRESSION_LAST_TYPE;
+fn attr_string_to_string(s: &sys::exr_attr_string_t) -> String {
+    if s.str_.is_null() || s.length <= 0 {
+        String::new()
+    } else {
+        // # Safety
+        // length does not include the null terminator
+        let bytes = unsafe {
+            std::slice::from_raw_parts(s.str_ as *const u8, s.length as usize)
+        };
+        String::from_utf8_lossy(bytes).into_owned()
+    }
+}
+
+/// # Safety
+/// `ptr` must be null or point to `len` valid elements
+unsafe fn slice_or_empty<'a, T>(ptr: *const T, len: usize) -> &'a [T] {
+    if ptr.is_null() || len == 0 {
+        &[]
+    } else {
+        std::slice::from_raw_parts(ptr, len)
+    }
+}
+
+impl From<&Attribute> for AttributeValue {
+    fn from(attr: &Attribute) -> AttributeValue {
+        use sys::exr_attribute_type_t as T;
+
+        let a = &attr.0;
+        // values that can't be represented otherwise, such as an enum
+        // attribute with an out-of-range value, are kept as their raw bytes
+        let opaque = |data| AttributeValue::Opaque {
+            type_name: attr.type_name().to_string(),
+            data,
+        };
+        // # Safety
+        // The union member read is selected by the attribute's type, and the
+        // library guarantees the pointer members are valid for a parsed
+        // header
         unsafe {
-            let c_name = CString::new(name).unwrap();
-            sys::exr_attr_get_compression(
-                ctx.inner,
-                part_index.try_into().unwrap(),
-                c_name.as_ptr(),
-                &mut result,
-            )
-            .ok(result.into())
+            let u = &a.__bindgen_anon_1;
+            match a.type_ {
+                T::EXR_ATTR_BOX2I => {
+                    AttributeValue::Box2i(*(u.box2i as *const [i32; 4]))
+                }
+                T::EXR_ATTR_BOX2F => {
+                    AttributeValue::Box2f(*(u.box2f as *const [f32; 4]))
+                }
+                T::EXR_ATTR_CHLIST => {
+                    match (*(u.chlist as *const ChannelList)).descs() {
+                        Ok(descs) => AttributeValue::Chlist(descs),
+                        Err(_) => opaque(Vec::new()),
+                    }
+                }
+                T::EXR_ATTR_CHROMATICITIES => AttributeValue::Chromaticities(
+                    AttrChromaticities(*u.chromaticities),
+                ),
+                T::EXR_ATTR_COMPRESSION => AttributeValue::Compression(
+                    sys::exr_compression_t(u.uc as u32).into(),
+                ),
+                T::EXR_ATTR_DOUBLE => AttributeValue::Double(u.d),
+                T::EXR_ATTR_ENVMAP => {
+                    match sys::exr_envmap_t(u.uc as u32).try_into() {
+                        Ok(envmap) => AttributeValue::Envmap(envmap),
+                        Err(_) => opaque(vec![u.uc]),
+                    }
+                }
+                T::EXR_ATTR_FLOAT => AttributeValue::Float(u.f),
+                T::EXR_ATTR_FLOAT_VECTOR => {
+                    let fv = &*u.floatvector;
+                    AttributeValue::FloatVector(
+                        slice_or_empty(fv.arr, fv.length.max(0) as usize)
+                            .to_vec(),
+                    )
+                }
+                T::EXR_ATTR_INT => AttributeValue::Int(u.i),
+                T::EXR_ATTR_KEYCODE => {
+                    AttributeValue::Keycode(AttrKeycode(*u.keycode))
+                }
+                T::EXR_ATTR_LINEORDER => {
+                    match sys::exr_lineorder_t(u.uc as u32).try_into() {
+                        Ok(line_order) => AttributeValue::LineOrder(line_order),
+                        Err(_) => opaque(vec![u.uc]),
+                    }
+                }
+                T::EXR_ATTR_M33F => AttributeValue::M33f((*u.m33f).m),
+                T::EXR_ATTR_M33D => AttributeValue::M33d((*u.m33d).m),
+                T::EXR_ATTR_M44F => AttributeValue::M44f((*u.m44f).m),
+                T::EXR_ATTR_M44D => AttributeValue::M44d((*u.m44d).m),
+                T::EXR_ATTR_PREVIEW => {
+                    let p = &*u.preview;
+                    let len = p.width as usize * p.height as usize * 4;
+                    AttributeValue::Preview(PreviewBuf {
+                        width: p.width,
+                        height: p.height,
+                        rgba: slice_or_empty(p.rgba, len).to_vec(),
+                    })
+                }
+                T::EXR_ATTR_RATIONAL => {
+                    AttributeValue::Rational(AttrRational(*u.rational))
+                }
+                T::EXR_ATTR_STRING => {
+                    AttributeValue::String(attr_string_to_string(&*u.string))
+                }
+                T::EXR_ATTR_STRING_VECTOR => {
+                    let sv = &*u.stringvector;
+                    AttributeValue::StringVector(
+                        slice_or_empty(
+                            sv.strings,
+                            sv.n_strings.max(0) as usize,
+                        )
+                        .iter()
+                        .map(attr_string_to_string)
+                        .collect(),
+                    )
+                }
+                T::EXR_ATTR_TILEDESC => {
+                    let td = &*u.tiledesc;
+                    match TileDescriptor::try_from(td) {
+                        Ok(t) => AttributeValue::Tiledesc(t),
+                        Err(_) => {
+                            let mut data = td.x_size.to_le_bytes().to_vec();
+                            data.extend_from_slice(&td.y_size.to_le_bytes());
+                            data.push(td.level_and_round);
+                            opaque(data)
+                        }
+                    }
+                }
+                T::EXR_ATTR_TIMECODE => {
+                    AttributeValue::Timecode(AttrTimecode(*u.timecode))
+                }
+                T::EXR_ATTR_V2I => AttributeValue::V2i(*(u.v2i as *const _)),
+                T::EXR_ATTR_V2F => AttributeValue::V2f(*(u.v2f as *const _)),
+                T::EXR_ATTR_V2D => AttributeValue::V2d(*(u.v2d as *const _)),
+                T::EXR_ATTR_V3I => AttributeValue::V3i(*(u.v3i as *const _)),
+                T::EXR_ATTR_V3F => AttributeValue::V3f(*(u.v3f as *const _)),
+                T::EXR_ATTR_V3D => AttributeValue::V3d(*(u.v3d as *const _)),
+                _ => opaque(attr.opaque_data().unwrap_or(&[]).to_vec()),
+            }
         }
     }
 }
 
-impl AttributeRead for [i32; 4] {
-    fn get<S: ContextState>(
+impl AttributeWrite for AttributeValue {
+    /// Set the attribute `name` to `value` with the value's type, e.g. to
+    /// copy an attribute read with [`Attribute::value`] to another file
+    ///
+    /// # Errors
+    /// * `[Error::AttrTypeMismatch]` - If `value` is opaque with the name of
+    /// a built-in type, as values the library could not interpret are kept
+    /// * `[Error::InvalidArgument]` - If a string or channel name contains
+    /// internal null bytes
+    ///
+    fn set<S: WritableHeaderState>(
         ctx: &Context<S>,
         part_index: usize,
         name: &str,
-    ) -> Result<[i32; 4]> {
-        let mut result = [0i32; 4];
-        unsafe {
-            let c_name = CString::new(name).unwrap();
-            sys::exr_attr_get_box2i(
-                ctx.inner,
-                part_index.try_into().unwrap(),
-                c_name.as_ptr(),
-                result.as_mut_ptr() as *mut sys::exr_attr_box2i_t,
-            )
-            .ok(result.into())
+        value: &AttributeValue,
+    ) -> Result<()> {
+        match value {
+            AttributeValue::Box2i(v) => {
+                AttributeWrite::set(ctx, part_index, name, v)
+            }
+            AttributeValue::Box2f(v) => {
+                AttributeWrite::set(ctx, part_index, name, v)
+            }
+            AttributeValue::Chlist(channels) => {
+                with_c_channel_list(channels, |list| unsafe {
+                    let c_name = CString::new(name).unwrap();
+                    sys::exr_attr_set_channels(
+                        ctx.inner,
+                        c_part_index(part_index)?,
+                        c_name.as_ptr(),
+                        list,
+                    )
+                    .ok_in(ctx.inner, ())
+                })
+            }
+            AttributeValue::Chromaticities(v) => {
+                AttributeWrite::set(ctx, part_index, name, v)
+            }
+            AttributeValue::Compression(v) => {
+                AttributeWrite::set(ctx, part_index, name, v)
+            }
+            AttributeValue::Double(v) => {
+                AttributeWrite::set(ctx, part_index, name, v)
+            }
+            AttributeValue::Envmap(v) => {
+                AttributeWrite::set(ctx, part_index, name, v)
+            }
+            AttributeValue::Float(v) => {
+                AttributeWrite::set(ctx, part_index, name, v)
+            }
+            AttributeValue::FloatVector(v) => {
+                AttributeWrite::set(ctx, part_index, name, v)
+            }
+            AttributeValue::Int(v) => {
+                AttributeWrite::set(ctx, part_index, name, v)
+            }
+            AttributeValue::Keycode(v) => {
+                AttributeWrite::set(ctx, part_index, name, v)
+            }
+            AttributeValue::LineOrder(v) => {
+                AttributeWrite::set(ctx, part_index, name, v)
+            }
+            AttributeValue::M33f(v) => {
+                AttributeWrite::set(ctx, part_index, name, v)
+            }
+            AttributeValue::M33d(v) => {
+                AttributeWrite::set(ctx, part_index, name, v)
+            }
+            AttributeValue::M44f(v) => {
+                AttributeWrite::set(ctx, part_index, name, v)
+            }
+            AttributeValue::M44d(v) => {
+                AttributeWrite::set(ctx, part_index, name, v)
+            }
+            AttributeValue::Preview(v) => {
+                AttributeWrite::set(ctx, part_index, name, v)
+            }
+            AttributeValue::Rational(v) => {
+                AttributeWrite::set(ctx, part_index, name, v)
+            }
+            AttributeValue::String(v) => {
+                AttributeWrite::set(ctx, part_index, name, v)
+            }
+            AttributeValue::StringVector(v) => {
+                AttributeWrite::set(ctx, part_index, name, v)
+            }
+            AttributeValue::Tiledesc(v) => {
+                AttributeWrite::set(ctx, part_index, name, v)
+            }
+            AttributeValue::Timecode(v) => {
+                AttributeWrite::set(ctx, part_index, name, v)
+            }
+            AttributeValue::V2i(v) => {
+                AttributeWrite::set(ctx, part_index, name, v)
+            }
+            AttributeValue::V2f(v) => {
+                AttributeWrite::set(ctx, part_index, name, v)
+            }
+            AttributeValue::V2d(v) => {
+                AttributeWrite::set(ctx, part_index, name, v)
+            }
+            AttributeValue::V3i(v) => {
+                AttributeWrite::set(ctx, part_index, name, v)
+            }
+            AttributeValue::V3f(v) => {
+                AttributeWrite::set(ctx, part_index, name, v)
+            }
+            AttributeValue::V3d(v) => {
+                AttributeWrite::set(ctx, part_index, name, v)
+            }
+            AttributeValue::Opaque { type_name, data } => {
+                set_user(ctx, part_index, name, type_name, data)
+            }
         }
     }
 }
+
+/// Call `f` with a C channel list borrowing the names of `channels`, which
+/// the C core copies
+///
+/// # Errors
+/// * `[Error::InvalidArgument]` - If a channel's name contains internal null
+/// bytes
+///
+pub(crate) fn with_c_channel_list<T, F>(
+    channels: &[ChannelDesc],
+    f: F,
+) -> Result<T>
+where
+    F: FnOnce(&sys::exr_attr_chlist_t) -> Result<T>,
+{
+    let names = channels
+        .iter()
+        .map(|c| CString::new(c.name.as_str()))
+        .collect::<std::result::Result<Vec<_>, _>>()
+        .map_err(|_| Error::InvalidArgument)?;
+    let entries = channels
+        .iter()
+        .zip(&names)
+        .map(|(c, name)| sys::exr_attr_chlist_entry_t {
+            name: sys::exr_attr_string_t {
+                length: c.name.len() as i32,
+                alloc_size: 0,
+                str_: name.as_ptr(),
+            },
+            pixel_type: c.pixel_type.into(),
+            p_linear: c.p_linear as u8,
+            reserved: [0; 3],
+            x_sampling: c.sampling.0,
+            y_sampling: c.sampling.1,
+        })
+        .collect::<Vec<_>>();
+    let list = sys::exr_attr_chlist_t {
+        num_channels: entries.len() as i32,
+        num_alloced: entries.len() as i32,
+        entries: entries.as_ptr(),
+    };
+    f(&list)
+}
+
+/// Set the attribute `name` of a type the library does not know about to
+/// the packed bytes `data`
+///
+/// # Errors
+/// * `[Error::AttrTypeMismatch]` - If the attribute already exists with a
+/// different type, or `type_name` is one of the built-in types
+/// * `[Error::ArgumentOutOfRange]` - If `data` is too large for an
+/// attribute
+///
+/// # Panics
+/// * If `name` or `type_name` contains internal null bytes
+///
+pub(crate) fn set_user<S: WritableHeaderState>(
+    ctx: &Context<S>,
+    part_index: usize,
+    name: &str,
+    type_name: &str,
+    data: &[u8],
+) -> Result<()> {
+    let c_name = CString::new(name).expect("invalid bytes in name");
+    let c_type = CString::new(type_name).expect("invalid bytes in type_name");
+    let size =
+        i32::try_from(data.len()).map_err(|_| Error::ArgumentOutOfRange)?;
+    unsafe {
+        sys::exr_attr_set_user(
+            ctx.inner,
+            c_part_index(part_index)?,
+            c_name.as_ptr(),
+            c_type.as_ptr(),
+            size,
+            data.as_ptr() as *const c_void,
+        )
+        .ok_in(ctx.inner, ())
+    }
+}
+
+/// SMPTE timecode, packed as described in SMPTE 12M
+///
+/// The time fields are stored as binary coded decimal in `time_and_flags`,
+/// alongside the drop frame, colour frame, field phase and binary group
+/// flags. `user_data` holds eight 4-bit binary groups. The setters check
+/// their arguments are in range, returning `Err(Error::ArgumentOutOfRange)`
+/// otherwise.
+///
+#[repr(transparent)]
+#[derive(Debug, Copy, Clone)]
+pub struct AttrTimecode(pub(crate) sys::exr_attr_timecode_t);
+
+impl AttrTimecode {
+    const DROP_FRAME: u32 = 1 << 6;
+    const COLOR_FRAME: u32 = 1 << 7;
+    const FIELD_PHASE: u32 = 1 << 15;
+    const BGF0: u32 = 1 << 23;
+    const BGF1: u32 = 1 << 30;
+    const BGF2: u32 = 1 << 31;
+
+    /// Create a new timecode at `hours:minutes:seconds:frame`, with no flags
+    /// set and empty user data
+    ///
+    pub fn new(
+        hours: i32,
+        minutes: i32,
+        seconds: i32,
+        frame: i32,
+    ) -> Result<AttrTimecode> {
+        let mut tc = AttrTimecode::from_packed(0, 0);
+        tc.set_hours(hours)?;
+        tc.set_minutes(minutes)?;
+        tc.set_seconds(seconds)?;
+        tc.set_frame(frame)?;
+        Ok(tc)
+    }
+
+    /// Create a timecode from its packed representation as it is stored in
+    /// the file
+    ///
+    pub fn from_packed(time_and_flags: u32, user_data: u32) -> AttrTimecode {
+        AttrTimecode(sys::exr_attr_timecode_t {
+            time_and_flags,
+            user_data,
+        })
+    }
+
+    /// The packed time and flags
+    ///
+    pub fn time_and_flags(&self) -> u32 {
+        self.0.time_and_flags
+    }
+
+    /// The packed binary groups
+    ///
+    pub fn user_data(&self) -> u32 {
+        self.0.user_data
+    }
+
+    pub fn set_user_data(&mut self, value: u32) {
+        self.0.user_data = value;
+    }
+
+    /// Hours, in the range [0, 23]
+    ///
+    pub fn hours(&self) -> i32 {
+        self.bcd(24, 29)
+    }
+
+    pub fn set_hours(&mut self, value: i32) -> Result<()> {
+        check_range(value, 0, 23)?;
+        self.set_bcd(24, 29, value);
+        Ok(())
+    }
+
+    /// Minutes, in the range [0, 59]
+    ///
+    pub fn minutes(&self) -> i32 {
+        self.bcd(16, 22)
+    }
+
+    pub fn set_minutes(&mut self, value: i32) -> Result<()> {
+        check_range(value, 0, 59)?;
+        self.set_bcd(16, 22, value);
+        Ok(())
+    }
+
+    /// Seconds, in the range [0, 59]
+    ///
+    pub fn seconds(&self) -> i32 {
+        self.bcd(8, 14)
+    }
+
+    pub fn set_seconds(&mut self, value: i32) -> Result<()> {
+        check_range(value, 0, 59)?;
+        self.set_bcd(8, 14, value);
+        Ok(())
+    }
+
+    /// Frame within the second, in the range [0, 59]
+    ///
+    pub fn frame(&self) -> i32 {
+        self.bcd(0, 5)
+    }
+
+    pub fn set_frame(&mut self, value: i32) -> Result<()> {
+        check_range(value, 0, 59)?;
+        self.set_bcd(0, 5, value);
+        Ok(())
+    }
+
+    /// Is the timecode counted with drop frames, as for 29.97fps video?
+    ///
+    pub fn drop_frame(&self) -> bool {
+        self.flag(Self::DROP_FRAME)
+    }
+
+    pub fn set_drop_frame(&mut self, value: bool) {
+        self.set_flag(Self::DROP_FRAME, value);
+    }
+
+    pub fn color_frame(&self) -> bool {
+        self.flag(Self::COLOR_FRAME)
+    }
+
+    pub fn set_color_frame(&mut self, value: bool) {
+        self.set_flag(Self::COLOR_FRAME, value);
+    }
+
+    pub fn field_phase(&self) -> bool {
+        self.flag(Self::FIELD_PHASE)
+    }
+
+    pub fn set_field_phase(&mut self, value: bool) {
+        self.set_flag(Self::FIELD_PHASE, value);
+    }
+
+    pub fn bgf0(&self) -> bool {
+        self.flag(Self::BGF0)
+    }
+
+    pub fn set_bgf0(&mut self, value: bool) {
+        self.set_flag(Self::BGF0, value);
+    }
+
+    pub fn bgf1(&self) -> bool {
+        self.flag(Self::BGF1)
+    }
+
+    pub fn set_bgf1(&mut self, value: bool) {
+        self.set_flag(Self::BGF1, value);
+    }
+
+    pub fn bgf2(&self) -> bool {
+        self.flag(Self::BGF2)
+    }
+
+    pub fn set_bgf2(&mut self, value: bool) {
+        self.set_flag(Self::BGF2, value);
+    }
+
+    /// The 4-bit binary group `group` of the user data, where `group` is in
+    /// the range [1, 8]
+    ///
+    /// # Errors
+    /// * `[Error::ArgumentOutOfRange]` - If `group` is out of range
+    ///
+    pub fn binary_group(&self, group: i32) -> Result<u32> {
+        check_range(group, 1, 8)?;
+        Ok((self.0.user_data >> (4 * (group - 1))) & 0xf)
+    }
+
+    /// Set the binary group `group`, where `group` is in the range [1, 8]
+    /// and `value` in [0, 15]
+    ///
+    /// # Errors
+    /// * `[Error::ArgumentOutOfRange]` - If `group` or `value` is out of
+    /// range
+    ///
+    pub fn set_binary_group(&mut self, group: i32, value: u32) -> Result<()> {
+        check_range(group, 1, 8)?;
+        if value > 0xf {
+            return Err(Error::ArgumentOutOfRange);
+        }
+        let shift = 4 * (group - 1);
+        self.0.user_data =
+            (self.0.user_data & !(0xf << shift)) | (value << shift);
+        Ok(())
+    }
+
+    /// Read the binary coded decimal value in bits `min` to `max` inclusive,
+    /// with the units in the lowest four bits
+    ///
+    fn bcd(&self, min: u32, max: u32) -> i32 {
+        let bits =
+            (self.0.time_and_flags >> min) & ((1 << (max - min + 1)) - 1);
+        ((bits >> 4) * 10 + (bits & 0xf)) as i32
+    }
+
+    fn set_bcd(&mut self, min: u32, max: u32, value: i32) {
+        let mask = ((1u32 << (max - min + 1)) - 1) << min;
+        let bits = (((value / 10) << 4) | (value % 10)) as u32;
+        self.0.time_and_flags =
+            (self.0.time_and_flags & !mask) | ((bits << min) & mask);
+    }
+
+    fn flag(&self, flag: u32) -> bool {
+        self.0.time_and_flags & flag != 0
+    }
+
+    fn set_flag(&mut self, flag: u32, value: bool) {
+        if value {
+            self.0.time_and_flags |= flag;
+        } else {
+            self.0.time_and_flags &= !flag;
+        }
+    }
+}
+
+impl PartialEq for AttrTimecode {
+    fn eq(&self, other: &AttrTimecode) -> bool {
+        self.0.time_and_flags == other.0.time_and_flags
+            && self.0.user_data == other.0.user_data
+    }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for AttrTimecode {
+    fn serialize<S: serde::Serializer>(
+        &self,
+        serializer: S,
+    ) -> std::result::Result<S::Ok, S::Error> {
+        use serde::ser::SerializeStruct;
+        let mut s = serializer.serialize_struct("AttrTimecode", 2)?;
+        s.serialize_field("time_and_flags", &self.time_and_flags())?;
+        s.serialize_field("user_data", &self.user_data())?;
+        s.end()
+    }
+}
+
+impl fmt::Display for AttrTimecode {
+    /// Formats the time as `"HH:MM:SS:FF"`, with a `;` before the frame for
+    /// drop frame timecodes
+    ///
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:02}:{:02}:{:02}{}{:02}",
+            self.hours(),
+            self.minutes(),
+            self.seconds(),
+            if self.drop_frame() { ';' } else { ':' },
+            self.frame()
+        )
+    }
+}
+
+/// Film keycode (KeyKode) information identifying a frame on a film scan
+///
+/// Each field has a valid range defined by the OpenEXR specification. The
+/// setters and [`AttrKeycode::new`] check these, returning
+/// `Err(Error::ArgumentOutOfRange)` for values outside them.
+///
+#[repr(transparent)]
+#[derive(Debug, Copy, Clone)]
+pub struct AttrKeycode(pub(crate) sys::exr_attr_keycode_t);
+
+impl AttrKeycode {
+    /// Create a new keycode, checking that every field is within range
+    ///
+    pub fn new(
+        film_mfc_code: i32,
+        film_type: i32,
+        prefix: i32,
+        count: i32,
+        perf_offset: i32,
+        perfs_per_frame: i32,
+        perfs_per_count: i32,
+    ) -> Result<AttrKeycode> {
+        let kc = AttrKeycode(sys::exr_attr_keycode_t {
+            film_mfc_code,
+            film_type,
+            prefix,
+            count,
+            perf_offset,
+            perfs_per_frame,
+            perfs_per_count,
+        });
+        kc.validate().map(|_| kc)
+    }
+
+    /// Check that every field is within the range allowed by the spec
+    ///
+    /// # Errors
+    /// * `[Error::ArgumentOutOfRange]` - If any field is out of range
+    ///
+    pub fn validate(&self) -> Result<()> {
+        let k = &self.0;
+        if (0..=99).contains(&k.film_mfc_code)
+            && (0..=99).contains(&k.film_type)
+            && (0..=999_999).contains(&k.prefix)
+            && (0..=9999).contains(&k.count)
+            && (0..=119).contains(&k.perf_offset)
+            && (1..=15).contains(&k.perfs_per_frame)
+            && (20..=120).contains(&k.perfs_per_count)
+        {
+            Ok(())
+        } else {
+            Err(Error::ArgumentOutOfRange)
+        }
+    }
+
+    /// Film manufacturer code, in the range [0, 99]
+    ///
+    pub fn film_mfc_code(&self) -> i32 {
+        self.0.film_mfc_code
+    }
+
+    pub fn set_film_mfc_code(&mut self, value: i32) -> Result<()> {
+        check_range(value, 0, 99)?;
+        self.0.film_mfc_code = value;
+        Ok(())
+    }
+
+    /// Film type code, in the range [0, 99]
+    ///
+    pub fn film_type(&self) -> i32 {
+        self.0.film_type
+    }
+
+    pub fn set_film_type(&mut self, value: i32) -> Result<()> {
+        check_range(value, 0, 99)?;
+        self.0.film_type = value;
+        Ok(())
+    }
+
+    /// Prefix identifying the film roll, in the range [0, 999999]
+    ///
+    pub fn prefix(&self) -> i32 {
+        self.0.prefix
+    }
+
+    pub fn set_prefix(&mut self, value: i32) -> Result<()> {
+        check_range(value, 0, 999_999)?;
+        self.0.prefix = value;
+        Ok(())
+    }
+
+    /// Count, in the range [0, 9999]
+    ///
+    pub fn count(&self) -> i32 {
+        self.0.count
+    }
+
+    pub fn set_count(&mut self, value: i32) -> Result<()> {
+        check_range(value, 0, 9999)?;
+        self.0.count = value;
+        Ok(())
+    }
+
+    /// Offset of the frame, in perfs, from the zero-frame reference mark.
+    /// In the range [0, 119]
+    ///
+    pub fn perf_offset(&self) -> i32 {
+        self.0.perf_offset
+    }
+
+    pub fn set_perf_offset(&mut self, value: i32) -> Result<()> {
+        check_range(value, 0, 119)?;
+        self.0.perf_offset = value;
+        Ok(())
+    }
+
+    /// Number of perfs per frame, in the range [1, 15]
+    ///
+    pub fn perfs_per_frame(&self) -> i32 {
+        self.0.perfs_per_frame
+    }
+
+    pub fn set_perfs_per_frame(&mut self, value: i32) -> Result<()> {
+        check_range(value, 1, 15)?;
+        self.0.perfs_per_frame = value;
+        Ok(())
+    }
+
+    /// Number of perfs per count, in the range [20, 120]
+    ///
+    pub fn perfs_per_count(&self) -> i32 {
+        self.0.perfs_per_count
+    }
+
+    pub fn set_perfs_per_count(&mut self, value: i32) -> Result<()> {
+        check_range(value, 20, 120)?;
+        self.0.perfs_per_count = value;
+        Ok(())
+    }
+}
+
+fn check_range(value: i32, min: i32, max: i32) -> Result<()> {
+    if value < min || value > max {
+        Err(Error::ArgumentOutOfRange)
+    } else {
+        Ok(())
+    }
+}
+
+impl PartialEq for AttrKeycode {
+    fn eq(&self, other: &AttrKeycode) -> bool {
+        self.0.film_mfc_code == other.0.film_mfc_code
+            && self.0.film_type == other.0.film_type
+            && self.0.prefix == other.0.prefix
+            && self.0.count == other.0.count
+            && self.0.perf_offset == other.0.perf_offset
+            && self.0.perfs_per_frame == other.0.perfs_per_frame
+            && self.0.perfs_per_count == other.0.perfs_per_count
+    }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for AttrKeycode {
+    fn serialize<S: serde::Serializer>(
+        &self,
+        serializer: S,
+    ) -> std::result::Result<S::Ok, S::Error> {
+        use serde::ser::SerializeStruct;
+        let mut s = serializer.serialize_struct("AttrKeycode", 7)?;
+        s.serialize_field("film_mfc_code", &self.film_mfc_code())?;
+        s.serialize_field("film_type", &self.film_type())?;
+        s.serialize_field("prefix", &self.prefix())?;
+        s.serialize_field("count", &self.count())?;
+        s.serialize_field("perf_offset", &self.perf_offset())?;
+        s.serialize_field("perfs_per_frame", &self.perfs_per_frame())?;
+        s.serialize_field("perfs_per_count", &self.perfs_per_count())?;
+        s.end()
+    }
+}
+
+impl fmt::Display for AttrKeycode {
+    /// Formats the keycode the way it is printed on a lab report, i.e.
+    /// manufacturer, film type, prefix, and count+perf offset:
+    /// `"02 15 123456 7890+12"`
+    ///
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:02} {:02} {:06} {:04}+{:02}",
+            self.0.film_mfc_code,
+            self.0.film_type,
+            self.0.prefix,
+            self.0.count,
+            self.0.perf_offset
+        )
+    }
+}
+
+/// A rational number, as used for e.g. the `framesPerSecond` attribute
+///
+/// The denominator is unsigned, so the sign is carried by the numerator.
+///
+#[repr(transparent)]
+#[derive(Debug, Copy, Clone)]
+pub struct AttrRational(pub(crate) sys::exr_attr_rational_t);
+
+impl AttrRational {
+    pub fn new(num: i32, denom: u32) -> AttrRational {
+        AttrRational(sys::exr_attr_rational_t { num, denom })
+    }
+
+    pub fn num(&self) -> i32 {
+        self.0.num
+    }
+
+    pub fn denom(&self) -> u32 {
+        self.0.denom
+    }
+
+    /// Return this rational reduced to its lowest terms, e.g. 48/2 -> 24/1
+    ///
+    pub fn reduce(&self) -> AttrRational {
+        let g = gcd(self.0.num.unsigned_abs(), self.0.denom);
+        if g <= 1 {
+            *self
+        } else {
+            AttrRational::new(
+                (self.0.num as i64 / g as i64) as i32,
+                self.0.denom / g,
+            )
+        }
+    }
+
+    /// Find the rational closest to `x` whose denominator is no larger than
+    /// `max_denom`, using its continued fraction expansion.
+    ///
+    /// The expansion stops early once the approximation is within the
+    /// precision the numerator and denominator can represent, so e.g.
+    /// `0.5` gives `1/2` rather than `1073741824/2147483648`.
+    ///
+    /// # Errors
+    /// * `[Error::ArgumentOutOfRange]` - If `x` is not finite, its magnitude is
+    /// too large to be represented, or `max_denom` is 0
+    ///
+    pub fn approximate(x: f64, max_denom: u32) -> Result<AttrRational> {
+        if !x.is_finite() || x.abs() > i32::MAX as f64 || max_denom == 0 {
+            return Err(Error::ArgumentOutOfRange);
+        }
+
+        let sign = if x < 0.0 { -1 } else { 1 };
+        let x = x.abs();
+        let tolerance = x.max(1.0) / (1u64 << 30) as f64;
+
+        // convergents h/k, seeded with h(-2)/k(-2) = 0/1 and
+        // h(-1)/k(-1) = 1/0
+        let (mut h0, mut h1) = (0i64, 1i64);
+        let (mut k0, mut k1) = (1i64, 0i64);
+        let mut r = x;
+        loop {
+            let a = r.floor() as i64;
+            let h2 = a * h1 + h0;
+            let k2 = a * k1 + k0;
+            if h2 > i32::MAX as i64 || k2 > max_denom as i64 {
+                break;
+            }
+
+            h0 = h1;
+            h1 = h2;
+            k0 = k1;
+            k1 = k2;
+
+            let frac = r - r.floor();
+            if (x - h1 as f64 / k1 as f64).abs() <= tolerance || frac == 0.0 {
+                break;
+            }
+            r = 1.0 / frac;
+        }
+
+        if k1 == 0 {
+            // x is smaller than 1/max_denom
+            Ok(AttrRational::new(0, 1))
+        } else {
+            Ok(AttrRational::new(sign * h1 as i32, k1 as u32))
+        }
+    }
+}
+
+fn gcd(mut a: u32, mut b: u32) -> u32 {
+    while b != 0 {
+        let t = b;
+        b = a % b;
+        a = t;
+    }
+    a
+}
+
+impl From<AttrRational> for f64 {
+    fn from(r: AttrRational) -> f64 {
+        r.0.num as f64 / r.0.denom as f64
+    }
+}
+
+impl TryFrom<f64> for AttrRational {
+    type Error = Error;
+
+    /// Convert `x` to the closest representable rational. See
+    /// [`AttrRational::approximate`]
+    ///
+    fn try_from(x: f64) -> Result<AttrRational> {
+        AttrRational::approximate(x, u32::MAX)
+    }
+}
+
+impl PartialEq for AttrRational {
+    fn eq(&self, other: &AttrRational) -> bool {
+        self.partial_cmp(other) == Some(Ordering::Equal)
+    }
+}
+
+impl PartialOrd for AttrRational {
+    /// Rationals are compared by value, so 1/2 == 2/4. A zero denominator is
+    /// treated as a division by zero in floating point
+    ///
+    fn partial_cmp(&self, other: &AttrRational) -> Option<Ordering> {
+        if self.0.denom == 0 || other.0.denom == 0 {
+            f64::from(*self).partial_cmp(&f64::from(*other))
+        } else {
+            let lhs = self.0.num as i64 * other.0.denom as i64;
+            let rhs = other.0.num as i64 * self.0.denom as i64;
+            Some(lhs.cmp(&rhs))
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for AttrRational {
+    fn serialize<S: serde::Serializer>(
+        &self,
+        serializer: S,
+    ) -> std::result::Result<S::Ok, S::Error> {
+        use serde::ser::SerializeStruct;
+        let mut s = serializer.serialize_struct("AttrRational", 2)?;
+        s.serialize_field("num", &self.num())?;
+        s.serialize_field("denom", &self.denom())?;
+        s.end()
+    }
+}
+
+impl fmt::Display for AttrRational {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.0.num, self.0.denom)
+    }
+}
+
+/// CIE xy chromaticities of the RGB primaries and white point
+///
+/// Common colour spaces are provided as associated constants so a file's
+/// primaries can be identified with [`AttrChromaticities::approx_eq`] or
+/// [`AttrChromaticities::name`].
+///
+#[repr(transparent)]
+#[derive(Debug, Copy, Clone)]
+pub struct AttrChromaticities(pub(crate) sys::exr_attr_chromaticities_t);
+
+const WHITE_D65: [f32; 2] = [0.3127, 0.3290];
+const WHITE_DCI: [f32; 2] = [0.314, 0.351];
+const WHITE_ACES: [f32; 2] = [0.32168, 0.33767];
+
+impl AttrChromaticities {
+    /// ITU-R BT.709 / sRGB primaries with a D65 white point. This is also
+    /// what OpenEXR assumes when a file has no chromaticities attribute.
+    pub const REC709: AttrChromaticities = AttrChromaticities::new(
+        [0.64, 0.33],
+        [0.30, 0.60],
+        [0.15, 0.06],
+        WHITE_D65,
+    );
+
+    /// ITU-R BT.2020 primaries with a D65 white point
+    pub const REC2020: AttrChromaticities = AttrChromaticities::new(
+        [0.708, 0.292],
+        [0.170, 0.797],
+        [0.131, 0.046],
+        WHITE_D65,
+    );
+
+    /// SMPTE RP 431-2 DCI-P3 primaries with the DCI white point
+    pub const DCI_P3: AttrChromaticities = AttrChromaticities::new(
+        [0.680, 0.320],
+        [0.265, 0.690],
+        [0.150, 0.060],
+        WHITE_DCI,
+    );
+
+    /// P3 primaries with a D65 white point (Display P3, P3-D65)
+    pub const P3_D65: AttrChromaticities = AttrChromaticities::new(
+        [0.680, 0.320],
+        [0.265, 0.690],
+        [0.150, 0.060],
+        WHITE_D65,
+    );
+
+    /// ACES AP0 primaries (ACES2065-1) with the ACES white point
+    pub const ACES_AP0: AttrChromaticities = AttrChromaticities::new(
+        [0.7347, 0.2653],
+        [0.0, 1.0],
+        [0.0001, -0.0770],
+        WHITE_ACES,
+    );
+
+    /// ACES AP1 primaries (ACEScg, ACEScc, ACEScct) with the ACES white point
+    pub const ACES_AP1: AttrChromaticities = AttrChromaticities::new(
+        [0.713, 0.293],
+        [0.165, 0.830],
+        [0.128, 0.044],
+        WHITE_ACES,
+    );
+
+    const NAMED: [(&'static str, AttrChromaticities); 6] = [
+        ("Rec.709", AttrChromaticities::REC709),
+        ("Rec.2020", AttrChromaticities::REC2020),
+        ("DCI-P3", AttrChromaticities::DCI_P3),
+        ("P3-D65", AttrChromaticities::P3_D65),
+        ("ACES AP0", AttrChromaticities::ACES_AP0),
+        ("ACES AP1", AttrChromaticities::ACES_AP1),
+    ];
+
+    /// Create a new set of chromaticities from the xy coordinates of the red,
+    /// green and blue primaries and the white point
+    ///
+    pub const fn new(
+        red: [f32; 2],
+        green: [f32; 2],
+        blue: [f32; 2],
+        white: [f32; 2],
+    ) -> AttrChromaticities {
+        AttrChromaticities(sys::exr_attr_chromaticities_t {
+            red_x: red[0],
+            red_y: red[1],
+            green_x: green[0],
+            green_y: green[1],
+            blue_x: blue[0],
+            blue_y: blue[1],
+            white_x: white[0],
+            white_y: white[1],
+        })
+    }
+
+    pub fn red<V: Vec2<f32>>(&self) -> V {
+        V::from_slice(&[self.0.red_x, self.0.red_y])
+    }
+
+    pub fn green<V: Vec2<f32>>(&self) -> V {
+        V::from_slice(&[self.0.green_x, self.0.green_y])
+    }
+
+    pub fn blue<V: Vec2<f32>>(&self) -> V {
+        V::from_slice(&[self.0.blue_x, self.0.blue_y])
+    }
+
+    pub fn white<V: Vec2<f32>>(&self) -> V {
+        V::from_slice(&[self.0.white_x, self.0.white_y])
+    }
+
+    fn as_array(&self) -> [f32; 8] {
+        [
+            self.0.red_x,
+            self.0.red_y,
+            self.0.green_x,
+            self.0.green_y,
+            self.0.blue_x,
+            self.0.blue_y,
+            self.0.white_x,
+            self.0.white_y,
+        ]
+    }
+
+    /// Returns true if every coordinate of `self` is within `tolerance` of
+    /// the corresponding coordinate of `other`.
+    ///
+    /// Chromaticities are stored as f32 and frequently written with only
+    /// three or four significant digits, so a tolerance of around `1e-3` is
+    /// appropriate for identifying a colour space.
+    ///
+    pub fn approx_eq(
+        &self,
+        other: &AttrChromaticities,
+        tolerance: f32,
+    ) -> bool {
+        self.as_array()
+            .iter()
+            .zip(other.as_array().iter())
+            .all(|(a, b)| (a - b).abs() <= tolerance)
+    }
+
+    /// Get the name of the preset these chromaticities match within
+    /// `tolerance`, e.g. `Some("ACES AP0")`, or `None` if they don't match any
+    /// of the presets on this type.
+    ///
+    pub fn name(&self, tolerance: f32) -> Option<&'static str> {
+        AttrChromaticities::NAMED
+            .iter()
+            .find(|(_, c)| self.approx_eq(c, tolerance))
+            .map(|(name, _)| *name)
+    }
+}
+
+impl PartialEq for AttrChromaticities {
+    fn eq(&self, other: &AttrChromaticities) -> bool {
+        self.as_array() == other.as_array()
+    }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for AttrChromaticities {
+    /// Serializes as the x, y coordinates of each primary and the white
+    /// point, i.e. `{"red": [x, y], "green": ..., "blue": ..., "white": ...}`
+    ///
+    fn serialize<S: serde::Serializer>(
+        &self,
+        serializer: S,
+    ) -> std::result::Result<S::Ok, S::Error> {
+        use serde::ser::SerializeStruct;
+        let mut s = serializer.serialize_struct("AttrChromaticities", 4)?;
+        s.serialize_field("red", &self.red::<[f32; 2]>())?;
+        s.serialize_field("green", &self.green::<[f32; 2]>())?;
+        s.serialize_field("blue", &self.blue::<[f32; 2]>())?;
+        s.serialize_field("white", &self.white::<[f32; 2]>())?;
+        s.end()
+    }
+}
+
+/// A preview image stored in the header, for showing a thumbnail without
+/// decoding the whole image
+///
+/// The pixels are borrowed from the header they were read from. Use
+/// [`Preview::to_buf`] for a copy that outlives it.
+///
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Preview<'a> {
+    pub width: u32,
+    pub height: u32,
+    /// 8-bit, non-linear RGBA pixels, a row at a time from the top
+    pub rgba: &'a [u8],
+}
+
+impl<'a> Preview<'a> {
+    /// Make an owned copy of the preview
+    ///
+    pub fn to_buf(&self) -> PreviewBuf {
+        PreviewBuf {
+            width: self.width,
+            height: self.height,
+            rgba: self.rgba.to_vec(),
+        }
+    }
+
+    /// Get the RGBA value of the pixel at (`x`, `y`), or `None` if it is
+    /// outside the preview
+    ///
+    pub fn pixel(&self, x: u32, y: u32) -> Option<[u8; 4]> {
+        if x >= self.width || y >= self.height {
+            return None;
+        }
+        let i = (y as usize * self.width as usize + x as usize) * 4;
+        self.rgba.get(i..i + 4).map(|p| [p[0], p[1], p[2], p[3]])
+    }
+}
+
+/// An owned copy of a [`Preview`]
+///
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct PreviewBuf {
+    pub width: u32,
+    pub height: u32,
+    /// 8-bit, non-linear RGBA pixels, a row at a time from the top
+    pub rgba: Vec<u8>,
+}
+
+impl PreviewBuf {
+    pub fn as_preview(&self) -> Preview<'_> {
+        Preview {
+            width: self.width,
+            height: self.height,
+            rgba: &self.rgba,
+        }
+    }
+}
+
+impl<'a> From<Preview<'a>> for PreviewBuf {
+    fn from(p: Preview<'a>) -> PreviewBuf {
+        p.to_buf()
+    }
+}
+
+/// An attribute type the library does not know about, that can be converted
+/// to and from the bytes it is stored as in the file
+///
+/// The C core's pack and unpack handlers take no user data, so rather than
+/// closures, handlers are given as implementations of this trait and
+/// registered with [`Context::register_custom_attribute`].
+///
+pub trait CustomAttribute: Sized {
+    /// The type name the attribute is stored with in the file, e.g.
+    /// "cameraRig"
+    const TYPE_NAME: &'static str;
+
+    /// Parse the attribute from its packed bytes
+    ///
+    fn unpack(data: &[u8]) -> Result<Self>;
+
+    /// Serialize the attribute to its packed bytes. This must give the same
+    /// bytes every time it is called on the same value.
+    ///
+    fn pack(&self) -> Vec<u8>;
+}
+
+pub(crate) unsafe extern "C" fn unpack_custom<T: CustomAttribute>(
+    _ctxt: sys::exr_context_t,
+    data: *const c_void,
+    attrsize: i32,
+    outsize: *mut i32,
+    outbuffer: *mut *mut c_void,
+) -> sys::exr_result_t {
+    let data = slice_or_empty(data as *const u8, attrsize.max(0) as usize);
+    // don't let a panic in the handler unwind into C
+    match std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        T::unpack(data)
+    })) {
+        Ok(Ok(value)) => {
+            *outbuffer = Box::into_raw(Box::new(value)) as *mut c_void;
+            *outsize = std::mem::size_of::<T>() as i32;
+            sys::exr_result_t::from_code(sys::exr_error_code_t::EXR_ERR_SUCCESS)
+        }
+        _ => sys::exr_result_t::from_code(
+            sys::exr_error_code_t::EXR_ERR_INVALID_ATTR,
+        ),
+    }
+}
+
+/// Called once with a null `outbuffer` to get the packed size, then again to
+/// fill the buffer the core has allocated
+///
+pub(crate) unsafe extern "C" fn pack_custom<T: CustomAttribute>(
+    _ctxt: sys::exr_context_t,
+    data: *const c_void,
+    _datasize: i32,
+    outsize: *mut i32,
+    outbuffer: *mut c_void,
+) -> sys::exr_result_t {
+    if data.is_null() {
+        return sys::exr_result_t::from_code(
+            sys::exr_error_code_t::EXR_ERR_INVALID_ARGUMENT,
+        );
+    }
+    let value = &*(data as *const T);
+    let packed =
+        match std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            value.pack()
+        })) {
+            Ok(packed) => packed,
+            Err(_) => {
+                return sys::exr_result_t::from_code(
+                    sys::exr_error_code_t::EXR_ERR_INVALID_ATTR,
+                )
+            }
+        };
+    let len = match i32::try_from(packed.len()) {
+        Ok(len) => len,
+        Err(_) => {
+            return sys::exr_result_t::from_code(
+                sys::exr_error_code_t::EXR_ERR_ARGUMENT_OUT_OF_RANGE,
+            )
+        }
+    };
+
+    if !outbuffer.is_null() {
+        if *outsize < len {
+            return sys::exr_result_t::from_code(
+                sys::exr_error_code_t::EXR_ERR_ATTR_SIZE_MISMATCH,
+            );
+        }
+        std::ptr::copy_nonoverlapping(
+            packed.as_ptr(),
+            outbuffer as *mut u8,
+            packed.len(),
+        );
+    }
+    *outsize = len;
+    sys::exr_result_t::from_code(sys::exr_error_code_t::EXR_ERR_SUCCESS)
+}
+
+pub(crate) unsafe extern "C" fn destroy_custom<T: CustomAttribute>(
+    _ctxt: sys::exr_context_t,
+    data: *mut c_void,
+    _datasize: i32,
+) {
+    if !data.is_null() {
+        drop(Box::from_raw(data as *mut T));
+    }
+}
+
+pub trait AttributeRead: Sized {
+    fn get<S: ContextState>(
+        ctx: &Context<S>,
+        part_index: usize,
+        name: &str,
+    ) -> Result<Self>;
+}
+
+pub trait AttributeWrite: Sized {
+    fn set<S: WritableHeaderState>(
+        ctx: &Context<S>,
+        part_index: usize,
+        name: &str,
+        value: &Self,
+    ) -> Result<()>;
+}
+
+impl AttributeRead for f32 {
+    fn get<S: ContextState>(
+        ctx: &Context<S>,
+        part_index: usize,
+        name: &str,
+    ) -> Result<Self> {
+        let mut result = Default::default();
+        unsafe {
+            let c_name = CString::new(name).unwrap();
+            sys::exr_attr_get_float(
+                ctx.inner,
+                c_part_index(part_index)?,
+                c_name.as_ptr(),
+                &mut result,
+            )
+            .ok_in(ctx.inner, result)
+        }
+    }
+}
+
+impl AttributeWrite for f32 {
+    fn set<S: WritableHeaderState>(
+        ctx: &Context<S>,
+        part_index: usize,
+        name: &str,
+        value: &f32,
+    ) -> Result<()> {
+        unsafe {
+            let c_name = CString::new(name).unwrap();
+            sys::exr_attr_set_float(
+                ctx.inner,
+                c_part_index(part_index)?,
+                c_name.as_ptr(),
+                *value,
+            )
+            .ok_in(ctx.inner, ())
+        }
+    }
+}
+
+impl AttributeRead for i32 {
+    fn get<S: ContextState>(
+        ctx: &Context<S>,
+        part_index: usize,
+        name: &str,
+    ) -> Result<Self> {
+        let mut result = Default::default();
+        unsafe {
+            let c_name = CString::new(name).unwrap();
+            sys::exr_attr_get_int(
+                ctx.inner,
+                c_part_index(part_index)?,
+                c_name.as_ptr(),
+                &mut result,
+            )
+            .ok_in(ctx.inner, result)
+        }
+    }
+}
+
+impl AttributeWrite for i32 {
+    fn set<S: WritableHeaderState>(
+        ctx: &Context<S>,
+        part_index: usize,
+        name: &str,
+        value: &i32,
+    ) -> Result<()> {
+        unsafe {
+            let c_name = CString::new(name).unwrap();
+            sys::exr_attr_set_int(
+                ctx.inner,
+                c_part_index(part_index)?,
+                c_name.as_ptr(),
+                *value,
+            )
+            .ok_in(ctx.inner, ())
+        }
+    }
+}
+
+impl AttributeRead for &[f32] {
+    fn get<S: ContextState>(
+        ctx: &Context<S>,
+        part_index: usize,
+        name: &str,
+    ) -> Result<Self> {
+        unsafe {
+            let c_name = CString::new(name).unwrap();
+            let mut sz = 0;
+            let mut ptr = std::ptr::null();
+            sys::exr_attr_get_float_vector(
+                ctx.inner,
+                c_part_index(part_index)?,
+                c_name.as_ptr(),
+                &mut sz,
+                &mut ptr,
+            )
+            .ok_in(ctx.inner, slice_or_empty(ptr, sz.max(0) as usize))
+        }
+    }
+}
+
+impl AttributeWrite for &[f32] {
+    fn set<S: WritableHeaderState>(
+        ctx: &Context<S>,
+        part_index: usize,
+        name: &str,
+        value: &&[f32],
+    ) -> Result<()> {
+        let len = i32::try_from(value.len())
+            .map_err(|_| Error::ArgumentOutOfRange)?;
+        unsafe {
+            let c_name = CString::new(name).unwrap();
+            sys::exr_attr_set_float_vector(
+                ctx.inner,
+                c_part_index(part_index)?,
+                c_name.as_ptr(),
+                len,
+                value.as_ptr(),
+            )
+            .ok_in(ctx.inner, ())
+        }
+    }
+}
+
+impl AttributeWrite for Vec<f32> {
+    fn set<S: WritableHeaderState>(
+        ctx: &Context<S>,
+        part_index: usize,
+        name: &str,
+        value: &Vec<f32>,
+    ) -> Result<()> {
+        <&[f32]>::set(ctx, part_index, name, &value.as_slice())
+    }
+}
+
+impl AttributeRead for f64 {
+    fn get<S: ContextState>(
+        ctx: &Context<S>,
+        part_index: usize,
+        name: &str,
+    ) -> Result<Self> {
+        let mut result = Default::default();
+        unsafe {
+            let c_name = CString::new(name).unwrap();
+            sys::exr_attr_get_double(
+                ctx.inner,
+                c_part_index(part_index)?,
+                c_name.as_ptr(),
+                &mut result,
+            )
+            .ok_in(ctx.inner, result)
+        }
+    }
+}
+
+impl AttributeWrite for f64 {
+    fn set<S: WritableHeaderState>(
+        ctx: &Context<S>,
+        part_index: usize,
+        name: &str,
+        value: &f64,
+    ) -> Result<()> {
+        unsafe {
+            let c_name = CString::new(name).unwrap();
+            sys::exr_attr_set_double(
+                ctx.inner,
+                c_part_index(part_index)?,
+                c_name.as_ptr(),
+                *value,
+            )
+            .ok_in(ctx.inner, ())
+        }
+    }
+}
+
+impl AttributeRead for AttrTimecode {
+    fn get<S: ContextState>(
+        ctx: &Context<S>,
+        part_index: usize,
+        name: &str,
+    ) -> Result<Self> {
+        let mut result = AttrTimecode::from_packed(0, 0);
+        unsafe {
+            let c_name = CString::new(name).unwrap();
+            sys::exr_attr_get_timecode(
+                ctx.inner,
+                c_part_index(part_index)?,
+                c_name.as_ptr(),
+                &mut result.0,
+            )
+            .ok_in(ctx.inner, result)
+        }
+    }
+}
+
+impl AttributeWrite for AttrTimecode {
+    fn set<S: WritableHeaderState>(
+        ctx: &Context<S>,
+        part_index: usize,
+        name: &str,
+        value: &AttrTimecode,
+    ) -> Result<()> {
+        unsafe {
+            let c_name = CString::new(name).unwrap();
+            sys::exr_attr_set_timecode(
+                ctx.inner,
+                c_part_index(part_index)?,
+                c_name.as_ptr(),
+                &value.0,
+            )
+            .ok_in(ctx.inner, ())
+        }
+    }
+}
+
+impl AttributeRead for AttrKeycode {
+    fn get<S: ContextState>(
+        ctx: &Context<S>,
+        part_index: usize,
+        name: &str,
+    ) -> Result<Self> {
+        let mut result = AttrKeycode(sys::exr_attr_keycode_t {
+            film_mfc_code: 0,
+            film_type: 0,
+            prefix: 0,
+            count: 0,
+            perf_offset: 0,
+            perfs_per_frame: 0,
+            perfs_per_count: 0,
+        });
+        unsafe {
+            let c_name = CString::new(name).unwrap();
+            sys::exr_attr_get_keycode(
+                ctx.inner,
+                c_part_index(part_index)?,
+                c_name.as_ptr(),
+                &mut result.0,
+            )
+            .ok_in(ctx.inner, result)
+        }
+    }
+}
+
+impl AttributeWrite for AttrKeycode {
+    /// # Errors
+    /// * `[Error::ArgumentOutOfRange]` - If any field of `value` is out of
+    /// range
+    ///
+    fn set<S: WritableHeaderState>(
+        ctx: &Context<S>,
+        part_index: usize,
+        name: &str,
+        value: &AttrKeycode,
+    ) -> Result<()> {
+        value.validate()?;
+        unsafe {
+            let c_name = CString::new(name).unwrap();
+            sys::exr_attr_set_keycode(
+                ctx.inner,
+                c_part_index(part_index)?,
+                c_name.as_ptr(),
+                &value.0,
+            )
+            .ok_in(ctx.inner, ())
+        }
+    }
+}
+
+impl AttributeRead for AttrRational {
+    fn get<S: ContextState>(
+        ctx: &Context<S>,
+        part_index: usize,
+        name: &str,
+    ) -> Result<Self> {
+        let mut result = AttrRational::new(0, 1);
+        unsafe {
+            let c_name = CString::new(name).unwrap();
+            sys::exr_attr_get_rational(
+                ctx.inner,
+                c_part_index(part_index)?,
+                c_name.as_ptr(),
+                &mut result.0,
+            )
+            .ok_in(ctx.inner, result)
+        }
+    }
+}
+
+impl AttributeWrite for AttrRational {
+    fn set<S: WritableHeaderState>(
+        ctx: &Context<S>,
+        part_index: usize,
+        name: &str,
+        value: &AttrRational,
+    ) -> Result<()> {
+        unsafe {
+            let c_name = CString::new(name).unwrap();
+            sys::exr_attr_set_rational(
+                ctx.inner,
+                c_part_index(part_index)?,
+                c_name.as_ptr(),
+                &value.0,
+            )
+            .ok_in(ctx.inner, ())
+        }
+    }
+}
+
+impl AttributeRead for AttrChromaticities {
+    fn get<S: ContextState>(
+        ctx: &Context<S>,
+        part_index: usize,
+        name: &str,
+    ) -> Result<Self> {
+        let mut result =
+            AttrChromaticities::new([0.0; 2], [0.0; 2], [0.0; 2], [0.0; 2]);
+        unsafe {
+            let c_name = CString::new(name).unwrap();
+            sys::exr_attr_get_chromaticities(
+                ctx.inner,
+                c_part_index(part_index)?,
+                c_name.as_ptr(),
+                &mut result.0,
+            )
+            .ok_in(ctx.inner, result)
+        }
+    }
+}
+
+impl AttributeWrite for AttrChromaticities {
+    fn set<S: WritableHeaderState>(
+        ctx: &Context<S>,
+        part_index: usize,
+        name: &str,
+        value: &AttrChromaticities,
+    ) -> Result<()> {
+        unsafe {
+            let c_name = CString::new(name).unwrap();
+            sys::exr_attr_set_chromaticities(
+                ctx.inner,
+                c_part_index(part_index)?,
+                c_name.as_ptr(),
+                &value.0,
+            )
+            .ok_in(ctx.inner, ())
+        }
+    }
+}
+
+impl AttributeRead for String {
+    fn get<S: ContextState>(
+        ctx: &Context<S>,
+        part_index: usize,
+        name: &str,
+    ) -> Result<Self> {
+        let mut length = 0;
+        let mut ptr = std::ptr::null();
+        unsafe {
+            let c_name = CString::new(name).unwrap();
+            sys::exr_attr_get_string(
+                ctx.inner,
+                c_part_index(part_index)?,
+                c_name.as_ptr(),
+                &mut length,
+                &mut ptr,
+            )
+            .ok_in(ctx.inner, ())?;
+            // length does not include the null terminator
+            let bytes =
+                slice_or_empty(ptr as *const u8, length.max(0) as usize);
+            Ok(String::from_utf8_lossy(bytes).into_owned())
+        }
+    }
+}
+
+impl AttributeWrite for &str {
+    /// # Errors
+    /// * `[Error::InvalidArgument]` - If `value` contains internal null bytes
+    ///
+    fn set<S: WritableHeaderState>(
+        ctx: &Context<S>,
+        part_index: usize,
+        name: &str,
+        value: &&str,
+    ) -> Result<()> {
+        let c_value =
+            CString::new(*value).map_err(|_| Error::InvalidArgument)?;
+        unsafe {
+            let c_name = CString::new(name).unwrap();
+            sys::exr_attr_set_string(
+                ctx.inner,
+                c_part_index(part_index)?,
+                c_name.as_ptr(),
+                c_value.as_ptr(),
+            )
+            .ok_in(ctx.inner, ())
+        }
+    }
+}
+
+impl AttributeWrite for String {
+    fn set<S: WritableHeaderState>(
+        ctx: &Context<S>,
+        part_index: usize,
+        name: &str,
+        value: &String,
+    ) -> Result<()> {
+        <&str>::set(ctx, part_index, name, &value.as_str())
+    }
+}
+
+impl AttributeRead for Vec<String> {
+    fn get<S: ContextState>(
+        ctx: &Context<S>,
+        part_index: usize,
+        name: &str,
+    ) -> Result<Self> {
+        let c_name = CString::new(name).unwrap();
+        let c_part_index = c_part_index(part_index)?;
+        let mut size = 0;
+        unsafe {
+            // ask for the number of strings first, then for the strings
+            sys::exr_attr_get_string_vector(
+                ctx.inner,
+                c_part_index,
+                c_name.as_ptr(),
+                &mut size,
+                std::ptr::null_mut(),
+            )
+            .ok_in(ctx.inner, ())?;
+
+            let mut strings = vec![std::ptr::null(); size.max(0) as usize];
+            sys::exr_attr_get_string_vector(
+                ctx.inner,
+                c_part_index,
+                c_name.as_ptr(),
+                &mut size,
+                strings.as_mut_ptr(),
+            )
+            .ok_in(ctx.inner, ())?;
+
+            Ok(strings
+                .into_iter()
+                .map(|s| {
+                    if s.is_null() {
+                        String::new()
+                    } else {
+                        CStr::from_ptr(s).to_string_lossy().into_owned()
+                    }
+                })
+                .collect())
+        }
+    }
+}
+
+/// Borrows the string from the context's header, so must not outlive the
+/// context
+///
+impl AttributeWrite for Vec<String> {
+    fn set<S: WritableHeaderState>(
+        ctx: &Context<S>,
+        part_index: usize,
+        name: &str,
+        value: &Vec<String>,
+    ) -> Result<()> {
+        set_string_vector(ctx, part_index, name, value)
+    }
+}
+
+impl AttributeWrite for &[&str] {
+    fn set<S: WritableHeaderState>(
+        ctx: &Context<S>,
+        part_index: usize,
+        name: &str,
+        value: &&[&str],
+    ) -> Result<()> {
+        set_string_vector(ctx, part_index, name, value)
+    }
+}
+
+/// # Errors
+/// * `[Error::InvalidArgument]` - If any of `values` contains internal null
+/// bytes
+///
+fn set_string_vector<T: AsRef<str>, S: WritableHeaderState>(
+    ctx: &Context<S>,
+    part_index: usize,
+    name: &str,
+    values: &[T],
+) -> Result<()> {
+    let c_values = values
+        .iter()
+        .map(|v| CString::new(v.as_ref()))
+        .collect::<std::result::Result<Vec<_>, _>>()
+        .map_err(|_| Error::InvalidArgument)?;
+    let mut ptrs = c_values.iter().map(|v| v.as_ptr()).collect::<Vec<_>>();
+    let len =
+        i32::try_from(ptrs.len()).map_err(|_| Error::ArgumentOutOfRange)?;
+    unsafe {
+        let c_name = CString::new(name).unwrap();
+        sys::exr_attr_set_string_vector(
+            ctx.inner,
+            c_part_index(part_index)?,
+            c_name.as_ptr(),
+            len,
+            ptrs.as_mut_ptr(),
+        )
+        .ok_in(ctx.inner, ())
+    }
+}
+
+impl AttributeRead for &CStr {
+    fn get<S: ContextState>(
+        ctx: &Context<S>,
+        part_index: usize,
+        name: &str,
+    ) -> Result<Self> {
+        let mut length = 0;
+        let mut ptr = std::ptr::null();
+        unsafe {
+            let c_name = CString::new(name).unwrap();
+            sys::exr_attr_get_string(
+                ctx.inner,
+                c_part_index(part_index)?,
+                c_name.as_ptr(),
+                &mut length,
+                &mut ptr,
+            )
+            .ok_in(ctx.inner, ())?;
+            if ptr.is_null() {
+                return Err(Error::InvalidAttr);
+            }
+            Ok(CStr::from_ptr(ptr))
+        }
+    }
+}
+
+impl AttributeRead for Preview<'_> {
+    fn get<S: ContextState>(
+        ctx: &Context<S>,
+        part_index: usize,
+        name: &str,
+    ) -> Result<Self> {
+        let mut preview = sys::exr_attr_preview_t {
+            width: 0,
+            height: 0,
+            alloc_size: 0,
+            rgba: std::ptr::null(),
+        };
+        unsafe {
+            let c_name = CString::new(name).unwrap();
+            sys::exr_attr_get_preview(
+                ctx.inner,
+                c_part_index(part_index)?,
+                c_name.as_ptr(),
+                &mut preview,
+            )
+            .ok_in(ctx.inner, ())?;
+            let len = preview.width as usize * preview.height as usize * 4;
+            if preview.rgba.is_null() && len > 0 {
+                return Err(Error::InvalidAttr);
+            }
+            Ok(Preview {
+                width: preview.width,
+                height: preview.height,
+                rgba: slice_or_empty(preview.rgba, len),
+            })
+        }
+    }
+}
+
+impl AttributeRead for PreviewBuf {
+    fn get<S: ContextState>(
+        ctx: &Context<S>,
+        part_index: usize,
+        name: &str,
+    ) -> Result<Self> {
+        Preview::get(ctx, part_index, name).map(|p| p.to_buf())
+    }
+}
+
+impl AttributeWrite for Preview<'_> {
+    /// # Errors
+    /// * `[Error::InvalidArgument]` - If `rgba` does not hold exactly
+    /// `width * height` pixels
+    ///
+    fn set<S: WritableHeaderState>(
+        ctx: &Context<S>,
+        part_index: usize,
+        name: &str,
+        value: &Self,
+    ) -> Result<()> {
+        if value.rgba.len() != value.width as usize * value.height as usize * 4
+        {
+            return Err(Error::InvalidArgument);
+        }
+
+        let preview = sys::exr_attr_preview_t {
+            width: value.width,
+            height: value.height,
+            alloc_size: 0,
+            rgba: value.rgba.as_ptr(),
+        };
+        unsafe {
+            let c_name = CString::new(name).unwrap();
+            sys::exr_attr_set_preview(
+                ctx.inner,
+                c_part_index(part_index)?,
+                c_name.as_ptr(),
+                &preview,
+            )
+            .ok_in(ctx.inner, ())
+        }
+    }
+}
+
+impl AttributeWrite for PreviewBuf {
+    fn set<S: WritableHeaderState>(
+        ctx: &Context<S>,
+        part_index: usize,
+        name: &str,
+        value: &Self,
+    ) -> Result<()> {
+        Preview::set(ctx, part_index, name, &value.as_preview())
+    }
+}
+
+impl AttributeRead for Compression {
+    fn get<S: ContextState>(
+        ctx: &Context<S>,
+        part_index: usize,
+        name: &str,
+    ) -> Result<Self> {
+        let mut result = sys::exr_compression_t::EXR_COMPRESSION_LAST_TYPE;
+        unsafe {
+            let c_name = CString::new(name).unwrap();
+            sys::exr_attr_get_compression(
+                ctx.inner,
+                c_part_index(part_index)?,
+                c_name.as_ptr(),
+                &mut result,
+            )
+            .ok_in(ctx.inner, result.into())
+        }
+    }
+}
+
+impl AttributeWrite for Compression {
+    fn set<S: WritableHeaderState>(
+        ctx: &Context<S>,
+        part_index: usize,
+        name: &str,
+        value: &Compression,
+    ) -> Result<()> {
+        unsafe {
+            let c_name = CString::new(name).unwrap();
+            sys::exr_attr_set_compression(
+                ctx.inner,
+                c_part_index(part_index)?,
+                c_name.as_ptr(),
+                (*value).into(),
+            )
+            .ok_in(ctx.inner, ())
+        }
+    }
+}
+
+impl AttributeRead for LineOrder {
+    fn get<S: ContextState>(
+        ctx: &Context<S>,
+        part_index: usize,
+        name: &str,
+    ) -> Result<Self> {
+        let mut result = sys::exr_lineorder_t::EXR_LINEORDER_LAST_TYPE;
+        unsafe {
+            let c_name = CString::new(name).unwrap();
+            sys::exr_attr_get_lineorder(
+                ctx.inner,
+                c_part_index(part_index)?,
+                c_name.as_ptr(),
+                &mut result,
+            )
+            .ok_in(ctx.inner, ())?;
+        }
+        result.try_into()
+    }
+}
+
+impl AttributeWrite for LineOrder {
+    fn set<S: WritableHeaderState>(
+        ctx: &Context<S>,
+        part_index: usize,
+        name: &str,
+        value: &LineOrder,
+    ) -> Result<()> {
+        unsafe {
+            let c_name = CString::new(name).unwrap();
+            sys::exr_attr_set_lineorder(
+                ctx.inner,
+                c_part_index(part_index)?,
+                c_name.as_ptr(),
+                (*value).into(),
+            )
+            .ok_in(ctx.inner, ())
+        }
+    }
+}
+
+impl AttributeRead for Envmap {
+    fn get<S: ContextState>(
+        ctx: &Context<S>,
+        part_index: usize,
+        name: &str,
+    ) -> Result<Self> {
+        let mut result = sys::exr_envmap_t::EXR_ENVMAP_LAST_TYPE;
+        unsafe {
+            let c_name = CString::new(name).unwrap();
+            sys::exr_attr_get_envmap(
+                ctx.inner,
+                c_part_index(part_index)?,
+                c_name.as_ptr(),
+                &mut result,
+            )
+            .ok_in(ctx.inner, ())?;
+        }
+        result.try_into()
+    }
+}
+
+impl AttributeWrite for Envmap {
+    fn set<S: WritableHeaderState>(
+        ctx: &Context<S>,
+        part_index: usize,
+        name: &str,
+        value: &Envmap,
+    ) -> Result<()> {
+        unsafe {
+            let c_name = CString::new(name).unwrap();
+            sys::exr_attr_set_envmap(
+                ctx.inner,
+                c_part_index(part_index)?,
+                c_name.as_ptr(),
+                (*value).into(),
+            )
+            .ok_in(ctx.inner, ())
+        }
+    }
+}
+
+impl AttributeRead for Storage {
+    /// There is no storage attribute type, so this reads the string
+    /// attribute `name`, which should be the part's `type`, and maps its
+    /// value to the corresponding storage
+    ///
+    /// # Errors
+    /// * `[Error::InvalidAttr]` - If the string is not one of the values
+    /// `type` may take
+    ///
+    fn get<S: ContextState>(
+        ctx: &Context<S>,
+        part_index: usize,
+        name: &str,
+    ) -> Result<Self> {
+        match <&CStr>::get(ctx, part_index, name)?.to_bytes() {
+            b"scanlineimage" => Ok(Storage::Scanline),
+            b"tiledimage" => Ok(Storage::Tiled),
+            b"deepscanline" => Ok(Storage::DeepScanline),
+            b"deeptile" => Ok(Storage::DeepTiled),
+            _ => Err(Error::InvalidAttr),
+        }
+    }
+}
+
+impl AttributeRead for TileDescriptor {
+    fn get<S: ContextState>(
+        ctx: &Context<S>,
+        part_index: usize,
+        name: &str,
+    ) -> Result<Self> {
+        let mut result = sys::exr_attr_tiledesc_t {
+            x_size: 0,
+            y_size: 0,
+            level_and_round: 0,
+        };
+        unsafe {
+            let c_name = CString::new(name).unwrap();
+            sys::exr_attr_get_tiledesc(
+                ctx.inner,
+                c_part_index(part_index)?,
+                c_name.as_ptr(),
+                &mut result,
+            )
+            .ok_in(ctx.inner, ())?;
+        }
+        TileDescriptor::try_from(&result)
+    }
+}
+
+impl AttributeWrite for TileDescriptor {
+    fn set<S: WritableHeaderState>(
+        ctx: &Context<S>,
+        part_index: usize,
+        name: &str,
+        value: &TileDescriptor,
+    ) -> Result<()> {
+        let td = sys::exr_attr_tiledesc_t::from(*value);
+        unsafe {
+            let c_name = CString::new(name).unwrap();
+            sys::exr_attr_set_tiledesc(
+                ctx.inner,
+                c_part_index(part_index)?,
+                c_name.as_ptr(),
+                &td,
+            )
+            .ok_in(ctx.inner, ())
+        }
+    }
+}
+
+/// Implement AttributeRead and AttributeWrite for a fixed-size array that has
+/// the same layout as the C attribute type
+///
+macro_rules! impl_attr_array {
+    ($ty:ty, $sys_ty:ty, $get:ident, $set:ident) => {
+        impl AttributeRead for $ty {
+            fn get<S: ContextState>(
+                ctx: &Context<S>,
+                part_index: usize,
+                name: &str,
+            ) -> Result<$ty> {
+                let mut result = <$ty>::default();
+                unsafe {
+                    let c_name = CString::new(name).unwrap();
+                    sys::$get(
+                        ctx.inner,
+                        c_part_index(part_index)?,
+                        c_name.as_ptr(),
+                        result.as_mut_ptr() as *mut $sys_ty,
+                    )
+                    .ok_in(ctx.inner, result)
+                }
+            }
+        }
+
+        impl AttributeWrite for $ty {
+            fn set<S: WritableHeaderState>(
+                ctx: &Context<S>,
+                part_index: usize,
+                name: &str,
+                value: &$ty,
+            ) -> Result<()> {
+                unsafe {
+                    let c_name = CString::new(name).unwrap();
+                    sys::$set(
+                        ctx.inner,
+                        c_part_index(part_index)?,
+                        c_name.as_ptr(),
+                        value.as_ptr() as *const $sys_ty,
+                    )
+                    .ok_in(ctx.inner, ())
+                }
+            }
+        }
+    };
+}
+
+impl_attr_array!(
+    [i32; 4],
+    sys::exr_attr_box2i_t,
+    exr_attr_get_box2i,
+    exr_attr_set_box2i
+);
+impl_attr_array!(
+    [f32; 4],
+    sys::exr_attr_box2f_t,
+    exr_attr_get_box2f,
+    exr_attr_set_box2f
+);
+impl_attr_array!(
+    [f32; 9],
+    sys::exr_attr_m33f_t,
+    exr_attr_get_m33f,
+    exr_attr_set_m33f
+);
+impl_attr_array!(
+    [f64; 9],
+    sys::exr_attr_m33d_t,
+    exr_attr_get_m33d,
+    exr_attr_set_m33d
+);
+impl_attr_array!(
+    [f32; 16],
+    sys::exr_attr_m44f_t,
+    exr_attr_get_m44f,
+    exr_attr_set_m44f
+);
+impl_attr_array!(
+    [f64; 16],
+    sys::exr_attr_m44d_t,
+    exr_attr_get_m44d,
+    exr_attr_set_m44d
+);
+
+impl_attr_array!(
+    [i32; 2],
+    sys::exr_attr_v2i_t,
+    exr_attr_get_v2i,
+    exr_attr_set_v2i
+);
+impl_attr_array!(
+    [f32; 2],
+    sys::exr_attr_v2f_t,
+    exr_attr_get_v2f,
+    exr_attr_set_v2f
+);
+impl_attr_array!(
+    [f64; 2],
+    sys::exr_attr_v2d_t,
+    exr_attr_get_v2d,
+    exr_attr_set_v2d
+);
+impl_attr_array!(
+    [i32; 3],
+    sys::exr_attr_v3i_t,
+    exr_attr_get_v3i,
+    exr_attr_set_v3i
+);
+impl_attr_array!(
+    [f32; 3],
+    sys::exr_attr_v3f_t,
+    exr_attr_get_v3f,
+    exr_attr_set_v3f
+);
+impl_attr_array!(
+    [f64; 3],
+    sys::exr_attr_v3d_t,
+    exr_attr_get_v3d,
+    exr_attr_set_v3d
+);
+
+#[cfg(test)]
+mod tests {
+    use crate as exr;
+    use exr::attr::AttrKeycode;
+
+    #[test]
+    fn keycode() -> Result<(), exr::Error> {
+        let mut kc = AttrKeycode::new(2, 15, 123456, 7890, 12, 4, 64)?;
+        assert_eq!(kc.to_string(), "02 15 123456 7890+12");
+
+        assert_eq!(
+            kc.set_perfs_per_frame(16),
+            Err(exr::Error::ArgumentOutOfRange)
+        );
+        assert_eq!(kc.perfs_per_frame(), 4);
+
+        kc.set_count(1)?;
+        assert_eq!(kc.count(), 1);
+
+        assert_eq!(
+            AttrKeycode::new(100, 15, 123456, 7890, 12, 4, 64),
+            Err(exr::Error::ArgumentOutOfRange)
+        );
+
+        let path = std::env::temp_dir().join("keycode_attribute.exr");
+        write_ferris_with(&path, |header| {
+            header.set_attribute(0, "keyCode", &kc)
+        })?;
+
+        let ctx = exr::context::ReadContext::new(&path)?;
+        let read: AttrKeycode = ctx.get_attribute(0, "keyCode")?;
+        assert_eq!(read, kc);
+        assert_eq!(read.prefix(), 123456);
+        assert_eq!(read.perfs_per_count(), 64);
+
+        Ok(())
+    }
+
+    #[test]
+    fn split_layers() {
+        use exr::attr::split_layer;
+
+        assert_eq!(split_layer("R"), (None, "R"));
+        assert_eq!(split_layer("diffuse.R"), (Some("diffuse"), "R"));
+        assert_eq!(split_layer("a.b.A"), (Some("a.b"), "A"));
+    }
+
+    #[test]
+    fn layers() -> Result<(), exr::Error> {
+        let path_ferris = std::path::Path::new(
+            &std::env::var("CARGO_MANIFEST_DIR")
+                .expect("CARGO_MANIFEST_DIR not set"),
+        )
+        .join("images")
+        .join("ferris.exr");
+
+        let ctx = exr::context::ReadContext::new(&path_ferris)?;
+        let channels = ctx.channels(0)?;
+        let layers = channels.layers();
+        assert_eq!(layers.len(), 1);
+        assert_eq!(layers[0].name(), None);
+        assert_eq!(
+            layers[0].base_names().collect::<Vec<_>>(),
+            ["A", "B", "G", "R"]
+        );
+        assert_eq!(layers[0].channel("G").map(|c| c.name()), Some("G"));
+        assert!(channels.layer_names().is_empty());
+        assert!(channels.layer("diffuse").is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn rational() -> Result<(), exr::Error> {
+        use exr::attr::AttrRational;
+        use std::convert::TryFrom;
+
+        let r = AttrRational::new(48, 2);
+        assert_eq!(r.reduce().num(), 24);
+        assert_eq!(r.reduce().denom(), 1);
+        assert_eq!(r, AttrRational::new(24, 1));
+        assert!(AttrRational::new(1, 3) < AttrRational::new(1, 2));
+        assert_eq!(f64::from(AttrRational::new(-1, 4)), -0.25);
+
+        let ntsc = AttrRational::try_from(24000.0 / 1001.0)?;
+        assert_eq!((ntsc.num(), ntsc.denom()), (24000, 1001));
+
+        let half = AttrRational::try_from(0.5)?;
+        assert_eq!((half.num(), half.denom()), (1, 2));
+
+        let pi = AttrRational::approximate(std::f64::consts::PI, 1000)?;
+        assert_eq!((pi.num(), pi.denom()), (355, 113));
+
+        assert_eq!(
+            AttrRational::try_from(f64::NAN),
+            Err(exr::Error::ArgumentOutOfRange)
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn chromaticities() {
+        use exr::attr::AttrChromaticities;
+
+        let c = AttrChromaticities::new(
+            [0.7347, 0.2653],
+            [0.0, 1.0],
+            [0.0001, -0.077],
+            [0.32168, 0.33767],
+        );
+        assert_eq!(c, AttrChromaticities::ACES_AP0);
+        assert_eq!(c.name(1e-3), Some("ACES AP0"));
+
+        let red: [f32; 2] = AttrChromaticities::REC709.red();
+        assert_eq!(red, [0.64, 0.33]);
+
+        let almost = AttrChromaticities::new(
+            [0.6401, 0.33],
+            [0.3, 0.6],
+            [0.15, 0.06],
+            [0.3127, 0.329],
+        );
+        assert!(almost.approx_eq(&AttrChromaticities::REC709, 1e-3));
+        assert!(!almost.approx_eq(&AttrChromaticities::REC709, 1e-6));
+        assert_eq!(almost.name(1e-3), Some("Rec.709"));
+        assert_eq!(almost.name(1e-6), None);
+    }
+
+    #[test]
+    fn unknown_enum_values() {
+        use exr::attr::{Envmap, LineOrder, PixelType, Storage};
+        use openexr_core_sys as sys;
+        use std::convert::TryFrom;
+
+        assert_eq!(
+            Storage::try_from(sys::exr_storage_t::EXR_STORAGE_TILED),
+            Ok(Storage::Tiled)
+        );
+        assert_eq!(
+            Storage::try_from(sys::exr_storage_t::EXR_STORAGE_LAST_TYPE),
+            Err(exr::Error::Unknown)
+        );
+        assert!(Envmap::try_from(sys::exr_envmap_t(99)).is_err());
+        assert!(LineOrder::try_from(sys::exr_lineorder_t(99)).is_err());
+        assert!(PixelType::try_from(sys::exr_pixel_type_t(99)).is_err());
+    }
+
+    #[test]
+    fn attr_type() -> Result<(), exr::Error> {
+        use exr::attr::AttrType;
+
+        let path_ferris = std::path::Path::new(
+            &std::env::var("CARGO_MANIFEST_DIR")
+                .expect("CARGO_MANIFEST_DIR not set"),
+        )
+        .join("images")
+        .join("ferris.exr");
+        let ctx = exr::context::ReadContext::new(&path_ferris)?;
+
+        let kind = |name| -> Result<AttrType, exr::Error> {
+            Ok(ctx.get_attribute_by_name(0, name)?.kind())
+        };
+        assert_eq!(kind("channels")?, AttrType::Chlist);
+        assert_eq!(kind("compression")?, AttrType::Compression);
+        assert_eq!(kind("dataWindow")?, AttrType::Box2i);
+        assert_eq!(kind("lineOrder")?, AttrType::LineOrder);
+        assert_eq!(kind("pixelAspectRatio")?, AttrType::Float);
+        assert_eq!(kind("screenWindowCenter")?, AttrType::V2f);
+
+        Ok(())
+    }
+
+    #[test]
+    fn attribute_value() -> Result<(), exr::Error> {
+        use exr::attr::{AttributeValue, Compression};
+
+        let path_ferris = std::path::Path::new(
+            &std::env::var("CARGO_MANIFEST_DIR")
+                .expect("CARGO_MANIFEST_DIR not set"),
+        )
+        .join("images")
+        .join("ferris.exr");
+        let ctx = exr::context::ReadContext::new(&path_ferris)?;
+
+        let attr = ctx.get_attribute_by_name(0, "compression")?;
+        assert_eq!(attr.value(), AttributeValue::Compression(Compression::Piz));
+        assert_eq!(attr.value().kind(), attr.kind());
+
+        let attr = ctx.get_attribute_by_name(0, "dataWindow")?;
+        assert_eq!(attr.value(), AttributeValue::Box2i([0, 0, 1199, 799]));
+        assert_eq!(attr.value().to_string(), "(0, 0) - (1199, 799)");
+
+        for attr in
+            ctx.attribute_list(0, exr::part::AttrListAccessMode::FileOrder)?
+        {
+            assert_eq!(attr.value().kind(), attr.kind());
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn string_attribute() -> Result<(), exr::Error> {
+        use exr::attr::AttributeValue;
+        use std::ffi::CStr;
+
+        let path = std::path::Path::new(
+            &std::env::var("CARGO_MANIFEST_DIR")
+                .expect("CARGO_MANIFEST_DIR not set"),
+        )
+        .join("images")
+        .join("custom_attributes.exr");
+        let ctx = exr::context::ReadContext::new(&path)?;
+
+        let comments = "this is an awesome image of Ferris";
+        assert_eq!(ctx.get_attribute::<String>(0, "comments")?, comments);
+        assert_eq!(
+            ctx.get_attribute::<&CStr>(0, "comments")?.to_str().unwrap(),
+            comments
+        );
+        assert_eq!(
+            ctx.get_attribute_by_name(0, "comments")?.value(),
+            AttributeValue::String(comments.into())
+        );
+        assert!(ctx.get_attribute::<String>(0, "compression").is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn string_vector_attribute() -> Result<(), exr::Error> {
+        use exr::attr::AttributeValue;
+        use openexr_core_sys as sys;
+        use std::ffi::CString;
+
+        let path = std::env::temp_dir().join("string_vector_attribute.exr");
+        write_ferris_with(&path, |header| {
+            let name = CString::new("multiView").unwrap();
+            let views = ["left", "right"]
+                .iter()
+                .map(|v| CString::new(*v).unwrap())
+                .collect::<Vec<_>>();
+            let mut ptrs = views.iter().map(|v| v.as_ptr()).collect::<Vec<_>>();
+            unsafe {
+                sys::exr_attr_set_string_vector(
+                    header.inner,
+                    0,
+                    name.as_ptr(),
+                    ptrs.len() as i32,
+                    ptrs.as_mut_ptr(),
+                )
+                .ok_in(header.inner, ())
+            }
+        })?;
+
+        let ctx = exr::context::ReadContext::new(&path)?;
+        let views = vec!["left".to_string(), "right".to_string()];
+        assert_eq!(ctx.get_attribute::<Vec<String>>(0, "multiView")?, views);
+        assert_eq!(
+            ctx.get_attribute_by_name(0, "multiView")?.value(),
+            AttributeValue::StringVector(views)
+        );
+        assert!(ctx.get_attribute::<Vec<String>>(0, "compression").is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn double_attribute() -> Result<(), exr::Error> {
+        use exr::attr::AttributeValue;
+
+        let path = std::env::temp_dir().join("double_attribute.exr");
+        let focus = 1.0f64 / 3.0;
+        write_ferris_with(&path, |header| {
+            header.set_attribute(0, "focus", &focus)
+        })?;
+
+        let ctx = exr::context::ReadContext::new(&path)?;
+        assert_eq!(ctx.get_attribute::<f64>(0, "focus")?, focus);
+        assert_eq!(
+            ctx.get_attribute_by_name(0, "focus")?.value(),
+            AttributeValue::Double(focus)
+        );
+        assert!(ctx.get_attribute::<f64>(0, "pixelAspectRatio").is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn vector_attributes() -> Result<(), exr::Error> {
+        let path_ferris = std::path::Path::new(
+            &std::env::var("CARGO_MANIFEST_DIR")
+                .expect("CARGO_MANIFEST_DIR not set"),
+        )
+        .join("images")
+        .join("ferris.exr");
+        let ctx = exr::context::ReadContext::new(&path_ferris)?;
+
+        let center: [f32; 2] =
+            ctx.get_attribute_v2f(0, "screenWindowCenter")?;
+        assert_eq!(center, [0.0, 0.0]);
+        assert_eq!(
+            ctx.get_attribute::<[f32; 2]>(0, "screenWindowCenter")?,
+            ctx.screen_window_center::<[f32; 2]>(0)?
+        );
+        assert!(ctx
+            .get_attribute_v3f::<[f32; 3]>(0, "screenWindowCenter")
+            .is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn timecode() -> Result<(), exr::Error> {
+        let mut tc = exr::attr::AttrTimecode::new(1, 2, 3, 4)?;
+        assert_eq!(tc.time_and_flags(), 0x01020304);
+        assert_eq!(tc.to_string(), "01:02:03:04");
+        tc.set_drop_frame(true);
+        tc.set_frame(29)?;
+        tc.set_binary_group(2, 0xa)?;
+        assert_eq!(tc.to_string(), "01:02:03;29");
+        assert_eq!(tc.binary_group(2)?, 0xa);
+        assert_eq!(tc.user_data(), 0xa0);
+        assert!(tc.set_minutes(60).is_err());
+        assert!(tc.binary_group(9).is_err());
+
+        let path = std::env::temp_dir().join("timecode_attribute.exr");
+        write_ferris_with(&path, |header| {
+            header.set_attribute(0, "timeCode", &tc)
+        })?;
+
+        let ctx = exr::context::ReadContext::new(&path)?;
+        let read: exr::attr::AttrTimecode = ctx.get_attribute(0, "timeCode")?;
+        assert_eq!(read, tc);
+        assert_eq!(read.hours(), 1);
+        assert!(read.drop_frame());
+        let value = ctx.get_attribute_by_name(0, "timeCode")?.value();
+        assert_eq!(value, exr::attr::AttributeValue::Timecode(tc));
+        assert_eq!(value.to_string(), "01:02:03;29");
+
+        Ok(())
+    }
+
+    #[test]
+    fn rational_attribute() -> Result<(), exr::Error> {
+        use std::convert::TryFrom;
+
+        let fps = exr::attr::AttrRational::try_from(24000.0 / 1001.0)?;
+        assert_eq!((fps.num(), fps.denom()), (24000, 1001));
+
+        let path = std::env::temp_dir().join("rational_attribute.exr");
+        write_ferris_with(&path, |header| {
+            header.set_frames_per_second(0, &fps)
+        })?;
+
+        let ctx = exr::context::ReadContext::new(&path)?;
+        let read = ctx.frames_per_second(0)?;
+        assert_eq!((read.num(), read.denom()), (24000, 1001));
+        assert_eq!(
+            ctx.get_attribute::<exr::attr::AttrRational>(0, "framesPerSecond")?,
+            read
+        );
+        assert!((f64::from(read) - 23.976).abs() < 1e-3);
+
+        Ok(())
+    }
+
+    #[test]
+    fn chromaticities_attribute() -> Result<(), exr::Error> {
+        use exr::attr::AttrChromaticities;
+
+        let path = std::env::temp_dir().join("chromaticities_attribute.exr");
+        write_ferris_with(&path, |header| {
+            header.set_attribute(
+                0,
+                "chromaticities",
+                &AttrChromaticities::ACES_AP0,
+            )
+        })?;
+
+        let ctx = exr::context::ReadContext::new(&path)?;
+        let chroma: AttrChromaticities =
+            ctx.get_attribute(0, "chromaticities")?;
+        assert_eq!(chroma, AttrChromaticities::ACES_AP0);
+        assert_eq!(chroma.name(1e-3), Some("ACES AP0"));
+        assert_eq!(
+            ctx.get_attribute::<f32>(0, "chromaticities"),
+            Err(exr::Error::AttrTypeMismatch)
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn preview_attribute() -> Result<(), exr::Error> {
+        use exr::attr::{Preview, PreviewBuf};
+        use exr::rgba::Rgba;
+        use openexr_core_sys as sys;
+        use std::ffi::CString;
+
+        let rgba = (0..4 * 3 * 4).map(|i| i as u8).collect::<Vec<_>>();
+        let path = std::env::temp_dir().join("preview_attribute.exr");
+        write_ferris_with(&path, |header| {
+            let name = CString::new("preview").unwrap();
+            let preview = sys::exr_attr_preview_t {
+                width: 4,
+                height: 3,
+                alloc_size: 0,
+                rgba: rgba.as_ptr(),
+            };
+            unsafe {
+                sys::exr_attr_set_preview(
+                    header.inner,
+                    0,
+                    name.as_ptr(),
+                    &preview,
+                )
+                .ok_in(header.inner, ())
+            }
+        })?;
+
+        let ctx = exr::context::ReadContext::new(&path)?;
+        let preview = ctx.preview(0)?;
+        assert_eq!((preview.width, preview.height), (4, 3));
+        assert_eq!(preview.rgba, &rgba[..]);
+        assert_eq!(preview.pixel(1, 2), Some([36, 37, 38, 39]));
+        assert_eq!(preview.pixel(4, 0), None);
+
+        let owned: PreviewBuf = ctx.get_attribute(0, "preview")?;
+        assert_eq!(owned.as_preview(), preview);
+        assert_eq!(
+            ctx.get_attribute_by_name(0, "preview")?.value(),
+            exr::attr::AttributeValue::Preview(owned)
+        );
+
+        let image = ctx.preview_image(0)?.unwrap();
+        assert_eq!((image.width, image.height), (4, 3));
+        assert_eq!(image.pixel(1, 2), Rgba::new(36, 37, 38, 39));
+        let path_ferris = std::path::Path::new(
+            &std::env::var("CARGO_MANIFEST_DIR")
+                .expect("CARGO_MANIFEST_DIR not set"),
+        )
+        .join("images")
+        .join("ferris.exr");
+        let ferris = exr::context::ReadContext::new(&path_ferris)?;
+        assert_eq!(ferris.preview_image(0)?, None);
+        assert_eq!(
+            ctx.get_attribute::<Preview>(0, "compression"),
+            Err(exr::Error::AttrTypeMismatch)
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn enum_attributes() -> Result<(), exr::Error> {
+        use exr::attr::{Compression, Envmap, LineOrder, Storage};
+
+        let images = std::path::Path::new(
+            &std::env::var("CARGO_MANIFEST_DIR")
+                .expect("CARGO_MANIFEST_DIR not set"),
+        )
+        .join("images");
+
+        let ctx = exr::context::ReadContext::new(images.join("ferris.exr"))?;
+        assert_eq!(
+            ctx.get_attribute::<LineOrder>(0, "lineOrder")?,
+            ctx.lineorder(0)?
+        );
+        assert_eq!(
+            ctx.get_attribute::<Compression>(0, "compression")?,
+            Compression::Piz
+        );
+        assert_eq!(
+            ctx.get_attribute::<Envmap>(0, "lineOrder"),
+            Err(exr::Error::AttrTypeMismatch)
+        );
+
+        let ctx = exr::context::ReadContext::new(
+            images.join("ferris-multipart.exr"),
+        )?;
+        assert_eq!(ctx.get_attribute::<Storage>(0, "type")?, ctx.storage(0)?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn tile_descriptor() -> Result<(), exr::Error> {
+        use exr::attr::{LevelMode, TileDescriptor};
+        use std::convert::TryFrom;
+
+        let path_tiled = std::path::Path::new(
+            &std::env::var("CARGO_MANIFEST_DIR")
+                .expect("CARGO_MANIFEST_DIR not set"),
+        )
+        .join("images")
+        .join("ferris-tiled.exr");
+        let ctx = exr::context::ReadContext::new(&path_tiled)?;
+
+        let td = ctx.tile_descriptor(0)?;
+        assert_eq!(td, ctx.get_attribute::<TileDescriptor>(0, "tiles")?);
+        assert_eq!(
+            ctx.get_attribute_by_name(0, "tiles")?.value(),
+            exr::attr::AttributeValue::Tiledesc(td)
+        );
+        assert_eq!(
+            (td.x_size as usize, td.y_size as usize),
+            ctx.tile_sizes(0, 0, 0)?
+        );
+        if td.level_mode == LevelMode::OneLevel {
+            assert_eq!(ctx.tile_levels(0)?, (1, 1));
+        }
+
+        let (width, height) = ctx.level_sizes(0, 0, 0)?;
+        let (tiles_x, tiles_y) = ctx.tile_counts(0, 0, 0)?;
+        assert!(tiles_x * td.x_size as usize >= width);
+        assert!((tiles_x - 1) * (td.x_size as usize) < width);
+        assert!(tiles_y * td.y_size as usize >= height);
+        assert!((tiles_y - 1) * (td.y_size as usize) < height);
+
+        // levels that don't fit the C core's int are out of range rather than
+        // wrapping round to a valid one
+        let huge = i32::MAX as usize + 1;
+        assert_eq!(
+            ctx.tile_sizes(0, huge, 0).unwrap_err().kind(),
+            exr::ErrorKind::ArgumentOutOfRange
+        );
+        assert_eq!(
+            ctx.level_sizes(0, 0, huge).unwrap_err().kind(),
+            exr::ErrorKind::ArgumentOutOfRange
+        );
+
+        let sys_td = openexr_core_sys::exr_attr_tiledesc_t::from(td);
+        assert_eq!(TileDescriptor::try_from(&sys_td)?, td);
+
+        Ok(())
+    }
+
+    #[test]
+    fn compound_attributes() -> Result<(), exr::Error> {
+        let path = std::env::temp_dir().join("compound_attributes.exr");
+        let floats = vec![1.0f32, 2.0, 3.0];
+        write_ferris_with(&path, |header| {
+            header.set_attribute_box2i(0, "cropWindow", &[1, 2, 30, 40])?;
+            header.set_attribute_box2f(
+                0,
+                "uvBounds",
+                &[0.0f32, 0.0, 1.0, 1.0],
+            )?;
+            header.set_attribute_v2i(0, "offset", &[-3, 4])?;
+            header.set_attribute_v3f(
+                0,
+                "cameraPosition",
+                &[1.0f32, 2.0, 3.0],
+            )?;
+            header.set_attribute_v3d(0, "target", &[0.5f64, 0.25, 0.125])?;
+            header.set_attribute(0, "weights", &floats)?;
+            header.set_attribute(0, "empty", &&[0.0f32; 0][..])
+        })?;
+
+        let ctx = exr::context::ReadContext::new(&path)?;
+        assert_eq!(
+            ctx.get_attribute_box2i::<[i32; 4]>(0, "cropWindow")?,
+            [1, 2, 30, 40]
+        );
+        assert_eq!(
+            ctx.get_attribute::<[f32; 4]>(0, "uvBounds")?,
+            [0.0, 0.0, 1.0, 1.0]
+        );
+        assert_eq!(ctx.get_attribute::<[i32; 2]>(0, "offset")?, [-3, 4]);
+        assert_eq!(
+            ctx.get_attribute_v3f::<[f32; 3]>(0, "cameraPosition")?,
+            [1.0, 2.0, 3.0]
+        );
+        assert_eq!(
+            ctx.get_attribute::<[f64; 3]>(0, "target")?,
+            [0.5, 0.25, 0.125]
+        );
+        assert_eq!(ctx.get_attribute::<&[f32]>(0, "weights")?, &floats[..]);
+        assert!(ctx.get_attribute::<&[f32]>(0, "empty")?.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn declare_by_type() -> Result<(), exr::Error> {
+        use exr::attr::{AttrType, AttributeValue};
+
+        let path = std::env::temp_dir().join("declare_by_type.exr");
+        let rig = [1u8, 2, 3, 4, 5];
+        write_ferris_with(&path, |header| {
+            assert_eq!(
+                header.declare_attribute(0, "rig", "cameraRig")?,
+                AttrType::Opaque
+            );
+            header.set_attribute_bytes(0, "rig", "cameraRig", &rig)?;
+            assert_eq!(
+                header.declare_attribute(0, "up", "v3f")?,
+                AttrType::V3f
+            );
+            header.set_attribute_by_type(
+                0,
+                "up",
+                "v3f",
+                &[0.0f32, 1.0, 0.0],
+            )?;
+            assert!(header
+                .set_attribute_by_type(0, "focal", "int", &35.0f64)
+                .is_err());
+            Ok(())
+        })?;
+
+        let ctx = exr::context::ReadContext::new(&path)?;
+        assert_eq!(
+            ctx.get_attribute_by_name(0, "rig")?.value(),
+            AttributeValue::Opaque {
+                type_name: "cameraRig".to_string(),
+                data: rig.to_vec(),
+            }
+        );
+        assert_eq!(ctx.get_attribute::<[f32; 3]>(0, "up")?, [0.0, 1.0, 0.0]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn opaque_attribute() -> Result<(), exr::Error> {
+        let path = std::env::temp_dir().join("opaque_attribute.exr");
+        let rig = b"left: 0.0 0.0 0.0; right: 6.5 0.0 0.0";
+        write_ferris_with(&path, |header| {
+            header.set_attribute_bytes(0, "rig", "cameraRig", rig)
+        })?;
+
+        // read the blob and write it to another file untouched
+        let src = exr::context::ReadContext::new(&path)?;
+        let attr = src.get_attribute_by_name(0, "rig")?;
+        assert_eq!(attr.opaque_data(), Some(&rig[..]));
+        assert_eq!(
+            src.get_attribute_by_name(0, "compression")?.opaque_data(),
+            None
+        );
+
+        let path_copy = std::env::temp_dir().join("opaque_attribute_copy.exr");
+        write_ferris_with(&path_copy, |header| {
+            header.set_opaque_attribute(0, attr)?;
+            assert!(header
+                .set_opaque_attribute(
+                    0,
+                    src.get_attribute_by_name(0, "compression")?
+                )
+                .is_err());
+            Ok(())
+        })?;
+
+        let ctx = exr::context::ReadContext::new(&path_copy)?;
+        let copy = ctx.get_attribute_by_name(0, "rig")?;
+        assert_eq!(copy.type_name(), "cameraRig");
+        assert_eq!(copy.opaque_data(), Some(&rig[..]));
+
+        Ok(())
+    }
+
+    #[derive(Debug, PartialEq)]
+    struct CameraRig {
+        interaxial: f32,
+        convergence: f32,
+    }
+
+    impl exr::attr::CustomAttribute for CameraRig {
+        const TYPE_NAME: &'static str = "cameraRig";
+
+        fn unpack(data: &[u8]) -> Result<Self, exr::Error> {
+            if data.len() != 8 {
+                return Err(exr::Error::AttrSizeMismatch);
+            }
+            let f = |b: &[u8]| f32::from_le_bytes([b[0], b[1], b[2], b[3]]);
+            Ok(CameraRig {
+                interaxial: f(&data[0..4]),
+                convergence: f(&data[4..8]),
+            })
+        }
+
+        fn pack(&self) -> Vec<u8> {
+            let mut data = self.interaxial.to_le_bytes().to_vec();
+            data.extend_from_slice(&self.convergence.to_le_bytes());
+            data
+        }
+    }
+
+    #[test]
+    fn custom_attribute() -> Result<(), exr::Error> {
+        let path = std::env::temp_dir().join("custom_attribute.exr");
+        let rig = CameraRig {
+            interaxial: 6.5,
+            convergence: 300.0,
+        };
+        write_ferris_with(&path, |header| {
+            header.register_custom_attribute::<CameraRig>()?;
+            header.set_custom_attribute(0, "rig", &rig)
+        })?;
+
+        let mut ctx = exr::context::ReadContext::new(&path)?;
+        ctx.register_custom_attribute::<CameraRig>()?;
+        assert_eq!(ctx.get_custom_attribute::<CameraRig>(0, "rig")?, rig);
+        assert_eq!(
+            ctx.get_custom_attribute::<CameraRig>(0, "compression"),
+            Err(exr::Error::AttrTypeMismatch)
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn copy_attributes_from() -> Result<(), exr::Error> {
+        let path_custom = std::path::Path::new(
+            &std::env::var("CARGO_MANIFEST_DIR")
+                .expect("CARGO_MANIFEST_DIR not set"),
+        )
+        .join("images")
+        .join("custom_attributes.exr");
+        let src = exr::context::ReadContext::new(&path_custom)?;
+
+        let path = std::env::temp_dir().join("copy_attributes_from.exr");
+        write_ferris_with(&path, |header| {
+            header.set_attribute(0, "comments", &"overridden")?;
+            header.copy_attributes_from(&src, 0, 0)
+        })?;
+
+        let ctx = exr::context::ReadContext::new(&path)?;
+        assert_eq!(ctx.get_attribute::<String>(0, "comments")?, "overridden");
+        assert_eq!(
+            ctx.get_attribute::<[f32; 16]>(0, "cameraTransform")?,
+            src.get_attribute::<[f32; 16]>(0, "cameraTransform")?
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn copy_attributes_except() -> Result<(), exr::Error> {
+        use exr::context::ReadContext;
+        use exr::part::AttrListAccessMode;
+
+        let images = std::path::Path::new(
+            &std::env::var("CARGO_MANIFEST_DIR")
+                .expect("CARGO_MANIFEST_DIR not set"),
+        )
+        .join("images");
+        let src = ReadContext::new(images.join("custom_attributes.exr"))?;
+        let ferris = ReadContext::new(images.join("ferris.exr"))?;
+
+        let path = std::env::temp_dir().join("copy_attributes_except.exr");
+        write_ferris_with(&path, |header| {
+            header.copy_attributes_except(&src, 0, 0, &["cameraTransform"])
+        })?;
+
+        // attributes of every type are copied by value, unless skipped or
+        // already set
+        let ctx = ReadContext::new(&path)?;
+        for attr in src.attribute_list(0, AttrListAccessMode::FileOrder)? {
+            let name = attr.name();
+            let copied = ctx.get_attribute_by_name(0, name).ok();
+            if name == "cameraTransform" {
+                assert!(copied.is_none());
+            } else if ferris.get_attribute_by_name(0, name).is_err() {
+                assert_eq!(copied.map(|c| c.value()), Some(attr.value()));
+            }
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn part_setters() -> Result<(), exr::Error> {
+        use exr::attr::{Compression, LineOrder};
+
+        let path = std::env::temp_dir().join("part_setters.exr");
+        let mut data_window = [0i32; 4];
+        write_ferris_with(&path, |header| {
+            data_window = header.data_window::<[i32; 4]>(0)?;
+            header.set_data_window(0, &data_window)?;
+            header.set_display_window(0, &[-8i32, -8, 1207, 807])?;
+            header.set_compression(0, Compression::Zip)?;
+            header.set_lineorder(0, LineOrder::DecreasingY)?;
+            header.set_pixel_aspect_ratio(0, 2.0)?;
+            header.set_screen_window_center(0, &[0.5f32, -0.5])?;
+            header.set_screen_window_width(0, 4.0)
+        })?;
+
+        let ctx = exr::context::ReadContext::new(&path)?;
+        assert_eq!(ctx.data_window::<[i32; 4]>(0)?, data_window);
+        assert_eq!(ctx.display_window::<[i32; 4]>(0)?, [-8, -8, 1207, 807]);
+        assert_eq!(ctx.compression(0)?, Compression::Zip);
+        assert_eq!(ctx.lineorder(0)?, LineOrder::DecreasingY);
+        assert_eq!(ctx.pixel_aspect_ratio(0)?, 2.0);
+        assert_eq!(ctx.screen_window_center::<[f32; 2]>(0)?, [0.5, -0.5]);
+        assert_eq!(ctx.screen_window_width(0)?, 4.0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn required_attributes() -> Result<(), exr::Error> {
+        use exr::attr::{Compression, LineOrder, Storage};
+        use exr::context::{DefaultWriteMode, WriteHeaderContext};
+        use exr::part::RequiredAttributes;
+
+        let path = std::env::temp_dir().join("required_attributes.exr");
+        let mut header =
+            WriteHeaderContext::new(path, DefaultWriteMode::WriteFileDirectly)?;
+
+        let simple = header.add_part("simple", Storage::Scanline)?;
+        header.initialize_required_attributes_simple(
+            simple,
+            64,
+            32,
+            Compression::Piz,
+        )?;
+        assert_eq!(header.data_window::<[i32; 4]>(simple)?, [0, 0, 63, 31]);
+        assert_eq!(header.display_window::<[i32; 4]>(simple)?, [0, 0, 63, 31]);
+        assert_eq!(header.compression(simple)?, Compression::Piz);
+        assert_eq!(header.pixel_aspect_ratio(simple)?, 1.0);
+
+        let full = header.add_part("full", Storage::Tiled)?;
+        let attributes = RequiredAttributes {
+            data_window: [8, 8, 71, 39],
+            pixel_aspect_ratio: 2.0,
+            lineorder: LineOrder::RandomY,
+            ..RequiredAttributes::new(80, 48, Compression::Zip)
+        };
+        header.initialize_required_attributes(full, &attributes)?;
+        assert_eq!(header.data_window::<[i32; 4]>(full)?, [8, 8, 71, 39]);
+        assert_eq!(header.display_window::<[i32; 4]>(full)?, [0, 0, 79, 47]);
+        assert_eq!(header.pixel_aspect_ratio(full)?, 2.0);
+        assert_eq!(header.lineorder(full)?, LineOrder::RandomY);
+        assert_eq!(header.compression(full)?, Compression::Zip);
+
+        Ok(())
+    }
+
+    #[test]
+    fn add_channels() -> Result<(), exr::Error> {
+        use exr::attr::{ChannelDesc, Compression, PixelType, Storage};
+        use exr::context::{DefaultWriteMode, WriteHeaderContext};
+
+        let path = std::env::temp_dir().join("add_channels.exr");
+        let mut header =
+            WriteHeaderContext::new(path, DefaultWriteMode::WriteFileDirectly)?;
+        let part = header.add_part("", Storage::Scanline)?;
+        header.initialize_required_attributes_simple(
+            part,
+            64,
+            32,
+            Compression::Zip,
+        )?;
+
+        header.add_channel(part, "Z", PixelType::Float, true, 1, 1)?;
+        header.add_channels(
+            part,
+            &[
+                ChannelDesc::new("Y", PixelType::Half),
+                ChannelDesc {
+                    sampling: (2, 2),
+                    ..ChannelDesc::new("BY", PixelType::Half)
+                },
+            ],
+        )?;
+        assert!(header
+            .add_channel(part, "Z", PixelType::Half, false, 1, 1)
+            .is_err());
+
+        // channels are sorted by name
+        let channels = header.channels(part)?.descs()?;
+        assert_eq!(
+            channels,
+            vec![
+                ChannelDesc {
+                    sampling: (2, 2),
+                    ..ChannelDesc::new("BY", PixelType::Half)
+                },
+                ChannelDesc::new("Y", PixelType::Half),
+                ChannelDesc {
+                    p_linear: true,
+                    ..ChannelDesc::new("Z", PixelType::Float)
+                },
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn channel_list_builder() -> Result<(), exr::Error> {
+        use exr::attr::{ChannelDesc, ChannelListBuilder, PixelType};
+        use std::convert::TryFrom;
+
+        let mut builder = ChannelListBuilder::new();
+        builder
+            .channel("R", PixelType::Half)?
+            .channel("G", PixelType::Half)?;
+        assert!(builder.channel("R", PixelType::Float).is_err());
+        assert!(builder.channel("", PixelType::Float).is_err());
+        assert!(builder
+            .add(ChannelDesc {
+                sampling: (0, 1),
+                ..ChannelDesc::new("B", PixelType::Half)
+            })
+            .is_err());
+        assert_eq!(
+            builder
+                .channel(&"x".repeat(256), PixelType::Half)
+                .expect_err("name is too long")
+                .kind(),
+            exr::ErrorKind::NameTooLong
+        );
+        assert!(!builder.needs_long_names());
+        assert_eq!(builder.channels().len(), 2);
+
+        // copy ferris's channels, replacing alpha with depth
+        let path = std::env::temp_dir().join("channel_list_builder.exr");
+        write_ferris_with(&path, |header| {
+            let mut builder =
+                ChannelListBuilder::try_from(header.channels(0)?)?;
+            assert!(builder.remove("A").is_some());
+            builder.channel("Z", PixelType::Float)?;
+            header.set_channels(0, &builder)?;
+
+            let channels = header.channels(0)?.descs()?;
+            let names = channels.iter().map(|c| c.name.as_str());
+            assert_eq!(names.collect::<Vec<_>>(), ["B", "G", "R", "Z"]);
+
+            // put the channels back so the image can be written
+            builder.remove("Z");
+            builder.channel("A", PixelType::Half)?;
+            header.set_channels(0, &builder)
+        })?;
+
+        Ok(())
+    }
+
+    /// Write a copy of ferris.exr to `path`, calling `f` to add to the header
+    /// before it is written
+    ///
+    fn write_ferris_with<F>(
+        path: &std::path::Path,
+        f: F,
+    ) -> Result<(), exr::Error>
+    where
+        F: FnOnce(
+            &mut exr::context::WriteHeaderContext,
+        ) -> Result<(), exr::Error>,
+    {
+        use exr::attr::PixelType;
+        use exr::context::{DefaultWriteMode, ReadContext, WriteHeaderContext};
+        use exr::write::ImageDesc;
+        use imath_traits::f16;
+
+        let path_ferris = std::path::Path::new(
+            &std::env::var("CARGO_MANIFEST_DIR")
+                .expect("CARGO_MANIFEST_DIR not set"),
+        )
+        .join("images")
+        .join("ferris.exr");
+        let src = ReadContext::new(&path_ferris)?;
+        let channels = ["A", "B", "G", "R"];
+        let image = src.read_image::<f16>(0, &channels)?;
+
+        let mut header =
+            WriteHeaderContext::new(path, DefaultWriteMode::WriteFileDirectly)?;
+        header.add_part_copy(&src, 0, None)?;
+        f(&mut header)?;
+
+        let mut ctx = header.write_header()?;
+        let bytes = image
+            .pixels
+            .iter()
+            .flat_map(|p| p.to_le_bytes())
+            .collect::<Vec<_>>();
+        ctx.write_image(
+            0,
+            &ImageDesc::new(
+                image.width,
+                image.height,
+                &channels,
+                PixelType::Half,
+            ),
+            &bytes,
+        )?;
+        ctx.close()
+    }
+}
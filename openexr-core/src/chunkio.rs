@@ -1,11 +1,14 @@
 use crate::attr::{
-    Attribute, AttributeRead, Compression, LevelMode, LineOrder, Storage,
+    Attribute, AttributeRead, Compression, LevelMode, LineOrder, PixelType,
+    Storage,
 };
 use crate::context::*;
 use crate::error::Error;
+use crate::validate::{part_levels, ChunkLocation};
 use openexr_core_sys as sys;
-use std::convert::TryInto;
+use std::convert::TryFrom;
 use std::ffi::{CStr, CString};
+use std::mem::MaybeUninit;
 use std::os::raw::c_void;
 use std::path::Path;
 
@@ -47,11 +50,12 @@ impl ReadContext {
         unsafe {
             sys::exr_read_scanline_chunk_info(
                 self.inner,
-                part_index.try_into().unwrap(),
+                c_part_index(part_index)?,
                 y,
                 &mut result as *mut ChunkInfo as *mut sys::exr_chunk_info_t,
             )
-            .ok(result)
+            .ok_in(self.inner, result)
+            .map_err(|e| e.with_part(part_index))
         }
     }
 
@@ -67,14 +71,15 @@ impl ReadContext {
         unsafe {
             sys::exr_read_tile_chunk_info(
                 self.inner,
-                part_index.try_into().unwrap(),
+                c_part_index(part_index)?,
                 tile_x,
                 tile_y,
                 level_x,
                 level_y,
                 &mut result as *mut ChunkInfo as *mut sys::exr_chunk_info_t,
             )
-            .ok(result)
+            .ok_in(self.inner, result)
+            .map_err(|e| e.with_part(part_index))
         }
     }
 
@@ -91,10 +96,919 @@ impl ReadContext {
     ) -> Result<()> {
         sys::exr_read_chunk(
             self.inner,
-            part_index.try_into().unwrap(),
+            c_part_index(part_index)?,
             chunk_info as *const ChunkInfo as *const sys::exr_chunk_info_t,
             packed_data.as_mut_ptr() as *mut c_void,
         )
-        .ok(())
+        .ok_in(self.inner, ())
+        .map_err(|e| {
+            e.with_part(part_index).with_chunk(chunk_info.idx as usize)
+        })
+    }
+
+    /// Read the packed data block for the given chunk into a newly allocated
+    /// buffer of exactly `chunk_info.packed_size` bytes
+    ///
+    /// The buffer is not zeroed before the chunk is read into it.
+    ///
+    /// # Errors
+    /// * `[Error::OutOfMemory]` - If the chunk is too large to allocate
+    /// * `[Error::ReadIo]` - If the chunk could not be read
+    ///
+    pub fn read_chunk_vec(
+        &self,
+        part_index: usize,
+        chunk_info: &ChunkInfo,
+    ) -> Result<Vec<u8>> {
+        let len = usize::try_from(chunk_info.packed_size)
+            .map_err(|_| Error::OutOfMemory)?;
+        let mut packed_data = Vec::new();
+        packed_data
+            .try_reserve_exact(len)
+            .map_err(|_| Error::OutOfMemory)?;
+
+        self.read_chunk_uninit(
+            part_index,
+            chunk_info,
+            &mut packed_data.spare_capacity_mut()[..len],
+        )?;
+        // Safety: the first len bytes have just been read into
+        unsafe { packed_data.set_len(len) };
+        Ok(packed_data)
+    }
+
+    /// Read the packed data block for the given chunk into the start of a
+    /// buffer that need not be initialized, returning the `packed_size`
+    /// bytes that were read
+    ///
+    /// This is the safe alternative to [`ReadContext::read_chunk`] for hot
+    /// loops that would rather not zero each buffer before reading into it.
+    ///
+    /// # Errors
+    /// * `[Error::InvalidArgument]` - If `packed_data` is smaller than the
+    /// chunk
+    /// * `[Error::ReadIo]` - If the chunk could not be read
+    ///
+    pub fn read_chunk_uninit<'a>(
+        &self,
+        part_index: usize,
+        chunk_info: &ChunkInfo,
+        packed_data: &'a mut [MaybeUninit<u8>],
+    ) -> Result<&'a mut [u8]> {
+        if (packed_data.len() as u64) < chunk_info.packed_size {
+            return Err(Error::InvalidArgument);
+        }
+        let len = chunk_info.packed_size as usize;
+
+        // Safety: the buffer has been checked against the size the C core
+        // will write to it, and the C core fails rather than leaving any of
+        // it unread
+        unsafe {
+            sys::exr_read_chunk(
+                self.inner,
+                c_part_index(part_index)?,
+                chunk_info as *const ChunkInfo as *const sys::exr_chunk_info_t,
+                packed_data.as_mut_ptr() as *mut c_void,
+            )
+            .ok_in(self.inner, ())
+            .map_err(|e| {
+                e.with_part(part_index).with_chunk(chunk_info.idx as usize)
+            })?;
+            Ok(std::slice::from_raw_parts_mut(
+                packed_data.as_mut_ptr() as *mut u8,
+                len,
+            ))
+        }
+    }
+
+    /// Read the packed sample data and packed sample count table of the given
+    /// deep chunk, either of which may be skipped by passing `None`
+    ///
+    /// `packed_data` receives `chunk_info.packed_size` bytes, and
+    /// `sample_counts` receives `chunk_info.sample_count_table_size` bytes.
+    /// Both are still compressed, exactly as they are stored in the file.
+    ///
+    /// # Errors
+    /// * `[Error::InvalidArgument]` - If a buffer is too small for its part of
+    /// the chunk, or both are `None`
+    /// * `[Error::ReadIo]` - If the chunk could not be read
+    ///
+    pub fn read_deep_chunk(
+        &self,
+        part_index: usize,
+        chunk_info: &ChunkInfo,
+        packed_data: Option<&mut [u8]>,
+        sample_counts: Option<&mut [u8]>,
+    ) -> Result<()> {
+        let packed_ptr = match packed_data {
+            Some(p) if p.len() as u64 >= chunk_info.packed_size => {
+                p.as_mut_ptr() as *mut c_void
+            }
+            Some(_) => return Err(Error::InvalidArgument),
+            None => std::ptr::null_mut(),
+        };
+        let counts_ptr = match sample_counts {
+            Some(c) if c.len() as u64 >= chunk_info.sample_count_table_size => {
+                c.as_mut_ptr() as *mut c_void
+            }
+            Some(_) => return Err(Error::InvalidArgument),
+            None => std::ptr::null_mut(),
+        };
+        if packed_ptr.is_null() && counts_ptr.is_null() {
+            return Err(Error::InvalidArgument);
+        }
+
+        // Safety: the buffers have been checked against the sizes the C core
+        // will write to them
+        unsafe {
+            sys::exr_read_deep_chunk(
+                self.inner,
+                c_part_index(part_index)?,
+                chunk_info as *const ChunkInfo as *const sys::exr_chunk_info_t,
+                packed_ptr,
+                counts_ptr,
+            )
+            .ok_in(self.inner, ())
+            .map_err(|e| {
+                e.with_part(part_index).with_chunk(chunk_info.idx as usize)
+            })
+        }
+    }
+
+    /// Check the decoded sample count table of a deep chunk against the
+    /// chunk, returning the total number of samples in the chunk
+    ///
+    /// `table` holds an entry for each pixel of the chunk a line at a time,
+    /// as given by the `sample_count_table` of a
+    /// [`DecodePipeline`](crate::decode::DecodePipeline). Each entry is a
+    /// running total, as stored in the file, unless `individual` is set, in
+    /// which case it is the number of samples in the pixel.
+    ///
+    /// The counts must not be negative or decrease, the stored table must be
+    /// no larger than the decoded one, or the same size if the part is not
+    /// compressed, and the chunk's unpacked size must match the total number
+    /// of samples.
+    ///
+    /// # Errors
+    /// * `[Error::CorruptChunk]` - If the table is inconsistent with the chunk
+    /// * `[Error::InvalidArgument]` - If the part does not hold deep data
+    ///
+    pub fn validate_sample_counts(
+        &self,
+        part_index: usize,
+        chunk_info: &ChunkInfo,
+        table: &[i32],
+        individual: bool,
+    ) -> Result<usize> {
+        if !matches!(
+            self.storage(part_index)?,
+            Storage::DeepScanline | Storage::DeepTiled
+        ) {
+            return Err(Error::InvalidArgument);
+        }
+
+        let corrupt = || {
+            Error::CorruptChunk
+                .with_part(part_index)
+                .with_chunk(chunk_info.idx as usize)
+        };
+
+        let num_pixels = chunk_info.width.max(0) as usize
+            * chunk_info.height.max(0) as usize;
+        if table.len() != num_pixels {
+            return Err(corrupt());
+        }
+
+        let mut total = 0u64;
+        for &count in table {
+            let count = u64::try_from(count).map_err(|_| corrupt())?;
+            total = if individual {
+                total + count
+            } else if count < total {
+                return Err(corrupt());
+            } else {
+                count
+            };
+        }
+
+        let table_bytes = (num_pixels * std::mem::size_of::<i32>()) as u64;
+        if chunk_info.sample_count_table_size > table_bytes
+            || (self.compression(part_index)? == Compression::None
+                && chunk_info.sample_count_table_size != table_bytes)
+        {
+            return Err(corrupt());
+        }
+
+        let sample_bytes = self
+            .channels(part_index)?
+            .iter()
+            .map(|c| match c.pixel_type() {
+                Ok(PixelType::Half) => Ok(2),
+                Ok(PixelType::Float) | Ok(PixelType::Uint) => Ok(4),
+                Err(e) => Err(e),
+            })
+            .sum::<Result<u64>>()?;
+        if total.checked_mul(sample_bytes) != Some(chunk_info.unpacked_size) {
+            return Err(corrupt());
+        }
+
+        Ok(total as usize)
+    }
+}
+
+/// Where a chunk's data is stored in the file, as listed in a part's chunk
+/// table
+///
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ChunkEntry {
+    /// The index of the chunk in the part's chunk table
+    pub index: usize,
+    pub location: ChunkLocation,
+    /// The offset of the chunk's packed data from the start of the file
+    pub offset: u64,
+    /// The size of the chunk's packed data in bytes
+    pub packed_size: u64,
+}
+
+impl ReadContext {
+    /// Get where the data of every chunk of a part is stored, in the order of
+    /// the part's chunk table, e.g. to analyze the layout of the file or plan
+    /// reads
+    ///
+    /// For deep parts, `offset` and `packed_size` describe the sample data,
+    /// which follows the chunk's sample count table.
+    ///
+    /// # Errors
+    /// * `[Error::ArgumentOutOfRange]` - If the part does not exist
+    /// * `[Error::BadChunkLeader]` - If a chunk's offset or leader is invalid.
+    /// Use [`validate`](ReadContext::validate) to find every such chunk
+    ///
+    pub fn chunk_table(&self, part_index: usize) -> Result<Vec<ChunkEntry>> {
+        let mut table = self
+            .chunk_locations(part_index)?
+            .into_iter()
+            .map(|location| {
+                let chunk_info = match location {
+                    ChunkLocation::Scanline { y } => {
+                        self.read_scanline_chunk_info(part_index, y)?
+                    }
+                    ChunkLocation::Tile {
+                        x,
+                        y,
+                        level_x,
+                        level_y,
+                    } => self.read_tile_chunk_info(
+                        part_index, x, y, level_x, level_y,
+                    )?,
+                };
+                Ok(ChunkEntry {
+                    index: chunk_info.idx as usize,
+                    location,
+                    offset: chunk_info.data_offset,
+                    packed_size: chunk_info.packed_size,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        table.sort_by_key(|entry| entry.index);
+        Ok(table)
+    }
+}
+
+impl<S: ContextState> Context<S> {
+    /// Get the index in the part's chunk table of the chunk at `location`
+    ///
+    /// A scanline location may be any line in the chunk, not just its first.
+    ///
+    /// # Errors
+    /// * `[Error::ArgumentOutOfRange]` - If the location is outside the part,
+    /// or is the wrong kind for the part's storage
+    ///
+    pub fn chunk_index(
+        &self,
+        part_index: usize,
+        location: ChunkLocation,
+    ) -> Result<usize> {
+        let tiled = self.tile_levels(part_index).is_ok();
+        match location {
+            ChunkLocation::Scanline { y } if !tiled => {
+                let dw = self.data_window::<[i32; 4]>(part_index)?;
+                if y < dw[1] || y > dw[3] {
+                    return Err(Error::ArgumentOutOfRange);
+                }
+                let lines = self.scanlines_per_chunk(part_index)?.max(1);
+                Ok((y - dw[1]) as usize / lines)
+            }
+            ChunkLocation::Tile {
+                x,
+                y,
+                level_x,
+                level_y,
+            } if tiled => {
+                let mut base = 0;
+                for (lx, ly) in part_levels(self, part_index)? {
+                    let (tiles_x, tiles_y) =
+                        self.tile_counts(part_index, lx, ly)?;
+                    if (lx as i32, ly as i32) == (level_x, level_y) {
+                        if x < 0
+                            || y < 0
+                            || x as usize >= tiles_x
+                            || y as usize >= tiles_y
+                        {
+                            return Err(Error::ArgumentOutOfRange);
+                        }
+                        return Ok(base + y as usize * tiles_x + x as usize);
+                    }
+                    base += tiles_x * tiles_y;
+                }
+                Err(Error::ArgumentOutOfRange)
+            }
+            _ => Err(Error::ArgumentOutOfRange),
+        }
+    }
+
+    /// Get the location of the chunk at `chunk_index` in the part's chunk
+    /// table, which for scanline parts is the first line of the chunk
+    ///
+    /// # Errors
+    /// * `[Error::ArgumentOutOfRange]` - If the part has no such chunk
+    ///
+    pub fn chunk_location(
+        &self,
+        part_index: usize,
+        chunk_index: usize,
+    ) -> Result<ChunkLocation> {
+        if self.tile_levels(part_index).is_err() {
+            let dw = self.data_window::<[i32; 4]>(part_index)?;
+            let lines = self.scanlines_per_chunk(part_index)?.max(1);
+            let y = i64::from(dw[1]) + (chunk_index * lines) as i64;
+            return if y <= i64::from(dw[3]) {
+                Ok(ChunkLocation::Scanline { y: y as i32 })
+            } else {
+                Err(Error::ArgumentOutOfRange)
+            };
+        }
+
+        let mut index = chunk_index;
+        for (level_x, level_y) in part_levels(self, part_index)? {
+            let (tiles_x, tiles_y) =
+                self.tile_counts(part_index, level_x, level_y)?;
+            let count = tiles_x * tiles_y;
+            if index < count {
+                return Ok(ChunkLocation::Tile {
+                    x: (index % tiles_x) as i32,
+                    y: (index / tiles_x) as i32,
+                    level_x: level_x as i32,
+                    level_y: level_y as i32,
+                });
+            }
+            index -= count;
+        }
+        Err(Error::ArgumentOutOfRange)
+    }
+
+    /// Get the chunks of level (`level_x`, `level_y`) that hold any of the
+    /// pixels in `bounds`, inclusive of the maximum, e.g. to split a region
+    /// between threads
+    ///
+    /// `bounds` is in the same coordinates as the data window, and parts of
+    /// it outside the data window or level are ignored. The level must be
+    /// (0, 0) for scanline parts. Chunks are returned in the order of the
+    /// part's chunk table.
+    ///
+    /// # Errors
+    /// * `[Error::ArgumentOutOfRange]` - If the part has no such level
+    ///
+    pub fn chunks_intersecting<B: Bound2<i32>>(
+        &self,
+        part_index: usize,
+        level_x: usize,
+        level_y: usize,
+        bounds: &B,
+    ) -> Result<Vec<ChunkLocation>> {
+        let dw = self.data_window::<[i32; 4]>(part_index)?;
+        let b = bounds.as_slice();
+        let mut chunks = Vec::new();
+
+        if self.tile_levels(part_index).is_err() {
+            if (level_x, level_y) != (0, 0) {
+                return Err(Error::ArgumentOutOfRange);
+            }
+            let min_y = b[1].max(dw[1]);
+            let max_y = b[3].min(dw[3]);
+            if b[0] > dw[2] || b[2] < dw[0] || min_y > max_y {
+                return Ok(chunks);
+            }
+
+            let lines = self.scanlines_per_chunk(part_index)?.max(1) as i32;
+            let first = dw[1] + (min_y - dw[1]) / lines * lines;
+            for y in (first..=max_y).step_by(lines as usize) {
+                chunks.push(ChunkLocation::Scanline { y });
+            }
+            return Ok(chunks);
+        }
+
+        if !part_levels(self, part_index)?.contains(&(level_x, level_y)) {
+            return Err(Error::ArgumentOutOfRange);
+        }
+        let (tiles_x, tiles_y) =
+            self.tile_counts(part_index, level_x, level_y)?;
+        let (tile_width, tile_height) =
+            self.tile_sizes(part_index, level_x, level_y)?;
+        // tiles are counted from the top-left of the data window
+        let tile_range =
+            |min: i32, max: i32, origin: i32, size: usize, n: usize| {
+                let first = (i64::from(min) - i64::from(origin)).max(0);
+                let last = i64::from(max) - i64::from(origin);
+                let size = size as i64;
+                (first / size, last.div_euclid(size).min(n as i64 - 1))
+            };
+        let (first_x, last_x) =
+            tile_range(b[0], b[2], dw[0], tile_width, tiles_x);
+        let (first_y, last_y) =
+            tile_range(b[1], b[3], dw[1], tile_height, tiles_y);
+
+        for y in first_y..=last_y {
+            for x in first_x..=last_x {
+                chunks.push(ChunkLocation::Tile {
+                    x: x as i32,
+                    y: y as i32,
+                    level_x: level_x as i32,
+                    level_y: level_y as i32,
+                });
+            }
+        }
+        Ok(chunks)
+    }
+}
+
+impl WriteContext {
+    /// Get the chunk info for the scanline chunk containing line `y`, ready to
+    /// initialize an [`EncodePipeline`](crate::encode::EncodePipeline) with
+    ///
+    pub fn write_scanline_chunk_info(
+        &self,
+        part_index: usize,
+        y: i32,
+    ) -> Result<ChunkInfo> {
+        let mut result = ChunkInfo::default();
+        unsafe {
+            sys::exr_write_scanline_chunk_info(
+                self.inner,
+                c_part_index(part_index)?,
+                y,
+                &mut result as *mut ChunkInfo as *mut sys::exr_chunk_info_t,
+            )
+            .ok_in(self.inner, result)
+            .map_err(|e| e.with_part(part_index))
+        }
+    }
+
+    /// Get the chunk info for the given tile, ready to initialize an
+    /// [`EncodePipeline`](crate::encode::EncodePipeline) with
+    ///
+    pub fn write_tile_chunk_info(
+        &self,
+        part_index: usize,
+        tile_x: i32,
+        tile_y: i32,
+        level_x: i32,
+        level_y: i32,
+    ) -> Result<ChunkInfo> {
+        let mut result = ChunkInfo::default();
+        unsafe {
+            sys::exr_write_tile_chunk_info(
+                self.inner,
+                c_part_index(part_index)?,
+                tile_x,
+                tile_y,
+                level_x,
+                level_y,
+                &mut result as *mut ChunkInfo as *mut sys::exr_chunk_info_t,
+            )
+            .ok_in(self.inner, result)
+            .map_err(|e| e.with_part(part_index))
+        }
+    }
+
+    /// Write an already packed and compressed scanline chunk starting at line
+    /// `y`
+    ///
+    /// # Errors
+    /// * `[Error::UseScanDeepWrite]` - If the part is a deep scanline part
+    /// * `[Error::IncorrectPart]` - If a previous part has not yet been
+    /// completely written
+    /// * `[Error::IncorrectChunk]` - If `y` is not the start of a chunk that
+    /// can be written at this point
+    ///
+    pub fn write_scanline_chunk(
+        &mut self,
+        part_index: usize,
+        y: i32,
+        packed_data: &[u8],
+    ) -> Result<()> {
+        unsafe {
+            sys::exr_write_scanline_chunk(
+                self.inner,
+                c_part_index(part_index)?,
+                y,
+                packed_data.as_ptr() as *const c_void,
+                packed_data.len() as u64,
+            )
+            .ok_in(self.inner, ())
+            .map_err(|e| e.with_part(part_index))
+        }
+    }
+
+    /// Write an already packed and compressed deep scanline chunk starting at
+    /// line `y`
+    ///
+    /// `packed_data` holds the packed sample data, which is `unpacked_size`
+    /// bytes once decompressed, and `sample_counts` holds the packed sample
+    /// count table for the chunk.
+    ///
+    /// # Errors
+    /// * `[Error::InvalidSampleData]` - If `sample_counts` is empty or larger
+    /// than an uncompressed table for the chunk would be
+    /// * `[Error::InvalidArgument]` - If `packed_data` is larger than
+    /// `unpacked_size`
+    /// * `[Error::UseScanNonDeepWrite]` - If the part is not a deep part
+    /// * `[Error::IncorrectChunk]` - If `y` is not the start of a chunk that
+    /// can be written at this point
+    ///
+    pub fn write_deep_scanline_chunk(
+        &mut self,
+        part_index: usize,
+        y: i32,
+        packed_data: &[u8],
+        unpacked_size: u64,
+        sample_counts: &[u8],
+    ) -> Result<()> {
+        let chunk_info = self.write_scanline_chunk_info(part_index, y)?;
+        validate_deep_sizes(
+            &chunk_info,
+            packed_data,
+            unpacked_size,
+            sample_counts,
+        )?;
+
+        unsafe {
+            sys::exr_write_deep_scanline_chunk(
+                self.inner,
+                c_part_index(part_index)?,
+                y,
+                packed_data.as_ptr() as *const c_void,
+                packed_data.len() as u64,
+                unpacked_size,
+                sample_counts.as_ptr() as *const c_void,
+                sample_counts.len() as u64,
+            )
+            .ok_in(self.inner, ())
+            .map_err(|e| e.with_part(part_index))
+        }
+    }
+
+    /// Write an already packed and compressed tile chunk
+    ///
+    /// # Errors
+    /// * `[Error::UseTileDeepWrite]` - If the part is a deep tiled part
+    /// * `[Error::TileScanMixedApi]` - If the part is not tiled
+    /// * `[Error::IncorrectPart]` - If a previous part has not yet been
+    /// completely written
+    /// * `[Error::IncorrectChunk]` - If the tile cannot be written at this
+    /// point
+    ///
+    pub fn write_tile_chunk(
+        &mut self,
+        part_index: usize,
+        tile_x: i32,
+        tile_y: i32,
+        level_x: i32,
+        level_y: i32,
+        packed_data: &[u8],
+    ) -> Result<()> {
+        unsafe {
+            sys::exr_write_tile_chunk(
+                self.inner,
+                c_part_index(part_index)?,
+                tile_x,
+                tile_y,
+                level_x,
+                level_y,
+                packed_data.as_ptr() as *const c_void,
+                packed_data.len() as u64,
+            )
+            .ok_in(self.inner, ())
+            .map_err(|e| e.with_part(part_index))
+        }
+    }
+
+    /// Write an already packed and compressed deep tile chunk
+    ///
+    /// `packed_data` holds the packed sample data, which is `unpacked_size`
+    /// bytes once decompressed, and `sample_counts` holds the packed sample
+    /// count table for the tile.
+    ///
+    /// # Errors
+    /// * `[Error::InvalidSampleData]` - If `sample_counts` is empty or larger
+    /// than an uncompressed table for the tile would be
+    /// * `[Error::InvalidArgument]` - If `packed_data` is larger than
+    /// `unpacked_size`
+    /// * `[Error::UseTileNonDeepWrite]` - If the part is not a deep part
+    /// * `[Error::IncorrectChunk]` - If the tile cannot be written at this
+    /// point
+    ///
+    #[allow(clippy::too_many_arguments)]
+    pub fn write_deep_tile_chunk(
+        &mut self,
+        part_index: usize,
+        tile_x: i32,
+        tile_y: i32,
+        level_x: i32,
+        level_y: i32,
+        packed_data: &[u8],
+        unpacked_size: u64,
+        sample_counts: &[u8],
+    ) -> Result<()> {
+        let chunk_info = self.write_tile_chunk_info(
+            part_index, tile_x, tile_y, level_x, level_y,
+        )?;
+        validate_deep_sizes(
+            &chunk_info,
+            packed_data,
+            unpacked_size,
+            sample_counts,
+        )?;
+
+        unsafe {
+            sys::exr_write_deep_tile_chunk(
+                self.inner,
+                c_part_index(part_index)?,
+                tile_x,
+                tile_y,
+                level_x,
+                level_y,
+                packed_data.as_ptr() as *const c_void,
+                packed_data.len() as u64,
+                unpacked_size,
+                sample_counts.as_ptr() as *const c_void,
+                sample_counts.len() as u64,
+            )
+            .ok_in(self.inner, ())
+            .map_err(|e| e.with_part(part_index))
+        }
+    }
+}
+
+/// Check the buffers passed to a deep chunk write against the chunk they are
+/// for. Compressed data is never stored larger than its uncompressed form, so
+/// the uncompressed sizes bound the packed buffers.
+///
+fn validate_deep_sizes(
+    chunk_info: &ChunkInfo,
+    packed_data: &[u8],
+    unpacked_size: u64,
+    sample_counts: &[u8],
+) -> Result<()> {
+    let table_size = chunk_info.width as u64
+        * chunk_info.height as u64
+        * std::mem::size_of::<i32>() as u64;
+
+    if sample_counts.is_empty() || sample_counts.len() as u64 > table_size {
+        return Err(Error::InvalidSampleData);
+    }
+
+    if packed_data.len() as u64 > unpacked_size {
+        return Err(Error::InvalidArgument);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate as exr;
+    use std::mem::MaybeUninit;
+    use std::path::Path;
+
+    #[test]
+    fn chunk_table() -> Result<(), exr::Error> {
+        let path_ferris = Path::new(
+            &std::env::var("CARGO_MANIFEST_DIR")
+                .expect("CARGO_MANIFEST_DIR not set"),
+        )
+        .join("images")
+        .join("ferris.exr");
+
+        let ctx = exr::context::ReadContext::new(&path_ferris)?;
+        let table = ctx.chunk_table(0)?;
+        assert_eq!(table.len(), ctx.chunk_count(0)?);
+
+        let file_size = std::fs::metadata(&path_ferris).unwrap().len();
+        for (i, entry) in table.iter().enumerate() {
+            assert_eq!(entry.index, i);
+            assert!(entry.packed_size > 0);
+            assert!(entry.offset + entry.packed_size <= file_size);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn read_deep_chunk() -> Result<(), exr::Error> {
+        use exr::validate::ChunkLocation;
+
+        let path_deep = Path::new(
+            &std::env::var("CARGO_MANIFEST_DIR")
+                .expect("CARGO_MANIFEST_DIR not set"),
+        )
+        .join("images")
+        .join("deep_plane.exr");
+
+        let ctx = exr::context::ReadContext::new(&path_deep)?;
+        let entry = ctx.chunk_table(0)?[0];
+        let chunk_info = match entry.location {
+            ChunkLocation::Scanline { y } => {
+                ctx.read_scanline_chunk_info(0, y)?
+            }
+            ChunkLocation::Tile {
+                x,
+                y,
+                level_x,
+                level_y,
+            } => ctx.read_tile_chunk_info(0, x, y, level_x, level_y)?,
+        };
+        assert!(chunk_info.sample_count_table_size > 0);
+
+        let mut packed_data = vec![0u8; chunk_info.packed_size as usize];
+        let mut sample_counts =
+            vec![0u8; chunk_info.sample_count_table_size as usize];
+        ctx.read_deep_chunk(
+            0,
+            &chunk_info,
+            Some(&mut packed_data),
+            Some(&mut sample_counts),
+        )?;
+
+        // the sample counts can be read on their own
+        let mut counts_only = vec![0u8; sample_counts.len()];
+        ctx.read_deep_chunk(0, &chunk_info, None, Some(&mut counts_only))?;
+        assert_eq!(counts_only, sample_counts);
+
+        assert!(ctx
+            .read_deep_chunk(0, &chunk_info, Some(&mut []), None)
+            .is_err());
+        assert!(ctx.read_deep_chunk(0, &chunk_info, None, None).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn chunk_index() -> Result<(), exr::Error> {
+        use exr::validate::ChunkLocation;
+
+        let images = Path::new(
+            &std::env::var("CARGO_MANIFEST_DIR")
+                .expect("CARGO_MANIFEST_DIR not set"),
+        )
+        .join("images");
+
+        for name in &["ferris.exr", "ferris-tiled.exr"] {
+            let ctx = exr::context::ReadContext::new(images.join(name))?;
+            for entry in ctx.chunk_table(0)? {
+                assert_eq!(ctx.chunk_index(0, entry.location)?, entry.index);
+                assert_eq!(ctx.chunk_location(0, entry.index)?, entry.location);
+            }
+            let count = ctx.chunk_count(0)?;
+            assert!(ctx.chunk_location(0, count).is_err());
+
+            // every chunk intersects the whole data window
+            let dw = ctx.data_window::<[i32; 4]>(0)?;
+            assert_eq!(ctx.chunks_intersecting(0, 0, 0, &dw)?.len(), count);
+        }
+
+        // a line in the middle of a chunk maps to that chunk
+        let ctx = exr::context::ReadContext::new(images.join("ferris.exr"))?;
+        let dw = ctx.data_window::<[i32; 4]>(0)?;
+        let lines = ctx.scanlines_per_chunk(0)? as i32;
+        let location = ChunkLocation::Scanline {
+            y: dw[1] + lines + 1,
+        };
+        assert_eq!(ctx.chunk_index(0, location)?, 1);
+        assert_eq!(
+            ctx.chunks_intersecting(
+                0,
+                0,
+                0,
+                &[dw[0], dw[1] + lines + 1, dw[0], dw[1] + lines + 2]
+            )?,
+            vec![ChunkLocation::Scanline { y: dw[1] + lines }]
+        );
+        assert!(ctx
+            .chunk_index(
+                0,
+                ChunkLocation::Tile {
+                    x: 0,
+                    y: 0,
+                    level_x: 0,
+                    level_y: 0
+                }
+            )
+            .is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn validate_sample_counts() -> Result<(), exr::Error> {
+        use exr::decode::DeepSamples;
+        use exr::validate::ChunkLocation;
+
+        let path_deep = Path::new(
+            &std::env::var("CARGO_MANIFEST_DIR")
+                .expect("CARGO_MANIFEST_DIR not set"),
+        )
+        .join("images")
+        .join("deep_plane.exr");
+
+        let ctx = exr::context::ReadContext::new(&path_deep)?;
+        let entry = ctx.chunk_table(0)?[0];
+        let chunk_info = match entry.location {
+            ChunkLocation::Scanline { y } => {
+                ctx.read_scanline_chunk_info(0, y)?
+            }
+            ChunkLocation::Tile {
+                x,
+                y,
+                level_x,
+                level_y,
+            } => ctx.read_tile_chunk_info(0, x, y, level_x, level_y)?,
+        };
+
+        let mut samples = DeepSamples::<f32>::new();
+        let pipeline = ctx
+            .decoding_initialize(0, &chunk_info)?
+            .decode_deep(&["Z"], &mut samples)?;
+        let mut counts = pipeline.sample_count_table().unwrap().to_vec();
+        let total = samples.sample_offsets[counts.len()];
+        assert_eq!(
+            ctx.validate_sample_counts(0, &chunk_info, &counts, true)?,
+            total
+        );
+
+        // a running total can't go down
+        let running = samples.sample_offsets[1..]
+            .iter()
+            .map(|&o| o as i32)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            ctx.validate_sample_counts(0, &chunk_info, &running, false)?,
+            total
+        );
+        counts[0] += 1;
+        let err = ctx
+            .validate_sample_counts(0, &chunk_info, &counts, true)
+            .expect_err("the counts don't match the unpacked size");
+        assert_eq!(err.kind(), exr::ErrorKind::CorruptChunk);
+        counts[0] = -1;
+        assert!(ctx
+            .validate_sample_counts(0, &chunk_info, &counts, true)
+            .is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn read_chunk_vec() -> Result<(), exr::Error> {
+        let path_ferris = Path::new(
+            &std::env::var("CARGO_MANIFEST_DIR")
+                .expect("CARGO_MANIFEST_DIR not set"),
+        )
+        .join("images")
+        .join("ferris.exr");
+
+        let ctx = exr::context::ReadContext::new(&path_ferris)?;
+        let dw = ctx.data_window::<[i32; 4]>(0)?;
+        let chunk_info = ctx.read_scanline_chunk_info(0, dw[1])?;
+        let mut expected = vec![0u8; chunk_info.packed_size as usize];
+        unsafe { ctx.read_chunk(0, &chunk_info, &mut expected)? };
+
+        let packed_data = ctx.read_chunk_vec(0, &chunk_info)?;
+        assert_eq!(packed_data, expected);
+
+        let mut buffer = vec![MaybeUninit::uninit(); expected.len() + 8];
+        let read = ctx.read_chunk_uninit(0, &chunk_info, &mut buffer)?;
+        assert_eq!(read, &expected[..]);
+
+        let err = ctx
+            .read_chunk_uninit(
+                0,
+                &chunk_info,
+                &mut buffer[..expected.len() - 1],
+            )
+            .expect_err("read into a buffer smaller than the chunk");
+        assert_eq!(err.kind(), exr::ErrorKind::InvalidArgument);
+
+        Ok(())
     }
 }
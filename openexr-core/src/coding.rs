@@ -8,14 +8,54 @@ use crate::error::Error;
 use openexr_core_sys as sys;
 use std::convert::TryInto;
 use std::ffi::{CStr, CString};
+use std::ops::Range;
 use std::path::Path;
 
-use imath_traits::{Bound2, Vec2};
+use imath_traits::{f16, Bound2, Vec2};
 
 type Result<T, E = Error> = std::result::Result<T, E>;
 
+/// Plain-old-data types that may be decoded into, i.e. every bit pattern is
+/// a valid value
+///
+/// # Safety
+/// Implementors must be `Copy`, have no padding and be valid for any bit
+/// pattern
+///
+pub unsafe trait Pod: Copy + 'static {}
+
+unsafe impl Pod for u8 {}
+unsafe impl Pod for u16 {}
+unsafe impl Pod for u32 {}
+unsafe impl Pod for f16 {}
+unsafe impl Pod for f32 {}
+
+/// Types that channel data can be decoded to directly
+///
+pub trait ChannelElement: Pod + Default {
+    /// The pixel type the element corresponds to
+    const PIXEL_TYPE: PixelType;
+}
+
+impl ChannelElement for f16 {
+    const PIXEL_TYPE: PixelType = PixelType::Half;
+}
+
+impl ChannelElement for f32 {
+    const PIXEL_TYPE: PixelType = PixelType::Float;
+}
+
+impl ChannelElement for u32 {
+    const PIXEL_TYPE: PixelType = PixelType::Uint;
+}
+
 #[repr(transparent)]
-pub struct ChannelInfo(sys::exr_coding_channel_info_t);
+pub struct ChannelInfo(pub(crate) sys::exr_coding_channel_info_t);
+
+// Safety: the channel's name is owned by its context, and its decode or
+// encode pointer is only used while the pipeline holding it is run
+unsafe impl Send for ChannelInfo {}
+unsafe impl Sync for ChannelInfo {}
 
 impl ChannelInfo {
     /// Name of the channel
@@ -59,12 +99,12 @@ impl ChannelInfo {
         self.0.bytes_per_element as usize
     }
 
-    pub fn data_type(&self) -> PixelType {
+    pub fn data_type(&self) -> Result<PixelType> {
         match self.0.data_type {
-            0 => PixelType::Uint,
-            1 => PixelType::Half,
-            2 => PixelType::Float,
-            _ => panic!("Unhandled value for pixel type"),
+            0 => Ok(PixelType::Uint),
+            1 => Ok(PixelType::Half),
+            2 => Ok(PixelType::Float),
+            _ => Err(Error::Unknown),
         }
     }
 
@@ -85,12 +125,12 @@ impl ChannelInfo {
             value.try_into().expect("value is not representable");
     }
 
-    pub fn user_data_type(&self) -> PixelType {
+    pub fn user_data_type(&self) -> Result<PixelType> {
         match self.0.user_data_type {
-            0 => PixelType::Uint,
-            1 => PixelType::Half,
-            2 => PixelType::Float,
-            _ => panic!("Unhandled value for pixel type"),
+            0 => Ok(PixelType::Uint),
+            1 => Ok(PixelType::Half),
+            2 => Ok(PixelType::Float),
+            _ => Err(Error::Unknown),
         }
     }
 
@@ -133,4 +173,134 @@ impl ChannelInfo {
         self.0.__bindgen_anon_1.decode_to_ptr = ptr;
     }
 
+    /// Set the channel to be decoded into `data`, converting to `T`
+    ///
+    /// `pixel_stride` and `line_stride` are the number of elements of `T` from
+    /// one pixel of the channel to the next, and from one line to the next,
+    /// so e.g. an interleaved RGBA buffer of width `w` would use strides of 4
+    /// and `4 * w` with `data` offset to the first element of the channel.
+    ///
+    /// The size of `data` is checked against the dimensions of the channel in
+    /// the current chunk. As the pipeline does not hold on to the borrow,
+    /// running it safely requires passing the buffer again to
+    /// [`DecodePipeline::run_checked`](crate::decode::DecodePipeline::run_checked).
+    ///
+    /// # Errors
+    /// * `[Error::InvalidArgument]` - If `data` is too small to hold the
+    /// channel with the given strides
+    ///
+    pub fn set_decode_to_slice<T: ChannelElement>(
+        &mut self,
+        data: &mut [T],
+        pixel_stride: usize,
+        line_stride: usize,
+    ) -> Result<()> {
+        let element_bytes = std::mem::size_of::<T>();
+        let pixel_stride = pixel_stride
+            .checked_mul(element_bytes)
+            .and_then(|s| s.try_into().ok())
+            .ok_or(Error::InvalidArgument)?;
+        let line_stride = line_stride
+            .checked_mul(element_bytes)
+            .and_then(|s| s.try_into().ok())
+            .ok_or(Error::InvalidArgument)?;
+
+        let previous = self.0;
+        self.set_user_data_type(T::PIXEL_TYPE);
+        self.set_user_bytes_per_element(element_bytes);
+        self.0.user_pixel_stride = pixel_stride;
+        self.0.user_line_stride = line_stride;
+        self.0.__bindgen_anon_1.decode_to_ptr = data.as_mut_ptr() as *mut u8;
+
+        let start = data.as_ptr() as usize;
+        let end = start + std::mem::size_of_val(data);
+        match self.decode_to_range() {
+            Ok(Some(range)) if range.start < start || range.end > end => {
+                self.0 = previous;
+                Err(Error::InvalidArgument)
+            }
+            Err(e) => {
+                self.0 = previous;
+                Err(e)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// The range of memory the channel will be decoded to, given its
+    /// dimensions and user strides, or `None` if no decode pointer is set or
+    /// the channel is empty in this chunk
+    ///
+    /// The decoder writes elements of the user data type whatever the user
+    /// bytes per element, so the two must agree for the range to be known.
+    ///
+    /// # Errors
+    /// * `[Error::InvalidArgument]` - If the strides are negative, the range
+    /// would overflow, or the user bytes per element is not the size of the
+    /// user data type
+    ///
+    pub(crate) fn decode_to_range(&self) -> Result<Option<Range<usize>>> {
+        let start = unsafe { self.0.__bindgen_anon_1.decode_to_ptr } as usize;
+        if start == 0 || self.0.width <= 0 || self.0.height <= 0 {
+            return Ok(None);
+        }
+
+        let last_line: usize = (self.0.height - 1)
+            .try_into()
+            .map_err(|_| Error::InvalidArgument)?;
+        let last_pixel: usize = (self.0.width - 1)
+            .try_into()
+            .map_err(|_| Error::InvalidArgument)?;
+        let line_stride: usize = self
+            .0
+            .user_line_stride
+            .try_into()
+            .map_err(|_| Error::InvalidArgument)?;
+        let pixel_stride: usize = self
+            .0
+            .user_pixel_stride
+            .try_into()
+            .map_err(|_| Error::InvalidArgument)?;
+        let element_bytes: usize = self
+            .0
+            .user_bytes_per_element
+            .try_into()
+            .map_err(|_| Error::InvalidArgument)?;
+        let type_bytes = match self.user_data_type() {
+            Ok(PixelType::Half) => 2,
+            Ok(PixelType::Float) | Ok(PixelType::Uint) => 4,
+            Err(_) => return Err(Error::InvalidArgument),
+        };
+        if element_bytes != type_bytes {
+            return Err(Error::InvalidArgument);
+        }
+
+        let len = last_line
+            .checked_mul(line_stride)
+            .zip(last_pixel.checked_mul(pixel_stride))
+            .and_then(|(l, p)| l.checked_add(p))
+            .and_then(|o| o.checked_add(element_bytes))
+            .ok_or(Error::InvalidArgument)?;
+        let end = start.checked_add(len).ok_or(Error::InvalidArgument)?;
+
+        Ok(Some(start..end))
+    }
+
+    /// Set the buffer the channel's pixels are read from when encoding
+    ///
+    pub unsafe fn set_encode_from(&mut self, ptr: *const u8) {
+        self.0.__bindgen_anon_1.encode_from_ptr = ptr;
+    }
+
+}
+
+/// Number of values of a channel with the given sampling in the `len` pixels
+/// starting at `start`, i.e. the number of multiples of `sampling` in that
+/// range
+///
+pub(crate) fn sampled_count(start: i32, len: usize, sampling: i32) -> usize {
+    let sampling = i64::from(sampling.max(1));
+    let start = i64::from(start);
+    let end = start + len as i64;
+    ((end - 1).div_euclid(sampling) - (start - 1).div_euclid(sampling)) as usize
 }
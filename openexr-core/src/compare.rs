@@ -0,0 +1,396 @@
+//! Comparing two files, in the spirit of the `exrdiff` tool, e.g. to check
+//! renders against a reference or that a transcode didn't change anything.
+//!
+use crate::attr::Storage;
+use crate::context::ReadContext;
+use crate::error::Error;
+use crate::header::HeaderDiff;
+
+type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// What to compare when calling [`compare`]
+///
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CompareOptions {
+    /// Values that differ by no more than this are not counted as differing
+    pub tolerance: f64,
+    /// The names of attributes to leave out of the header comparison, e.g.
+    /// "compression" when checking a transcode
+    pub ignore_attributes: Vec<String>,
+    /// Only compare the headers, skipping the pixels
+    pub headers_only: bool,
+}
+
+/// How much a channel's values differ between the two parts
+///
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChannelStats {
+    pub name: String,
+    /// The largest absolute difference between two values
+    pub max_abs_diff: f64,
+    /// The root mean square of the differences between the values
+    pub rmse: f64,
+    /// The number of values that differ by more than the tolerance
+    pub differing_values: usize,
+}
+
+/// How much the pixels of the two parts differ
+///
+#[derive(Debug, Clone, PartialEq)]
+pub struct PixelStats {
+    /// Statistics for each channel the parts have in common
+    pub channels: Vec<ChannelStats>,
+    /// The number of pixels where any channel differs by more than the
+    /// tolerance
+    pub differing_pixels: usize,
+}
+
+/// The comparison of a pair of parts with the same index
+///
+#[derive(Debug, Clone, PartialEq)]
+pub struct PartComparison {
+    pub part_index: usize,
+    /// The attributes that differ going from the first file to the second
+    pub header: Vec<HeaderDiff>,
+    /// How much the pixels differ, or `None` if they were not compared,
+    /// because the parts hold deep data, have different data windows or
+    /// [`CompareOptions::headers_only`] was set
+    pub pixels: Option<PixelStats>,
+}
+
+/// The result of [`compare`]
+///
+#[derive(Debug, Clone, PartialEq)]
+pub struct Comparison {
+    /// The number of parts in each file
+    pub part_counts: (usize, usize),
+    /// The comparison of each part the files have in common
+    pub parts: Vec<PartComparison>,
+}
+
+impl Comparison {
+    /// Did the files match within the tolerance?
+    ///
+    /// Parts whose pixels were not compared match if their headers do.
+    ///
+    pub fn matches(&self) -> bool {
+        self.part_counts.0 == self.part_counts.1
+            && self.parts.iter().all(|p| {
+                p.header.is_empty()
+                    && p.pixels.iter().all(|p| p.differing_pixels == 0)
+            })
+    }
+}
+
+/// Compare the headers and pixels of each part of `a` with those of the part
+/// at the same index in `b`
+///
+/// Pixels are compared as `f32`, for the channels the parts have in common.
+/// NaNs are equal to each other, and a NaN compared with a number counts as
+/// differing but is left out of [`ChannelStats::max_abs_diff`] and
+/// [`ChannelStats::rmse`].
+///
+/// # Errors
+/// Differences between the files are reported in the [`Comparison`]. An
+/// error is only returned if a file can't be read.
+///
+pub fn compare(
+    a: &ReadContext,
+    b: &ReadContext,
+    options: CompareOptions,
+) -> Result<Comparison> {
+    let part_counts = (a.count()?, b.count()?);
+    let parts = (0..part_counts.0.min(part_counts.1))
+        .map(|part_index| {
+            let header = compare_headers(a, b, part_index, &options)?;
+            let pixels = if options.headers_only {
+                None
+            } else {
+                compare_pixels(a, b, part_index, options.tolerance)?
+            };
+            Ok(PartComparison {
+                part_index,
+                header,
+                pixels,
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    Ok(Comparison { part_counts, parts })
+}
+
+fn compare_headers(
+    a: &ReadContext,
+    b: &ReadContext,
+    part_index: usize,
+    options: &CompareOptions,
+) -> Result<Vec<HeaderDiff>> {
+    let mut header_a = a.header(part_index)?;
+    let mut header_b = b.header(part_index)?;
+    for name in &options.ignore_attributes {
+        header_a.remove(name);
+        header_b.remove(name);
+    }
+    Ok(header_a.diff(&header_b))
+}
+
+fn compare_pixels(
+    a: &ReadContext,
+    b: &ReadContext,
+    part_index: usize,
+    tolerance: f64,
+) -> Result<Option<PixelStats>> {
+    let flat = |ctx: &ReadContext| -> Result<bool> {
+        Ok(matches!(
+            ctx.storage(part_index)?,
+            Storage::Scanline | Storage::Tiled
+        ))
+    };
+    if !flat(a)?
+        || !flat(b)?
+        || a.data_window::<[i32; 4]>(part_index)?
+            != b.data_window::<[i32; 4]>(part_index)?
+    {
+        return Ok(None);
+    }
+
+    let channels_b = b.channels(part_index)?;
+    let names = a
+        .channels(part_index)?
+        .as_slice()
+        .iter()
+        .map(|c| c.name())
+        .filter(|n| channels_b.as_slice().iter().any(|c| c.name() == *n))
+        .collect::<Vec<_>>();
+
+    let image_a = a.read_image::<f32>(part_index, &names)?;
+    let image_b = b.read_image::<f32>(part_index, &names)?;
+
+    let num_channels = names.len();
+    let mut channels = names
+        .iter()
+        .map(|name| ChannelStats {
+            name: name.to_string(),
+            max_abs_diff: 0.0,
+            rmse: 0.0,
+            differing_values: 0,
+        })
+        .collect::<Vec<_>>();
+    let mut differing_pixels = 0;
+    let mut counted = vec![0usize; num_channels];
+
+    if num_channels > 0 {
+        for (pixel_a, pixel_b) in image_a
+            .pixels
+            .chunks_exact(num_channels)
+            .zip(image_b.pixels.chunks_exact(num_channels))
+        {
+            let mut pixel_differs = false;
+            for (c, (&va, &vb)) in pixel_a.iter().zip(pixel_b).enumerate() {
+                let (va, vb) = (f64::from(va), f64::from(vb));
+                let stats = &mut channels[c];
+                if va == vb || (va.is_nan() && vb.is_nan()) {
+                    counted[c] += 1;
+                    continue;
+                }
+
+                let diff = (va - vb).abs();
+                if !diff.is_nan() {
+                    stats.max_abs_diff = stats.max_abs_diff.max(diff);
+                    stats.rmse += diff * diff;
+                    counted[c] += 1;
+                }
+                if diff.is_nan() || diff > tolerance {
+                    stats.differing_values += 1;
+                    pixel_differs = true;
+                }
+            }
+            if pixel_differs {
+                differing_pixels += 1;
+            }
+        }
+    }
+
+    // rmse holds the sum of squared differences until now
+    for (stats, count) in channels.iter_mut().zip(counted) {
+        if count > 0 {
+            stats.rmse = (stats.rmse / count as f64).sqrt();
+        }
+    }
+
+    Ok(Some(PixelStats {
+        channels,
+        differing_pixels,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate as exr;
+    use exr::attr::Compression;
+    use exr::compare::{compare, CompareOptions};
+    use exr::context::ReadContext;
+    use exr::transcode::{transcode, TranscodeOptions};
+    use std::path::{Path, PathBuf};
+
+    /// Write a single line image with a float channel `Y` holding `values`
+    /// to a file called `name` in the temporary directory
+    ///
+    fn write_values(name: &str, values: &[f32]) -> Result<PathBuf, exr::Error> {
+        use exr::attr::{ChannelDesc, PixelType, Storage};
+        use exr::context::{DefaultWriteMode, WriteHeaderContext};
+        use exr::write::ImageDesc;
+
+        let path = std::env::temp_dir().join(name);
+        let mut header = WriteHeaderContext::new(
+            &path,
+            DefaultWriteMode::WriteFileDirectly,
+        )?;
+        let part = header.add_part("", Storage::Scanline)?;
+        header.initialize_required_attributes_simple(
+            part,
+            values.len(),
+            1,
+            Compression::None,
+        )?;
+        header
+            .add_channels(part, &[ChannelDesc::new("Y", PixelType::Float)])?;
+
+        let mut ctx = header.write_header()?;
+        let bytes = values
+            .iter()
+            .flat_map(|v| v.to_le_bytes())
+            .collect::<Vec<_>>();
+        let desc = ImageDesc::new(values.len(), 1, &["Y"], PixelType::Float);
+        ctx.write_image(part, &desc, &bytes)?;
+        ctx.close()?;
+        Ok(path)
+    }
+
+    #[test]
+    fn compare_files() -> Result<(), exr::Error> {
+        let path_ferris = Path::new(
+            &std::env::var("CARGO_MANIFEST_DIR")
+                .expect("CARGO_MANIFEST_DIR not set"),
+        )
+        .join("images")
+        .join("ferris.exr");
+
+        let path = std::env::temp_dir().join("compare_files.exr");
+        transcode(
+            &path_ferris,
+            &path,
+            TranscodeOptions {
+                compression: Some(Compression::Zip),
+                ..Default::default()
+            },
+        )?;
+
+        let a = ReadContext::new(&path_ferris)?;
+        let b = ReadContext::new(&path)?;
+
+        let comparison = compare(&a, &a, CompareOptions::default())?;
+        assert!(comparison.matches());
+
+        let comparison = compare(&a, &b, CompareOptions::default())?;
+        assert!(!comparison.matches());
+        assert_eq!(comparison.part_counts, (1, 1));
+        let part = &comparison.parts[0];
+        assert_eq!(part.header.len(), 1);
+        assert_eq!(part.header[0].name(), "compression");
+        let pixels = part.pixels.as_ref().unwrap();
+        assert_eq!(pixels.differing_pixels, 0);
+        assert_eq!(pixels.channels.len(), 4);
+        assert!(pixels.channels.iter().all(|c| c.max_abs_diff == 0.0));
+
+        let comparison = compare(
+            &a,
+            &b,
+            CompareOptions {
+                ignore_attributes: vec!["compression".to_string()],
+                ..Default::default()
+            },
+        )?;
+        assert!(comparison.matches());
+
+        Ok(())
+    }
+
+    #[test]
+    fn compare_pixels() -> Result<(), exr::Error> {
+        let a = ReadContext::new(write_values(
+            "compare_pixels_a.exr",
+            &[0.0, 1.0, 2.0, 3.0],
+        )?)?;
+        let b = ReadContext::new(write_values(
+            "compare_pixels_b.exr",
+            &[0.0, 1.5, 2.0, 1.0],
+        )?)?;
+
+        // the differences are 0, 0.5, 0 and 2
+        let comparison = compare(&a, &b, CompareOptions::default())?;
+        assert!(!comparison.matches());
+        let pixels = comparison.parts[0].pixels.as_ref().unwrap();
+        assert_eq!(pixels.differing_pixels, 2);
+        let stats = &pixels.channels[0];
+        assert_eq!(stats.name, "Y");
+        assert_eq!(stats.max_abs_diff, 2.0);
+        assert!((stats.rmse - (4.25f64 / 4.0).sqrt()).abs() < 1e-12);
+        assert_eq!(stats.differing_values, 2);
+
+        // differences up to the tolerance don't count, but are still in the
+        // statistics
+        let options = CompareOptions {
+            tolerance: 0.5,
+            ..Default::default()
+        };
+        let comparison = compare(&a, &b, options.clone())?;
+        assert!(!comparison.matches());
+        let pixels = comparison.parts[0].pixels.as_ref().unwrap();
+        assert_eq!(pixels.differing_pixels, 1);
+        assert_eq!(pixels.channels[0].differing_values, 1);
+        assert_eq!(pixels.channels[0].max_abs_diff, 2.0);
+
+        let c = ReadContext::new(write_values(
+            "compare_pixels_c.exr",
+            &[0.0, 1.25, 2.0, 3.0],
+        )?)?;
+        let comparison = compare(&a, &c, options)?;
+        assert!(comparison.matches());
+        let stats = &comparison.parts[0].pixels.as_ref().unwrap().channels[0];
+        assert_eq!(stats.max_abs_diff, 0.25);
+        assert_eq!(stats.rmse, 0.125);
+        assert_eq!(stats.differing_values, 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn compare_nans() -> Result<(), exr::Error> {
+        let a = ReadContext::new(write_values(
+            "compare_nans_a.exr",
+            &[f32::NAN, f32::NAN, 1.0, 2.0],
+        )?)?;
+        let b = ReadContext::new(write_values(
+            "compare_nans_b.exr",
+            &[f32::NAN, 1.0, 1.0, 2.0],
+        )?)?;
+
+        // NaN matches NaN
+        let comparison = compare(&a, &a, CompareOptions::default())?;
+        assert!(comparison.matches());
+
+        // NaN against a number differs, but has no size to add to the
+        // statistics
+        let comparison = compare(&a, &b, CompareOptions::default())?;
+        assert!(!comparison.matches());
+        let pixels = comparison.parts[0].pixels.as_ref().unwrap();
+        assert_eq!(pixels.differing_pixels, 1);
+        let stats = &pixels.channels[0];
+        assert_eq!(stats.differing_values, 1);
+        assert_eq!(stats.max_abs_diff, 0.0);
+        assert_eq!(stats.rmse, 0.0);
+
+        Ok(())
+    }
+}
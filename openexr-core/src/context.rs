@@ -1,7 +1,13 @@
-use crate::error::Error;
+use crate::defaults::c_size;
+use crate::error::{Error, ErrorKind};
+use crate::stream::{default_initializer, StreamCallbacks};
 use openexr_core_sys as sys;
+use std::convert::TryFrom;
 use std::ffi::{CStr, CString};
+use std::io::{Read, Seek};
 use std::marker::PhantomData;
+use std::mem::ManuallyDrop;
+use std::ops::{BitOr, BitOrAssign};
 use std::path::Path;
 
 type Result<T, E = Error> = std::result::Result<T, E>;
@@ -12,6 +18,12 @@ type Result<T, E = Error> = std::result::Result<T, E>;
 /// for error handling and memory allocation. This is done to enable encoding or
 /// decoding on mixed hardware
 ///
+/// Every context is `Send`, so can be moved to another thread. A
+/// [`ReadContext`] is also `Sync`, so its chunks can be read and decoded from
+/// several threads at once, each with its own
+/// [`DecodePipeline`](crate::decode::DecodePipeline). Contexts for writing are
+/// not `Sync`, as their chunks have to be written one at a time.
+///
 // pub struct ReadContext(pub(crate) *mut sys::_priv_exr_context_t);
 // pub struct WriteContext(pub(crate) *mut sys::_priv_exr_context_t);
 // pub struct WriteHeaderContext(pub(crate) *mut sys::_priv_exr_context_t);
@@ -19,7 +31,7 @@ type Result<T, E = Error> = std::result::Result<T, E>;
 #[repr(transparent)]
 pub struct Context<S: ContextState> {
     pub(crate) inner: *mut sys::_priv_exr_context_t,
-    marker: PhantomData<S>,
+    pub(crate) marker: PhantomData<S>,
 }
 
 pub enum ReadState {}
@@ -33,12 +45,303 @@ impl ContextState for WriteState {}
 impl ContextState for WriteHeaderState {}
 impl ContextState for InplaceHeaderUpdateState {}
 
+/// The states in which attributes can be set, i.e. while defining a new
+/// header or updating an existing one in place
+///
+pub trait WritableHeaderState: ContextState {}
+impl WritableHeaderState for WriteHeaderState {}
+impl WritableHeaderState for InplaceHeaderUpdateState {}
+
+// Safety: a context owns the C core's state for its file or stream, and all
+// the user data it is given, i.e. custom streams and error handlers, is Send,
+// so it can be moved to another thread
+unsafe impl<S: ContextState> Send for Context<S> {}
+
+// Safety: once the header has been parsed, reading chunks only reads the
+// context's state, other than the chunk table, which the C core loads
+// atomically. Files are read with positional reads, and custom streams are
+// behind a mutex, so chunks can be read from several threads at once
+unsafe impl Sync for Context<ReadState> {}
+
 pub type ReadContext = Context<ReadState>;
 pub type WriteContext = Context<WriteState>;
 pub type WriteHeaderContext = Context<WriteHeaderState>;
 pub type InplaceHeaderUpdateContext = Context<InplaceHeaderUpdateState>;
 
+impl<S: ContextState> Context<S> {
+    /// Close the context, flushing any pending writes and freeing all the
+    /// resources associated with it.
+    ///
+    /// Dropping the context does the same, but any error is lost, so call this
+    /// when writing to find out whether the file was written successfully.
+    ///
+    /// # Errors
+    /// * `[Error::WriteIo]` - If the remaining data could not be written
+    /// * `[Error::IncorrectPart]` - If a part has not been completely written
+    ///
+    pub fn close(self) -> Result<()> {
+        let mut ctx = ManuallyDrop::new(self);
+        let ctxt = ctx.inner;
+        unsafe { sys::exr_finish(&mut ctx.inner).ok_finished(ctxt, ()) }
+    }
+}
+
+impl<S: ContextState> Drop for Context<S> {
+    fn drop(&mut self) {
+        if !self.inner.is_null() {
+            // There's nothing useful we can do with an error here. Use
+            // close() to see it instead
+            let ctxt = self.inner;
+            unsafe {
+                let _ = sys::exr_finish(&mut self.inner).ok_finished(ctxt, ());
+            }
+        }
+    }
+}
+
+/// Convert a part index to the `int` the C core takes
+///
+/// # Errors
+/// * `[Error::ArgumentOutOfRange]` - If `part_index` is too big to be an `int`
+///
+pub(crate) fn c_part_index(part_index: usize) -> Result<i32> {
+    i32::try_from(part_index)
+        .map_err(|_| Error::ArgumentOutOfRange.with_part(part_index))
+}
+
+/// Called with the error code and message whenever the C core reports an
+/// error, instead of the message being printed to stderr
+///
+pub type ErrorHandler = Box<dyn Fn(Error, &str) + Send + Sync>;
+
+/// Anything that can be read from with [`ContextOptions::reader`]
+///
+pub trait ReadSeek: Read + Seek + Send {}
+impl<T: Read + Seek + Send> ReadSeek for T {}
+
+/// Flags controlling how a context reads a file
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ContextFlags(i32);
+
+impl ContextFlags {
+    pub const NONE: ContextFlags = ContextFlags(0);
+    /// Fail on any header value that the C++ library would reject, rather
+    /// than accepting what can be made sense of
+    pub const STRICT_HEADER: ContextFlags = ContextFlags(1 << 0);
+    /// Don't report errors found while parsing the header to the error
+    /// handler
+    pub const SILENT_HEADER_PARSE: ContextFlags = ContextFlags(1 << 1);
+    /// Don't try to rebuild a corrupt chunk table by scanning the file
+    pub const DISABLE_CHUNK_RECONSTRUCTION: ContextFlags = ContextFlags(1 << 2);
+
+    pub fn bits(&self) -> i32 {
+        self.0
+    }
+
+    pub fn contains(&self, other: ContextFlags) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// Set or clear the flags in `other`
+    ///
+    pub fn set(&mut self, other: ContextFlags, enabled: bool) {
+        if enabled {
+            self.0 |= other.0;
+        } else {
+            self.0 &= !other.0;
+        }
+    }
+}
+
+impl BitOr for ContextFlags {
+    type Output = ContextFlags;
+
+    fn bitor(self, rhs: ContextFlags) -> ContextFlags {
+        ContextFlags(self.0 | rhs.0)
+    }
+}
+
+impl BitOrAssign for ContextFlags {
+    fn bitor_assign(&mut self, rhs: ContextFlags) {
+        self.0 |= rhs.0
+    }
+}
+
+/// Options for creating a context, mirroring the C core's
+/// `exr_context_initializer_t`
+///
+/// Rather than the initializer's raw `user_data` pointer and `read_fn`
+/// callback, custom IO is given as a [`reader`](ContextOptions::reader),
+/// which the context owns and reads from through callbacks of its own, so
+/// there is nothing for the caller to keep alive or free.
+///
+/// Passing an `error_handler` without a `reader` still reads the file, but
+/// opens it with `std::fs::File` rather than leaving it to the C core, as the
+/// C core keeps the state of files it opens where the handler would have to
+/// go. Failing to open it then gives an [`Error::FileAccess`] whose
+/// [`message`](Error::message) holds the [`std::io::ErrorKind`] of the
+/// failure.
+///
+#[derive(Default)]
+pub struct ContextOptions {
+    /// Called with every error the C core reports
+    ///
+    /// Unless a [`reader`](ContextOptions::reader) is also given, this
+    /// changes how the file is read: it is opened with `std::fs::File` and
+    /// read through this crate's callbacks rather than the C core's own file
+    /// IO, though failing to open it is still an [`Error::FileAccess`]
+    pub error_handler: Option<ErrorHandler>,
+    /// Read from this instead of opening the file, in which case the file
+    /// name is only used to describe the stream in error messages
+    pub reader: Option<Box<dyn ReadSeek>>,
+    pub flags: ContextFlags,
+    /// The largest image to accept, in pixels, or `None` to use
+    /// [`defaults::maximum_image_size`](crate::defaults::maximum_image_size)
+    pub max_image_size: Option<(usize, usize)>,
+    /// The largest tile to accept, in pixels, or `None` to use
+    /// [`defaults::maximum_tile_size`](crate::defaults::maximum_tile_size)
+    pub max_tile_size: Option<(usize, usize)>,
+}
+
+impl ContextOptions {
+    /// Fail on out-of-spec header values, such as attributes of the wrong
+    /// size or required attributes that don't make sense, instead of
+    /// tolerating them as far as possible
+    ///
+    pub fn strict_headers(mut self, enabled: bool) -> ContextOptions {
+        self.flags.set(ContextFlags::STRICT_HEADER, enabled);
+        self
+    }
+
+    /// Fail when the chunk table is corrupt, instead of the C core trying to
+    /// rebuild it by scanning the file for chunk leaders
+    ///
+    pub fn disable_chunk_reconstruction(
+        mut self,
+        disabled: bool,
+    ) -> ContextOptions {
+        self.flags
+            .set(ContextFlags::DISABLE_CHUNK_RECONSTRUCTION, disabled);
+        self
+    }
+
+    /// Reject files whose images are larger than `width` by `height` pixels
+    /// when they are opened, before anything is allocated for them, e.g. when
+    /// decoding untrusted files
+    ///
+    pub fn max_image_size(
+        mut self,
+        width: usize,
+        height: usize,
+    ) -> ContextOptions {
+        self.max_image_size = Some((width, height));
+        self
+    }
+
+    /// Reject files whose tiles are larger than `width` by `height` pixels
+    /// when they are opened
+    ///
+    pub fn max_tile_size(
+        mut self,
+        width: usize,
+        height: usize,
+    ) -> ContextOptions {
+        self.max_tile_size = Some((width, height));
+        self
+    }
+}
+
 impl Context<ReadState> {
+    /// Create a context reading from `filename` with the given `options`
+    ///
+    /// # Errors
+    /// * `[Error::FileAccess]` - If the file could not be opened
+    /// * `[Error::FileBadHeader]` - If the file does not have a valid header
+    ///
+    /// # Panics
+    /// * If `filename` is not valid UTF-8 or contains null bytes
+    ///
+    pub fn with_options<P: AsRef<Path>>(
+        filename: P,
+        options: ContextOptions,
+    ) -> Result<ReadContext> {
+        let ContextOptions {
+            error_handler,
+            reader,
+            flags,
+            max_image_size,
+            max_tile_size,
+        } = options;
+        let max_image_size = max_image_size
+            .map(|(w, h)| (c_size(w), c_size(h)))
+            .unwrap_or((0, 0));
+        let max_tile_size = max_tile_size
+            .map(|(w, h)| (c_size(w), c_size(h)))
+            .unwrap_or((0, 0));
+
+        let name = filename
+            .as_ref()
+            .to_str()
+            .expect("Invalid bytes in filename");
+
+        // The C core keeps its own state for files it opens in the user data,
+        // so to pass an error handler we have to open the file ourselves
+        let reader = match reader {
+            Some(reader) => Some(reader),
+            None if error_handler.is_some() => {
+                let file = std::fs::File::open(&filename).map_err(|e| {
+                    Error::new(
+                        ErrorKind::FileAccess,
+                        Some(format!(
+                            "Unable to open file '{}': {:?}: {}",
+                            name,
+                            e.kind(),
+                            e
+                        )),
+                    )
+                })?;
+                Some(Box::new(file) as Box<dyn ReadSeek>)
+            }
+            None => None,
+        };
+
+        match reader {
+            Some(reader) => {
+                let callbacks = StreamCallbacks {
+                    flags: flags.bits(),
+                    max_image_size,
+                    max_tile_size,
+                    ..StreamCallbacks::reader(reader, error_handler)
+                };
+                // Safety: the callbacks were set up together with their user
+                // data
+                unsafe { ReadContext::from_stream(name, callbacks) }
+            }
+            None => {
+                let c_filename = CString::new(name)
+                    .expect("Internal null bytes in filename");
+                let mut init = default_initializer();
+                init.flags = flags.bits();
+                init.max_image_width = max_image_size.0;
+                init.max_image_height = max_image_size.1;
+                init.max_tile_width = max_tile_size.0;
+                init.max_tile_height = max_tile_size.1;
+
+                let mut inner = std::ptr::null_mut();
+                unsafe {
+                    sys::exr_start_read(&mut inner, c_filename.as_ptr(), &init)
+                        .ok_started(inner, ())?;
+                }
+
+                Ok(ReadContext {
+                    inner,
+                    marker: PhantomData,
+                })
+            }
+        }
+    }
+
     pub fn new<P: AsRef<Path>>(filename: P) -> Result<ReadContext> {
         let c_filename = CString::new(
             filename
@@ -53,20 +356,22 @@ impl Context<ReadState> {
             sys::exr_start_read(
                 &mut inner,
                 c_filename.as_ptr(),
-                std::ptr::null(),
+                &default_initializer(),
             )
-            .ok(ReadContext {
-                inner,
-                marker: PhantomData,
-            })
+            .ok_started(inner, ())?;
         }
+
+        Ok(ReadContext {
+            inner,
+            marker: PhantomData,
+        })
     }
 
     pub fn file_name(&self) -> Result<&str> {
         let mut ptr = std::ptr::null();
         unsafe {
             sys::exr_get_file_name(self.inner, &mut ptr)
-                .ok(())
+                .ok_in(self.inner, ())
                 .map(|_| CStr::from_ptr(ptr).to_str().unwrap())
         }
     }
@@ -109,13 +414,15 @@ impl WriteHeaderContext {
                 &mut inner,
                 c_filename.as_ptr(),
                 default_write_mode.into(),
-                std::ptr::null(),
+                &default_initializer(),
             )
-            .ok(WriteHeaderContext {
-                inner,
-                marker: PhantomData,
-            })
+            .ok_started(inner, ())?;
         }
+
+        Ok(WriteHeaderContext {
+            inner,
+            marker: PhantomData,
+        })
     }
 
     pub fn set_longname_support(&mut self, enabled: bool) -> Result<()> {
@@ -124,21 +431,40 @@ impl WriteHeaderContext {
                 self.inner,
                 if enabled { 1 } else { 0 },
             )
-            .ok(())
+            .ok_in(self.inner, ())
         }
     }
 
     pub fn write_header(self) -> Result<WriteContext> {
         unsafe {
-            sys::exr_write_header(self.inner).ok(WriteContext {
-                inner: self.inner,
-                marker: PhantomData,
-            })
+            sys::exr_write_header(self.inner).ok_in(self.inner, ())?;
         }
+
+        // Ownership of the file passes to the WriteContext, so make sure we
+        // don't finish it when self goes out of scope
+        let ctx = ManuallyDrop::new(self);
+        Ok(WriteContext {
+            inner: ctx.inner,
+            marker: PhantomData,
+        })
     }
 }
 
 impl InplaceHeaderUpdateContext {
+    /// Open an existing file to change the values of attributes in its
+    /// headers without rewriting the rest of the file
+    ///
+    /// Values can only be replaced, with
+    /// [`update_attribute`](Self::update_attribute), by ones that take up the
+    /// same number of bytes in the file, as the header can't grow or shrink.
+    /// Call [`commit`](Self::commit) to write the changes back to the file.
+    ///
+    /// # Errors
+    /// * `[Error::FileAccess]` - If the file could not be opened
+    /// * `[Error::FileBadHeader]` - If the header could not be read
+    /// * `[Error::InvalidArgument]` - If the C core does not support
+    /// in-place updates
+    ///
     pub fn new<P: AsRef<Path>>(
         filename: P,
     ) -> Result<InplaceHeaderUpdateContext> {
@@ -152,16 +478,29 @@ impl InplaceHeaderUpdateContext {
 
         let mut inner = std::ptr::null_mut();
         unsafe {
-            sys::exr_start_read(
+            sys::exr_start_inplace_header_update(
                 &mut inner,
                 c_filename.as_ptr(),
-                std::ptr::null(),
+                &default_initializer(),
             )
-            .ok(InplaceHeaderUpdateContext {
-                inner,
-                marker: PhantomData,
-            })
+            .ok_started(inner, ())?;
         }
+
+        Ok(InplaceHeaderUpdateContext {
+            inner,
+            marker: PhantomData,
+        })
+    }
+
+    /// Write the updated headers back to the file and close it
+    ///
+    /// Dropping the context does the same, but any error is lost.
+    ///
+    /// # Errors
+    /// * `[Error::WriteIo]` - If the headers could not be written
+    ///
+    pub fn commit(self) -> Result<()> {
+        self.close()
     }
 }
 
@@ -189,8 +528,14 @@ mod tests {
         assert_eq!(ctx.count()?, 1);
         assert_eq!(ctx.name(0)?, None);
         assert_eq!(ctx.storage(0)?, exr::attr::Storage::Scanline);
-        assert_eq!(ctx.tile_levels(0), Err(exr::Error::TileScanMixedApi));
-        assert_eq!(ctx.tile_sizes(0, 0, 0), Err(exr::Error::TileScanMixedApi));
+        assert_eq!(
+            ctx.tile_levels(0).unwrap_err(),
+            exr::ErrorKind::TileScanMixedApi
+        );
+        assert_eq!(
+            ctx.tile_sizes(0, 0, 0).unwrap_err(),
+            exr::ErrorKind::TileScanMixedApi
+        );
 
         let chunk_count = ctx.chunk_count(0)?;
         let scanlines_per_chunk = ctx.scanlines_per_chunk(0)?;
@@ -208,8 +553,27 @@ mod tests {
             println!("Attribute {} - {}", i, attr.name());
         }
 
+        let attr_list =
+            ctx.attribute_list(0, exr::part::AttrListAccessMode::FileOrder)?;
+        assert_eq!(attr_list.len(), attr_count);
+        for (i, attr) in attr_list.iter().enumerate() {
+            let by_index = ctx.get_attribute_by_index(
+                0,
+                exr::part::AttrListAccessMode::FileOrder,
+                i,
+            )?;
+            assert_eq!(attr.name(), by_index.name());
+        }
+
         let attr_channels = ctx.get_attribute_by_name(0, "channels")?;
 
+        let channels = ctx.channels(0)?.descs()?;
+        assert_eq!(channels.len(), 4);
+        assert_eq!(
+            channels[0],
+            exr::attr::ChannelDesc::new("A", exr::attr::PixelType::Half)
+        );
+
         assert_eq!(ctx.get_attribute::<f32>(0, "screenWindowWidth")?, 1.0f32);
         assert_eq!(
             ctx.get_attribute::<exr::attr::Compression>(0, "compression")?,
@@ -234,9 +598,6 @@ mod tests {
 
         let channels_to_read = ["R", "G", "B", "A"];
         let nchan = channels_to_read.len();
-        let channel_bytes = 2;
-        let pixel_bytes = channels_to_read.len() * channel_bytes;
-        let scanline_bytes = pixel_bytes * width;
 
         let num_chunk_lines = scanlines_per_chunk * chunk_count;
 
@@ -253,47 +614,33 @@ mod tests {
 
         let chunk_info =
             ctx.read_scanline_chunk_info(0, chunk_scanline_start as i32)?;
-        let mut decoder = exr::decode::DecodePipeline::default();
+        let mut decoder = ctx.decoding_initialize(0, &chunk_info)?;
 
-        ctx.decoding_initialize(0, &chunk_info, &mut decoder)?;
+        loop {
+            let chunk_pixels =
+                &mut pixel_data[chunk_scanline_start * width * nchan
+                    ..chunk_scanline_end * width * nchan];
 
-        while chunk_scanline_end <= num_chunk_lines {
-            let pixel_ptr = pixel_data[chunk_scanline_start * width * nchan
-                ..chunk_scanline_end * width * nchan]
-                .as_mut_ptr();
-
-            let chunk_info =
-                ctx.read_scanline_chunk_info(0, chunk_scanline_start as i32)?;
-
-            ctx.decoding_update(0, &chunk_info, &mut decoder)?;
-
-            let mut chan_offset = 0;
-            for req_chan_name in &channels_to_read {
-                for decode_channel in decoder.channels_mut() {
-                    if decode_channel.name() == *req_chan_name {
-                        unsafe {
-                            decode_channel.set_decode_to(
-                                pixel_ptr.offset(chan_offset) as *mut u8,
-                            );
-                        }
-
-                        decode_channel.set_user_bytes_per_element(2);
-                        decode_channel.set_user_pixel_stride(pixel_bytes);
-                        decode_channel.set_user_line_stride(scanline_bytes);
-                        chan_offset += 1;
-                    }
-                }
-            }
+            decoder.select_interleaved(&channels_to_read, chunk_pixels)?;
 
-            ctx.decoding_choose_default_routines(0, &mut decoder)?;
-            unsafe { ctx.decoding_run(0, &mut decoder)? };
+            let mut runnable = decoder.choose_default_routines()?;
+            runnable.run_checked(chunk_pixels)?;
 
             chunk_scanline_start += scanlines_per_chunk;
             chunk_scanline_end += scanlines_per_chunk;
+
+            if chunk_scanline_end > num_chunk_lines {
+                // finished with the decoder, clean up
+                ctx.decoding_destroy(runnable)?;
+                break;
+            }
+
+            let chunk_info =
+                ctx.read_scanline_chunk_info(0, chunk_scanline_start as i32)?;
+            decoder = runnable.update(&chunk_info)?;
         }
 
-        // finished with the decoder, clean up
-        ctx.decoding_destroy(decoder)?;
+        ctx.close()?;
 
         // now truncate the pixels to the correct length and convert to u8
         // to write out a png for comparison
@@ -313,4 +660,149 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn with_options() -> Result<(), exr::Error> {
+        use exr::context::{ContextFlags, ContextOptions, ReadContext};
+        use std::sync::{Arc, Mutex};
+
+        let path_ferris = Path::new(
+            &std::env::var("CARGO_MANIFEST_DIR")
+                .expect("CARGO_MANIFEST_DIR not set"),
+        )
+        .join("images")
+        .join("ferris.exr");
+
+        let ctx = ReadContext::with_options(
+            &path_ferris,
+            ContextOptions::default().strict_headers(true),
+        )?;
+        assert_eq!(ctx.count()?, 1);
+
+        let options = ContextOptions::default().strict_headers(true);
+        assert!(options.flags.contains(ContextFlags::STRICT_HEADER));
+        let options = options.strict_headers(false);
+        assert_eq!(options.flags, ContextFlags::NONE);
+
+        let options = ContextOptions::default()
+            .strict_headers(true)
+            .disable_chunk_reconstruction(true);
+        assert_eq!(
+            options.flags,
+            ContextFlags::STRICT_HEADER
+                | ContextFlags::DISABLE_CHUNK_RECONSTRUCTION
+        );
+        let ctx = ReadContext::with_options(&path_ferris, options)?;
+        assert_eq!(ctx.count()?, 1);
+
+        // ferris is bigger than this, so is rejected as soon as it is opened
+        assert!(ReadContext::with_options(
+            &path_ferris,
+            ContextOptions::default().max_image_size(16, 16),
+        )
+        .is_err());
+
+        let messages = Arc::new(Mutex::new(Vec::new()));
+        let handler_messages = messages.clone();
+        let result = ReadContext::with_options(
+            "garbage",
+            ContextOptions {
+                error_handler: Some(Box::new(move |code, msg| {
+                    handler_messages
+                        .lock()
+                        .unwrap()
+                        .push((code, msg.to_string()))
+                })),
+                reader: Some(Box::new(std::io::Cursor::new(vec![0u8; 64]))),
+                ..Default::default()
+            },
+        );
+        assert!(result.is_err());
+        assert!(!messages.lock().unwrap().is_empty());
+
+        // files opened for an error handler say why they couldn't be opened
+        let err = ReadContext::with_options(
+            "does_not_exist.exr",
+            ContextOptions {
+                error_handler: Some(Box::new(|_, _| ())),
+                ..Default::default()
+            },
+        )
+        .err()
+        .unwrap();
+        assert_eq!(err.kind(), exr::ErrorKind::FileAccess);
+        assert!(err.message().unwrap().contains("NotFound"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn error_message() {
+        use exr::context::ReadContext;
+
+        let err = ReadContext::new("does_not_exist.exr").err().unwrap();
+        assert_eq!(err.kind(), exr::ErrorKind::FileAccess);
+        assert!(err.message().is_some());
+        assert!(err.to_string().contains(err.message().unwrap()));
+        assert!(err.raw_code().is_some());
+
+        let path_ferris = Path::new(
+            &std::env::var("CARGO_MANIFEST_DIR")
+                .expect("CARGO_MANIFEST_DIR not set"),
+        )
+        .join("images")
+        .join("ferris.exr");
+        let ctx = ReadContext::new(&path_ferris).unwrap();
+        let err = ctx.get_attribute::<f32>(0, "notThere").err().unwrap();
+        assert_eq!(err.kind(), exr::ErrorKind::NoAttrByName);
+        assert_eq!(err.part_index(), Some(0));
+        assert_eq!(err.attribute(), Some("notThere"));
+        assert!(err.to_string().contains("notThere"));
+    }
+
+    #[test]
+    fn read_from_threads() -> Result<(), exr::Error> {
+        use exr::chunkio::ChunkInfo;
+        use exr::context::{ReadContext, WriteContext};
+        use exr::decode::DecodePipeline;
+        use exr::encode::EncodePipeline;
+        use std::sync::Arc;
+
+        fn send_sync<T: Send + Sync>() {}
+        fn send<T: Send>() {}
+        send_sync::<ReadContext>();
+        send_sync::<DecodePipeline>();
+        send_sync::<ChunkInfo>();
+        send_sync::<EncodePipeline>();
+        send::<WriteContext>();
+
+        let path_ferris = Path::new(
+            &std::env::var("CARGO_MANIFEST_DIR")
+                .expect("CARGO_MANIFEST_DIR not set"),
+        )
+        .join("images")
+        .join("ferris.exr");
+        let ctx = Arc::new(ReadContext::new(&path_ferris)?);
+        let channels = ["R", "G", "B", "A"];
+        let image = ctx.read_image::<f16>(0, &channels)?;
+
+        // each thread decodes every chunk of the part from the same context
+        let threads = (0..4)
+            .map(|_| {
+                let ctx = Arc::clone(&ctx);
+                std::thread::spawn(move || {
+                    ctx.scanline_rows::<f16>(0, &channels)?
+                        .map(|row| row.map(|r| r.pixels))
+                        .collect::<Result<Vec<_>, _>>()
+                        .map(|rows| rows.concat())
+                })
+            })
+            .collect::<Vec<_>>();
+        for thread in threads {
+            let pixels = thread.join().expect("thread panicked")?;
+            assert_eq!(pixels, image.pixels);
+        }
+
+        Ok(())
+    }
 }
@@ -2,133 +2,1025 @@ use crate::attr::{
     Attribute, AttributeRead, Compression, LevelMode, LineOrder, Storage,
 };
 use crate::chunkio::ChunkInfo;
-use crate::coding::ChannelInfo;
+use crate::coding::{ChannelElement, ChannelInfo, Pod};
 use crate::context::*;
 use crate::error::Error;
 use openexr_core_sys as sys;
-use std::convert::TryInto;
+use std::convert::TryFrom;
 use std::ffi::{CStr, CString};
+use std::marker::PhantomData;
+use std::mem::ManuallyDrop;
+use std::ops::Range;
+use std::os::raw::c_void;
 use std::path::Path;
 
 use imath_traits::{Bound2, Vec2};
 
 type Result<T, E = Error> = std::result::Result<T, E>;
 
-#[repr(transparent)]
-// We have to box this because exr_decode_pipeline_t uses a small-buffer 
-// optimization internally
-pub struct DecodePipeline(Box<sys::exr_decode_pipeline_t>);
+/// Marker for the state a [`DecodePipeline`] is in
+///
+/// The pipeline moves through the states `Uninit` -> `Ready` -> `Runnable`,
+/// and the methods available at each state make sure that it is always
+/// initialized against a chunk, and has had its routines chosen, before it can
+/// be run.
+///
+pub trait PipelineState {}
 
-impl DecodePipeline {
+/// A newly created pipeline that is not yet associated with a chunk
+pub enum Uninit {}
+/// A pipeline initialized for a chunk, whose channel outputs can be set up
+pub enum Ready {}
+/// A pipeline with decode routines chosen, ready to be run
+pub enum Runnable {}
+
+impl PipelineState for Uninit {}
+impl PipelineState for Ready {}
+impl PipelineState for Runnable {}
+
+/// Pipeline states that have been initialized against a chunk
+pub trait Initialized: PipelineState {}
+impl Initialized for Ready {}
+impl Initialized for Runnable {}
+
+/// A buffer to decode a single channel into, for
+/// [`DecodePipeline::decode_planar`]
+///
+pub struct PlanarTarget<'a, T: ChannelElement> {
+    /// Name of the channel to decode
+    pub name: &'a str,
+    /// Buffer to hold the channel's pixels for a chunk
+    pub data: &'a mut [T],
+}
+
+impl<'a, T: ChannelElement> PlanarTarget<'a, T> {
+    pub fn new(name: &'a str, data: &'a mut [T]) -> PlanarTarget<'a, T> {
+        PlanarTarget { name, data }
+    }
+}
+
+/// Flags changing how a [`DecodePipeline`] handles the sample counts and
+/// sample data of deep parts
+///
+/// Flags are combined with `|`, e.g.
+/// `DecodeFlags::SAMPLE_COUNTS_AS_INDIVIDUAL | DecodeFlags::SAMPLE_DATA_ONLY`.
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct DecodeFlags(u16);
+
+impl DecodeFlags {
+    /// Store the number of samples in each pixel in the sample count table,
+    /// rather than the running total the file stores
+    pub const SAMPLE_COUNTS_AS_INDIVIDUAL: DecodeFlags = DecodeFlags(1 << 0);
+    /// Each channel's decode pointer points to an array of pointers, one per
+    /// pixel, to where that pixel's samples should be written
+    pub const NON_IMAGE_DATA_AS_POINTERS: DecodeFlags = DecodeFlags(1 << 1);
+    /// Only decode the sample count table, skipping the sample data
+    pub const SAMPLE_DATA_ONLY: DecodeFlags = DecodeFlags(1 << 2);
+
+    /// No flags set
+    ///
+    pub const fn empty() -> DecodeFlags {
+        DecodeFlags(0)
+    }
+
+    /// The flags as the C core's bit field
+    ///
+    pub const fn bits(&self) -> u16 {
+        self.0
+    }
+
+    /// Are all the flags in `other` set in `self`?
+    ///
+    pub const fn contains(&self, other: DecodeFlags) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl std::ops::BitOr for DecodeFlags {
+    type Output = DecodeFlags;
+
+    fn bitor(self, rhs: DecodeFlags) -> DecodeFlags {
+        DecodeFlags(self.0 | rhs.0)
+    }
+}
+
+/// The samples of the pixels in a chunk of a deep part, with a separate
+/// buffer per channel, filled by [`DecodePipeline::decode_deep`] and written
+/// by [`WriteContext::encode_deep`]
+///
+/// A `DeepSamples` may be passed to `decode_deep` for each chunk of a part in
+/// turn, re-using its buffers.
+///
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct DeepSamples<T> {
+    /// Width of the chunk in pixels
+    pub width: usize,
+    /// Height of the chunk in pixels
+    pub height: usize,
+    /// The names of the channels, in the same order as `samples`
+    pub channels: Vec<String>,
+    /// The index of the first sample of each pixel in each channel's samples,
+    /// a line at a time, followed by the total number of samples
+    pub sample_offsets: Vec<usize>,
+    /// The samples of each channel, with each pixel's samples one after the
+    /// other
+    pub samples: Vec<Vec<T>>,
+}
+
+impl<T: ChannelElement> DeepSamples<T> {
+    /// Create an empty set of buffers to decode into
+    ///
+    pub fn new() -> DeepSamples<T> {
+        DeepSamples {
+            width: 0,
+            height: 0,
+            channels: Vec::new(),
+            sample_offsets: vec![0],
+            samples: Vec::new(),
+        }
+    }
+
+    /// The number of samples in the pixel at (`x`, `y`), relative to the
+    /// top-left corner of the chunk
+    ///
+    /// # Panics
+    /// * If `x` or `y` are outside the chunk
+    ///
+    pub fn sample_count(&self, x: usize, y: usize) -> usize {
+        let range = self.pixel_range(x, y);
+        range.end - range.start
+    }
+
+    /// The samples of channel `channel`, an index into `channels`, in the
+    /// pixel at (`x`, `y`), relative to the top-left corner of the chunk
+    ///
+    /// # Panics
+    /// * If `x` or `y` are outside the chunk, or `channel` is out of range
+    ///
+    pub fn pixel(&self, channel: usize, x: usize, y: usize) -> &[T] {
+        &self.samples[channel][self.pixel_range(x, y)]
+    }
+
+    fn pixel_range(&self, x: usize, y: usize) -> Range<usize> {
+        assert!(x < self.width && y < self.height, "pixel out of bounds");
+        let i = y * self.width + x;
+        self.sample_offsets[i]..self.sample_offsets[i + 1]
+    }
+
+    /// The index in `channels` of the channel called `name`
+    ///
+    pub fn channel_index(&self, name: &str) -> Option<usize> {
+        self.channels.iter().position(|c| c == name)
+    }
+}
+
+/// Something wrong with the depths of a sample
+///
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DepthProblem {
+    /// The sample's Z or ZBack is NaN
+    NotANumber,
+    /// The sample's ZBack is in front of its Z
+    ZBackBeforeZ,
+    /// The sample is in front of the sample before it in the pixel
+    Unsorted,
+}
+
+/// A problem with the depths of a single sample, found by
+/// [`DeepSamples::check_depths`]
+///
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DepthIssue {
+    /// Position of the pixel, relative to the top-left corner of the chunk
+    pub x: usize,
+    pub y: usize,
+    /// Index of the sample within the pixel
+    pub sample: usize,
+    pub problem: DepthProblem,
+}
+
+impl<T: ChannelElement + Into<f64>> DeepSamples<T> {
+    /// The front and back depths of every sample, from the "Z" channel and
+    /// the "ZBack" channel if there is one
+    ///
+    fn depths(&self) -> Result<(&[T], &[T])> {
+        let z = self.channel_index("Z").ok_or(Error::InvalidArgument)?;
+        let z_back = self.channel_index("ZBack").unwrap_or(z);
+        Ok((&self.samples[z], &self.samples[z_back]))
+    }
+
+    /// Check that every pixel's samples are sorted front to back, by Z and
+    /// then by ZBack, and that no sample's ZBack is in front of its Z, as the
+    /// OpenEXR deep data conventions require
+    ///
+    /// Samples without a "ZBack" channel are points at their Z.
+    ///
+    /// # Errors
+    /// * `[Error::InvalidArgument]` - If there is no "Z" channel
+    ///
+    pub fn check_depths(&self) -> Result<Vec<DepthIssue>> {
+        let (z, z_back) = self.depths()?;
+
+        let mut issues = Vec::new();
+        for (i, w) in self.sample_offsets.windows(2).enumerate() {
+            for s in w[0]..w[1] {
+                let (front, back) = (z[s].into(), z_back[s].into());
+                let problem = if front.is_nan() || back.is_nan() {
+                    Some(DepthProblem::NotANumber)
+                } else if back < front {
+                    Some(DepthProblem::ZBackBeforeZ)
+                } else if s > w[0]
+                    && (front, back) < (z[s - 1].into(), z_back[s - 1].into())
+                {
+                    Some(DepthProblem::Unsorted)
+                } else {
+                    None
+                };
+
+                if let Some(problem) = problem {
+                    issues.push(DepthIssue {
+                        x: i % self.width,
+                        y: i / self.width,
+                        sample: s - w[0],
+                        problem,
+                    });
+                }
+            }
+        }
+
+        Ok(issues)
+    }
+
+    /// Sort the samples of every pixel front to back, by Z and then by
+    /// ZBack, keeping samples at the same depths in their existing order
+    ///
+    /// Samples with NaN depths are moved to the back.
+    ///
+    /// # Errors
+    /// * `[Error::InvalidArgument]` - If there is no "Z" channel
+    ///
+    pub fn sort_samples(&mut self) -> Result<()> {
+        let mut order = Vec::new();
+        let mut scratch = Vec::new();
+        for i in 0..self.sample_offsets.len().saturating_sub(1) {
+            let range = self.sample_offsets[i]..self.sample_offsets[i + 1];
+            let (z, z_back) = self.depths()?;
+            let key =
+                |s: usize| -> (f64, f64) { (z[s].into(), z_back[s].into()) };
+
+            order.clear();
+            order.extend(range.clone());
+            order.sort_by(|&a, &b| {
+                let (a, b) = (key(a), key(b));
+                match (
+                    a.0.is_nan() || a.1.is_nan(),
+                    b.0.is_nan() || b.1.is_nan(),
+                ) {
+                    (false, false) => {
+                        a.partial_cmp(&b).unwrap_or(std::cmp::Ordering::Equal)
+                    }
+                    (a, b) => a.cmp(&b),
+                }
+            });
+            if order.iter().copied().eq(range.clone()) {
+                continue;
+            }
+
+            for channel in &mut self.samples {
+                scratch.clear();
+                scratch.extend(order.iter().map(|&s| channel[s]));
+                channel[range.clone()].copy_from_slice(&scratch);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Combine the samples of `self` and `other`, e.g. two deep renders of
+    /// the same chunk, into a new set of samples with each pixel's samples
+    /// sorted front to back
+    ///
+    /// # Errors
+    /// * `[Error::InvalidArgument]` - If the two don't cover the same size of
+    /// chunk with the same channels, or there is no "Z" channel
+    ///
+    pub fn merge(&self, other: &DeepSamples<T>) -> Result<DeepSamples<T>> {
+        if self.width != other.width
+            || self.height != other.height
+            || self.channels != other.channels
+            || self.sample_offsets.len() != other.sample_offsets.len()
+        {
+            return Err(Error::InvalidArgument);
+        }
+
+        let mut sample_offsets = Vec::with_capacity(self.sample_offsets.len());
+        sample_offsets.push(0);
+        let mut samples = vec![Vec::new(); self.channels.len()];
+        for (a, b) in self
+            .sample_offsets
+            .windows(2)
+            .zip(other.sample_offsets.windows(2))
+        {
+            for (c, merged) in samples.iter_mut().enumerate() {
+                merged.extend_from_slice(&self.samples[c][a[0]..a[1]]);
+                merged.extend_from_slice(&other.samples[c][b[0]..b[1]]);
+            }
+            let last = sample_offsets[sample_offsets.len() - 1];
+            sample_offsets.push(last + (a[1] - a[0]) + (b[1] - b[0]));
+        }
+
+        let mut merged = DeepSamples {
+            width: self.width,
+            height: self.height,
+            channels: self.channels.clone(),
+            sample_offsets,
+            samples,
+        };
+        merged.sort_samples()?;
+        Ok(merged)
+    }
+}
+
+/// Holds the state needed to decode chunks of a part of a [`ReadContext`].
+///
+/// A pipeline is created in the [`Uninit`] state, then:
+/// 1. [`initialize`](DecodePipeline::initialize) sets it up for a part and its
+/// first chunk, giving a [`Ready`] pipeline whose channel outputs can be set
+/// with [`channels_mut`](DecodePipeline::channels_mut).
+/// 2. [`choose_default_routines`](DecodePipeline::choose_default_routines)
+/// picks the routines to decode to those outputs, giving a [`Runnable`]
+/// pipeline.
+/// 3. [`run`](DecodePipeline::run) decodes the chunk, after which
+/// [`update`](DecodePipeline::update) moves the pipeline on to the next chunk,
+/// back in the [`Ready`] state.
+///
+/// Any intermediate memory held by the pipeline is freed when it is dropped,
+/// or explicitly with [`DecodePipeline::destroy`] or
+/// [`ReadContext::decoding_destroy`] to find out whether that succeeded.
+///
+pub struct DecodePipeline<'c, S: PipelineState = Runnable> {
+    // We have to box this because exr_decode_pipeline_t uses a small-buffer
+    // optimization internally
+    inner: Box<sys::exr_decode_pipeline_t>,
+    ctx: &'c ReadContext,
+    marker: PhantomData<S>,
+}
+
+// Safety: the pipeline owns its intermediate buffers, and only refers to the
+// context, which is Sync, and to the outputs set on its channels, which are
+// only written to while it is run
+unsafe impl<'c, S: PipelineState> Send for DecodePipeline<'c, S> {}
+unsafe impl<'c, S: PipelineState> Sync for DecodePipeline<'c, S> {}
+
+impl<'c, S: PipelineState> DecodePipeline<'c, S> {
     pub fn channels(&self) -> &[ChannelInfo] {
         unsafe {
             std::slice::from_raw_parts(
-                self.0.channels as *const ChannelInfo,
-                self.0.channel_count as usize,
+                self.inner.channels as *const ChannelInfo,
+                self.inner.channel_count as usize,
             )
         }
     }
 
-    pub fn channels_mut(&mut self) -> &mut [ChannelInfo] {
+    /// Index of the part the pipeline is decoding
+    ///
+    pub fn part_index(&self) -> usize {
+        self.inner.part_index as usize
+    }
+
+    /// The flags controlling how deep data is decoded
+    ///
+    pub fn decode_flags(&self) -> DecodeFlags {
+        DecodeFlags(self.inner.decode_flags)
+    }
+
+    /// Free any intermediate memory in the decoding pipeline
+    ///
+    /// This does *not* free any pointers referred to in the channel info
+    /// areas, but rather only the intermediate buffers and memory needed
+    /// for the structure itself.
+    ///
+    pub fn destroy(self) -> Result<()> {
+        let mut pipeline = ManuallyDrop::new(self);
         unsafe {
-            std::slice::from_raw_parts_mut(
-                self.0.channels as *mut ChannelInfo,
-                self.0.channel_count as usize,
+            let result = sys::exr_decoding_destroy(
+                pipeline.ctx.inner,
+                &mut *pipeline.inner,
             )
+            .ok_in(pipeline.ctx.inner, ());
+            // we're bypassing Drop so have to free the box ourselves
+            std::ptr::drop_in_place(&mut pipeline.inner);
+            result
+        }
+    }
+
+    /// Stop every channel from being decoded, e.g. so that no channel is left
+    /// pointing at a buffer that is about to be freed
+    ///
+    fn clear_decode_to(&mut self) {
+        for i in 0..self.inner.channel_count.max(0) as usize {
+            // Safety: channels holds channel_count entries
+            unsafe {
+                (*self.inner.channels.add(i)).__bindgen_anon_1.decode_to_ptr =
+                    std::ptr::null_mut();
+            }
+        }
+    }
+
+    /// Move the pipeline into state `T`, keeping its contents
+    ///
+    fn transition<T: PipelineState>(self) -> DecodePipeline<'c, T> {
+        let pipeline = ManuallyDrop::new(self);
+        // Safety: pipeline is never dropped, so ownership of the box moves
+        // into the new pipeline
+        DecodePipeline {
+            inner: unsafe { std::ptr::read(&pipeline.inner) },
+            ctx: pipeline.ctx,
+            marker: PhantomData,
         }
     }
 }
 
-impl Default for DecodePipeline {
-    fn default() -> Self {
-        let d = std::mem::MaybeUninit::<sys::exr_decode_pipeline_t>::zeroed();
-        DecodePipeline(Box::new(unsafe { d.assume_init() }))
+impl<'c, S: PipelineState> Drop for DecodePipeline<'c, S> {
+    fn drop(&mut self) {
+        unsafe {
+            let _ = sys::exr_decoding_destroy(self.ctx.inner, &mut *self.inner);
+        }
     }
 }
 
-impl ReadContext {
+impl<'c> DecodePipeline<'c, Uninit> {
+    /// Create a new, empty pipeline for decoding chunks from `ctx`
+    ///
+    pub fn new(ctx: &'c ReadContext) -> DecodePipeline<'c, Uninit> {
+        let d = std::mem::MaybeUninit::<sys::exr_decode_pipeline_t>::zeroed();
+        DecodePipeline {
+            inner: Box::new(unsafe { d.assume_init() }),
+            ctx,
+            marker: PhantomData,
+        }
+    }
+
     /// Initialize the decoding pipeline structure with the channel info
     /// for the specified part, and the first block to be read.
     ///
-    pub fn decoding_initialize(
-        &self,
+    /// # Errors
+    /// * `[Error::FeatureNotImplemented]` - If the part uses a compression
+    /// type these bindings do not know about (see [`Compression::Unknown`])
+    ///
+    pub fn initialize(
+        mut self,
         part_index: usize,
         chunk_info: &ChunkInfo,
-        decode_pipeline: &mut DecodePipeline,
-    ) -> Result<()> {
+    ) -> Result<DecodePipeline<'c, Ready>> {
+        if !self.ctx.compression(part_index)?.is_known() {
+            return Err(Error::FeatureNotImplemented);
+        }
+
         unsafe {
             sys::exr_decoding_initialize(
-                self.inner,
-                part_index.try_into().unwrap(),
+                self.ctx.inner,
+                c_part_index(part_index)?,
                 chunk_info as *const ChunkInfo as *const sys::exr_chunk_info_t,
-                &mut *decode_pipeline.0,
+                &mut *self.inner,
+            )
+            .ok_in(self.ctx.inner, ())
+            .map_err(|e| {
+                e.with_part(part_index).with_chunk(chunk_info.idx as usize)
+            })?;
+        }
+
+        Ok(self.transition())
+    }
+}
+
+impl<'c> DecodePipeline<'c, Ready> {
+    /// Get the channels in the chunk so that their outputs can be set
+    ///
+    pub fn channels_mut(&mut self) -> &mut [ChannelInfo] {
+        unsafe {
+            std::slice::from_raw_parts_mut(
+                self.inner.channels as *mut ChannelInfo,
+                self.inner.channel_count as usize,
+            )
+        }
+    }
+
+    /// Set up the pipeline to decode the channels called `names` into
+    /// `buffer`, interleaved in the given order and converted to `T`
+    ///
+    /// `buffer` holds the pixels of the current chunk only, so must be at
+    /// least `names.len() * width * height` of the chunk, with lines packed
+    /// one after the other. Channels in the part that are not in `names` are
+    /// not decoded, and names that do not match a channel in the part leave
+    /// their slot in the buffer untouched.
+    ///
+    /// # Errors
+    /// * `[Error::InvalidArgument]` - If `buffer` is too small for the chunk
+    ///
+    pub fn select_interleaved<T: ChannelElement>(
+        &mut self,
+        names: &[&str],
+        buffer: &mut [T],
+    ) -> Result<()> {
+        let num_channels = names.len();
+        let line_stride = num_channels * self.inner.chunk.width.max(0) as usize;
+
+        for channel in self.channels_mut() {
+            match names.iter().position(|n| *n == channel.name()) {
+                Some(offset) => {
+                    let dest = buffer
+                        .get_mut(offset..)
+                        .ok_or(Error::InvalidArgument)?;
+                    channel.set_decode_to_slice(
+                        dest,
+                        num_channels,
+                        line_stride,
+                    )?;
+                }
+                None => unsafe { channel.set_decode_to(std::ptr::null_mut()) },
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Like [`DecodePipeline::select_interleaved`], but any channel in
+    /// `channels` that does not exist in the part has its slot in `buffer`
+    /// filled with the accompanying value instead, as the C++ library's
+    /// `FrameBuffer` does
+    ///
+    /// This allows e.g. reading RGBA from a file with only RGB by passing a
+    /// fill of 1.0 for "A".
+    ///
+    /// # Errors
+    /// * `[Error::InvalidArgument]` - If `buffer` is too small for the chunk
+    ///
+    pub fn select_interleaved_with_fill<T: ChannelElement>(
+        &mut self,
+        channels: &[(&str, T)],
+        buffer: &mut [T],
+    ) -> Result<()> {
+        let num_channels = channels.len();
+        if num_channels == 0 {
+            return self.select_interleaved(&[], buffer);
+        }
+
+        let num_pixels = self.inner.chunk.width.max(0) as usize
+            * self.inner.chunk.height.max(0) as usize;
+        if buffer.len() < num_pixels * num_channels {
+            return Err(Error::InvalidArgument);
+        }
+
+        let names = channels.iter().map(|(n, _)| *n).collect::<Vec<_>>();
+        self.select_interleaved(&names, buffer)?;
+
+        for (offset, (name, fill)) in channels.iter().enumerate() {
+            if !self.channels().iter().any(|c| c.name() == *name) {
+                buffer[offset..]
+                    .iter_mut()
+                    .step_by(num_channels)
+                    .take(num_pixels)
+                    .for_each(|v| *v = *fill);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Decode the current chunk with each channel named in `targets` written
+    /// to its own contiguous buffer, then return the pipeline ready to be
+    /// [`update`](DecodePipeline::update)d for the next chunk
+    ///
+    /// Each target's buffer holds the channel's pixels for the current chunk
+    /// only, one line after another, so must be at least the channel's
+    /// `width * height` (which takes the channel's sampling into account).
+    /// Channels in the part that are not targeted are not decoded, and
+    /// targets that do not match a channel in the part are left untouched.
+    ///
+    /// # Errors
+    /// * `[Error::InvalidArgument]` - If a target's buffer is too small for
+    /// the channel
+    ///
+    pub fn decode_planar<T: ChannelElement>(
+        mut self,
+        targets: &mut [PlanarTarget<T>],
+    ) -> Result<DecodePipeline<'c, Runnable>> {
+        for channel in self.channels_mut() {
+            let width = channel.width();
+            match targets.iter_mut().find(|t| t.name == channel.name()) {
+                Some(target) => {
+                    channel.set_decode_to_slice(target.data, 1, width)?
+                }
+                None => unsafe { channel.set_decode_to(std::ptr::null_mut()) },
+            }
+        }
+
+        let mut runnable = self.choose_default_routines()?;
+        // Safety: every channel is either skipped or has been checked to fit
+        // in its target, all of which we hold mutably for the whole call
+        unsafe { runnable.run()? };
+        Ok(runnable)
+    }
+
+    /// Set the flags controlling how deep data is decoded
+    ///
+    /// The routines chosen depend on the flags, so these must be set before
+    /// calling [`DecodePipeline::choose_default_routines`].
+    ///
+    /// With [`DecodeFlags::NON_IMAGE_DATA_AS_POINTERS`] set, the decode
+    /// pointers are read as tables of pointers which cannot be checked, so the
+    /// pipeline can then only be run with [`DecodePipeline::run`] or
+    /// [`DecodePipeline::run_packed`].
+    ///
+    pub fn set_decode_flags(&mut self, flags: DecodeFlags) {
+        self.inner.decode_flags = flags.bits();
+    }
+
+    /// Decode the samples of the current deep chunk into `samples`, converted
+    /// to `T`, with a buffer for each channel named in `names`, then return
+    /// the pipeline ready to be [`update`](DecodePipeline::update)d for the
+    /// next chunk
+    ///
+    /// The chunk is decoded twice, first for its sample counts, to size the
+    /// buffers, then for the samples themselves. Names that do not match a
+    /// channel in the part have their samples set to `T::default()`. Any
+    /// decode flags previously set are replaced.
+    ///
+    /// # Errors
+    /// * `[Error::InvalidArgument]` - If the part does not hold deep data, or
+    /// the chunk's sample counts are corrupt
+    ///
+    pub fn decode_deep<T: ChannelElement>(
+        mut self,
+        names: &[&str],
+        samples: &mut DeepSamples<T>,
+    ) -> Result<DecodePipeline<'c, Runnable>> {
+        let part_index = self.part_index();
+        if !matches!(
+            self.ctx.storage(part_index)?,
+            Storage::DeepScanline | Storage::DeepTiled
+        ) {
+            return Err(Error::InvalidArgument);
+        }
+
+        // first pass: just the sample counts
+        self.clear_decode_to();
+        self.set_decode_flags(
+            DecodeFlags::SAMPLE_COUNTS_AS_INDIVIDUAL
+                | DecodeFlags::SAMPLE_DATA_ONLY,
+        );
+        unsafe {
+            sys::exr_decoding_choose_default_routines(
+                self.ctx.inner,
+                self.inner.part_index,
+                &mut *self.inner,
             )
-            .ok(())
+            .ok_in(self.ctx.inner, ())?;
+            crate::trace::decoding_run(
+                self.ctx.inner,
+                self.inner.part_index,
+                &mut *self.inner,
+            )
+            .ok_in(self.ctx.inner, ())?;
+        }
+
+        let width = self.inner.chunk.width.max(0) as usize;
+        let height = self.inner.chunk.height.max(0) as usize;
+        let counts = self
+            .sample_count_table()
+            .filter(|t| t.len() == width * height)
+            .ok_or(Error::InvalidArgument)?;
+
+        samples.width = width;
+        samples.height = height;
+        samples.channels = names.iter().map(|n| n.to_string()).collect();
+        samples.sample_offsets.clear();
+        samples.sample_offsets.push(0);
+        let mut total = 0usize;
+        for &count in counts {
+            total = usize::try_from(count)
+                .ok()
+                .and_then(|c| total.checked_add(c))
+                .ok_or(Error::InvalidArgument)?;
+            samples.sample_offsets.push(total);
+        }
+        samples.samples.resize_with(names.len(), Vec::new);
+        for buffer in &mut samples.samples {
+            buffer.clear();
+            buffer.resize(total, T::default());
+        }
+
+        // second pass: the samples, written through an array of pointers to
+        // each pixel's samples for each channel
+        let offsets = &samples.sample_offsets;
+        let mut pointers = Vec::new();
+        for channel in self.channels_mut() {
+            let target = names.iter().position(|n| *n == channel.name());
+            let buffer = match target {
+                Some(i) => &mut samples.samples[i],
+                None => {
+                    unsafe { channel.set_decode_to(std::ptr::null_mut()) };
+                    continue;
+                }
+            };
+
+            let base = buffer.as_mut_ptr();
+            let table = offsets
+                .windows(2)
+                .map(|w| match w[1] - w[0] {
+                    0 => std::ptr::null_mut(),
+                    // Safety: the offsets are within the buffer
+                    _ => unsafe { base.add(w[0]) },
+                })
+                .collect::<Vec<*mut T>>();
+
+            let pointer_bytes = std::mem::size_of::<*mut T>();
+            channel.set_user_data_type(T::PIXEL_TYPE);
+            channel.set_user_bytes_per_element(std::mem::size_of::<T>());
+            channel.set_user_pixel_stride(pointer_bytes);
+            channel.set_user_line_stride(pointer_bytes * width);
+            unsafe { channel.set_decode_to(table.as_ptr() as *mut u8) };
+            pointers.push(table);
         }
+
+        self.set_decode_flags(
+            DecodeFlags::SAMPLE_COUNTS_AS_INDIVIDUAL
+                | DecodeFlags::NON_IMAGE_DATA_AS_POINTERS,
+        );
+        let result = self.choose_default_routines().and_then(|mut runnable| {
+            // Safety: every pixel's pointer has room for its samples in the
+            // buffers, which we hold mutably for the whole call
+            unsafe { runnable.run()? };
+            Ok(runnable)
+        });
+
+        // the pointer tables are about to be dropped
+        let mut runnable = result?;
+        runnable.clear_decode_to();
+        drop(pointers);
+
+        Ok(runnable)
     }
 
     /// Given an initialized decode pipeline, find appropriate functions
     /// to read and shuffle / convert data into the defined channel outputs
     ///
-    /// Calling this is not required if custom routines will be used, or if
-    /// just the raw compressed data is desired. Although in that scenario,
-    /// it is probably easier to just read the chunk directly using \ref
-    /// exr_read_chunk
+    /// The routines chosen depend on the channel outputs, so these must be set
+    /// up before calling this.
     ///
-    pub fn decoding_choose_default_routines(
-        &self,
-        part_index: usize,
-        decode_pipeline: &mut DecodePipeline,
-    ) -> Result<()> {
+    pub fn choose_default_routines(
+        mut self,
+    ) -> Result<DecodePipeline<'c, Runnable>> {
         unsafe {
             sys::exr_decoding_choose_default_routines(
-                self.inner,
-                part_index.try_into().unwrap(),
-                &mut *decode_pipeline.0,
+                self.ctx.inner,
+                self.inner.part_index,
+                &mut *self.inner,
             )
-            .ok(())
+            .ok_in(self.ctx.inner, ())?;
+        }
+
+        Ok(self.transition())
+    }
+}
+
+impl<'c> DecodePipeline<'c, Runnable> {
+    /// Execute the decoding pipeline
+    ///
+    /// # Safety
+    /// The pointers set with [`ChannelInfo::set_decode_to`] on each channel
+    /// of the pipeline must be valid for writes of the whole channel, as
+    /// described by its width, height and user strides.
+    ///
+    pub unsafe fn run(&mut self) -> Result<()> {
+        crate::trace::decoding_run(
+            self.ctx.inner,
+            self.inner.part_index,
+            &mut *self.inner,
+        )
+        .ok_in(self.ctx.inner, ())
+    }
+
+    /// Execute the decoding pipeline, after checking that every channel will
+    /// be decoded to somewhere within `dest`
+    ///
+    /// This is the safe alternative to [`DecodePipeline::run`] for when all
+    /// channels are decoded into the same buffer, e.g. one set up with
+    /// [`ChannelInfo::set_decode_to_slice`]. The check covers each channel's
+    /// decode pointer together with its width, height and user strides and
+    /// element size. Channels with no decode pointer set are skipped by the
+    /// decoder so are not checked.
+    ///
+    /// # Errors
+    /// * `[Error::InvalidArgument]` - If any channel would be written outside
+    /// of `dest`, or [`DecodeFlags::NON_IMAGE_DATA_AS_POINTERS`] is set
+    ///
+    pub fn run_checked<T: Pod>(&mut self, dest: &mut [T]) -> Result<()> {
+        self.check_outputs(dest)?;
+
+        // Safety: every channel's output lies within dest, which we hold
+        // exclusively for the duration of the call
+        unsafe { self.run() }
+    }
+
+    /// Execute the decoding pipeline on the packed data of the chunk that has
+    /// already been read, e.g. by a
+    /// [`ChunkPrefetcher`](crate::prefetch::ChunkPrefetcher), rather than
+    /// reading it from the file
+    ///
+    /// `packed_data` must hold the chunk's `packed_size` bytes and
+    /// `sample_counts` its `sample_count_table_size` bytes, which is none
+    /// unless the part is deep. Both are used in place, so are not copied.
+    ///
+    /// # Errors
+    /// * `[Error::InvalidArgument]` - If either buffer is too small for the
+    /// chunk the pipeline was last updated for
+    ///
+    /// # Safety
+    /// As for [`DecodePipeline::run`]
+    ///
+    pub unsafe fn run_packed(
+        &mut self,
+        packed_data: &mut [u8],
+        sample_counts: &mut [u8],
+    ) -> Result<()> {
+        if (packed_data.len() as u64) < self.inner.chunk.packed_size
+            || (sample_counts.len() as u64)
+                < self.inner.chunk.sample_count_table_size
+        {
+            return Err(Error::InvalidArgument);
         }
+
+        // Lend the buffers to the pipeline without it owning them, so it
+        // neither reads into them nor frees them, and put its own buffers
+        // back afterwards so they are re-used for later chunks
+        let inner = &mut *self.inner;
+        let packed_ptr = packed_data.as_mut_ptr() as *mut c_void;
+        let counts_ptr = sample_counts.as_mut_ptr() as *mut c_void;
+        let read_fn = inner.read_fn.replace(packed_read);
+        let packed = (inner.packed_buffer, inner.packed_alloc_size);
+        let counts = (
+            inner.packed_sample_count_table,
+            inner.packed_sample_count_alloc_size,
+        );
+        inner.packed_buffer = packed_ptr;
+        inner.packed_alloc_size = 0;
+        inner.packed_sample_count_table = counts_ptr;
+        inner.packed_sample_count_alloc_size = 0;
+
+        let result = self.run();
+
+        let inner = &mut *self.inner;
+        inner.read_fn = read_fn;
+        inner.packed_buffer = packed.0;
+        inner.packed_alloc_size = packed.1;
+        inner.packed_sample_count_table = counts.0;
+        inner.packed_sample_count_alloc_size = counts.1;
+        // uncompressed chunks are unpacked in place
+        if inner.unpacked_buffer == packed_ptr {
+            inner.unpacked_buffer = std::ptr::null_mut();
+            inner.unpacked_alloc_size = 0;
+        }
+
+        result
     }
 
-    ///  Given a decode pipeline previously initialized, update it for the
+    /// Execute the decoding pipeline on packed data that has already been
+    /// read, as for [`DecodePipeline::run_packed`], after checking that every
+    /// channel will be decoded to somewhere within `dest`, as for
+    /// [`DecodePipeline::run_checked`]
+    ///
+    /// # Errors
+    /// * `[Error::InvalidArgument]` - If either buffer is too small for the
+    /// chunk, any channel would be written outside of `dest`, or
+    /// [`DecodeFlags::NON_IMAGE_DATA_AS_POINTERS`] is set
+    ///
+    pub fn run_packed_checked<T: Pod>(
+        &mut self,
+        packed_data: &mut [u8],
+        sample_counts: &mut [u8],
+        dest: &mut [T],
+    ) -> Result<()> {
+        self.check_outputs(dest)?;
+
+        // Safety: every channel's output lies within dest, which we hold
+        // exclusively for the duration of the call
+        unsafe { self.run_packed(packed_data, sample_counts) }
+    }
+
+    /// Check that every channel with a decode pointer set will be decoded to
+    /// somewhere within `dest`
+    ///
+    /// Decode pointers holding tables of pointers are rejected outright, as
+    /// where those point cannot be checked.
+    ///
+    fn check_outputs<T: Pod>(&self, dest: &[T]) -> Result<()> {
+        if DecodeFlags(self.inner.decode_flags)
+            .contains(DecodeFlags::NON_IMAGE_DATA_AS_POINTERS)
+        {
+            return Err(Error::InvalidArgument);
+        }
+
+        let dest_start = dest.as_ptr() as usize;
+        let dest_end = dest_start + std::mem::size_of_val(dest);
+
+        for channel in self.channels() {
+            if let Some(range) = channel.decode_to_range()? {
+                if range.start < dest_start || range.end > dest_end {
+                    return Err(Error::InvalidArgument);
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Read function for [`DecodePipeline::run_packed`], where the packed data
+/// is already in the pipeline's buffers
+///
+unsafe extern "C" fn packed_read(
+    _decode: *mut sys::exr_decode_pipeline_t,
+) -> sys::exr_result_t {
+    sys::exr_result_t::from_code(sys::exr_error_code_t::EXR_ERR_SUCCESS)
+}
+
+impl<'c, S: Initialized> DecodePipeline<'c, S> {
+    /// Given a decode pipeline previously initialized, update it for the
     /// new chunk to be read.
     ///
     /// In this manner, memory buffers can be re-used to avoid continual
-    /// allocations. Further, it allows the previous choices for
-    /// the various functions to be quickly re-used.
+    /// allocations. The channel outputs then need setting for the new chunk
+    /// and the routines choosing again before the pipeline can be run.
     ///
-    pub fn decoding_update(
-        &self,
-        part_index: usize,
+    pub fn update(
+        mut self,
         chunk_info: &ChunkInfo,
-        decode_pipeline: &mut DecodePipeline,
-    ) -> Result<()> {
+    ) -> Result<DecodePipeline<'c, Ready>> {
         unsafe {
             sys::exr_decoding_update(
-                self.inner,
-                part_index.try_into().unwrap(),
+                self.ctx.inner,
+                self.inner.part_index,
                 chunk_info as *const ChunkInfo as *const sys::exr_chunk_info_t,
-                &mut *decode_pipeline.0,
+                &mut *self.inner,
             )
-            .ok(())
+            .ok_in(self.ctx.inner, ())?;
+        }
+
+        Ok(self.transition())
+    }
+
+    /// The sample count table of a deep part, holding an entry for each pixel
+    /// of the chunk a line at a time, or `None` if the pipeline has not been
+    /// run yet, or the part is not deep
+    ///
+    /// The table is filled when the pipeline is run, so after an
+    /// [`update`](DecodePipeline::update) it still holds the counts of the
+    /// previous chunk until the pipeline is run again.
+    ///
+    /// Each entry is the running total of the samples up to and including
+    /// the pixel, as stored in the file, unless
+    /// [`DecodeFlags::SAMPLE_COUNTS_AS_INDIVIDUAL`] was set, in which case it
+    /// is the number of samples in the pixel.
+    ///
+    pub fn sample_count_table(&self) -> Option<&[i32]> {
+        if self.inner.sample_count_table.is_null() {
+            return None;
+        }
+
+        let len = self.inner.chunk.width.max(0) as usize
+            * self.inner.chunk.height.max(0) as usize;
+        if self.inner.sample_count_alloc_size < len * std::mem::size_of::<i32>()
+        {
+            return None;
         }
+
+        // Safety: the table has been allocated for every pixel in the chunk
+        Some(unsafe {
+            std::slice::from_raw_parts(self.inner.sample_count_table, len)
+        })
     }
+}
 
-    /// Execute the decoding pipeline
+impl ReadContext {
+    /// Create a decoding pipeline initialized with the channel info
+    /// for the specified part, and the first block to be read.
+    ///
+    /// This is equivalent to calling [`DecodePipeline::new`] followed by
+    /// [`DecodePipeline::initialize`].
     ///
-    pub unsafe fn decoding_run(
+    /// # Errors
+    /// * `[Error::FeatureNotImplemented]` - If the part uses a compression
+    /// type these bindings do not know about (see [`Compression::Unknown`])
+    ///
+    pub fn decoding_initialize(
         &self,
         part_index: usize,
-        decode_pipeline: &mut DecodePipeline,
-    ) -> Result<()> {
-        unsafe {
-            sys::exr_decoding_run(
-                self.inner,
-                part_index.try_into().unwrap(),
-                &mut *decode_pipeline.0,
-            )
-            .ok(())
-        }
+        chunk_info: &ChunkInfo,
+    ) -> Result<DecodePipeline<'_, Ready>> {
+        DecodePipeline::new(self).initialize(part_index, chunk_info)
     }
 
     /// Free any intermediate memory in the decoding pipeline
@@ -137,13 +1029,205 @@ impl ReadContext {
     /// areas, but rather only the intermediate buffers and memory needed
     /// for the structure itself.
     ///
-    pub fn decoding_destroy(
+    /// This is equivalent to [`DecodePipeline::destroy`]
+    ///
+    pub fn decoding_destroy<S: PipelineState>(
         &self,
-        decode_pipeline: DecodePipeline,
+        decode_pipeline: DecodePipeline<'_, S>,
     ) -> Result<()> {
-        let mut decode_pipeline = decode_pipeline;
-        unsafe {
-            sys::exr_decoding_destroy(self.inner, &mut *decode_pipeline.0).ok(())
+        decode_pipeline.destroy()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate as exr;
+    use exr::decode::{DecodeFlags, DeepSamples, DepthIssue, DepthProblem};
+    use exr::validate::ChunkLocation;
+    use std::path::Path;
+
+    #[test]
+    fn decode_deep() -> Result<(), exr::Error> {
+        let images = Path::new(
+            &std::env::var("CARGO_MANIFEST_DIR")
+                .expect("CARGO_MANIFEST_DIR not set"),
+        )
+        .join("images");
+
+        let ctx =
+            exr::context::ReadContext::new(images.join("deep_plane.exr"))?;
+        let names = ctx
+            .channels(0)?
+            .iter()
+            .map(|c| c.name().to_string())
+            .collect::<Vec<_>>();
+        let mut names = names.iter().map(|n| n.as_str()).collect::<Vec<_>>();
+        names.push("missing");
+
+        let mut samples = DeepSamples::<f32>::new();
+        for entry in ctx.chunk_table(0)? {
+            let chunk_info = match entry.location {
+                ChunkLocation::Scanline { y } => {
+                    ctx.read_scanline_chunk_info(0, y)?
+                }
+                ChunkLocation::Tile {
+                    x,
+                    y,
+                    level_x,
+                    level_y,
+                } => ctx.read_tile_chunk_info(0, x, y, level_x, level_y)?,
+            };
+
+            let mut pipeline = ctx.decoding_initialize(0, &chunk_info)?;
+            pipeline.set_decode_flags(DecodeFlags::SAMPLE_DATA_ONLY);
+            assert_eq!(pipeline.decode_flags(), DecodeFlags::SAMPLE_DATA_ONLY);
+
+            let pipeline = pipeline.decode_deep(&names, &mut samples)?;
+            assert!(pipeline
+                .decode_flags()
+                .contains(DecodeFlags::SAMPLE_COUNTS_AS_INDIVIDUAL));
+
+            let num_pixels = samples.width * samples.height;
+            assert_eq!(
+                num_pixels,
+                (chunk_info.width * chunk_info.height) as usize
+            );
+            assert_eq!(samples.sample_offsets.len(), num_pixels + 1);
+            let total = samples.sample_offsets[num_pixels];
+            assert_eq!(samples.samples.len(), names.len());
+            assert!(samples.samples.iter().all(|s| s.len() == total));
+            assert!(samples.samples[names.len() - 1].iter().all(|v| *v == 0.0));
+
+            let counts = pipeline.sample_count_table().unwrap();
+            for y in 0..samples.height {
+                for x in 0..samples.width {
+                    let count = counts[y * samples.width + x] as usize;
+                    assert_eq!(samples.sample_count(x, y), count);
+                    assert_eq!(samples.pixel(0, x, y).len(), count);
+                }
+            }
+        }
+
+        // flat parts have no samples to decode
+        let ctx = exr::context::ReadContext::new(images.join("ferris.exr"))?;
+        let chunk_info = ctx.read_scanline_chunk_info(0, 0)?;
+        let err = ctx
+            .decoding_initialize(0, &chunk_info)?
+            .decode_deep(&["R"], &mut DeepSamples::<f32>::new())
+            .err()
+            .expect("decoded a flat part as deep");
+        assert_eq!(err.kind(), exr::ErrorKind::InvalidArgument);
+
+        Ok(())
+    }
+
+    #[test]
+    fn run_checked_pointers() -> Result<(), exr::Error> {
+        let ctx = exr::context::ReadContext::new(
+            Path::new(
+                &std::env::var("CARGO_MANIFEST_DIR")
+                    .expect("CARGO_MANIFEST_DIR not set"),
+            )
+            .join("images")
+            .join("ferris.exr"),
+        )?;
+        let chunk_info = ctx.read_scanline_chunk_info(0, 0)?;
+        let num_pixels = (chunk_info.width * chunk_info.height) as usize;
+
+        let mut pipeline = ctx.decoding_initialize(0, &chunk_info)?;
+        let mut pixels = vec![0.0f32; num_pixels * 4];
+        pipeline.select_interleaved(&["R", "G", "B", "A"], &mut pixels)?;
+
+        // the decode pointers would be read as tables of pointers
+        pipeline.set_decode_flags(DecodeFlags::NON_IMAGE_DATA_AS_POINTERS);
+        let mut runnable = pipeline.choose_default_routines()?;
+        let err = runnable
+            .run_checked(&mut pixels)
+            .expect_err("ran with unchecked pointers");
+        assert_eq!(err.kind(), exr::ErrorKind::InvalidArgument);
+        let err = runnable
+            .run_packed_checked(&mut [], &mut [], &mut pixels)
+            .expect_err("ran with unchecked pointers");
+        assert_eq!(err.kind(), exr::ErrorKind::InvalidArgument);
+
+        Ok(())
+    }
+
+    #[test]
+    fn run_checked_element_size() -> Result<(), exr::Error> {
+        let ctx = exr::context::ReadContext::new(
+            Path::new(
+                &std::env::var("CARGO_MANIFEST_DIR")
+                    .expect("CARGO_MANIFEST_DIR not set"),
+            )
+            .join("images")
+            .join("ferris.exr"),
+        )?;
+        let chunk_info = ctx.read_scanline_chunk_info(0, 0)?;
+        let num_pixels = (chunk_info.width * chunk_info.height) as usize;
+
+        let mut pipeline = ctx.decoding_initialize(0, &chunk_info)?;
+        let mut pixels = vec![0.0f32; num_pixels * 4];
+        pipeline.select_interleaved(&["R", "G", "B", "A"], &mut pixels)?;
+
+        // floats would still be written, overrunning the range checked
+        for channel in pipeline.channels_mut() {
+            channel.set_user_data_type(exr::attr::PixelType::Float);
+            channel.set_user_bytes_per_element(2);
         }
+        let mut runnable = pipeline.choose_default_routines()?;
+        let err = runnable
+            .run_checked(&mut pixels)
+            .expect_err("ran with mismatched element size");
+        assert_eq!(err.kind(), exr::ErrorKind::InvalidArgument);
+
+        Ok(())
+    }
+
+    #[test]
+    fn deep_depths() -> Result<(), exr::Error> {
+        // a 2x1 chunk with two samples in the first pixel and one in the
+        // second
+        let mut samples = DeepSamples::<f32> {
+            width: 2,
+            height: 1,
+            channels: vec!["A".to_string(), "Z".to_string()],
+            sample_offsets: vec![0, 2, 3],
+            samples: vec![vec![0.5, 0.25, 1.0], vec![2.0, 1.0, 3.0]],
+        };
+        assert_eq!(
+            samples.check_depths()?,
+            vec![DepthIssue {
+                x: 0,
+                y: 0,
+                sample: 1,
+                problem: DepthProblem::Unsorted,
+            }]
+        );
+
+        samples.sort_samples()?;
+        assert!(samples.check_depths()?.is_empty());
+        assert_eq!(samples.pixel(0, 0, 0), &[0.25, 0.5]);
+        assert_eq!(samples.pixel(1, 0, 0), &[1.0, 2.0]);
+
+        let other = DeepSamples::<f32> {
+            width: 2,
+            height: 1,
+            channels: samples.channels.clone(),
+            sample_offsets: vec![0, 0, 1],
+            samples: vec![vec![0.75], vec![0.5]],
+        };
+        let merged = samples.merge(&other)?;
+        assert_eq!(merged.sample_count(0, 0), 2);
+        assert_eq!(merged.sample_count(1, 0), 2);
+        assert_eq!(merged.pixel(1, 1, 0), &[0.5, 3.0]);
+        assert_eq!(merged.pixel(0, 1, 0), &[0.75, 1.0]);
+
+        let mut no_z = DeepSamples::<f32>::new();
+        no_z.channels = vec!["A".to_string()];
+        let err = no_z.check_depths().expect_err("there is no Z channel");
+        assert_eq!(err.kind(), exr::ErrorKind::InvalidArgument);
+
+        Ok(())
     }
 }
@@ -0,0 +1,318 @@
+//! Library-wide settings used by every context created after they are
+//! changed, so applications can configure the library once at startup.
+//!
+//! Contexts that have already been created keep the values that were in
+//! effect when they were created.
+//!
+use crate::error::Error;
+use openexr_core_sys as sys;
+use std::alloc::{GlobalAlloc, Layout};
+use std::convert::TryFrom;
+use std::os::raw::c_void;
+use std::sync::atomic::{AtomicU8, Ordering};
+
+type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// Set the zlib compression level used by contexts created from now on when
+/// writing ZIP and ZIPS compressed parts
+///
+/// Levels run from 0 (no compression) to 9 (best compression), with -1
+/// meaning zlib's own default. Values outside that range are clamped to it.
+///
+pub fn set_zip_compression_level(level: i32) {
+    unsafe { sys::exr_set_default_zip_compression_level(level) }
+}
+
+/// Get the zlib compression level new contexts use when writing ZIP and ZIPS
+/// compressed parts
+///
+pub fn zip_compression_level() -> i32 {
+    let mut level = 0;
+    unsafe { sys::exr_get_default_zip_compression_level(&mut level) };
+    level
+}
+
+/// Set the quality used by contexts created from now on when writing DWAA
+/// and DWAB compressed parts
+///
+/// Higher values give larger files that are closer to the original. The
+/// library's default is 45, and values below 0 or above 100 are clamped.
+///
+pub fn set_dwa_compression_quality(quality: f32) {
+    unsafe { sys::exr_set_default_dwa_compression_quality(quality) }
+}
+
+/// Get the quality new contexts use when writing DWAA and DWAB compressed
+/// parts
+///
+pub fn dwa_compression_quality() -> f32 {
+    let mut quality = 0.0;
+    unsafe { sys::exr_get_default_dwa_compression_quality(&mut quality) };
+    quality
+}
+
+/// Set the largest image that contexts created from now on will accept when
+/// reading, in pixels, so that files with absurd data windows are rejected
+/// before anything is allocated for them
+///
+/// A size of 0 means there is no limit in that dimension, which is the
+/// library's default. This can be overridden for a single context with
+/// [`ContextOptions`](crate::context::ContextOptions).
+///
+pub fn set_maximum_image_size(width: usize, height: usize) {
+    unsafe {
+        sys::exr_set_default_maximum_image_size(c_size(width), c_size(height))
+    }
+}
+
+/// Get the largest image new contexts will accept, where 0 means no limit
+///
+pub fn maximum_image_size() -> (usize, usize) {
+    let mut width = 0;
+    let mut height = 0;
+    unsafe { sys::exr_get_default_maximum_image_size(&mut width, &mut height) };
+    (width.max(0) as usize, height.max(0) as usize)
+}
+
+/// Set the largest tile that contexts created from now on will accept when
+/// reading, in pixels
+///
+/// A size of 0 means there is no limit in that dimension, which is the
+/// library's default. This can be overridden for a single context with
+/// [`ContextOptions`](crate::context::ContextOptions).
+///
+pub fn set_maximum_tile_size(width: usize, height: usize) {
+    unsafe {
+        sys::exr_set_default_maximum_tile_size(c_size(width), c_size(height))
+    }
+}
+
+/// Get the largest tile new contexts will accept, where 0 means no limit
+///
+pub fn maximum_tile_size() -> (usize, usize) {
+    let mut width = 0;
+    let mut height = 0;
+    unsafe { sys::exr_get_default_maximum_tile_size(&mut width, &mut height) };
+    (width.max(0) as usize, height.max(0) as usize)
+}
+
+/// Convert a size limit for the C core, where anything too big to represent
+/// is as good as no limit
+///
+pub(crate) fn c_size(size: usize) -> i32 {
+    i32::try_from(size).unwrap_or(0)
+}
+
+/// No context has been created and no allocator has been set
+const ALLOC_UNUSED: u8 = 0;
+/// A context has been created, so the allocator can no longer be changed
+const ALLOC_LOCKED: u8 = 1;
+/// [`set_allocator`] is installing an allocator
+const ALLOC_INSTALLING: u8 = 2;
+/// An allocator has been set
+const ALLOC_INSTALLED: u8 = 3;
+
+static ALLOC_STATE: AtomicU8 = AtomicU8::new(ALLOC_UNUSED);
+static mut ALLOCATOR: Option<&'static (dyn GlobalAlloc + Sync)> = None;
+
+/// Route every allocation the C library makes through `allocator`, e.g. a
+/// tracking allocator that accounts for, or bounds, the memory used while
+/// decoding
+///
+/// The allocator is used by every context created afterwards, and can only be
+/// set once, before any context has been created, as memory allocated by one
+/// allocator must not be freed by another. Allocations that `allocator`
+/// refuses, by returning null, fail with [`Error::OutOfMemory`].
+///
+/// Use [`RustAllocator`] to send the library's allocations to the program's
+/// `#[global_allocator]`.
+///
+/// # Errors
+/// * `[Error::InvalidArgument]` - If a context has already been created or an
+/// allocator has already been set
+///
+pub fn set_allocator(
+    allocator: &'static (dyn GlobalAlloc + Sync),
+) -> Result<()> {
+    ALLOC_STATE
+        .compare_exchange(
+            ALLOC_UNUSED,
+            ALLOC_INSTALLING,
+            Ordering::AcqRel,
+            Ordering::Acquire,
+        )
+        .map_err(|_| Error::InvalidArgument)?;
+
+    // Safety: ALLOCATOR is only written here, once, while ALLOC_STATE keeps
+    // any context from being created, and so the trampolines from being
+    // called
+    unsafe {
+        ALLOCATOR = Some(allocator);
+        sys::exr_set_default_memory_routines(
+            Some(alloc_trampoline),
+            Some(free_trampoline),
+        );
+    }
+    ALLOC_STATE.store(ALLOC_INSTALLED, Ordering::Release);
+    Ok(())
+}
+
+/// A [`GlobalAlloc`] that forwards to the program's `#[global_allocator]`,
+/// for passing to [`set_allocator`]
+///
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RustAllocator;
+
+unsafe impl GlobalAlloc for RustAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        std::alloc::alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        std::alloc::dealloc(ptr, layout)
+    }
+}
+
+/// Stop the allocator from being changed, as a context is about to be
+/// created, waiting for [`set_allocator`] to finish if it is running
+///
+pub(crate) fn lock_allocator() {
+    loop {
+        match ALLOC_STATE.compare_exchange(
+            ALLOC_UNUSED,
+            ALLOC_LOCKED,
+            Ordering::AcqRel,
+            Ordering::Acquire,
+        ) {
+            Err(ALLOC_INSTALLING) => std::hint::spin_loop(),
+            _ => return,
+        }
+    }
+}
+
+/// Space kept before each allocation handed to the C library, to remember its
+/// size for [`free_trampoline`]. This is also the alignment of allocations, as
+/// `malloc` would give.
+///
+const ALLOC_HEADER: usize = 16;
+
+unsafe extern "C" fn alloc_trampoline(bytes: usize) -> *mut c_void {
+    let allocator = match ALLOCATOR {
+        Some(allocator) => allocator,
+        None => return std::ptr::null_mut(),
+    };
+    let layout = match bytes
+        .checked_add(ALLOC_HEADER)
+        .and_then(|size| Layout::from_size_align(size, ALLOC_HEADER).ok())
+    {
+        Some(layout) => layout,
+        None => return std::ptr::null_mut(),
+    };
+
+    let ptr = allocator.alloc(layout);
+    if ptr.is_null() {
+        return std::ptr::null_mut();
+    }
+    (ptr as *mut usize).write(layout.size());
+    ptr.add(ALLOC_HEADER) as *mut c_void
+}
+
+unsafe extern "C" fn free_trampoline(ptr: *mut c_void) {
+    let allocator = match ALLOCATOR {
+        Some(allocator) => allocator,
+        None => return,
+    };
+    if ptr.is_null() {
+        return;
+    }
+
+    let ptr = (ptr as *mut u8).sub(ALLOC_HEADER);
+    let size = (ptr as *const usize).read();
+    allocator
+        .dealloc(ptr, Layout::from_size_align_unchecked(size, ALLOC_HEADER));
+}
+
+#[cfg(test)]
+mod tests {
+    use crate as exr;
+    use exr::defaults;
+    use std::sync::{Mutex, MutexGuard};
+
+    /// Held by the tests that change the defaults, as they are shared by
+    /// every test running in the process
+    ///
+    static DEFAULTS: Mutex<()> = Mutex::new(());
+
+    fn lock_defaults() -> MutexGuard<'static, ()> {
+        // a test failing while holding the lock still restores the defaults
+        DEFAULTS
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Runs its closure when dropped, to put a default back even if the test
+    /// changing it fails
+    ///
+    struct Restore<F: FnMut()>(F);
+
+    impl<F: FnMut()> Drop for Restore<F> {
+        fn drop(&mut self) {
+            (self.0)()
+        }
+    }
+
+    #[test]
+    fn compression_defaults() {
+        let _lock = lock_defaults();
+
+        let level = defaults::zip_compression_level();
+        let _restore_level =
+            Restore(|| defaults::set_zip_compression_level(level));
+        defaults::set_zip_compression_level(9);
+        assert_eq!(defaults::zip_compression_level(), 9);
+
+        let quality = defaults::dwa_compression_quality();
+        let _restore_quality =
+            Restore(|| defaults::set_dwa_compression_quality(quality));
+        defaults::set_dwa_compression_quality(90.0);
+        assert_eq!(defaults::dwa_compression_quality(), 90.0);
+    }
+
+    #[test]
+    fn maximum_sizes() {
+        let _lock = lock_defaults();
+
+        let image = defaults::maximum_image_size();
+        let _restore_image =
+            Restore(|| defaults::set_maximum_image_size(image.0, image.1));
+        defaults::set_maximum_image_size(1 << 20, 1 << 20);
+        assert_eq!(defaults::maximum_image_size(), (1 << 20, 1 << 20));
+
+        let tile = defaults::maximum_tile_size();
+        let _restore_tile =
+            Restore(|| defaults::set_maximum_tile_size(tile.0, tile.1));
+        defaults::set_maximum_tile_size(4096, 4096);
+        assert_eq!(defaults::maximum_tile_size(), (4096, 4096));
+    }
+
+    #[test]
+    fn allocator_after_context() -> Result<(), exr::Error> {
+        use exr::context::ReadContext;
+        use std::path::Path;
+
+        let path_ferris = Path::new(
+            &std::env::var("CARGO_MANIFEST_DIR")
+                .expect("CARGO_MANIFEST_DIR not set"),
+        )
+        .join("images")
+        .join("ferris.exr");
+        let _ctx = ReadContext::new(&path_ferris)?;
+
+        // the C library may already hold memory from the default allocator
+        let err = defaults::set_allocator(&defaults::RustAllocator)
+            .expect_err("allocator set after a context was created");
+        assert_eq!(err.kind(), exr::ErrorKind::InvalidArgument);
+
+        Ok(())
+    }
+}
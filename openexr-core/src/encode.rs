@@ -0,0 +1,482 @@
+use crate::attr::Storage;
+use crate::chunkio::ChunkInfo;
+use crate::coding::{ChannelElement, ChannelInfo};
+use crate::context::*;
+use crate::decode::DeepSamples;
+use crate::error::Error;
+use openexr_core_sys as sys;
+use std::convert::TryFrom;
+
+type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// Flags changing how an [`EncodePipeline`] reads the sample counts and
+/// sample data of deep parts
+///
+/// Flags are combined with `|`.
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct EncodeFlags(u16);
+
+impl EncodeFlags {
+    /// The sample count table holds the number of samples in each pixel,
+    /// rather than a running total
+    pub const SAMPLE_COUNTS_ARE_INDIVIDUAL: EncodeFlags = EncodeFlags(1 << 0);
+    /// Each channel's encode pointer points to an array of pointers, one per
+    /// pixel, to where that pixel's samples should be read from
+    pub const NON_IMAGE_DATA_AS_POINTERS: EncodeFlags = EncodeFlags(1 << 1);
+
+    /// No flags set
+    ///
+    pub const fn empty() -> EncodeFlags {
+        EncodeFlags(0)
+    }
+
+    /// The flags as the C core's bit field
+    ///
+    pub const fn bits(&self) -> u16 {
+        self.0
+    }
+
+    /// Are all the flags in `other` set in `self`?
+    ///
+    pub const fn contains(&self, other: EncodeFlags) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl std::ops::BitOr for EncodeFlags {
+    type Output = EncodeFlags;
+
+    fn bitor(self, rhs: EncodeFlags) -> EncodeFlags {
+        EncodeFlags(self.0 | rhs.0)
+    }
+}
+
+/// Holds the state needed to encode chunks of a part of a [`WriteContext`]
+///
+/// A pipeline is created empty with [`EncodePipeline::default`], set up for
+/// a part and its first chunk with [`WriteContext::encoding_initialize`] and
+/// moved on to each following chunk with [`WriteContext::encoding_update`].
+///
+/// Any intermediate memory held by the pipeline is freed when it is dropped,
+/// or explicitly with [`WriteContext::encoding_destroy`] to find out whether
+/// that succeeded.
+///
+pub struct EncodePipeline<'c> {
+    // We have to box this because exr_encode_pipeline_t uses a small-buffer
+    // optimization internally
+    pub(crate) inner: Box<sys::exr_encode_pipeline_t>,
+    /// The context the pipeline was initialized for, which frees its
+    /// intermediate memory
+    ctx: Option<&'c WriteContext>,
+}
+
+// Safety: the pipeline owns its intermediate buffers, and only uses the
+// context to free them, which the C core allows from any thread, and the
+// inputs set on its channels are only read while it is run
+unsafe impl Send for EncodePipeline<'_> {}
+unsafe impl Sync for EncodePipeline<'_> {}
+
+impl EncodePipeline<'_> {
+    pub fn channels(&self) -> &[ChannelInfo] {
+        unsafe {
+            std::slice::from_raw_parts(
+                self.inner.channels as *const ChannelInfo,
+                self.inner.channel_count as usize,
+            )
+        }
+    }
+
+    pub fn channels_mut(&mut self) -> &mut [ChannelInfo] {
+        unsafe {
+            std::slice::from_raw_parts_mut(
+                self.inner.channels as *mut ChannelInfo,
+                self.inner.channel_count as usize,
+            )
+        }
+    }
+
+    /// The flags controlling how deep data is encoded
+    ///
+    pub fn encode_flags(&self) -> EncodeFlags {
+        EncodeFlags(self.inner.encode_flags)
+    }
+
+    /// Set the flags controlling how deep data is encoded
+    ///
+    /// The routines chosen depend on the flags, so these must be set before
+    /// calling [`WriteContext::encoding_choose_default_routines`].
+    ///
+    pub fn set_encode_flags(&mut self, flags: EncodeFlags) {
+        self.inner.encode_flags = flags.bits();
+    }
+
+    /// Set the sample count table of the current chunk of a deep part,
+    /// holding an entry for each pixel of the chunk a line at a time
+    ///
+    /// Each entry is a running total of the samples up to and including the
+    /// pixel, unless [`EncodeFlags::SAMPLE_COUNTS_ARE_INDIVIDUAL`] is set, in
+    /// which case it is the number of samples in the pixel.
+    ///
+    /// # Safety
+    /// `table` must be valid for reads and writes of `len` entries until the
+    /// pipeline has been run, as the C core temporarily byte-swaps it in
+    /// place while encoding, and must not be freed by the pipeline, so should
+    /// be cleared again with a null pointer before the pipeline is destroyed.
+    ///
+    pub unsafe fn set_sample_count_table(
+        &mut self,
+        table: *mut i32,
+        len: usize,
+    ) {
+        self.inner.sample_count_table = table;
+        self.inner.sample_count_alloc_size = len * std::mem::size_of::<i32>();
+    }
+
+    /// Stop every channel from being encoded from, and clear the sample
+    /// count table, so that nothing is left pointing at buffers that are
+    /// about to be freed
+    ///
+    pub(crate) fn clear_inputs(&mut self) {
+        for channel in self.channels_mut() {
+            unsafe { channel.set_encode_from(std::ptr::null()) };
+        }
+        unsafe { self.set_sample_count_table(std::ptr::null_mut(), 0) };
+    }
+}
+
+impl Default for EncodePipeline<'_> {
+    fn default() -> Self {
+        let e = std::mem::MaybeUninit::<sys::exr_encode_pipeline_t>::zeroed();
+        EncodePipeline {
+            inner: Box::new(unsafe { e.assume_init() }),
+            ctx: None,
+        }
+    }
+}
+
+impl Drop for EncodePipeline<'_> {
+    fn drop(&mut self) {
+        if let Some(ctx) = self.ctx.take() {
+            unsafe {
+                let _ = sys::exr_encoding_destroy(ctx.inner, &mut *self.inner);
+            }
+        }
+    }
+}
+
+impl WriteContext {
+    /// Initialize the encoding pipeline structure with the channel info
+    /// for the specified part, and the first block to be written.
+    ///
+    /// # Errors
+    /// * `[Error::FeatureNotImplemented]` - If the part uses a compression
+    /// type these bindings do not know about (see
+    /// [`Compression::Unknown`](crate::attr::Compression::Unknown))
+    ///
+    pub fn encoding_initialize<'c>(
+        &'c self,
+        part_index: usize,
+        chunk_info: &ChunkInfo,
+        encode_pipeline: &mut EncodePipeline<'c>,
+    ) -> Result<()> {
+        if !self.compression(part_index)?.is_known() {
+            return Err(Error::FeatureNotImplemented);
+        }
+
+        unsafe {
+            sys::exr_encoding_initialize(
+                self.inner,
+                c_part_index(part_index)?,
+                chunk_info as *const ChunkInfo as *const sys::exr_chunk_info_t,
+                &mut *encode_pipeline.inner,
+            )
+            .ok_in(self.inner, ())
+            .map_err(|e| {
+                e.with_part(part_index).with_chunk(chunk_info.idx as usize)
+            })?;
+        }
+        encode_pipeline.ctx = Some(self);
+        Ok(())
+    }
+
+    /// Given an initialized encode pipeline, find the appropriate functions
+    /// to shuffle and convert data from the defined channel inputs, then
+    /// compress and write the chunk
+    ///
+    /// Calling this is not required if custom routines will be used, or if
+    /// just the raw data is to be written. Although in that scenario, it is
+    /// probably easier to just write the chunk directly
+    ///
+    pub fn encoding_choose_default_routines(
+        &self,
+        part_index: usize,
+        encode_pipeline: &mut EncodePipeline,
+    ) -> Result<()> {
+        unsafe {
+            sys::exr_encoding_choose_default_routines(
+                self.inner,
+                c_part_index(part_index)?,
+                &mut *encode_pipeline.inner,
+            )
+            .ok_in(self.inner, ())
+        }
+    }
+
+    /// Given an encode pipeline previously initialized, update it for the
+    /// new chunk to be written.
+    ///
+    /// In this manner, memory buffers can be re-used to avoid continual
+    /// allocations. Further, it allows the previous choices for
+    /// the various functions to be quickly re-used.
+    ///
+    pub fn encoding_update(
+        &self,
+        part_index: usize,
+        chunk_info: &ChunkInfo,
+        encode_pipeline: &mut EncodePipeline,
+    ) -> Result<()> {
+        unsafe {
+            sys::exr_encoding_update(
+                self.inner,
+                c_part_index(part_index)?,
+                chunk_info as *const ChunkInfo as *const sys::exr_chunk_info_t,
+                &mut *encode_pipeline.inner,
+            )
+            .ok_in(self.inner, ())
+        }
+    }
+
+    /// Execute the encoding pipeline, writing the chunk to the file
+    ///
+    /// # Safety
+    /// The pointers set with [`ChannelInfo::set_encode_from`] on each channel
+    /// of the pipeline must be valid for reads of the whole channel, as
+    /// described by its width, height and user strides.
+    ///
+    pub unsafe fn encoding_run(
+        &self,
+        part_index: usize,
+        encode_pipeline: &mut EncodePipeline,
+    ) -> Result<()> {
+        crate::trace::encoding_run(
+            self.inner,
+            c_part_index(part_index)?,
+            &mut *encode_pipeline.inner,
+        )
+        .ok_in(self.inner, ())
+    }
+
+    /// Encode the samples of the current chunk of a deep part from
+    /// `samples`, converting from `T`, and write the chunk to the file
+    ///
+    /// `encode_pipeline` must have been initialized or updated for the chunk,
+    /// and `samples` must match its size and have a buffer for every channel
+    /// in the part. The routines are chosen, and any encode flags previously
+    /// set replaced, by this call.
+    ///
+    /// # Errors
+    /// * `[Error::InvalidArgument]` - If the part does not hold deep data, a
+    /// channel in the part is missing from `samples`, or `samples` does not
+    /// match the chunk
+    ///
+    pub fn encode_deep<T: ChannelElement>(
+        &self,
+        part_index: usize,
+        encode_pipeline: &mut EncodePipeline,
+        samples: &DeepSamples<T>,
+    ) -> Result<()> {
+        if !matches!(
+            self.storage(part_index)?,
+            Storage::DeepScanline | Storage::DeepTiled
+        ) {
+            return Err(Error::InvalidArgument);
+        }
+
+        let width = encode_pipeline.inner.chunk.width.max(0) as usize;
+        let height = encode_pipeline.inner.chunk.height.max(0) as usize;
+        let offsets = &samples.sample_offsets;
+        if samples.width != width
+            || samples.height != height
+            || offsets.len() != width * height + 1
+            || offsets.windows(2).any(|w| w[1] < w[0])
+        {
+            return Err(Error::InvalidArgument);
+        }
+        let total = offsets[width * height];
+
+        let mut counts = offsets
+            .windows(2)
+            .map(|w| i32::try_from(w[1] - w[0]))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|_| Error::InvalidArgument)?;
+
+        // each channel is read through an array of pointers to each pixel's
+        // samples
+        let mut pointers = Vec::new();
+        for channel in encode_pipeline.channels_mut() {
+            let buffer = samples
+                .channels
+                .iter()
+                .position(|c| c == channel.name())
+                .and_then(|i| samples.samples.get(i))
+                .filter(|b| b.len() >= total)
+                .ok_or(Error::InvalidArgument)?;
+
+            let base = buffer.as_ptr();
+            let table = offsets
+                .windows(2)
+                .map(|w| match w[1] - w[0] {
+                    0 => std::ptr::null(),
+                    // Safety: the offsets are within the buffer
+                    _ => unsafe { base.add(w[0]) },
+                })
+                .collect::<Vec<*const T>>();
+
+            let pointer_bytes = std::mem::size_of::<*const T>();
+            channel.set_user_data_type(T::PIXEL_TYPE);
+            channel.set_user_bytes_per_element(std::mem::size_of::<T>());
+            channel.set_user_pixel_stride(pointer_bytes);
+            channel.set_user_line_stride(pointer_bytes * width);
+            unsafe { channel.set_encode_from(table.as_ptr() as *const u8) };
+            pointers.push(table);
+        }
+
+        encode_pipeline.set_encode_flags(
+            EncodeFlags::SAMPLE_COUNTS_ARE_INDIVIDUAL
+                | EncodeFlags::NON_IMAGE_DATA_AS_POINTERS,
+        );
+        unsafe {
+            encode_pipeline
+                .set_sample_count_table(counts.as_mut_ptr(), counts.len())
+        };
+
+        let result = self
+            .encoding_choose_default_routines(part_index, encode_pipeline)
+            .and_then(|_| {
+                // Safety: every pixel's pointer has room for its samples in
+                // the buffers, which we hold for the whole call, as we do
+                // the sample counts
+                unsafe { self.encoding_run(part_index, encode_pipeline) }
+            });
+
+        // the pointer tables and counts are about to be dropped
+        encode_pipeline.clear_inputs();
+        drop(pointers);
+        result
+    }
+
+    /// Free any intermediate memory in the encoding pipeline
+    ///
+    /// This does *not* free any pointers referred to in the channel info
+    /// areas, but rather only the intermediate buffers and memory needed
+    /// for the structure itself.
+    ///
+    pub fn encoding_destroy(
+        &self,
+        encode_pipeline: EncodePipeline,
+    ) -> Result<()> {
+        let mut encode_pipeline = encode_pipeline;
+        // destroyed here rather than when dropped
+        encode_pipeline.ctx = None;
+        unsafe {
+            sys::exr_encoding_destroy(self.inner, &mut *encode_pipeline.inner)
+                .ok_in(self.inner, ())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate as exr;
+    use exr::context::{DefaultWriteMode, ReadContext, WriteHeaderContext};
+    use exr::decode::DeepSamples;
+    use exr::encode::EncodePipeline;
+    use exr::validate::ChunkLocation;
+    use std::path::Path;
+
+    #[test]
+    fn encode_deep() -> Result<(), exr::Error> {
+        let path_deep = Path::new(
+            &std::env::var("CARGO_MANIFEST_DIR")
+                .expect("CARGO_MANIFEST_DIR not set"),
+        )
+        .join("images")
+        .join("deep_plane.exr");
+        let src = ReadContext::new(&path_deep)?;
+        let names = src
+            .channels(0)?
+            .iter()
+            .map(|c| c.name().to_string())
+            .collect::<Vec<_>>();
+        let names = names.iter().map(|n| n.as_str()).collect::<Vec<_>>();
+
+        let read_chunk = |ctx: &ReadContext, location| {
+            let chunk_info = match location {
+                ChunkLocation::Scanline { y } => {
+                    ctx.read_scanline_chunk_info(0, y)?
+                }
+                ChunkLocation::Tile {
+                    x,
+                    y,
+                    level_x,
+                    level_y,
+                } => ctx.read_tile_chunk_info(0, x, y, level_x, level_y)?,
+            };
+            let mut samples = DeepSamples::<f32>::new();
+            ctx.decoding_initialize(0, &chunk_info)?
+                .decode_deep(&names, &mut samples)?;
+            Ok::<_, exr::Error>(samples)
+        };
+
+        let path = std::env::temp_dir().join("encode_deep.exr");
+        let mut header = WriteHeaderContext::new(
+            &path,
+            DefaultWriteMode::WriteFileDirectly,
+        )?;
+        header.add_part_copy(&src, 0, None)?;
+        let ctx = header.write_header()?;
+
+        let table = src.chunk_table(0)?;
+        let mut encoder = EncodePipeline::default();
+        for (i, entry) in table.iter().enumerate() {
+            let samples = read_chunk(&src, entry.location)?;
+            let chunk_info = match entry.location {
+                ChunkLocation::Scanline { y } => {
+                    ctx.write_scanline_chunk_info(0, y)?
+                }
+                ChunkLocation::Tile {
+                    x,
+                    y,
+                    level_x,
+                    level_y,
+                } => ctx.write_tile_chunk_info(0, x, y, level_x, level_y)?,
+            };
+            if i == 0 {
+                ctx.encoding_initialize(0, &chunk_info, &mut encoder)?;
+            } else {
+                ctx.encoding_update(0, &chunk_info, &mut encoder)?;
+            }
+
+            // the samples must match the chunk
+            let err = ctx
+                .encode_deep(0, &mut encoder, &DeepSamples::<f32>::new())
+                .expect_err("encoded samples that don't match the chunk");
+            assert_eq!(err.kind(), exr::ErrorKind::InvalidArgument);
+
+            ctx.encode_deep(0, &mut encoder, &samples)?;
+        }
+        ctx.encoding_destroy(encoder)?;
+        ctx.close()?;
+
+        let written = ReadContext::new(&path)?;
+        for entry in table {
+            assert_eq!(
+                read_chunk(&written, entry.location)?,
+                read_chunk(&src, entry.location)?
+            );
+        }
+
+        Ok(())
+    }
+}
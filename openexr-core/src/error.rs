@@ -1,3 +1,3 @@
 use openexr_core_sys as sys;
 
-pub use sys::Error;
+pub use sys::{Error, ErrorKind};
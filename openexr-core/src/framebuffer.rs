@@ -0,0 +1,603 @@
+//! A `FrameBuffer` describing where each channel's pixels live in memory, in
+//! the style of the C++ library's `Imf::FrameBuffer`.
+//!
+//! Unlike the C++ API, each [`Slice`] borrows the memory it describes, so the
+//! reads and writes driven by the frame buffer are checked to stay within it.
+//! The position of a pixel (`x`, `y`) in a slice is
+//! `(y - data_window.min.y) * y_stride + (x - data_window.min.x) * x_stride`
+//! bytes, i.e. the start of the slice is the top-left corner of the data
+//! window rather than the origin.
+//!
+//! A slice for a subsampled channel holds only the channel's samples, so the
+//! position of its `n`th sample on its `m`th sampled line is
+//! `m * y_stride + n * x_stride` bytes, counting from the first sampled line
+//! and column of the data window.
+//!
+use crate::attr::{ChannelDesc, LineOrder, PixelType, Storage};
+use crate::coding::{sampled_count, ChannelElement};
+use crate::context::{ReadContext, WriteContext};
+use crate::decode::{DecodePipeline, Runnable};
+use crate::encode::EncodePipeline;
+use crate::error::Error;
+use std::collections::BTreeMap;
+use std::marker::PhantomData;
+
+type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// Describes the memory holding a single channel's pixels
+///
+pub struct Slice<'a> {
+    ptr: *mut u8,
+    len: usize,
+    pixel_type: PixelType,
+    x_stride: usize,
+    y_stride: usize,
+    fill: Option<[u8; 4]>,
+    marker: PhantomData<&'a mut [u8]>,
+}
+
+impl<'a> Slice<'a> {
+    /// Create a slice over `data`, where `x_stride` and `y_stride` are the
+    /// number of bytes from one pixel to the next, and one line to the next
+    ///
+    pub fn new<T: ChannelElement>(
+        data: &'a mut [T],
+        x_stride: usize,
+        y_stride: usize,
+    ) -> Slice<'a> {
+        Slice {
+            ptr: data.as_mut_ptr() as *mut u8,
+            len: std::mem::size_of_val(data),
+            pixel_type: T::PIXEL_TYPE,
+            x_stride,
+            y_stride,
+            fill: None,
+            marker: PhantomData,
+        }
+    }
+
+    /// Create a slice as with [`Slice::new`], that is filled with `fill` when
+    /// reading from a part that does not have the channel
+    ///
+    pub fn with_fill<T: ChannelElement>(
+        data: &'a mut [T],
+        x_stride: usize,
+        y_stride: usize,
+        fill: T,
+    ) -> Slice<'a> {
+        let mut bytes = [0u8; 4];
+        // Safety: T is Pod and at most 4 bytes
+        unsafe {
+            std::ptr::copy_nonoverlapping(
+                &fill as *const T as *const u8,
+                bytes.as_mut_ptr(),
+                std::mem::size_of::<T>(),
+            );
+        }
+        Slice {
+            fill: Some(bytes),
+            ..Slice::new(data, x_stride, y_stride)
+        }
+    }
+
+    pub fn pixel_type(&self) -> PixelType {
+        self.pixel_type
+    }
+
+    pub fn x_stride(&self) -> usize {
+        self.x_stride
+    }
+
+    pub fn y_stride(&self) -> usize {
+        self.y_stride
+    }
+
+    fn bytes_per_element(&self) -> usize {
+        match self.pixel_type {
+            PixelType::Half => 2,
+            PixelType::Float | PixelType::Uint => 4,
+        }
+    }
+
+    /// Offset in bytes of pixel (`x`, `y`) relative to the data window, or
+    /// an error if its element does not fit in the slice
+    ///
+    fn offset(&self, x: usize, y: usize) -> Result<usize> {
+        let offset = y
+            .checked_mul(self.y_stride)
+            .zip(x.checked_mul(self.x_stride))
+            .and_then(|(y, x)| y.checked_add(x))
+            .ok_or(Error::InvalidArgument)?;
+        match offset.checked_add(self.bytes_per_element()) {
+            Some(end) if end <= self.len => Ok(offset),
+            _ => Err(Error::InvalidArgument),
+        }
+    }
+}
+
+/// A set of [`Slice`]s, keyed by channel name
+///
+#[derive(Default)]
+pub struct FrameBuffer<'a> {
+    slices: BTreeMap<String, Slice<'a>>,
+}
+
+impl<'a> FrameBuffer<'a> {
+    pub fn new() -> FrameBuffer<'a> {
+        FrameBuffer::default()
+    }
+
+    /// Add a slice for the channel `name`, replacing any existing one
+    ///
+    pub fn insert(&mut self, name: &str, slice: Slice<'a>) {
+        self.slices.insert(name.to_string(), slice);
+    }
+
+    /// Add slices for the channels `names`, interleaved in that order in
+    /// `data` with no padding, e.g. RGBRGBRGB... for `["R", "G", "B"]`
+    ///
+    /// Lines are `width` pixels long. This is the equivalent of inserting a
+    /// slice per channel with the same base pointer, offset by the channel's
+    /// position in the pixel, which can't be done with [`Slice::new`] as the
+    /// slices would all borrow `data`.
+    ///
+    pub fn insert_interleaved<T: ChannelElement>(
+        &mut self,
+        names: &[&str],
+        data: &'a mut [T],
+        width: usize,
+    ) {
+        let size = std::mem::size_of::<T>();
+        let x_stride = size * names.len();
+        let base = Slice::new(data, x_stride, x_stride * width);
+        for (i, name) in names.iter().enumerate() {
+            let offset = (i * size).min(base.len);
+            // the slices only overlap in the bytes of other channels, which
+            // they never touch as each element is x_stride apart
+            let slice = Slice {
+                ptr: unsafe { base.ptr.add(offset) },
+                len: base.len - offset,
+                pixel_type: base.pixel_type,
+                x_stride: base.x_stride,
+                y_stride: base.y_stride,
+                fill: None,
+                marker: PhantomData,
+            };
+            self.insert(name, slice);
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Slice<'a>> {
+        self.slices.get(name)
+    }
+
+    pub fn remove(&mut self, name: &str) -> Option<Slice<'a>> {
+        self.slices.remove(name)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &Slice<'a>)> {
+        self.slices.iter().map(|(n, s)| (n.as_str(), s))
+    }
+
+    pub fn len(&self) -> usize {
+        self.slices.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.slices.is_empty()
+    }
+
+    /// Check that every slice holds all the samples of lines `y1` to `y2` of
+    /// the data window `dw`, as sampled by its channel in `channels`, or at
+    /// full resolution if the part has no such channel
+    ///
+    fn check_bounds(
+        &self,
+        channels: &[ChannelDesc],
+        dw: [i32; 4],
+        y1: i32,
+        y2: i32,
+    ) -> Result<()> {
+        for (name, slice) in &self.slices {
+            let sampling = channels
+                .iter()
+                .find(|c| c.name == *name)
+                .map_or((1, 1), |c| c.sampling);
+            let width =
+                sampled_count(dw[0], (dw[2] - dw[0] + 1) as usize, sampling.0);
+            let first = sampled_count(dw[1], (y1 - dw[1]) as usize, sampling.1);
+            let end =
+                sampled_count(dw[1], (y2 - dw[1] + 1) as usize, sampling.1);
+            if width > 0 && end > first {
+                slice.offset(0, first)?;
+                slice.offset(width - 1, end - 1)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A channel's samples for one chunk, decoded by
+/// [`ReadContext::read_pixels`] before the requested lines are copied out
+///
+struct Temp {
+    name: String,
+    /// Number of samples in a line
+    width: usize,
+    y_sampling: i32,
+    data: Vec<u8>,
+}
+
+impl ReadContext {
+    /// Decode lines `y1` to `y2` inclusive of a scanline part into the slices
+    /// of `frame_buffer`
+    ///
+    /// Only the requested lines are written to the slices, even though whole
+    /// chunks are decoded, and of those only the lines a subsampled channel
+    /// has samples on. Slices for channels the part does not have are filled
+    /// with their fill value, if they have one.
+    ///
+    /// # Errors
+    /// * `[Error::TileScanMixedApi]` - If the part is not a scanline part
+    /// * `[Error::ArgumentOutOfRange]` - If the lines are not all inside the
+    /// data window
+    /// * `[Error::InvalidArgument]` - If a slice is too small to hold the
+    /// lines with its strides
+    ///
+    pub fn read_pixels(
+        &self,
+        part_index: usize,
+        y1: i32,
+        y2: i32,
+        frame_buffer: &mut FrameBuffer,
+    ) -> Result<()> {
+        if self.storage(part_index)? != Storage::Scanline {
+            return Err(Error::TileScanMixedApi);
+        }
+
+        let dw = self.data_window::<[i32; 4]>(part_index)?;
+        if y1 > y2 || y1 < dw[1] || y2 > dw[3] {
+            return Err(Error::ArgumentOutOfRange);
+        }
+        let width = (dw[2] - dw[0] + 1) as usize;
+        let channels = self.channels(part_index)?.descs()?;
+        frame_buffer.check_bounds(&channels, dw, y1, y2)?;
+
+        let mut pipeline: Option<DecodePipeline<Runnable>> = None;
+        let mut y = y1;
+        while y <= y2 {
+            let chunk_info = self.read_scanline_chunk_info(part_index, y)?;
+            let mut ready = match pipeline.take() {
+                Some(p) => p.update(&chunk_info)?,
+                None => self.decoding_initialize(part_index, &chunk_info)?,
+            };
+
+            // decode each channel we want into its own temporary buffer, so
+            // that lines of the chunk outside y1..=y2 don't get written
+            let mut temps = Vec::new();
+            for channel in ready.channels() {
+                if let Some(slice) = frame_buffer.get(channel.name()) {
+                    let bpe = slice.bytes_per_element();
+                    let size = channel.width() * channel.height() * bpe;
+                    temps.push(Temp {
+                        name: channel.name().to_string(),
+                        width: channel.width(),
+                        y_sampling: channel.y_samples() as i32,
+                        data: vec![0u8; size],
+                    });
+                }
+            }
+
+            for channel in ready.channels_mut() {
+                let name = channel.name().to_string();
+                match temps.iter_mut().find(|t| t.name == name) {
+                    Some(temp) => {
+                        let slice = frame_buffer.get(&name).unwrap();
+                        let bpe = slice.bytes_per_element();
+                        let width = channel.width();
+                        unsafe {
+                            channel.set_decode_to(temp.data.as_mut_ptr())
+                        };
+                        channel.set_user_data_type(slice.pixel_type);
+                        channel.set_user_bytes_per_element(bpe);
+                        channel.set_user_pixel_stride(bpe);
+                        channel.set_user_line_stride(width * bpe);
+                    }
+                    None => unsafe {
+                        channel.set_decode_to(std::ptr::null_mut())
+                    },
+                }
+            }
+
+            let mut runnable = ready.choose_default_routines()?;
+            // Safety: every channel decodes to a temporary buffer sized for
+            // its width and height in this chunk, or is skipped
+            unsafe { runnable.run()? };
+
+            let first = y.max(chunk_info.start_y);
+            let last = y2.min(chunk_info.start_y + chunk_info.height - 1);
+            for temp in &temps {
+                let slice = frame_buffer.get(&temp.name).unwrap();
+                let bpe = slice.bytes_per_element();
+                let line_bytes = temp.width * bpe;
+                for line in first..=last {
+                    if line.rem_euclid(temp.y_sampling) != 0 {
+                        continue;
+                    }
+                    let src_line = sampled_count(
+                        chunk_info.start_y,
+                        (line - chunk_info.start_y) as usize,
+                        temp.y_sampling,
+                    );
+                    let dst_line = sampled_count(
+                        dw[1],
+                        (line - dw[1]) as usize,
+                        temp.y_sampling,
+                    );
+                    let src = &temp.data[src_line * line_bytes..][..line_bytes];
+                    for (x, value) in src.chunks_exact(bpe).enumerate() {
+                        let offset = slice.offset(x, dst_line)?;
+                        // Safety: offset has checked the element is inside
+                        // the slice, which is mutably borrowed by the frame
+                        // buffer
+                        unsafe {
+                            std::ptr::copy_nonoverlapping(
+                                value.as_ptr(),
+                                slice.ptr.add(offset),
+                                bpe,
+                            );
+                        }
+                    }
+                }
+            }
+
+            pipeline = Some(runnable);
+            y = chunk_info.start_y + chunk_info.height;
+        }
+
+        // fill any slices the part has no channel for
+        for (name, slice) in frame_buffer.iter() {
+            let fill = match slice.fill {
+                Some(fill) if !channels.iter().any(|c| c.name == name) => fill,
+                _ => continue,
+            };
+            let bpe = slice.bytes_per_element();
+            for line in (y1 - dw[1]) as usize..=(y2 - dw[1]) as usize {
+                for x in 0..width {
+                    let offset = slice.offset(x, line)?;
+                    // Safety: as above
+                    unsafe {
+                        std::ptr::copy_nonoverlapping(
+                            fill.as_ptr(),
+                            slice.ptr.add(offset),
+                            bpe,
+                        );
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl WriteContext {
+    /// Encode and write the whole of a scanline part from the slices of
+    /// `frame_buffer`, in the order required by the part's line order
+    ///
+    /// Every channel in the part must have a slice in the frame buffer.
+    ///
+    /// # Errors
+    /// * `[Error::TileScanMixedApi]` - If the part is not a scanline part
+    /// * `[Error::InvalidArgument]` - If a channel of the part has no slice,
+    /// or a slice is too small to hold the image with its strides
+    ///
+    pub fn write_pixels(
+        &mut self,
+        part_index: usize,
+        frame_buffer: &FrameBuffer,
+    ) -> Result<()> {
+        if self.storage(part_index)? != Storage::Scanline {
+            return Err(Error::TileScanMixedApi);
+        }
+
+        let dw = self.data_window::<[i32; 4]>(part_index)?;
+        let channels = self.channels(part_index)?.descs()?;
+        frame_buffer.check_bounds(&channels, dw, dw[1], dw[3])?;
+
+        let mut chunks = Vec::new();
+        let mut y = dw[1];
+        while y <= dw[3] {
+            let chunk_info = self.write_scanline_chunk_info(part_index, y)?;
+            y = chunk_info.start_y + chunk_info.height;
+            chunks.push(chunk_info);
+        }
+        if self.lineorder(part_index)? == LineOrder::DecreasingY {
+            chunks.reverse();
+        }
+
+        let mut encoder = EncodePipeline::default();
+        for (i, chunk_info) in chunks.iter().enumerate() {
+            if i == 0 {
+                self.encoding_initialize(part_index, chunk_info, &mut encoder)?;
+            } else {
+                self.encoding_update(part_index, chunk_info, &mut encoder)?;
+            }
+
+            for channel in encoder.channels_mut() {
+                let slice = frame_buffer
+                    .get(channel.name())
+                    .ok_or(Error::InvalidArgument)?;
+                // subsampled channels may have no lines in the chunk, in
+                // which case nothing is read from the slice
+                let from = if channel.width() > 0 && channel.height() > 0 {
+                    let line = sampled_count(
+                        dw[1],
+                        (chunk_info.start_y - dw[1]) as usize,
+                        channel.y_samples() as i32,
+                    );
+                    unsafe { slice.ptr.add(slice.offset(0, line)?) }
+                } else {
+                    slice.ptr
+                };
+                unsafe { channel.set_encode_from(from) };
+                channel.set_user_data_type(slice.pixel_type);
+                channel.set_user_bytes_per_element(slice.bytes_per_element());
+                channel.set_user_pixel_stride(slice.x_stride);
+                channel.set_user_line_stride(slice.y_stride);
+            }
+
+            self.encoding_choose_default_routines(part_index, &mut encoder)?;
+            // Safety: check_bounds has made sure every slice holds all the
+            // samples of the image with its strides
+            unsafe { self.encoding_run(part_index, &mut encoder)? };
+        }
+
+        self.encoding_destroy(encoder)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate as exr;
+    use exr::framebuffer::{FrameBuffer, Slice};
+    use imath_traits::f16;
+    use std::path::Path;
+
+    #[test]
+    fn read_pixels() -> Result<(), exr::Error> {
+        let path_ferris = Path::new(
+            &std::env::var("CARGO_MANIFEST_DIR")
+                .expect("CARGO_MANIFEST_DIR not set"),
+        )
+        .join("images")
+        .join("ferris.exr");
+
+        let ctx = exr::context::ReadContext::new(&path_ferris)?;
+        let (width, height) = (1200, 800);
+
+        let mut red = vec![0.0f32; width * height];
+        let mut rgb = vec![f16::from_f32(0.0); width * height * 3];
+        let mut missing = vec![0.0f32; width * height];
+
+        let mut fb = FrameBuffer::new();
+        fb.insert("R", Slice::new(&mut red, 4, 4 * width));
+        fb.insert("missing", Slice::with_fill(&mut missing, 4, 4 * width, 0.5));
+        ctx.read_pixels(0, 0, 799, &mut fb)?;
+        drop(fb);
+        assert!(missing.iter().all(|v| *v == 0.5));
+
+        let mut fb = FrameBuffer::new();
+        fb.insert_interleaved(&["R", "G", "B"], &mut rgb, width);
+        ctx.read_pixels(0, 100, 199, &mut fb)?;
+        drop(fb);
+
+        for y in 100..200 {
+            for x in 0..width {
+                let i = y * width + x;
+                assert_eq!(f32::from(rgb[i * 3]), red[i]);
+            }
+        }
+        assert!(rgb[..100 * width * 3].iter().all(|v| f32::from(*v) == 0.0));
+
+        Ok(())
+    }
+
+    #[test]
+    fn subsampled() -> Result<(), exr::Error> {
+        use exr::attr::{ChannelDesc, Compression, PixelType, Storage};
+        use exr::context::{DefaultWriteMode, WriteHeaderContext};
+
+        let path = std::env::temp_dir().join("framebuffer_subsampled.exr");
+        let (width, height) = (64, 32);
+        let mut header = WriteHeaderContext::new(
+            &path,
+            DefaultWriteMode::WriteFileDirectly,
+        )?;
+        let part = header.add_part("", Storage::Scanline)?;
+        // 16 lines to a chunk
+        header.initialize_required_attributes_simple(
+            part,
+            width,
+            height,
+            Compression::Zip,
+        )?;
+        let chroma = |name| ChannelDesc {
+            sampling: (2, 2),
+            ..ChannelDesc::new(name, PixelType::Half)
+        };
+        header.add_channels(
+            part,
+            &[
+                ChannelDesc::new("Y", PixelType::Half),
+                chroma("RY"),
+                chroma("BY"),
+            ],
+        )?;
+
+        let mut y = (0..width * height)
+            .map(|i| f16::from_f32(i as f32))
+            .collect::<Vec<_>>();
+        let mut ry = (0..width * height / 4)
+            .map(|i| f16::from_f32(i as f32 * 0.5))
+            .collect::<Vec<_>>();
+        let mut by = (0..width * height / 4)
+            .map(|i| f16::from_f32(-(i as f32)))
+            .collect::<Vec<_>>();
+
+        let mut ctx = header.write_header()?;
+        let mut fb = FrameBuffer::new();
+        fb.insert("Y", Slice::new(&mut y, 2, 2 * width));
+        fb.insert("RY", Slice::new(&mut ry, 2, width));
+        fb.insert("BY", Slice::new(&mut by, 2, width));
+        ctx.write_pixels(part, &fb)?;
+        drop(fb);
+        ctx.close()?;
+
+        // start on a line without chroma samples, and end in the next chunk
+        let ctx = exr::context::ReadContext::new(&path)?;
+        let mut read_y = vec![f16::from_f32(0.0); width * height];
+        let mut read_ry = vec![f16::from_f32(0.0); width * height / 4];
+        let mut read_by = vec![f16::from_f32(0.0); width * height / 4];
+        let mut fb = FrameBuffer::new();
+        fb.insert("Y", Slice::new(&mut read_y, 2, 2 * width));
+        fb.insert("RY", Slice::new(&mut read_ry, 2, width));
+        fb.insert("BY", Slice::new(&mut read_by, 2, width));
+        ctx.read_pixels(0, 3, 20, &mut fb)?;
+        drop(fb);
+
+        let zero = f16::from_f32(0.0);
+        for line in 0..height {
+            let expected = &y[line * width..][..width];
+            let read = &read_y[line * width..][..width];
+            if (3..=20).contains(&line) {
+                assert_eq!(read, expected);
+            } else {
+                assert!(read.iter().all(|v| *v == zero));
+            }
+        }
+        // chroma lines 2 to 10 are on image lines 4 to 20
+        let chroma_width = width / 2;
+        for line in 0..height / 2 {
+            let range = line * chroma_width..(line + 1) * chroma_width;
+            if (2..=10).contains(&line) {
+                assert_eq!(read_ry[range.clone()], ry[range.clone()]);
+                assert_eq!(read_by[range.clone()], by[range]);
+            } else {
+                assert!(read_ry[range.clone()].iter().all(|v| *v == zero));
+                assert!(read_by[range].iter().all(|v| *v == zero));
+            }
+        }
+
+        // a slice too small for the samples of the lines read is rejected
+        let mut short = vec![f16::from_f32(0.0); width];
+        let mut fb = FrameBuffer::new();
+        fb.insert("RY", Slice::new(&mut short, 2, width));
+        let err = ctx
+            .read_pixels(0, 0, 7, &mut fb)
+            .expect_err("read past the end of the slice");
+        assert_eq!(err.kind(), exr::ErrorKind::InvalidArgument);
+
+        Ok(())
+    }
+}
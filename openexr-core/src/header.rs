@@ -0,0 +1,401 @@
+use crate::attr::{AttributeValue, LevelMode};
+use crate::context::*;
+use crate::error::Error;
+use crate::part::AttrListAccessMode;
+use crate::read::num_tiles;
+use openexr_core_sys as sys;
+use std::collections::BTreeMap;
+use std::ffi::CStr;
+use std::io::Write;
+
+type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// An owned snapshot of all the attributes in a part's header
+///
+/// A `Header` is detached from the context it was read from, so it can be
+/// kept around, compared against other headers with [`Header::diff`], or
+/// checked against a reference header. With the `serde` feature enabled it
+/// serializes as a map from attribute names to values.
+///
+#[derive(Debug, Clone, PartialEq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[cfg_attr(feature = "serde", serde(transparent))]
+pub struct Header {
+    attributes: BTreeMap<String, AttributeValue>,
+}
+
+/// A single difference between two headers, as reported by [`Header::diff`]
+///
+#[derive(Debug, Clone, PartialEq)]
+pub enum HeaderDiff {
+    /// The attribute is present in the other header but not in this one
+    Added { name: String, value: AttributeValue },
+    /// The attribute is present in this header but not in the other one
+    Removed { name: String, value: AttributeValue },
+    /// The attribute is present in both headers with different values
+    Changed {
+        name: String,
+        old: AttributeValue,
+        new: AttributeValue,
+    },
+}
+
+impl HeaderDiff {
+    /// Name of the attribute that differs
+    ///
+    pub fn name(&self) -> &str {
+        match self {
+            HeaderDiff::Added { name, .. } => name,
+            HeaderDiff::Removed { name, .. } => name,
+            HeaderDiff::Changed { name, .. } => name,
+        }
+    }
+}
+
+impl Header {
+    /// Get the value of the attribute `name`, if present
+    ///
+    pub fn get(&self, name: &str) -> Option<&AttributeValue> {
+        self.attributes.get(name)
+    }
+
+    /// Set the value of the attribute `name`, returning the previous value if
+    /// there was one
+    ///
+    pub fn insert<S: Into<String>>(
+        &mut self,
+        name: S,
+        value: AttributeValue,
+    ) -> Option<AttributeValue> {
+        self.attributes.insert(name.into(), value)
+    }
+
+    /// Remove the attribute `name`, returning its value if it was present
+    ///
+    pub fn remove(&mut self, name: &str) -> Option<AttributeValue> {
+        self.attributes.remove(name)
+    }
+
+    /// Number of attributes in the header
+    ///
+    pub fn len(&self) -> usize {
+        self.attributes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.attributes.is_empty()
+    }
+
+    /// Iterate over the attributes in the header, sorted by name
+    ///
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &AttributeValue)> {
+        self.attributes.iter().map(|(k, v)| (k.as_str(), v))
+    }
+
+    /// Compare this header against `other`, returning every attribute that
+    /// was added, removed or changed going from `self` to `other`, sorted by
+    /// attribute name.
+    ///
+    /// An empty result means the headers are identical.
+    ///
+    pub fn diff(&self, other: &Header) -> Vec<HeaderDiff> {
+        let mut result = Vec::new();
+        for (name, value) in &self.attributes {
+            match other.attributes.get(name) {
+                None => result.push(HeaderDiff::Removed {
+                    name: name.clone(),
+                    value: value.clone(),
+                }),
+                Some(other_value) if other_value != value => {
+                    result.push(HeaderDiff::Changed {
+                        name: name.clone(),
+                        old: value.clone(),
+                        new: other_value.clone(),
+                    })
+                }
+                Some(_) => (),
+            }
+        }
+
+        for (name, value) in &other.attributes {
+            if !self.attributes.contains_key(name) {
+                result.push(HeaderDiff::Added {
+                    name: name.clone(),
+                    value: value.clone(),
+                });
+            }
+        }
+
+        result.sort_by(|a, b| a.name().cmp(b.name()));
+        result
+    }
+}
+
+impl<S: ContextState> Context<S> {
+    /// Take an owned snapshot of the header of the given part
+    ///
+    /// # Errors
+    /// * `[Error::ArgumentOutOfRange]` - If `part_index` does not refer to a
+    /// valid part
+    ///
+    pub fn header(&self, part_index: usize) -> Result<Header> {
+        let mut header = Header::default();
+        for attr in
+            self.attribute_list(part_index, AttrListAccessMode::SortedOrder)?
+        {
+            header.insert(attr.name(), attr.value());
+        }
+        Ok(header)
+    }
+
+    /// Write a summary of the file's parts to `out`, as the C library's
+    /// `exr_print_context_info` does to stdout
+    ///
+    /// Each part's required attributes are listed, along with its tile counts
+    /// if it is tiled. If `verbose` is set, every attribute is listed instead.
+    ///
+    /// # Errors
+    /// * `[Error::WriteIo]` - If writing to `out` fails
+    ///
+    pub fn print_info<W: Write>(
+        &self,
+        out: &mut W,
+        verbose: bool,
+    ) -> Result<()> {
+        let mut file_name = std::ptr::null();
+        let file_name = unsafe {
+            sys::exr_get_file_name(self.inner, &mut file_name)
+                .ok_in(self.inner, ())?;
+            CStr::from_ptr(file_name).to_string_lossy()
+        };
+        writeln!(out, "File '{}':", file_name).map_err(write_io)?;
+
+        let count = self.count()?;
+        if verbose {
+            writeln!(out, " parts: {}", count).map_err(write_io)?;
+        }
+
+        for part_index in 0..count {
+            let name = self.name(part_index)?;
+            if verbose || count > 1 || name.is_some() {
+                writeln!(
+                    out,
+                    " part {}: {}",
+                    part_index + 1,
+                    name.unwrap_or("<single>")
+                )
+                .map_err(write_io)?;
+            }
+
+            if verbose {
+                for attr in self
+                    .attribute_list(part_index, AttrListAccessMode::FileOrder)?
+                {
+                    writeln!(out, "  {}: {}", attr.name(), attr.value())
+                        .map_err(write_io)?;
+                }
+            } else {
+                for name in &[
+                    "type",
+                    "compression",
+                    "chunkCount",
+                    "dataWindow",
+                    "displayWindow",
+                    "channels",
+                ] {
+                    if let Ok(attr) =
+                        self.get_attribute_by_name(part_index, name)
+                    {
+                        writeln!(out, "  {}: {}", name, attr.value())
+                            .map_err(write_io)?;
+                    }
+                }
+            }
+
+            if self.get_attribute_by_name(part_index, "tiles").is_ok() {
+                self.print_tile_counts(out, part_index)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Write the number and size of the tiles in each level of a tiled part
+    ///
+    fn print_tile_counts<W: Write>(
+        &self,
+        out: &mut W,
+        part_index: usize,
+    ) -> Result<()> {
+        let (levels_x, levels_y) = self.tile_levels(part_index)?;
+        let ripmap = self.tile_descriptor(part_index)?.level_mode
+            == LevelMode::RipmapLevels;
+        writeln!(
+            out,
+            "  tiled image has levels: x {} y {}",
+            levels_x, levels_y
+        )
+        .map_err(write_io)?;
+
+        write!(out, "    x tile count:").map_err(write_io)?;
+        for l in 0..levels_x {
+            let level = if ripmap { (l, 0) } else { (l, l) };
+            let (width, _) = self.level_sizes(part_index, level.0, level.1)?;
+            let (tile_width, _) =
+                self.tile_sizes(part_index, level.0, level.1)?;
+            write!(
+                out,
+                " {} (sz {})",
+                num_tiles(width, tile_width.max(1)),
+                tile_width
+            )
+            .map_err(write_io)?;
+        }
+        writeln!(out).map_err(write_io)?;
+
+        write!(out, "    y tile count:").map_err(write_io)?;
+        for l in 0..levels_y {
+            let level = if ripmap { (0, l) } else { (l, l) };
+            let (_, height) = self.level_sizes(part_index, level.0, level.1)?;
+            let (_, tile_height) =
+                self.tile_sizes(part_index, level.0, level.1)?;
+            write!(
+                out,
+                " {} (sz {})",
+                num_tiles(height, tile_height.max(1)),
+                tile_height
+            )
+            .map_err(write_io)?;
+        }
+        writeln!(out).map_err(write_io)
+    }
+}
+
+fn write_io(_: std::io::Error) -> Error {
+    Error::WriteIo
+}
+
+#[cfg(test)]
+mod tests {
+    use crate as exr;
+    use exr::attr::AttributeValue;
+    use exr::header::HeaderDiff;
+    use std::path::Path;
+
+    #[test]
+    fn header_diff() -> Result<(), exr::Error> {
+        let images = Path::new(
+            &std::env::var("CARGO_MANIFEST_DIR")
+                .expect("CARGO_MANIFEST_DIR not set"),
+        )
+        .join("images");
+
+        let ctx = exr::context::ReadContext::new(images.join("ferris.exr"))?;
+        let header = ctx.header(0)?;
+        assert_eq!(header.len(), ctx.attribute_count(0)?);
+        assert_eq!(
+            header.get("compression"),
+            Some(&AttributeValue::Compression(exr::attr::Compression::Piz))
+        );
+        assert!(header.diff(&ctx.header(0)?).is_empty());
+
+        let mut modified = header.clone();
+        modified.insert("owner", AttributeValue::String("ferris".into()));
+        modified.insert(
+            "compression",
+            AttributeValue::Compression(exr::attr::Compression::Zip),
+        );
+        modified.remove("screenWindowWidth");
+
+        let diff = header.diff(&modified);
+        assert_eq!(diff.len(), 3);
+        assert_eq!(
+            diff[0],
+            HeaderDiff::Changed {
+                name: "compression".into(),
+                old: AttributeValue::Compression(exr::attr::Compression::Piz),
+                new: AttributeValue::Compression(exr::attr::Compression::Zip),
+            }
+        );
+        assert_eq!(
+            diff[1],
+            HeaderDiff::Added {
+                name: "owner".into(),
+                value: AttributeValue::String("ferris".into()),
+            }
+        );
+        assert_eq!(diff[2].name(), "screenWindowWidth");
+
+        Ok(())
+    }
+
+    #[test]
+    fn print_info() -> Result<(), exr::Error> {
+        let images = Path::new(
+            &std::env::var("CARGO_MANIFEST_DIR")
+                .expect("CARGO_MANIFEST_DIR not set"),
+        )
+        .join("images");
+
+        let ctx = exr::context::ReadContext::new(images.join("ferris.exr"))?;
+        let mut info = Vec::new();
+        ctx.print_info(&mut info, false)?;
+        let info = String::from_utf8(info).unwrap();
+        assert!(info.starts_with("File '"));
+        assert!(info.contains("  compression: Piz\n"));
+        assert!(info.contains("  dataWindow: "));
+
+        let mut verbose = Vec::new();
+        ctx.print_info(&mut verbose, true)?;
+        let verbose = String::from_utf8(verbose).unwrap();
+        assert!(verbose.contains(" parts: 1\n"));
+        assert!(verbose.contains("  screenWindowWidth: "));
+
+        let ctx =
+            exr::context::ReadContext::new(images.join("ferris-tiled.exr"))?;
+        let mut info = Vec::new();
+        ctx.print_info(&mut info, false)?;
+        let info = String::from_utf8(info).unwrap();
+        assert!(info.contains("  tiled image has levels: "));
+
+        Ok(())
+    }
+    #[cfg(feature = "serde")]
+    #[test]
+    fn header_json() -> Result<(), exr::Error> {
+        let images = Path::new(
+            &std::env::var("CARGO_MANIFEST_DIR")
+                .expect("CARGO_MANIFEST_DIR not set"),
+        )
+        .join("images");
+
+        let ctx = exr::context::ReadContext::new(images.join("ferris.exr"))?;
+        let json = serde_json::to_value(&ctx.header(0)?).unwrap();
+        let attributes = json.as_object().expect("header is not a map");
+        assert_eq!(attributes.len(), ctx.attribute_count(0)?);
+        assert_eq!(attributes["compression"]["Compression"], "Piz");
+        assert_eq!(attributes["lineOrder"]["LineOrder"], "IncreasingY");
+
+        let dw = ctx.data_window::<[i32; 4]>(0)?;
+        assert_eq!(attributes["dataWindow"]["Box2i"], serde_json::json!(dw));
+
+        let channels = attributes["channels"]["Chlist"]
+            .as_array()
+            .expect("channels are not a list");
+        assert_eq!(channels.len(), 4);
+        assert_eq!(channels[0]["name"], "A");
+        assert_eq!(channels[0]["pixel_type"], "Half");
+
+        let mut header = exr::header::Header::default();
+        header.insert(
+            "framesPerSecond",
+            AttributeValue::Rational(exr::attr::AttrRational::new(24, 1)),
+        );
+        assert_eq!(
+            serde_json::to_string(&header).unwrap(),
+            r#"{"framesPerSecond":{"Rational":{"num":24,"denom":1}}}"#
+        );
+
+        Ok(())
+    }
+}
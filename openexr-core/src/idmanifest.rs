@@ -0,0 +1,585 @@
+//! Support for the `idmanifest` attribute.
+//!
+//! OpenEXR 3 files carrying object or material ID AOVs may store a manifest
+//! mapping the integer IDs in those channels back to the names of the objects
+//! they came from. On disk the attribute holds the uncompressed size of the
+//! manifest as an `i32` followed by the zlib-compressed serialized manifest.
+//!
+//! The C core library does not know about this type, so it reads it as an
+//! opaque attribute with the type name `"idmanifest"`. [`CompressedIdManifest`]
+//! handles getting it into and out of a part header and the zlib layer, and
+//! [`IdManifest`] parses the serialized manifest into its channel groups and
+//! their id → names tables, and serializes it again.
+//!
+//! The serialized manifest is laid out as follows, where integers are stored
+//! as unsigned LEB128 varints, and strings as a varint length followed by
+//! that many bytes of UTF-8:
+//!
+//! * a version byte, which is 0
+//! * the number of channel groups, then for each group:
+//!   * the number of channels, then each channel name
+//!   * the number of components, then each component name
+//!   * the lifetime of its ids as a byte, see [`IdLifetime`]
+//!   * the hash scheme and the encoding scheme
+//!   * the number of entries, then for each entry, in increasing order of id,
+//!     the difference between its id and the previous entry's, or the first
+//!     entry's id itself, followed by one name for each component
+//!
+use crate::attr::{AttributeRead, AttributeWrite};
+use crate::context::{
+    c_part_index, Context, ContextState, WritableHeaderState,
+};
+use crate::error::Error;
+use openexr_core_sys as sys;
+use std::collections::BTreeMap;
+use std::convert::{TryFrom, TryInto};
+use std::ffi::{CStr, CString};
+
+type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// The type name the `idmanifest` attribute is stored under in the header
+pub const IDMANIFEST_TYPE_NAME: &str = "idmanifest";
+
+/// The conventional name of the manifest attribute in the part header
+pub const IDMANIFEST_ATTR_NAME: &str = "idManifest";
+
+/// The raw, compressed form of an ID manifest as stored in the header
+///
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct CompressedIdManifest {
+    /// Size in bytes of the serialized manifest once decompressed
+    pub uncompressed_size: usize,
+    /// The zlib-compressed serialized manifest
+    pub data: Vec<u8>,
+}
+
+impl CompressedIdManifest {
+    /// Compress a serialized manifest
+    ///
+    pub fn compress(uncompressed: &[u8]) -> CompressedIdManifest {
+        CompressedIdManifest {
+            uncompressed_size: uncompressed.len(),
+            data: miniz_oxide::deflate::compress_to_vec_zlib(uncompressed, 6),
+        }
+    }
+
+    /// Decompress the manifest back to its serialized form
+    ///
+    /// # Errors
+    /// * `[Error::CorruptChunk]` - If the data cannot be decompressed or does
+    /// not decompress to `uncompressed_size` bytes
+    ///
+    pub fn decompress(&self) -> Result<Vec<u8>> {
+        use miniz_oxide::inflate::core::{
+            decompress, inflate_flags, DecompressorOxide,
+        };
+        use miniz_oxide::inflate::TINFLStatus;
+
+        // inflate into a buffer one byte bigger than the size given, so data
+        // that would inflate to more than that is caught without ever growing
+        // it. decompress_to_vec_zlib_with_limit is not used as it can fail
+        // when the data does fit, if doubling its buffer would overshoot
+        let capacity = self
+            .uncompressed_size
+            .checked_add(1)
+            .ok_or(Error::CorruptChunk)?;
+        let mut bytes = vec![0; capacity];
+        let (status, _, len) = decompress(
+            &mut Box::<DecompressorOxide>::default(),
+            &self.data,
+            &mut bytes,
+            0,
+            inflate_flags::TINFL_FLAG_PARSE_ZLIB_HEADER
+                | inflate_flags::TINFL_FLAG_USING_NON_WRAPPING_OUTPUT_BUF,
+        );
+        if status != TINFLStatus::Done || len != self.uncompressed_size {
+            return Err(Error::CorruptChunk);
+        }
+        bytes.truncate(len);
+        Ok(bytes)
+    }
+
+    /// Split the packed attribute bytes into the size prefix and zlib data
+    ///
+    fn from_packed(packed: &[u8]) -> Result<CompressedIdManifest> {
+        if packed.len() < 4 {
+            return Err(Error::AttrSizeMismatch);
+        }
+        let size = i32::from_le_bytes(
+            packed[0..4].try_into().map_err(|_| Error::CorruptChunk)?,
+        );
+        if size < 0 {
+            return Err(Error::InvalidAttr);
+        }
+        Ok(CompressedIdManifest {
+            uncompressed_size: size as usize,
+            data: packed[4..].to_vec(),
+        })
+    }
+
+    fn to_packed(&self) -> Result<Vec<u8>> {
+        let size: i32 = self
+            .uncompressed_size
+            .try_into()
+            .map_err(|_| Error::ArgumentOutOfRange)?;
+        let mut packed = Vec::with_capacity(4 + self.data.len());
+        packed.extend_from_slice(&size.to_le_bytes());
+        packed.extend_from_slice(&self.data);
+        Ok(packed)
+    }
+}
+
+impl AttributeRead for CompressedIdManifest {
+    fn get<S: ContextState>(
+        ctx: &Context<S>,
+        part_index: usize,
+        name: &str,
+    ) -> Result<Self> {
+        let c_name = CString::new(name).unwrap();
+        let mut type_name = std::ptr::null();
+        let mut size = 0;
+        let mut data = std::ptr::null();
+        unsafe {
+            sys::exr_attr_get_user(
+                ctx.inner,
+                c_part_index(part_index)?,
+                c_name.as_ptr(),
+                &mut type_name,
+                &mut size,
+                &mut data,
+            )
+            .ok_in(ctx.inner, ())?;
+
+            if type_name.is_null()
+                || CStr::from_ptr(type_name).to_bytes()
+                    != IDMANIFEST_TYPE_NAME.as_bytes()
+            {
+                return Err(Error::AttrTypeMismatch);
+            }
+
+            let packed = if data.is_null() || size <= 0 {
+                &[]
+            } else {
+                std::slice::from_raw_parts(data as *const u8, size as usize)
+            };
+            CompressedIdManifest::from_packed(packed)
+        }
+    }
+}
+
+impl AttributeWrite for CompressedIdManifest {
+    fn set<S: WritableHeaderState>(
+        ctx: &Context<S>,
+        part_index: usize,
+        name: &str,
+        value: &Self,
+    ) -> Result<()> {
+        let c_name = CString::new(name).unwrap();
+        let c_type = CString::new(IDMANIFEST_TYPE_NAME).unwrap();
+        let packed = value.to_packed()?;
+        let len = i32::try_from(packed.len())
+            .map_err(|_| Error::ArgumentOutOfRange)?;
+        unsafe {
+            sys::exr_attr_set_user(
+                ctx.inner,
+                c_part_index(part_index)?,
+                c_name.as_ptr(),
+                c_type.as_ptr(),
+                len,
+                packed.as_ptr() as *const std::ffi::c_void,
+            )
+            .ok_in(ctx.inner, ())
+        }
+    }
+}
+
+/// How long the ids in a channel group stay attached to the same objects
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum IdLifetime {
+    /// The ids may change from frame to frame
+    Frame,
+    /// The ids are the same for every frame of a shot
+    Shot,
+    /// The ids are the same across shots
+    Stable,
+}
+
+impl IdLifetime {
+    fn from_byte(byte: u8) -> Result<IdLifetime> {
+        match byte {
+            0 => Ok(IdLifetime::Frame),
+            1 => Ok(IdLifetime::Shot),
+            2 => Ok(IdLifetime::Stable),
+            _ => Err(Error::InvalidAttr),
+        }
+    }
+
+    fn to_byte(self) -> u8 {
+        match self {
+            IdLifetime::Frame => 0,
+            IdLifetime::Shot => 1,
+            IdLifetime::Stable => 2,
+        }
+    }
+}
+
+/// The ids used by a group of channels, and the names each one stands for
+///
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChannelGroupManifest {
+    /// The channels whose values are ids from this group
+    pub channels: Vec<String>,
+    /// What each of an entry's names is, e.g. `["model", "material"]`
+    pub components: Vec<String>,
+    pub lifetime: IdLifetime,
+    /// How the names were hashed to get the ids, e.g. `"MurmurHash3_32"`
+    pub hash_scheme: String,
+    /// How the ids are stored in the channels, e.g. `"id"`
+    pub encoding_scheme: String,
+    /// The names each id stands for, one for each of the components
+    pub entries: BTreeMap<u64, Vec<String>>,
+}
+
+impl ChannelGroupManifest {
+    /// Create a group with no entries, whose ids have the given `components`
+    /// and are stored in `channels`
+    ///
+    pub fn new(channels: &[&str], components: &[&str]) -> ChannelGroupManifest {
+        ChannelGroupManifest {
+            channels: channels.iter().map(|c| c.to_string()).collect(),
+            components: components.iter().map(|c| c.to_string()).collect(),
+            lifetime: IdLifetime::Stable,
+            hash_scheme: "MurmurHash3_32".to_string(),
+            encoding_scheme: "id".to_string(),
+            entries: BTreeMap::new(),
+        }
+    }
+
+    /// Add the entry for `id`, replacing any there was
+    ///
+    /// # Errors
+    /// * `[Error::InvalidArgument]` - If there is not one name for each
+    /// component
+    ///
+    pub fn insert(&mut self, id: u64, names: &[&str]) -> Result<()> {
+        if names.len() != self.components.len() {
+            return Err(Error::InvalidArgument);
+        }
+        self.entries
+            .insert(id, names.iter().map(|n| n.to_string()).collect());
+        Ok(())
+    }
+}
+
+/// The parsed contents of an `idmanifest` attribute
+///
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct IdManifest {
+    pub groups: Vec<ChannelGroupManifest>,
+}
+
+impl IdManifest {
+    /// The names `id` stands for in the group that `channel` belongs to
+    ///
+    pub fn lookup(&self, channel: &str, id: u64) -> Option<&[String]> {
+        self.groups
+            .iter()
+            .find(|g| g.channels.iter().any(|c| c == channel))
+            .and_then(|g| g.entries.get(&id))
+            .map(|names| names.as_slice())
+    }
+
+    /// Parse a serialized manifest, as laid out in the [module
+    /// documentation](self)
+    ///
+    /// # Errors
+    /// * `[Error::InvalidAttr]` - If the manifest is truncated or malformed,
+    /// or of an unknown version
+    ///
+    pub fn parse(serialized: &[u8]) -> Result<IdManifest> {
+        let mut reader = ManifestReader { bytes: serialized };
+        if reader.byte()? != 0 {
+            return Err(Error::InvalidAttr);
+        }
+
+        let mut groups = Vec::new();
+        for _ in 0..reader.varint()? {
+            let channels = reader.strings()?;
+            let components = reader.strings()?;
+            let lifetime = IdLifetime::from_byte(reader.byte()?)?;
+            let hash_scheme = reader.string()?;
+            let encoding_scheme = reader.string()?;
+
+            let mut entries = BTreeMap::new();
+            let mut id = 0u64;
+            for _ in 0..reader.varint()? {
+                id = id
+                    .checked_add(reader.varint()?)
+                    .ok_or(Error::InvalidAttr)?;
+                let names = (0..components.len())
+                    .map(|_| reader.string())
+                    .collect::<Result<Vec<_>>>()?;
+                entries.insert(id, names);
+            }
+
+            groups.push(ChannelGroupManifest {
+                channels,
+                components,
+                lifetime,
+                hash_scheme,
+                encoding_scheme,
+                entries,
+            });
+        }
+
+        if !reader.bytes.is_empty() {
+            return Err(Error::InvalidAttr);
+        }
+        Ok(IdManifest { groups })
+    }
+
+    /// Serialize the manifest, as laid out in the [module
+    /// documentation](self)
+    ///
+    /// # Errors
+    /// * `[Error::InvalidArgument]` - If an entry does not have one name for
+    /// each of its group's components
+    ///
+    pub fn serialize(&self) -> Result<Vec<u8>> {
+        let mut out = vec![0];
+        write_varint(&mut out, self.groups.len() as u64);
+        for group in &self.groups {
+            write_strings(&mut out, &group.channels);
+            write_strings(&mut out, &group.components);
+            out.push(group.lifetime.to_byte());
+            write_string(&mut out, &group.hash_scheme);
+            write_string(&mut out, &group.encoding_scheme);
+
+            write_varint(&mut out, group.entries.len() as u64);
+            let mut previous = 0;
+            for (id, names) in &group.entries {
+                if names.len() != group.components.len() {
+                    return Err(Error::InvalidArgument);
+                }
+                write_varint(&mut out, id - previous);
+                previous = *id;
+                for name in names {
+                    write_string(&mut out, name);
+                }
+            }
+        }
+        Ok(out)
+    }
+
+    /// Serialize and compress the manifest, ready to be stored in a header
+    ///
+    /// # Errors
+    /// * `[Error::InvalidArgument]` - If an entry does not have one name for
+    /// each of its group's components
+    ///
+    pub fn compress(&self) -> Result<CompressedIdManifest> {
+        Ok(CompressedIdManifest::compress(&self.serialize()?))
+    }
+}
+
+impl CompressedIdManifest {
+    /// Decompress and parse the manifest
+    ///
+    /// # Errors
+    /// * `[Error::CorruptChunk]` - If the data cannot be decompressed
+    /// * `[Error::InvalidAttr]` - If the manifest is malformed
+    ///
+    pub fn manifest(&self) -> Result<IdManifest> {
+        IdManifest::parse(&self.decompress()?)
+    }
+}
+
+/// Reads the fields of a serialized manifest from the front of `bytes`
+///
+struct ManifestReader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> ManifestReader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        if len > self.bytes.len() {
+            return Err(Error::InvalidAttr);
+        }
+        let (head, tail) = self.bytes.split_at(len);
+        self.bytes = tail;
+        Ok(head)
+    }
+
+    fn byte(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn varint(&mut self) -> Result<u64> {
+        let mut value = 0u64;
+        for shift in (0..64).step_by(7) {
+            let byte = self.byte()?;
+            value |= u64::from(byte & 0x7f) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(Error::InvalidAttr)
+    }
+
+    fn string(&mut self) -> Result<String> {
+        let len =
+            usize::try_from(self.varint()?).map_err(|_| Error::InvalidAttr)?;
+        String::from_utf8(self.take(len)?.to_vec())
+            .map_err(|_| Error::InvalidAttr)
+    }
+
+    fn strings(&mut self) -> Result<Vec<String>> {
+        (0..self.varint()?).map(|_| self.string()).collect()
+    }
+}
+
+fn write_varint(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push((value & 0x7f) as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn write_string(out: &mut Vec<u8>, s: &str) {
+    write_varint(out, s.len() as u64);
+    out.extend_from_slice(s.as_bytes());
+}
+
+fn write_strings(out: &mut Vec<u8>, strings: &[String]) {
+    write_varint(out, strings.len() as u64);
+    for s in strings {
+        write_string(out, s);
+    }
+}
+
+impl AttributeRead for IdManifest {
+    fn get<S: ContextState>(
+        ctx: &Context<S>,
+        part_index: usize,
+        name: &str,
+    ) -> Result<Self> {
+        CompressedIdManifest::get(ctx, part_index, name)?.manifest()
+    }
+}
+
+impl AttributeWrite for IdManifest {
+    fn set<S: WritableHeaderState>(
+        ctx: &Context<S>,
+        part_index: usize,
+        name: &str,
+        value: &Self,
+    ) -> Result<()> {
+        CompressedIdManifest::set(ctx, part_index, name, &value.compress()?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::idmanifest::{
+        ChannelGroupManifest, CompressedIdManifest, IdLifetime, IdManifest,
+    };
+
+    #[test]
+    fn compressed_roundtrip() -> Result<(), crate::Error> {
+        let serialized = b"\0\x01\x00some serialized manifest".to_vec();
+        let compressed = CompressedIdManifest::compress(&serialized);
+        assert_eq!(compressed.uncompressed_size, serialized.len());
+
+        let packed = compressed.to_packed()?;
+        let unpacked = CompressedIdManifest::from_packed(&packed)?;
+        assert_eq!(unpacked, compressed);
+        assert_eq!(unpacked.decompress()?, serialized);
+
+        assert!(CompressedIdManifest::from_packed(&[0, 0]).is_err());
+
+        // data that compresses well still inflates in full
+        let repetitive = vec![7u8; 4096];
+        let compressed_repetitive = CompressedIdManifest::compress(&repetitive);
+        assert!(compressed_repetitive.data.len() < 100);
+        assert_eq!(compressed_repetitive.decompress()?, repetitive);
+
+        // data that inflates to more or less than the size given is rejected
+        for uncompressed_size in &[serialized.len() - 1, serialized.len() + 1] {
+            let wrong_size = CompressedIdManifest {
+                uncompressed_size: *uncompressed_size,
+                ..compressed.clone()
+            };
+            assert_eq!(
+                wrong_size.decompress().unwrap_err().kind(),
+                crate::ErrorKind::CorruptChunk
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn manifest_roundtrip() -> Result<(), crate::Error> {
+        let mut group =
+            ChannelGroupManifest::new(&["id"], &["model", "material"]);
+        group.lifetime = IdLifetime::Shot;
+        group.insert(7, &["ferris", "shell"])?;
+        group.insert(300, &["ferris", "claw"])?;
+        group.insert(u64::MAX, &["world", ""])?;
+        assert!(group.insert(8, &["too few"]).is_err());
+
+        let manifest = IdManifest {
+            groups: vec![group, ChannelGroupManifest::new(&["A", "B"], &[])],
+        };
+        let serialized = manifest.serialize()?;
+        assert_eq!(IdManifest::parse(&serialized)?, manifest);
+        assert_eq!(manifest.compress()?.manifest()?, manifest);
+
+        let names = manifest.lookup("id", 300).expect("no entry for 300");
+        assert_eq!(names, ["ferris", "claw"]);
+        assert!(manifest.lookup("id", 8).is_none());
+        assert!(manifest.lookup("Z", 7).is_none());
+
+        // truncated or unknown versions are rejected
+        assert_eq!(
+            IdManifest::parse(&serialized[..serialized.len() - 1])
+                .unwrap_err()
+                .kind(),
+            crate::ErrorKind::InvalidAttr
+        );
+        assert!(IdManifest::parse(&[1, 0]).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn manifest_layout() -> Result<(), crate::Error> {
+        let mut group = ChannelGroupManifest::new(&["id"], &["name"]);
+        group.hash_scheme = "h".to_string();
+        group.encoding_scheme = "e".to_string();
+        group.insert(200, &["a"])?;
+        group.insert(201, &["b"])?;
+
+        #[rustfmt::skip]
+        let expected: &[u8] = &[
+            0, // version
+            1, // groups
+            1, 2, b'i', b'd', // channels
+            1, 4, b'n', b'a', b'm', b'e', // components
+            2, // lifetime
+            1, b'h', // hash scheme
+            1, b'e', // encoding scheme
+            2, // entries
+            0xc8, 0x01, 1, b'a', // 200 itself
+            1, 1, b'b', // 201 - 200
+        ];
+        let manifest = IdManifest {
+            groups: vec![group],
+        };
+        assert_eq!(manifest.serialize()?, expected);
+        assert_eq!(IdManifest::parse(expected)?, manifest);
+
+        Ok(())
+    }
+}
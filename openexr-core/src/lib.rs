@@ -1,11 +1,41 @@
 pub mod context;
 pub mod error;
-pub use error::Error;
+pub use error::{Error, ErrorKind};
 pub mod attr;
 pub mod part;
 pub mod decode;
+pub mod encode;
 pub mod chunkio;
 pub mod coding;
+pub mod header;
+pub mod idmanifest;
+pub mod read;
+pub mod rgba;
+pub mod write;
+pub mod framebuffer;
+pub mod stream;
+pub mod validate;
+pub mod standard_attributes;
+pub mod multiview;
+pub mod transcode;
+pub mod compare;
+pub mod defaults;
+pub mod prefetch;
+pub mod pool;
+pub mod progress;
+mod trace;
+
+#[cfg(feature = "async")]
+pub mod async_read;
+
+#[cfg(feature = "rayon")]
+pub mod parallel;
+
+#[cfg(feature = "streaming")]
+pub mod streaming;
+
+#[cfg(all(target_os = "linux", feature = "io-uring"))]
+pub mod uring;
 
 use openexr_core_sys as sys;
 use semver::{BuildMetadata, Prerelease, Version};
@@ -0,0 +1,200 @@
+//! Helpers for images holding several views, such as a stereo pair, following
+//! the OpenEXR multi-view convention.
+//!
+//! The `multiView` attribute lists the names of the views in a part, the
+//! first of which is the default view. A channel belongs to the view named by
+//! the second-to-last component of its name, e.g. "left.R" or
+//! "diffuse.right.G". Channels with only one component, e.g. "R", belong to
+//! the default view, and channels whose second-to-last component is not a
+//! view, e.g. "diffuse.R", don't belong to any view.
+//!
+use crate::attr::Channel;
+use crate::context::{Context, ContextState, WriteHeaderContext};
+use crate::error::{Error, ErrorKind};
+
+type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// The name of the attribute listing the views in a part
+///
+pub const MULTI_VIEW: &str = "multiView";
+
+impl<S: ContextState> Context<S> {
+    /// Get the names of the views in the specified part, or `None` if it is
+    /// not a multi-view part
+    ///
+    /// # Errors
+    /// * `[Error::AttrTypeMismatch]` - If `multiView` is not a string vector
+    ///
+    pub fn multi_view(&self, part_index: usize) -> Result<Option<Vec<String>>> {
+        match self.get_attribute(part_index, MULTI_VIEW) {
+            Ok(views) => Ok(Some(views)),
+            Err(e) if e.kind() == ErrorKind::NoAttrByName => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Get the name of the default view of the specified part, or `None` if
+    /// it is not a multi-view part
+    ///
+    /// # Errors
+    /// * `[Error::AttrTypeMismatch]` - If `multiView` is not a string vector
+    ///
+    pub fn default_view(&self, part_index: usize) -> Result<Option<String>> {
+        Ok(self
+            .multi_view(part_index)?
+            .and_then(|views| views.into_iter().next()))
+    }
+
+    /// Get the channels of the specified part that belong to `view`
+    ///
+    /// # Errors
+    /// * `[Error::NoAttrByName]` - If the part is not a multi-view part
+    /// * `[Error::AttrTypeMismatch]` - If `multiView` is not a string vector
+    ///
+    pub fn channels_in_view(
+        &self,
+        part_index: usize,
+        view: &str,
+    ) -> Result<Vec<&Channel>> {
+        let views: Vec<String> = self
+            .get_attribute(part_index, MULTI_VIEW)
+            .map_err(|e| e.with_part(part_index).with_attribute(MULTI_VIEW))?;
+        Ok(self
+            .channels(part_index)?
+            .iter()
+            .filter(|c| view_of_channel(c.name(), &views) == Some(view))
+            .collect())
+    }
+}
+
+impl WriteHeaderContext {
+    /// Set the names of the views in the specified part, the first of which
+    /// is the default view
+    ///
+    /// # Errors
+    /// * `[Error::InvalidArgument]` - If any of `views` contains internal null
+    /// bytes
+    /// * `[Error::AlreadyWroteAttrs]` - If the header has already been written
+    ///
+    pub fn set_multi_view(
+        &mut self,
+        part_index: usize,
+        views: &[&str],
+    ) -> Result<()> {
+        self.set_attribute(part_index, MULTI_VIEW, &views)
+    }
+}
+
+/// Get the view the channel called `channel` belongs to, given the `views`
+/// listed in a part's `multiView` attribute, or `None` if it doesn't belong
+/// to any view
+///
+pub fn view_of_channel<'a, V: AsRef<str>>(
+    channel: &str,
+    views: &'a [V],
+) -> Option<&'a str> {
+    let mut components = channel.rsplit('.');
+    components.next();
+    match components.next() {
+        None => views.first().map(|v| v.as_ref()),
+        Some(view) => views.iter().map(|v| v.as_ref()).find(|v| *v == view),
+    }
+}
+
+/// Get the name of the channel in `view` that corresponds to `channel`, e.g.
+/// "right.R" for "left.R", given the `views` listed in a part's `multiView`
+/// attribute
+///
+/// Returns `None` if `channel` doesn't belong to any view, or `view` is not
+/// one of `views`.
+///
+pub fn channel_in_view<V: AsRef<str>>(
+    channel: &str,
+    view: &str,
+    views: &[V],
+) -> Option<String> {
+    view_of_channel(channel, views)?;
+    let default = views.first()?.as_ref();
+    if !views.iter().any(|v| v.as_ref() == view) {
+        return None;
+    }
+
+    // strip the current view, if the name has one
+    let mut components = channel.split('.').collect::<Vec<_>>();
+    let base = components.pop()?;
+    components.pop();
+
+    if components.is_empty() && view == default {
+        Some(base.to_string())
+    } else {
+        components.push(view);
+        components.push(base);
+        Some(components.join("."))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate as exr;
+    use exr::multiview::{channel_in_view, view_of_channel};
+
+    #[test]
+    fn channel_views() {
+        let views = ["left", "right"];
+        assert_eq!(view_of_channel("R", &views), Some("left"));
+        assert_eq!(view_of_channel("right.R", &views), Some("right"));
+        assert_eq!(view_of_channel("diffuse.left.G", &views), Some("left"));
+        assert_eq!(view_of_channel("diffuse.R", &views), None);
+
+        assert_eq!(
+            channel_in_view("R", "right", &views).as_deref(),
+            Some("right.R")
+        );
+        assert_eq!(
+            channel_in_view("right.R", "left", &views).as_deref(),
+            Some("R")
+        );
+        assert_eq!(
+            channel_in_view("diffuse.right.B", "left", &views).as_deref(),
+            Some("diffuse.left.B")
+        );
+        assert_eq!(channel_in_view("diffuse.R", "left", &views), None);
+        assert_eq!(channel_in_view("R", "centre", &views), None);
+    }
+
+    #[test]
+    fn multi_view() -> Result<(), exr::Error> {
+        use exr::context::{DefaultWriteMode, ReadContext, WriteHeaderContext};
+        use std::path::Path;
+
+        let path_ferris = Path::new(
+            &std::env::var("CARGO_MANIFEST_DIR")
+                .expect("CARGO_MANIFEST_DIR not set"),
+        )
+        .join("images")
+        .join("ferris.exr");
+        let src = ReadContext::new(&path_ferris)?;
+        assert_eq!(src.multi_view(0)?, None);
+        assert_eq!(src.default_view(0)?, None);
+
+        let path = std::env::temp_dir().join("multi_view.exr");
+        let mut header = WriteHeaderContext::new(
+            &path,
+            DefaultWriteMode::WriteFileDirectly,
+        )?;
+        header.add_part_copy(&src, 0, None)?;
+        header.set_multi_view(0, &["left", "right"])?;
+
+        assert_eq!(
+            header.multi_view(0)?,
+            Some(vec!["left".to_string(), "right".to_string()])
+        );
+        assert_eq!(header.default_view(0)?.as_deref(), Some("left"));
+        // ferris's channels have no view in their names, so are all in the
+        // default view
+        assert_eq!(header.channels_in_view(0, "left")?.len(), 4);
+        assert!(header.channels_in_view(0, "right")?.is_empty());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,1005 @@
+//! Decoding and encoding chunks on a rayon thread pool, enabled with the
+//! `rayon` feature.
+//!
+//! The chunk-based C API lets any number of chunks of a context be read and
+//! decoded at once, so each worker gets its own pipeline and decodes its
+//! chunks straight into a disjoint region of the output. Chunks can likewise
+//! be compressed in parallel, leaving only the writes themselves to be made
+//! one after the other, in the order the file requires, by a writer thread
+//! that runs alongside the workers. Tiles can also be handed in one at a
+//! time, in any order, with a [`TileWriter`].
+//!
+use crate::attr::{LineOrder, Storage};
+use crate::chunkio::ChunkInfo;
+use crate::coding::ChannelElement;
+use crate::context::{c_part_index, ReadContext, WriteContext};
+use crate::decode::{DecodePipeline, Runnable};
+use crate::encode::EncodePipeline;
+use crate::error::Error;
+use crate::pool::{BufferPool, PooledBuffer};
+use crate::read::{num_tiles, ImageBuffer};
+use crate::validate::{part_levels, ChunkLocation};
+use crate::write::{set_encode_from, ImageChunk, ImageDesc};
+use openexr_core_sys as sys;
+use rayon::prelude::*;
+use std::collections::HashMap;
+use std::os::raw::c_void;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender};
+use std::sync::{Condvar, Mutex};
+
+type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// Shares a context opened for writing between the workers, which only
+/// encode chunks, and the writer thread, which writes them
+///
+struct SharedWriter<'a>(&'a WriteContext);
+
+// Safety: encoding a chunk only reads the context's state, as long as the
+// pipeline's write routine has been replaced, which encode_chunk does. The
+// only thread writing chunks is the writer, and the C core locks the context
+// while it writes each one
+unsafe impl Sync for SharedWriter<'_> {}
+unsafe impl Send for SharedWriter<'_> {}
+
+impl ReadContext {
+    /// Decode the whole of a part into an interleaved buffer of the channels
+    /// named in `channels`, converted to `T`, splitting the chunks across
+    /// rayon's global thread pool
+    ///
+    /// The result is the same as [`ReadContext::read_image`]. Scanline chunks
+    /// are decoded straight into the image, while tiled parts are split by
+    /// rows of tiles. For tiled parts, this reads the highest-resolution
+    /// level.
+    ///
+    /// # Errors
+    /// * `[Error::FeatureNotImplemented]` - If the part holds deep data
+    ///
+    pub fn read_image_parallel<T: ChannelElement + Send>(
+        &self,
+        part_index: usize,
+        channels: &[&str],
+    ) -> Result<ImageBuffer<T>> {
+        let dw = self.data_window::<[i32; 4]>(part_index)?;
+        let width = (dw[2] - dw[0] + 1).max(0) as usize;
+        let height = (dw[3] - dw[1] + 1).max(0) as usize;
+        let line_len = width * channels.len();
+
+        let mut image = ImageBuffer {
+            width,
+            height,
+            data_window: dw,
+            channels: channels.iter().map(|c| c.to_string()).collect(),
+            pixels: vec![T::default(); width * height * channels.len()],
+        };
+        if line_len == 0 || height == 0 {
+            return Ok(image);
+        }
+
+        match self.storage(part_index)? {
+            Storage::Scanline => {
+                let lines = self.scanlines_per_chunk(part_index)?.max(1);
+                image
+                    .pixels
+                    .par_chunks_mut(lines * line_len)
+                    .enumerate()
+                    .try_for_each_init(
+                        || None,
+                        |pipeline, (i, dest)| {
+                            let y = dw[1] + (i * lines) as i32;
+                            let chunk_info =
+                                self.read_scanline_chunk_info(part_index, y)?;
+                            decode_chunk(
+                                self,
+                                pipeline,
+                                part_index,
+                                &chunk_info,
+                                channels,
+                                dest,
+                            )
+                        },
+                    )?;
+            }
+            Storage::Tiled => {
+                let (tile_width, tile_height) =
+                    self.tile_sizes(part_index, 0, 0)?;
+                let tiles_x = num_tiles(width, tile_width);
+                image
+                    .pixels
+                    .par_chunks_mut(tile_height * line_len)
+                    .enumerate()
+                    .try_for_each_init(
+                        || (None, Vec::new()),
+                        |(pipeline, tile), (tile_y, dest)| {
+                            for tile_x in 0..tiles_x {
+                                let chunk_info = self.read_tile_chunk_info(
+                                    part_index,
+                                    tile_x,
+                                    tile_y as i32,
+                                    0,
+                                    0,
+                                )?;
+                                let tile_line_len = chunk_info.width.max(0)
+                                    as usize
+                                    * channels.len();
+                                let num_values = tile_line_len
+                                    * chunk_info.height.max(0) as usize;
+                                tile.clear();
+                                tile.resize(num_values, T::default());
+                                decode_chunk(
+                                    self,
+                                    pipeline,
+                                    part_index,
+                                    &chunk_info,
+                                    channels,
+                                    tile,
+                                )?;
+
+                                if tile_line_len == 0 {
+                                    continue;
+                                }
+                                let x = tile_x as usize
+                                    * tile_width
+                                    * channels.len();
+                                for (row, src) in
+                                    tile.chunks_exact(tile_line_len).enumerate()
+                                {
+                                    let start = row * line_len + x;
+                                    dest.get_mut(start..start + tile_line_len)
+                                        .ok_or(Error::CorruptChunk)?
+                                        .copy_from_slice(src);
+                                }
+                            }
+                            Ok::<_, Error>(())
+                        },
+                    )?;
+            }
+            Storage::DeepScanline | Storage::DeepTiled => {
+                return Err(Error::FeatureNotImplemented)
+            }
+        }
+
+        Ok(image)
+    }
+}
+
+/// Decode the chunk described by `chunk_info` into `dest`, interleaved,
+/// re-using the worker's pipeline if it has one
+///
+fn decode_chunk<'c, T: ChannelElement>(
+    ctx: &'c ReadContext,
+    pipeline: &mut Option<DecodePipeline<'c, Runnable>>,
+    part_index: usize,
+    chunk_info: &ChunkInfo,
+    channels: &[&str],
+    dest: &mut [T],
+) -> Result<()> {
+    let mut ready = match pipeline.take() {
+        Some(p) => p.update(chunk_info)?,
+        None => ctx.decoding_initialize(part_index, chunk_info)?,
+    };
+    ready.select_interleaved(channels, dest)?;
+    let mut runnable = ready.choose_default_routines()?;
+    runnable.run_checked(dest)?;
+    *pipeline = Some(runnable);
+    Ok(())
+}
+
+/// A worker's encode pipeline, whether it has been initialized yet, and the
+/// pool its compressed chunks are copied into
+///
+struct Encoder<'c> {
+    pipeline: EncodePipeline<'c>,
+    initialized: bool,
+    pool: BufferPool,
+}
+
+/// Where [`capture_chunk`] puts the compressed chunk
+///
+struct CapturedChunk<'p> {
+    pool: &'p BufferPool,
+    packed: Option<PooledBuffer>,
+}
+
+/// Keeps the workers compressing no more than `window` chunks ahead of the
+/// writer, so that only that many compressed chunks are held at once
+///
+struct WriteWindow {
+    window: usize,
+    /// The next chunk for a worker to take
+    next: AtomicUsize,
+    state: Mutex<WindowState>,
+    progress: Condvar,
+}
+
+struct WindowState {
+    written: usize,
+    stopped: bool,
+}
+
+impl WriteWindow {
+    fn new(window: usize) -> WriteWindow {
+        WriteWindow {
+            window: window.max(1),
+            next: AtomicUsize::new(0),
+            state: Mutex::new(WindowState {
+                written: 0,
+                stopped: false,
+            }),
+            progress: Condvar::new(),
+        }
+    }
+
+    /// Take the next of `count` chunks to compress, waiting until the writer
+    /// is close enough behind, or `None` once every chunk has been taken or
+    /// the writer has stopped
+    ///
+    fn take(&self, count: usize) -> Option<usize> {
+        let index = self.next.fetch_add(1, Ordering::SeqCst);
+        if index >= count {
+            return None;
+        }
+
+        let mut state = self.state.lock().ok()?;
+        while !state.stopped && index >= state.written + self.window {
+            state = self.progress.wait(state).ok()?;
+        }
+        if state.stopped {
+            None
+        } else {
+            Some(index)
+        }
+    }
+
+    /// Let the workers move on now that `written` chunks have been written
+    ///
+    fn advance(&self, written: usize) {
+        if let Ok(mut state) = self.state.lock() {
+            state.written = written;
+        }
+        self.progress.notify_all();
+    }
+
+    /// Stop the workers from taking any more chunks
+    ///
+    fn stop(&self) {
+        if let Ok(mut state) = self.state.lock() {
+            state.stopped = true;
+        }
+        self.progress.notify_all();
+    }
+}
+
+/// Used in place of the pipeline's write routine, to keep the compressed
+/// chunk in a buffer taken from the [`CapturedChunk`] pointed to by its user
+/// data, rather than writing it to the file
+///
+unsafe extern "C" fn capture_chunk(
+    encode: *mut sys::exr_encode_pipeline_t,
+) -> sys::exr_result_t {
+    let encode = &*encode;
+    let captured = &mut *(encode.encoding_user_data as *mut CapturedChunk);
+    let packed = if encode.compressed_buffer.is_null() {
+        captured.pool.take(0)
+    } else {
+        let mut packed = captured.pool.take(encode.compressed_bytes);
+        packed.copy_from_slice(std::slice::from_raw_parts(
+            encode.compressed_buffer as *const u8,
+            encode.compressed_bytes,
+        ));
+        packed
+    };
+    captured.packed = Some(packed);
+    sys::exr_result_t::from_code(sys::exr_error_code_t::EXR_ERR_SUCCESS)
+}
+
+impl WriteContext {
+    /// Work out where a chunk of an image goes in the part, which is at most
+    /// one level, as [`WriteContext::image_chunks`] requires
+    ///
+    fn image_chunk_location(
+        &self,
+        part_index: usize,
+        chunk: &ImageChunk,
+    ) -> Result<ChunkLocation> {
+        match self.storage(part_index)? {
+            Storage::Tiled => {
+                let (tile_width, tile_height) =
+                    self.tile_sizes(part_index, 0, 0)?;
+                Ok(ChunkLocation::Tile {
+                    x: (chunk.origin.0 / tile_width.max(1)) as i32,
+                    y: (chunk.origin.1 / tile_height.max(1)) as i32,
+                    level_x: 0,
+                    level_y: 0,
+                })
+            }
+            _ => Ok(ChunkLocation::Scanline {
+                y: chunk.info.start_y,
+            }),
+        }
+    }
+
+    /// Write an already packed and compressed chunk of a flat part at
+    /// `location`, as [`WriteContext::write_scanline_chunk`] and
+    /// [`WriteContext::write_tile_chunk`] do
+    ///
+    /// This only borrows the context, so that chunks can be written while
+    /// encode pipelines hold on to it. The C core serializes the writes
+    /// itself.
+    ///
+    fn write_chunk_at(
+        &self,
+        part_index: usize,
+        location: ChunkLocation,
+        packed_data: &[u8],
+    ) -> Result<()> {
+        let part = c_part_index(part_index)?;
+        let data = packed_data.as_ptr() as *const c_void;
+        let size = packed_data.len() as u64;
+        unsafe {
+            match location {
+                ChunkLocation::Scanline { y } => sys::exr_write_scanline_chunk(
+                    self.inner, part, y, data, size,
+                ),
+                ChunkLocation::Tile {
+                    x,
+                    y,
+                    level_x,
+                    level_y,
+                } => sys::exr_write_tile_chunk(
+                    self.inner, part, x, y, level_x, level_y, data, size,
+                ),
+            }
+            .ok_in(self.inner, ())
+            .map_err(|e| e.with_part(part_index))
+        }
+    }
+}
+
+/// Convert and compress a chunk of `pixels` with the worker's pipeline,
+/// returning the bytes to write to the file in a buffer from the worker's
+/// pool
+///
+fn encode_chunk<'c>(
+    ctx: &'c WriteContext,
+    encoder: &mut Encoder<'c>,
+    part_index: usize,
+    chunk: &ImageChunk,
+    desc: &ImageDesc,
+    pixels: &[u8],
+) -> Result<PooledBuffer> {
+    let pipeline = &mut encoder.pipeline;
+    if encoder.initialized {
+        ctx.encoding_update(part_index, &chunk.info, pipeline)?;
+    } else {
+        ctx.encoding_initialize(part_index, &chunk.info, pipeline)?;
+        encoder.initialized = true;
+    }
+
+    let (x, y) = chunk.origin;
+    set_encode_from(pipeline, &chunk.info, desc, pixels, x, y)?;
+    ctx.encoding_choose_default_routines(part_index, pipeline)?;
+
+    let mut captured = CapturedChunk {
+        pool: &encoder.pool,
+        packed: None,
+    };
+    pipeline.inner.write_fn = Some(capture_chunk);
+    pipeline.inner.encoding_user_data =
+        &mut captured as *mut CapturedChunk as *mut _;
+    // Safety: set_encode_from has checked every channel reads from within
+    // pixels, and the pipeline only writes to captured
+    let result = unsafe { ctx.encoding_run(part_index, pipeline) };
+    pipeline.inner.encoding_user_data = std::ptr::null_mut();
+    pipeline.clear_inputs();
+
+    result?;
+    captured.packed.ok_or(Error::Unknown)
+}
+
+/// A compressed chunk sent from a worker to the writer, with its position in
+/// the order the chunks are written
+///
+type PackedChunk = (usize, Result<PooledBuffer>);
+
+/// Take chunks from `window` and compress them with a pipeline of the
+/// worker's own, sending them to the writer until there are none left, one
+/// fails or the writer has stopped
+///
+#[allow(clippy::too_many_arguments)]
+fn compress_chunks(
+    ctx: &WriteContext,
+    part_index: usize,
+    chunks: &[ImageChunk],
+    desc: &ImageDesc,
+    pixels: &[u8],
+    pool: &BufferPool,
+    window: &WriteWindow,
+    sender: SyncSender<PackedChunk>,
+) {
+    let mut encoder = Encoder {
+        pipeline: EncodePipeline::default(),
+        initialized: false,
+        pool: pool.clone(),
+    };
+    while let Some(index) = window.take(chunks.len()) {
+        let packed = encode_chunk(
+            ctx,
+            &mut encoder,
+            part_index,
+            &chunks[index],
+            desc,
+            pixels,
+        );
+        let failed = packed.is_err();
+        if sender.send((index, packed)).is_err() || failed {
+            return;
+        }
+    }
+}
+
+/// Write the compressed chunks coming from the workers to the `locations`
+/// of the part, in order, holding on to any that arrive early
+///
+fn write_in_order(
+    ctx: &WriteContext,
+    part_index: usize,
+    locations: &[ChunkLocation],
+    receiver: Receiver<PackedChunk>,
+    window: &WriteWindow,
+) -> Result<()> {
+    let mut pending = HashMap::new();
+    let mut written = 0;
+    while written < locations.len() {
+        let packed = match pending.remove(&written) {
+            Some(packed) => packed,
+            None => {
+                // the workers only stop early if the writer has told them to
+                let (index, packed) =
+                    receiver.recv().map_err(|_| Error::Unknown)?;
+                let packed = packed?;
+                if index != written {
+                    pending.insert(index, packed);
+                    continue;
+                }
+                packed
+            }
+        };
+
+        ctx.write_chunk_at(part_index, locations[written], &packed)?;
+        written += 1;
+        window.advance(written);
+    }
+    Ok(())
+}
+
+impl WriteContext {
+    /// Encode and write the whole of a part from an interleaved buffer of
+    /// pixels, as [`WriteContext::write_image`] does, compressing the chunks
+    /// on rayon's global thread pool
+    ///
+    /// Each worker compresses chunks into memory with an encode pipeline of
+    /// its own, while a writer thread writes them as they are finished in the
+    /// order the part's line order requires, so compressing overlaps with
+    /// writing. The workers stay at most a couple of chunks each ahead of the
+    /// writer, so only that many compressed chunks are held in memory at
+    /// once, and the buffers they are held in are handed back to the workers
+    /// once written, rather than allocated afresh for each chunk. This pays
+    /// off most for the slower compression types, such as PIZ
+    /// and DWAA/DWAB.
+    ///
+    /// # Errors
+    /// * `[Error::InvalidArgument]` - If the size of the image or buffer does
+    /// not match the part, or a channel in the part is missing from the buffer
+    /// * `[Error::FeatureNotImplemented]` - If the part holds deep data, or is
+    /// tiled with more than one level
+    ///
+    pub fn write_image_parallel(
+        &mut self,
+        part_index: usize,
+        desc: &ImageDesc,
+        pixels: &[u8],
+    ) -> Result<()> {
+        let ctx = &*self;
+        let chunks = ctx.image_chunks(part_index, desc, pixels)?;
+        let locations = chunks
+            .iter()
+            .map(|chunk| ctx.image_chunk_location(part_index, chunk))
+            .collect::<Result<Vec<_>>>()?;
+
+        let workers = rayon::current_num_threads().max(1);
+        let pool = BufferPool::for_part(ctx, part_index)?;
+        let window = WriteWindow::new(workers * 2);
+        let (sender, receiver) = sync_channel(workers * 2);
+        let shared = SharedWriter(ctx);
+
+        std::thread::scope(|scope| {
+            let writer = scope.spawn(|| {
+                let result = write_in_order(
+                    shared.0, part_index, &locations, receiver, &window,
+                );
+                window.stop();
+                result
+            });
+
+            rayon::scope(|s| {
+                for _ in 0..workers {
+                    let sender = sender.clone();
+                    let (shared, chunks) = (&shared, &chunks);
+                    let (pool, window) = (&pool, &window);
+                    s.spawn(move |_| {
+                        compress_chunks(
+                            shared.0, part_index, chunks, desc, pixels, pool,
+                            window, sender,
+                        )
+                    });
+                }
+            });
+            drop(sender);
+
+            writer
+                .join()
+                .unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+        })
+    }
+}
+
+/// Writes the tiles of a tiled part as they are finished, in any order and
+/// from any number of threads, as a renderer produces them
+///
+/// Each tile is compressed on the thread that hands it in, with an encode
+/// pipeline taken from those the writer keeps for re-use, and is then written
+/// to the file. Parts whose line order is [`LineOrder::RandomY`] let tiles be
+/// written in any order, so each one is written as soon as it is compressed.
+/// For other line orders, the writer holds on to tiles that arrive ahead of
+/// the next one the file requires, and writes them once they are due, so it
+/// pays to hand tiles in roughly in order to keep fewer of them in memory.
+///
+/// Either way, the writer keeps track of which tiles have been written, and
+/// the C core records where each one went in the part's chunk table.
+///
+pub struct TileWriter<'a> {
+    ctx: SharedWriter<'a>,
+    part_index: usize,
+    /// The order tiles must be written in, by chunk index, or `None` if they
+    /// can be written in any order
+    order: Option<Vec<usize>>,
+    encoders: Mutex<Vec<Encoder<'a>>>,
+    pool: BufferPool,
+    state: Mutex<TileState>,
+}
+
+struct TileState {
+    /// Which tiles have been handed in, by chunk index
+    submitted: Vec<bool>,
+    /// The number of tiles written so far
+    written: usize,
+    /// Compressed tiles waiting for their turn to be written, by chunk index
+    pending: HashMap<usize, (ChunkLocation, PooledBuffer)>,
+}
+
+impl WriteContext {
+    /// Start writing the tiles of the part `part_index` in any order, from
+    /// any number of threads, with a [`TileWriter`]
+    ///
+    /// # Errors
+    /// * `[Error::TileScanMixedApi]` - If the part is not tiled
+    /// * `[Error::FeatureNotImplemented]` - If the part holds deep data
+    ///
+    pub fn tile_writer(&mut self, part_index: usize) -> Result<TileWriter<'_>> {
+        let ctx = &*self;
+        match ctx.storage(part_index)? {
+            Storage::Tiled => (),
+            Storage::DeepTiled => return Err(Error::FeatureNotImplemented),
+            _ => return Err(Error::TileScanMixedApi.with_part(part_index)),
+        }
+
+        let order = match ctx.lineorder(part_index)? {
+            LineOrder::RandomY => None,
+            lineorder => Some(tile_order(
+                ctx,
+                part_index,
+                lineorder == LineOrder::DecreasingY,
+            )?),
+        };
+
+        Ok(TileWriter {
+            ctx: SharedWriter(ctx),
+            part_index,
+            order,
+            encoders: Mutex::new(Vec::new()),
+            pool: BufferPool::for_part(ctx, part_index)?,
+            state: Mutex::new(TileState {
+                submitted: vec![false; ctx.chunk_count(part_index)?],
+                written: 0,
+                pending: HashMap::new(),
+            }),
+        })
+    }
+}
+
+/// The chunk indices of the tiles of a part in the order the C core requires
+/// them to be written, level by level, with the rows of tiles in each level
+/// from the top down, or from the bottom up if `reverse` is set
+///
+fn tile_order(
+    ctx: &WriteContext,
+    part_index: usize,
+    reverse: bool,
+) -> Result<Vec<usize>> {
+    let mut order = Vec::new();
+    for (level_x, level_y) in part_levels(ctx, part_index)? {
+        let (tiles_x, tiles_y) =
+            ctx.tile_counts(part_index, level_x, level_y)?;
+        let mut rows = (0..tiles_y as i32).collect::<Vec<_>>();
+        if reverse {
+            rows.reverse();
+        }
+        for tile_y in rows {
+            for tile_x in 0..tiles_x as i32 {
+                let info = ctx.write_tile_chunk_info(
+                    part_index,
+                    tile_x,
+                    tile_y,
+                    level_x as i32,
+                    level_y as i32,
+                )?;
+                order.push(info.idx as usize);
+            }
+        }
+    }
+    Ok(order)
+}
+
+impl<'a> TileWriter<'a> {
+    /// The index of the part being written
+    ///
+    pub fn part_index(&self) -> usize {
+        self.part_index
+    }
+
+    /// The number of tiles of the part that have not been handed in yet
+    ///
+    pub fn remaining_tiles(&self) -> usize {
+        self.state
+            .lock()
+            .map(|state| state.submitted.iter().filter(|s| !**s).count())
+            .unwrap_or(0)
+    }
+
+    /// Compress and write the tile at (`tile_x`, `tile_y`) in level
+    /// (`level_x`, `level_y`) from an interleaved buffer holding just that
+    /// tile, described by `desc`
+    ///
+    /// Tiles at the right and bottom edges of the part are cut short to fit
+    /// its data window, and `desc` must match the size of the tile as cut.
+    ///
+    /// # Errors
+    /// * `[Error::IncorrectChunk]` - If the tile has already been handed in
+    /// * `[Error::InvalidArgument]` - If the size of the tile or buffer does
+    /// not match the tile, or a channel in the part is missing from the buffer
+    /// * `[Error::ArgumentOutOfRange]` - If there is no such tile in the part
+    ///
+    #[allow(clippy::too_many_arguments)]
+    pub fn write_tile(
+        &self,
+        tile_x: i32,
+        tile_y: i32,
+        level_x: i32,
+        level_y: i32,
+        desc: &ImageDesc,
+        pixels: &[u8],
+    ) -> Result<()> {
+        let ctx = self.ctx.0;
+        let info = ctx.write_tile_chunk_info(
+            self.part_index,
+            tile_x,
+            tile_y,
+            level_x,
+            level_y,
+        )?;
+        if info.width as usize != desc.width
+            || info.height as usize != desc.height
+            || pixels.len() != desc.line_bytes() * desc.height
+        {
+            return Err(Error::InvalidArgument);
+        }
+
+        let idx = info.idx as usize;
+        self.mark_submitted(idx, true)?;
+
+        let location = ChunkLocation::Tile {
+            x: tile_x,
+            y: tile_y,
+            level_x,
+            level_y,
+        };
+        let chunk = ImageChunk {
+            info,
+            origin: (0, 0),
+        };
+        let packed = match self.encode_tile(&chunk, desc, pixels) {
+            Ok(packed) => packed,
+            Err(e) => {
+                // the tile can be handed in again once the problem is fixed
+                let _ = self.mark_submitted(idx, false);
+                return Err(e);
+            }
+        };
+
+        let mut state = self.state.lock().map_err(|_| Error::Unknown)?;
+        let order = match &self.order {
+            Some(order) => order,
+            None => {
+                ctx.write_chunk_at(self.part_index, location, &packed)?;
+                state.written += 1;
+                return Ok(());
+            }
+        };
+
+        state.pending.insert(idx, (location, packed));
+        while let Some(next) = order.get(state.written) {
+            let (location, packed) = match state.pending.remove(next) {
+                Some(tile) => tile,
+                None => break,
+            };
+            ctx.write_chunk_at(self.part_index, location, &packed)?;
+            state.written += 1;
+        }
+        Ok(())
+    }
+
+    /// Record whether the tile with chunk index `idx` has been handed in,
+    /// failing if it already has been when marking it as handed in
+    ///
+    fn mark_submitted(&self, idx: usize, submitted: bool) -> Result<()> {
+        let mut state = self.state.lock().map_err(|_| Error::Unknown)?;
+        match state.submitted.get_mut(idx) {
+            Some(s) if *s != submitted => {
+                *s = submitted;
+                Ok(())
+            }
+            _ => Err(Error::IncorrectChunk
+                .with_part(self.part_index)
+                .with_chunk(idx)),
+        }
+    }
+
+    /// Compress a tile with one of the writer's pipelines, creating a new one
+    /// if they are all in use
+    ///
+    fn encode_tile(
+        &self,
+        chunk: &ImageChunk,
+        desc: &ImageDesc,
+        pixels: &[u8],
+    ) -> Result<PooledBuffer> {
+        let mut encoder = self
+            .encoders
+            .lock()
+            .ok()
+            .and_then(|mut encoders| encoders.pop())
+            .unwrap_or_else(|| Encoder {
+                pipeline: EncodePipeline::default(),
+                initialized: false,
+                pool: self.pool.clone(),
+            });
+
+        let packed = encode_chunk(
+            self.ctx.0,
+            &mut encoder,
+            self.part_index,
+            chunk,
+            desc,
+            pixels,
+        );
+        if let Ok(mut encoders) = self.encoders.lock() {
+            encoders.push(encoder);
+        }
+        packed
+    }
+
+    /// Finish writing the part, freeing the writer's pipelines
+    ///
+    /// # Errors
+    /// * `[Error::IncorrectChunk]` - If not every tile of the part has been
+    /// written
+    ///
+    pub fn finish(self) -> Result<()> {
+        let state = self.state.lock().map_err(|_| Error::Unknown)?;
+        if state.written != state.submitted.len() {
+            return Err(Error::IncorrectChunk.with_part(self.part_index));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate as exr;
+    use exr::context::ReadContext;
+    use imath_traits::f16;
+    use std::path::Path;
+
+    #[test]
+    fn read_image_parallel() -> Result<(), exr::Error> {
+        let images = Path::new(
+            &std::env::var("CARGO_MANIFEST_DIR")
+                .expect("CARGO_MANIFEST_DIR not set"),
+        )
+        .join("images");
+
+        for name in &["ferris.exr", "ferris-tiled.exr"] {
+            let ctx = ReadContext::new(images.join(name))?;
+            let channels = ["R", "G", "B", "A", "missing"];
+            assert_eq!(
+                ctx.read_image_parallel::<f16>(0, &channels)?,
+                ctx.read_image::<f16>(0, &channels)?
+            );
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn write_image_parallel() -> Result<(), exr::Error> {
+        use exr::attr::{Compression, PixelType};
+        use exr::context::{DefaultWriteMode, WriteHeaderContext};
+        use exr::write::ImageDesc;
+
+        let images = Path::new(
+            &std::env::var("CARGO_MANIFEST_DIR")
+                .expect("CARGO_MANIFEST_DIR not set"),
+        )
+        .join("images");
+
+        for name in &["ferris.exr", "ferris-tiled.exr"] {
+            let src = ReadContext::new(images.join(name))?;
+            let channels = ["A", "B", "G", "R"];
+            let image = src.read_image::<f16>(0, &channels)?;
+            let bytes = image
+                .pixels
+                .iter()
+                .flat_map(|p| p.to_le_bytes())
+                .collect::<Vec<_>>();
+            let desc = ImageDesc::new(
+                image.width,
+                image.height,
+                &channels,
+                PixelType::Half,
+            );
+
+            // a single worker must still be able to keep the writer going
+            for threads in &[1, 4] {
+                let pool = rayon::ThreadPoolBuilder::new()
+                    .num_threads(*threads)
+                    .build()
+                    .expect("failed to build thread pool");
+
+                let path =
+                    std::env::temp_dir().join("write_image_parallel.exr");
+                let mut header = WriteHeaderContext::new(
+                    &path,
+                    DefaultWriteMode::WriteFileDirectly,
+                )?;
+                header.add_part_copy(&src, 0, None)?;
+                header.set_compression(0, Compression::Piz)?;
+                let mut ctx = header.write_header()?;
+                pool.install(|| ctx.write_image_parallel(0, &desc, &bytes))?;
+                ctx.close()?;
+
+                let written = ReadContext::new(&path)?;
+                assert_eq!(written.compression(0)?, Compression::Piz);
+                assert_eq!(written.read_image::<f16>(0, &channels)?, image);
+            }
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn tile_writer() -> Result<(), exr::Error> {
+        use exr::attr::{LineOrder, PixelType};
+        use exr::context::{DefaultWriteMode, WriteHeaderContext};
+        use exr::validate::ChunkLocation;
+        use exr::write::ImageDesc;
+        use rayon::prelude::*;
+
+        let path_tiled = Path::new(
+            &std::env::var("CARGO_MANIFEST_DIR")
+                .expect("CARGO_MANIFEST_DIR not set"),
+        )
+        .join("images")
+        .join("ferris-tiled.exr");
+        let src = ReadContext::new(&path_tiled)?;
+        let channels = ["A", "B", "G", "R"];
+        let image = src.read_image::<f16>(0, &channels)?;
+        let (tile_width, tile_height) = src.tile_sizes(0, 0, 0)?;
+        let (tiles_x, tiles_y) = src.tile_counts(0, 0, 0)?;
+
+        // cut the image up into tiles, last first
+        let mut tiles = Vec::new();
+        for tile_y in 0..tiles_y {
+            for tile_x in 0..tiles_x {
+                let x0 = tile_x * tile_width;
+                let y0 = tile_y * tile_height;
+                let width = tile_width.min(image.width - x0);
+                let height = tile_height.min(image.height - y0);
+                let mut bytes = Vec::new();
+                for y in y0..y0 + height {
+                    let start = (y * image.width + x0) * channels.len();
+                    let end = start + width * channels.len();
+                    for p in &image.pixels[start..end] {
+                        bytes.extend_from_slice(&p.to_le_bytes());
+                    }
+                }
+                let desc =
+                    ImageDesc::new(width, height, &channels, PixelType::Half);
+                tiles.push((tile_x as i32, tile_y as i32, desc, bytes));
+            }
+        }
+        tiles.reverse();
+
+        for lineorder in &[
+            LineOrder::IncreasingY,
+            LineOrder::DecreasingY,
+            LineOrder::RandomY,
+        ] {
+            let path = std::env::temp_dir().join("tile_writer.exr");
+            let mut header = WriteHeaderContext::new(
+                &path,
+                DefaultWriteMode::WriteFileDirectly,
+            )?;
+            header.add_part_copy(&src, 0, None)?;
+            header.set_lineorder(0, *lineorder)?;
+            let mut ctx = header.write_header()?;
+
+            let writer = ctx.tile_writer(0)?;
+            tiles.par_iter().try_for_each(|(x, y, desc, bytes)| {
+                writer.write_tile(*x, *y, 0, 0, desc, bytes)
+            })?;
+            assert_eq!(writer.remaining_tiles(), 0);
+
+            // each tile can only be written once
+            let (x, y, desc, bytes) = &tiles[0];
+            assert_eq!(
+                writer
+                    .write_tile(*x, *y, 0, 0, desc, bytes)
+                    .unwrap_err()
+                    .kind(),
+                exr::ErrorKind::IncorrectChunk
+            );
+            writer.finish()?;
+            ctx.close()?;
+
+            let written = ReadContext::new(&path)?;
+            assert_eq!(written.lineorder(0)?, *lineorder);
+            assert_eq!(written.read_image::<f16>(0, &channels)?, image);
+
+            // tiles must be laid out in the file in the line order, whatever
+            // order they were handed in
+            if *lineorder != LineOrder::RandomY {
+                let mut table = written.chunk_table(0)?;
+                table.sort_by_key(|entry| entry.offset);
+                let mut rows = (0..tiles_y as i32).collect::<Vec<_>>();
+                if *lineorder == LineOrder::DecreasingY {
+                    rows.reverse();
+                }
+                let expected = rows
+                    .into_iter()
+                    .flat_map(|y| (0..tiles_x as i32).map(move |x| (x, y)))
+                    .collect::<Vec<_>>();
+                let laid_out = table
+                    .iter()
+                    .map(|entry| match entry.location {
+                        ChunkLocation::Tile { x, y, .. } => (x, y),
+                        location => panic!("unexpected chunk {:?}", location),
+                    })
+                    .collect::<Vec<_>>();
+                assert_eq!(laid_out, expected);
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -1,15 +1,19 @@
 use crate::attr::{
-    Attribute, AttributeRead, ChannelList, Compression, LevelMode, LineOrder,
-    Storage,
+    destroy_custom, pack_custom, set_user, unpack_custom, with_c_channel_list,
+    AttrRational, AttrType, Attribute, AttributeRead, AttributeWrite,
+    ChannelDesc, ChannelList, ChannelListBuilder, Compression, CustomAttribute,
+    LevelMode, LineOrder, PixelType, Preview, Storage, TileDescriptor,
 };
 use crate::context::*;
-use crate::error::Error;
+use crate::error::{Error, ErrorKind};
+use crate::read::num_tiles;
+use crate::rgba::Rgba;
 use openexr_core_sys as sys;
-use std::convert::TryInto;
+use std::convert::{TryFrom, TryInto};
 use std::ffi::{CStr, CString};
 use std::path::Path;
 
-use imath_traits::{Bound2, Vec2};
+use imath_traits::{Bound2, Matrix33, Matrix44, Vec2, Vec3};
 
 type Result<T, E = Error> = std::result::Result<T, E>;
 
@@ -35,7 +39,10 @@ impl<S: ContextState> Context<S> {
     ///
     pub fn count(&self) -> Result<usize> {
         let mut count = 0;
-        unsafe { sys::exr_get_count(self.inner, &mut count).ok(count as usize) }
+        unsafe {
+            sys::exr_get_count(self.inner, &mut count)
+                .ok_in(self.inner, count as usize)
+        }
     }
 
     /// Get the name of the given part
@@ -53,11 +60,16 @@ impl<S: ContextState> Context<S> {
     pub fn name(&self, part_index: usize) -> Result<Option<&str>> {
         let mut ptr = std::ptr::null();
         unsafe {
-            match sys::exr_get_name(self.inner, part_index as i32, &mut ptr)
-                .ok(())
+            match sys::exr_get_name(
+                self.inner,
+                c_part_index(part_index)?,
+                &mut ptr,
+            )
+            .ok_in(self.inner, ())
+            .map_err(|e| e.with_part(part_index))
             {
                 Ok(_) => (),
-                Err(Error::NoAttrByName) => (),
+                Err(e) if e.kind() == ErrorKind::NoAttrByName => (),
                 Err(e) => return Err(e),
             }
             if ptr.is_null() {
@@ -78,9 +90,15 @@ impl<S: ContextState> Context<S> {
     pub fn storage(&self, part_index: usize) -> Result<Storage> {
         let mut storage = sys::exr_storage_t::EXR_STORAGE_LAST_TYPE;
         unsafe {
-            sys::exr_get_storage(self.inner, part_index as i32, &mut storage)
-                .ok(storage.into())
+            sys::exr_get_storage(
+                self.inner,
+                c_part_index(part_index)?,
+                &mut storage,
+            )
+            .ok_in(self.inner, ())
+            .map_err(|e| e.with_part(part_index))?;
         }
+        storage.try_into()
     }
 
     /// Get the number of levels in the specified part
@@ -99,15 +117,52 @@ impl<S: ContextState> Context<S> {
         unsafe {
             sys::exr_get_tile_levels(
                 self.inner,
-                part_index as i32,
+                c_part_index(part_index)?,
                 &mut x,
                 &mut y,
             )
-            .ok(())
+            .ok_in(self.inner, ())
+            .map_err(|e| e.with_part(part_index))
             .map(|_| (x as usize, y as usize))
         }
     }
 
+    /// Get the tile descriptor of the specified part, giving the size of its
+    /// tiles and how its levels are laid out
+    ///
+    /// # Errors
+    /// * `[Error::ArgumentOutOfRange]` - If `part_index` does not refer to a
+    /// valid part
+    /// * `[Error::TileScanMixedApi]` - If the part is not tiled
+    /// * `[Error::Unknown]` - If the level or round mode is not one this
+    /// library knows about
+    ///
+    pub fn tile_descriptor(&self, part_index: usize) -> Result<TileDescriptor> {
+        let mut x_size = 0;
+        let mut y_size = 0;
+        let mut level_mode = sys::exr_tile_level_mode_t::EXR_TILE_LAST_TYPE;
+        let mut round_mode =
+            sys::exr_tile_round_mode_t::EXR_TILE_ROUND_LAST_TYPE;
+        unsafe {
+            sys::exr_get_tile_descriptor(
+                self.inner,
+                c_part_index(part_index)?,
+                &mut x_size,
+                &mut y_size,
+                &mut level_mode,
+                &mut round_mode,
+            )
+            .ok_in(self.inner, ())
+            .map_err(|e| e.with_part(part_index))?;
+        }
+        Ok(TileDescriptor {
+            x_size,
+            y_size,
+            level_mode: level_mode.try_into()?,
+            round_mode: round_mode.try_into()?,
+        })
+    }
+
     /// Get the size of tiles in the given level in the given part
     ///
     /// # Returns
@@ -124,18 +179,23 @@ impl<S: ContextState> Context<S> {
         level_x: usize,
         level_y: usize,
     ) -> Result<(usize, usize)> {
+        let level_x =
+            i32::try_from(level_x).map_err(|_| Error::ArgumentOutOfRange)?;
+        let level_y =
+            i32::try_from(level_y).map_err(|_| Error::ArgumentOutOfRange)?;
         let mut w = 0;
         let mut h = 0;
         unsafe {
             sys::exr_get_tile_sizes(
                 self.inner,
-                part_index as i32,
-                level_x as i32,
-                level_y as i32,
+                c_part_index(part_index)?,
+                level_x,
+                level_y,
                 &mut w,
                 &mut h,
             )
-            .ok(())
+            .ok_in(self.inner, ())
+            .map_err(|e| e.with_part(part_index))
             .map(|_| (w as usize, h as usize))
         }
     }
@@ -155,22 +215,59 @@ impl<S: ContextState> Context<S> {
         level_x: usize,
         level_y: usize,
     ) -> Result<(usize, usize)> {
+        let level_x =
+            i32::try_from(level_x).map_err(|_| Error::ArgumentOutOfRange)?;
+        let level_y =
+            i32::try_from(level_y).map_err(|_| Error::ArgumentOutOfRange)?;
         let mut w = 0;
         let mut h = 0;
         unsafe {
             sys::exr_get_level_sizes(
                 self.inner,
-                part_index as i32,
-                level_x as i32,
-                level_y as i32,
+                c_part_index(part_index)?,
+                level_x,
+                level_y,
                 &mut w,
                 &mut h,
             )
-            .ok(())
+            .ok_in(self.inner, ())
+            .map_err(|e| e.with_part(part_index))
             .map(|_| (w as usize, h as usize))
         }
     }
 
+    /// Get the number of tiles across and down the given level in the given
+    /// part
+    ///
+    /// Tiles at the right and bottom edges of a level may be partly outside
+    /// it, so this rounds up, using the level size the part's round mode
+    /// gives.
+    ///
+    /// # Returns
+    /// * `Ok(usize, usize)` - the number of tiles in x and y at the level
+    /// * `Err(Error::ArgumentOutOfRange)` - If `part_index` does not refer to
+    /// a valid part, or `level_x` or `level_y` are not valid level indices
+    /// * `Err(Error::TileScanMixedApi)` - if the file is not tiled
+    /// * `Err(Error::MissingReqAttr)` - if the tile data is missing or corrupt
+    ///
+    pub fn tile_counts(
+        &self,
+        part_index: usize,
+        level_x: usize,
+        level_y: usize,
+    ) -> Result<(usize, usize)> {
+        let (width, height) = self.level_sizes(part_index, level_x, level_y)?;
+        let (tile_width, tile_height) =
+            self.tile_sizes(part_index, level_x, level_y)?;
+        if tile_width == 0 || tile_height == 0 {
+            return Err(Error::MissingReqAttr.with_part(part_index));
+        }
+        Ok((
+            num_tiles(width, tile_width) as usize,
+            num_tiles(height, tile_height) as usize,
+        ))
+    }
+
     /// Get the number of chunks in this part of the file.
     ///
     /// As in the technical documentation for OpenEXR, the chunk is the
@@ -191,8 +288,13 @@ impl<S: ContextState> Context<S> {
     pub fn chunk_count(&self, part_index: usize) -> Result<usize> {
         let mut count = 0;
         unsafe {
-            sys::exr_get_chunk_count(self.inner, part_index as i32, &mut count)
-                .ok(count as usize)
+            sys::exr_get_chunk_count(
+                self.inner,
+                c_part_index(part_index)?,
+                &mut count,
+            )
+            .ok_in(self.inner, count as usize)
+            .map_err(|e| e.with_part(part_index))
         }
     }
 
@@ -207,10 +309,11 @@ impl<S: ContextState> Context<S> {
         unsafe {
             sys::exr_get_scanlines_per_chunk(
                 self.inner,
-                part_index as i32,
+                c_part_index(part_index)?,
                 &mut count,
             )
-            .ok(count as usize)
+            .ok_in(self.inner, count as usize)
+            .map_err(|e| e.with_part(part_index))
         }
     }
 
@@ -226,10 +329,11 @@ impl<S: ContextState> Context<S> {
         unsafe {
             sys::exr_get_chunk_unpacked_size(
                 self.inner,
-                part_index as i32,
+                c_part_index(part_index)?,
                 &mut count,
             )
-            .ok(count as usize)
+            .ok_in(self.inner, count as usize)
+            .map_err(|e| e.with_part(part_index))
         }
     }
 
@@ -247,10 +351,11 @@ impl<S: ContextState> Context<S> {
         unsafe {
             sys::exr_get_compression(
                 self.inner,
-                part_index.try_into().unwrap(),
+                c_part_index(part_index)?,
                 &mut result,
             )
-            .ok(result.into())
+            .ok_in(self.inner, result.into())
+            .map_err(|e| e.with_part(part_index))
         }
     }
 
@@ -268,10 +373,11 @@ impl<S: ContextState> Context<S> {
         unsafe {
             sys::exr_get_data_window(
                 self.inner,
-                part_index.try_into().unwrap(),
+                c_part_index(part_index)?,
                 result.as_mut_ptr() as *mut sys::exr_attr_box2i_t,
             )
-            .ok(B::from_slice(&result))
+            .ok_in(self.inner, B::from_slice(&result))
+            .map_err(|e| e.with_part(part_index))
         }
     }
 
@@ -292,10 +398,11 @@ impl<S: ContextState> Context<S> {
         unsafe {
             sys::exr_get_display_window(
                 self.inner,
-                part_index.try_into().unwrap(),
+                c_part_index(part_index)?,
                 result.as_mut_ptr() as *mut sys::exr_attr_box2i_t,
             )
-            .ok(B::from_slice(&result))
+            .ok_in(self.inner, B::from_slice(&result))
+            .map_err(|e| e.with_part(part_index))
         }
     }
 
@@ -313,11 +420,13 @@ impl<S: ContextState> Context<S> {
         unsafe {
             sys::exr_get_lineorder(
                 self.inner,
-                part_index.try_into().unwrap(),
+                c_part_index(part_index)?,
                 &mut result,
             )
-            .ok(result.into())
+            .ok_in(self.inner, ())
+            .map_err(|e| e.with_part(part_index))?;
         }
+        result.try_into()
     }
 
     /// Get the pixel aspect ratio for the specified part
@@ -334,10 +443,11 @@ impl<S: ContextState> Context<S> {
         unsafe {
             sys::exr_get_pixel_aspect_ratio(
                 self.inner,
-                part_index.try_into().unwrap(),
+                c_part_index(part_index)?,
                 &mut result,
             )
-            .ok(result.into())
+            .ok_in(self.inner, result.into())
+            .map_err(|e| e.with_part(part_index))
         }
     }
 
@@ -358,10 +468,11 @@ impl<S: ContextState> Context<S> {
         unsafe {
             sys::exr_get_screen_window_center(
                 self.inner,
-                part_index.try_into().unwrap(),
+                c_part_index(part_index)?,
                 result.as_mut_ptr() as *mut sys::exr_attr_v2f_t,
             )
-            .ok(V::from_slice(&result))
+            .ok_in(self.inner, V::from_slice(&result))
+            .map_err(|e| e.with_part(part_index))
         }
     }
 
@@ -379,10 +490,11 @@ impl<S: ContextState> Context<S> {
         unsafe {
             sys::exr_get_screen_window_width(
                 self.inner,
-                part_index.try_into().unwrap(),
+                c_part_index(part_index)?,
                 &mut result,
             )
-            .ok(result.into())
+            .ok_in(self.inner, result.into())
+            .map_err(|e| e.with_part(part_index))
         }
     }
 
@@ -393,11 +505,59 @@ impl<S: ContextState> Context<S> {
         unsafe {
             sys::exr_get_channels(
                 self.inner,
-                part_index.try_into().unwrap(),
+                c_part_index(part_index)?,
                 &mut ptr as *mut *const ChannelList
                     as *mut *const sys::exr_attr_chlist_t,
             )
-            .ok(&*ptr)
+            .ok_in(self.inner, ())
+            .map(|_| &*ptr)
+            .map_err(|e| e.with_part(part_index))
+        }
+    }
+}
+
+/// The attributes every part is required to have, other than its channels,
+/// for [`WriteHeaderContext::initialize_required_attributes`]
+///
+/// Windows are given as `[min_x, min_y, max_x, max_y]`, inclusive of the
+/// maximum.
+///
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct RequiredAttributes {
+    pub display_window: [i32; 4],
+    pub data_window: [i32; 4],
+    pub pixel_aspect_ratio: f32,
+    pub screen_window_center: [f32; 2],
+    pub screen_window_width: f32,
+    pub lineorder: LineOrder,
+    pub compression: Compression,
+}
+
+impl RequiredAttributes {
+    /// The attributes for a `width` by `height` image with its origin at 0,
+    /// square pixels, a screen window of width 1 centred on the origin and
+    /// increasing Y line order
+    ///
+    /// # Panics
+    /// * If `width` or `height` is 0 or does not fit in an i32
+    ///
+    pub fn new(
+        width: usize,
+        height: usize,
+        compression: Compression,
+    ) -> RequiredAttributes {
+        let max_x = i32::try_from(width).expect("width out of range") - 1;
+        let max_y = i32::try_from(height).expect("height out of range") - 1;
+        assert!(max_x >= 0 && max_y >= 0, "image must not be empty");
+        let window = [0, 0, max_x, max_y];
+        RequiredAttributes {
+            display_window: window,
+            data_window: window,
+            pixel_aspect_ratio: 1.0,
+            screen_window_center: [0.0, 0.0],
+            screen_window_width: 1.0,
+            lineorder: LineOrder::IncreasingY,
+            compression,
         }
     }
 }
@@ -421,16 +581,20 @@ impl From<AttrListAccessMode> for sys::exr_attr_list_access_mode {
     }
 }
 
-impl From<sys::exr_attr_list_access_mode> for AttrListAccessMode {
-    fn from(s: sys::exr_attr_list_access_mode) -> AttrListAccessMode {
+impl TryFrom<sys::exr_attr_list_access_mode> for AttrListAccessMode {
+    type Error = Error;
+
+    fn try_from(
+        s: sys::exr_attr_list_access_mode,
+    ) -> Result<AttrListAccessMode> {
         match s {
             sys::exr_attr_list_access_mode::EXR_ATTR_LIST_FILE_ORDER => {
-                AttrListAccessMode::FileOrder
+                Ok(AttrListAccessMode::FileOrder)
             }
             sys::exr_attr_list_access_mode::EXR_ATTR_LIST_SORTED_ORDER => {
-                AttrListAccessMode::SortedOrder
+                Ok(AttrListAccessMode::SortedOrder)
             }
-            _ => panic!("Unhandled exr_attr_list_access_mode variant"),
+            _ => Err(Error::Unknown),
         }
     }
 }
@@ -445,10 +609,11 @@ impl<S: ContextState> Context<S> {
         unsafe {
             sys::exr_get_attribute_count(
                 self.inner,
-                part_index as i32,
+                c_part_index(part_index)?,
                 &mut count,
             )
-            .ok(count as usize)
+            .ok_in(self.inner, count as usize)
+            .map_err(|e| e.with_part(part_index))
         }
     }
 
@@ -464,12 +629,45 @@ impl<S: ContextState> Context<S> {
         unsafe {
             sys::exr_get_attribute_by_index(
                 self.inner,
-                part_index as i32,
+                c_part_index(part_index)?,
                 mode.into(),
-                index as i32,
+                i32::try_from(index).map_err(|_| Error::ArgumentOutOfRange)?,
                 &mut attr,
             )
-            .ok(&*(attr as *const Attribute))
+            .ok_in(self.inner, ())
+            .map(|_| &*(attr as *const Attribute))
+            .map_err(|e| e.with_part(part_index))
+        }
+    }
+
+    /// Get all the attributes in the part, in either file or sorted order
+    ///
+    /// This fetches the whole list from the library at once, so is faster than
+    /// calling [`Context::get_attribute_by_index`] for each attribute in turn.
+    ///
+    pub fn attribute_list(
+        &self,
+        part_index: usize,
+        mode: AttrListAccessMode,
+    ) -> Result<Vec<&Attribute>> {
+        let mut count = self.attribute_count(part_index)? as i32;
+        let mut list = vec![std::ptr::null(); count as usize];
+        unsafe {
+            sys::exr_get_attribute_list(
+                self.inner,
+                c_part_index(part_index)?,
+                mode.into(),
+                &mut count,
+                list.as_mut_ptr(),
+            )
+            .ok_in(self.inner, ())
+            .map_err(|e| e.with_part(part_index))?;
+
+            Ok(list
+                .into_iter()
+                .take(count as usize)
+                .map(|attr| &*(attr as *const Attribute))
+                .collect())
         }
     }
 
@@ -485,11 +683,13 @@ impl<S: ContextState> Context<S> {
         unsafe {
             sys::exr_get_attribute_by_name(
                 self.inner,
-                part_index as i32,
+                c_part_index(part_index)?,
                 c_name.as_ptr(),
                 &mut attr,
             )
-            .ok(&*(attr as *const Attribute))
+            .ok_in(self.inner, ())
+            .map(|_| &*(attr as *const Attribute))
+            .map_err(|e| e.with_part(part_index).with_attribute(name))
         }
     }
 
@@ -499,32 +699,1178 @@ impl<S: ContextState> Context<S> {
         name: &str,
     ) -> Result<Attr> {
         <Attr as AttributeRead>::get(self, part_index, name)
+            .map_err(|e| e.with_part(part_index).with_attribute(name))
+    }
+
+    /// Get a 3x3 float matrix attribute as any type implementing
+    /// [`Matrix33<f32>`]
+    ///
+    /// # Errors
+    /// * `[Error::NoAttrByName]` - If the attribute could not be found
+    /// * `[Error::AttrTypeMismatch]` - If the attribute is not an m33f
+    ///
+    pub fn get_attribute_m33f<M: Matrix33<f32>>(
+        &self,
+        part_index: usize,
+        name: &str,
+    ) -> Result<M> {
+        self.get_attribute::<[f32; 9]>(part_index, name)
+            .map(|m| M::from_slice(&m))
+    }
+
+    /// Get a 3x3 double matrix attribute as any type implementing
+    /// [`Matrix33<f64>`]
+    ///
+    /// # Errors
+    /// * `[Error::NoAttrByName]` - If the attribute could not be found
+    /// * `[Error::AttrTypeMismatch]` - If the attribute is not an m33d
+    ///
+    pub fn get_attribute_m33d<M: Matrix33<f64>>(
+        &self,
+        part_index: usize,
+        name: &str,
+    ) -> Result<M> {
+        self.get_attribute::<[f64; 9]>(part_index, name)
+            .map(|m| M::from_slice(&m))
+    }
+
+    /// Get a 4x4 float matrix attribute, such as `worldToCamera`, as any type
+    /// implementing [`Matrix44<f32>`]
+    ///
+    /// # Errors
+    /// * `[Error::NoAttrByName]` - If the attribute could not be found
+    /// * `[Error::AttrTypeMismatch]` - If the attribute is not an m44f
+    ///
+    pub fn get_attribute_m44f<M: Matrix44<f32>>(
+        &self,
+        part_index: usize,
+        name: &str,
+    ) -> Result<M> {
+        self.get_attribute::<[f32; 16]>(part_index, name)
+            .map(|m| M::from_slice(&m))
+    }
+
+    /// Get a 4x4 double matrix attribute as any type implementing
+    /// [`Matrix44<f64>`]
+    ///
+    /// # Errors
+    /// * `[Error::NoAttrByName]` - If the attribute could not be found
+    /// * `[Error::AttrTypeMismatch]` - If the attribute is not an m44d
+    ///
+    pub fn get_attribute_m44d<M: Matrix44<f64>>(
+        &self,
+        part_index: usize,
+        name: &str,
+    ) -> Result<M> {
+        self.get_attribute::<[f64; 16]>(part_index, name)
+            .map(|m| M::from_slice(&m))
+    }
+
+    /// Get a 2-element integer vector attribute as any type implementing
+    /// [`Vec2<i32>`]
+    ///
+    /// # Errors
+    /// * `[Error::NoAttrByName]` - If the attribute could not be found
+    /// * `[Error::AttrTypeMismatch]` - If the attribute is not a v2i
+    ///
+    pub fn get_attribute_v2i<V: Vec2<i32>>(
+        &self,
+        part_index: usize,
+        name: &str,
+    ) -> Result<V> {
+        self.get_attribute::<[i32; 2]>(part_index, name)
+            .map(|v| V::from_slice(&v))
+    }
+
+    /// Get a 2-element float vector attribute, such as `screenWindowCenter`,
+    /// as any type implementing [`Vec2<f32>`]
+    ///
+    /// # Errors
+    /// * `[Error::NoAttrByName]` - If the attribute could not be found
+    /// * `[Error::AttrTypeMismatch]` - If the attribute is not a v2f
+    ///
+    pub fn get_attribute_v2f<V: Vec2<f32>>(
+        &self,
+        part_index: usize,
+        name: &str,
+    ) -> Result<V> {
+        self.get_attribute::<[f32; 2]>(part_index, name)
+            .map(|v| V::from_slice(&v))
+    }
+
+    /// Get a 2-element double vector attribute as any type implementing
+    /// [`Vec2<f64>`]
+    ///
+    /// # Errors
+    /// * `[Error::NoAttrByName]` - If the attribute could not be found
+    /// * `[Error::AttrTypeMismatch]` - If the attribute is not a v2d
+    ///
+    pub fn get_attribute_v2d<V: Vec2<f64>>(
+        &self,
+        part_index: usize,
+        name: &str,
+    ) -> Result<V> {
+        self.get_attribute::<[f64; 2]>(part_index, name)
+            .map(|v| V::from_slice(&v))
+    }
+
+    /// Get a 3-element integer vector attribute as any type implementing
+    /// [`Vec3<i32>`]
+    ///
+    /// # Errors
+    /// * `[Error::NoAttrByName]` - If the attribute could not be found
+    /// * `[Error::AttrTypeMismatch]` - If the attribute is not a v3i
+    ///
+    pub fn get_attribute_v3i<V: Vec3<i32>>(
+        &self,
+        part_index: usize,
+        name: &str,
+    ) -> Result<V> {
+        self.get_attribute::<[i32; 3]>(part_index, name)
+            .map(|v| V::from_slice(&v))
+    }
+
+    /// Get a 3-element float vector attribute, such as a camera position, as
+    /// any type implementing [`Vec3<f32>`]
+    ///
+    /// # Errors
+    /// * `[Error::NoAttrByName]` - If the attribute could not be found
+    /// * `[Error::AttrTypeMismatch]` - If the attribute is not a v3f
+    ///
+    pub fn get_attribute_v3f<V: Vec3<f32>>(
+        &self,
+        part_index: usize,
+        name: &str,
+    ) -> Result<V> {
+        self.get_attribute::<[f32; 3]>(part_index, name)
+            .map(|v| V::from_slice(&v))
+    }
+
+    /// Get a 3-element double vector attribute as any type implementing
+    /// [`Vec3<f64>`]
+    ///
+    /// # Errors
+    /// * `[Error::NoAttrByName]` - If the attribute could not be found
+    /// * `[Error::AttrTypeMismatch]` - If the attribute is not a v3d
+    ///
+    pub fn get_attribute_v3d<V: Vec3<f64>>(
+        &self,
+        part_index: usize,
+        name: &str,
+    ) -> Result<V> {
+        self.get_attribute::<[f64; 3]>(part_index, name)
+            .map(|v| V::from_slice(&v))
+    }
+
+    /// Get an integer box attribute as any type implementing
+    /// [`Bound2<i32>`]
+    ///
+    /// # Errors
+    /// * `[Error::NoAttrByName]` - If the attribute could not be found
+    /// * `[Error::AttrTypeMismatch]` - If the attribute is not a box2i
+    ///
+    pub fn get_attribute_box2i<B: Bound2<i32>>(
+        &self,
+        part_index: usize,
+        name: &str,
+    ) -> Result<B> {
+        self.get_attribute::<[i32; 4]>(part_index, name)
+            .map(|b| B::from_slice(&b))
+    }
+
+    /// Get a float box attribute as any type implementing [`Bound2<f32>`]
+    ///
+    /// # Errors
+    /// * `[Error::NoAttrByName]` - If the attribute could not be found
+    /// * `[Error::AttrTypeMismatch]` - If the attribute is not a box2f
+    ///
+    pub fn get_attribute_box2f<B: Bound2<f32>>(
+        &self,
+        part_index: usize,
+        name: &str,
+    ) -> Result<B> {
+        self.get_attribute::<[f32; 4]>(part_index, name)
+            .map(|b| B::from_slice(&b))
+    }
+
+    /// Register `T` as the handler for attributes of type
+    /// [`T::TYPE_NAME`](CustomAttribute::TYPE_NAME), so the core can unpack
+    /// and pack them itself
+    ///
+    /// Attributes of that type already in the context are updated to use
+    /// the handler.
+    ///
+    /// # Errors
+    /// * `[Error::InvalidArgument]` - If the type name is one of the built-in
+    /// types
+    ///
+    pub fn register_custom_attribute<T: CustomAttribute>(
+        &mut self,
+    ) -> Result<()> {
+        let c_type =
+            CString::new(T::TYPE_NAME).expect("invalid bytes in TYPE_NAME");
+        unsafe {
+            sys::exr_register_attr_type_handler(
+                self.inner,
+                c_type.as_ptr(),
+                Some(unpack_custom::<T>),
+                Some(pack_custom::<T>),
+                Some(destroy_custom::<T>),
+            )
+            .ok_in(self.inner, ())
+        }
+    }
+
+    /// Get the attribute `name`, of the custom type `T`
+    ///
+    /// # Errors
+    /// * `[Error::NoAttrByName]` - If the attribute could not be found
+    /// * `[Error::AttrTypeMismatch]` - If the attribute's type name is not
+    /// [`T::TYPE_NAME`](CustomAttribute::TYPE_NAME)
+    /// * Any error returned by [`CustomAttribute::unpack`]
+    ///
+    pub fn get_custom_attribute<T: CustomAttribute>(
+        &self,
+        part_index: usize,
+        name: &str,
+    ) -> Result<T> {
+        let attr = self.get_attribute_by_name(part_index, name)?;
+        match attr.opaque_data() {
+            Some(data) if attr.type_name() == T::TYPE_NAME => T::unpack(data)
+                .map_err(|e| e.with_part(part_index).with_attribute(name)),
+            _ => Err(Error::AttrTypeMismatch
+                .with_part(part_index)
+                .with_attribute(name)),
+        }
+    }
+
+    /// Get the `preview` attribute of the specified part, a small 8-bit
+    /// thumbnail of the image
+    ///
+    /// # Errors
+    /// * `[Error::NoAttrByName]` - If the part has no preview
+    /// * `[Error::AttrTypeMismatch]` - If the attribute is not a preview
+    ///
+    pub fn preview(&self, part_index: usize) -> Result<Preview<'_>> {
+        self.get_attribute(part_index, "preview")
+    }
+
+    /// Get the `framesPerSecond` attribute of the specified part, which
+    /// gives the playback rate of an image sequence
+    ///
+    /// # Errors
+    /// * `[Error::NoAttrByName]` - If the attribute could not be found
+    /// * `[Error::AttrTypeMismatch]` - If the attribute is not a rational
+    ///
+    pub fn frames_per_second(&self, part_index: usize) -> Result<AttrRational> {
+        self.get_attribute(part_index, "framesPerSecond")
     }
 }
 
-impl WriteContext {
-    /// Add a new part in the file with name `part_name`
+impl InplaceHeaderUpdateContext {
+    /// Replace the value of the existing attribute `name` on the given part
     ///
-    /// # Returns
-    /// * `Ok(part_index)` - the index of the new part on success
-    /// * `Err(Error)`  - otherwise
+    /// # Errors
+    /// * `[Error::NoAttrByName]` - If the part has no attribute called `name`
+    /// * `[Error::AttrTypeMismatch]` - If the attribute has a different type
+    /// * `[Error::ModifySizeChange]` - If `value` would take up a different
+    /// number of bytes in the file than the current value
     ///
-    pub fn add_part(
+    pub fn update_attribute<Attr: AttributeWrite>(
         &mut self,
-        part_name: &str,
-        storage_type: Storage,
-    ) -> Result<usize> {
-        let c_part_name =
-            CString::new(part_name).expect("invalid bytes in part_name");
-        let mut part_index = 0;
+        part_index: usize,
+        name: &str,
+        value: &Attr,
+    ) -> Result<()> {
+        // check the attribute exists first, as setting would try to add it
+        self.get_attribute_by_name(part_index, name)?;
+        <Attr as AttributeWrite>::set(self, part_index, name, value)
+            .map_err(|e| e.with_part(part_index).with_attribute(name))
+    }
+}
+
+impl WriteHeaderContext {
+    //! Part metadata setters
+
+    /// Set the name of the given part
+    ///
+    /// # Errors
+    /// * `[Error::ArgumentOutOfRange]` - If `part_index` does not refer to
+    /// a valid part
+    /// * `[Error::InvalidArgument]` - If `name` is empty
+    /// * `[Error::AlreadyWroteAttrs]` - If the header has already been written
+    ///
+    /// # Panics
+    /// * If `name` contains internal null bytes
+    ///
+    pub fn set_name(&mut self, part_index: usize, name: &str) -> Result<()> {
+        let c_name = CString::new(name).expect("invalid bytes in name");
         unsafe {
-            sys::exr_add_part(
+            sys::exr_set_name(
                 self.inner,
-                c_part_name.as_ptr(),
-                storage_type.into(),
-                &mut part_index,
+                c_part_index(part_index)?,
+                c_name.as_ptr(),
+            )
+            .ok_in(self.inner, ())
+            .map_err(|e| e.with_part(part_index))
+        }
+    }
+
+    /// Set all the attributes a part is required to have in one go, i.e. the
+    /// data and display windows, pixel aspect ratio, screen window, line
+    /// order and compression
+    ///
+    /// The channels still need to be added before the header is written.
+    ///
+    /// # Errors
+    /// * `[Error::ArgumentOutOfRange]` - If `part_index` does not refer to
+    /// a valid part
+    /// * `[Error::InvalidArgument]` - If either window's minimum is greater
+    /// than its maximum
+    /// * `[Error::AlreadyWroteAttrs]` - If the header has already been written
+    ///
+    pub fn initialize_required_attributes(
+        &mut self,
+        part_index: usize,
+        attributes: &RequiredAttributes,
+    ) -> Result<()> {
+        unsafe {
+            sys::exr_initialize_required_attr(
+                self.inner,
+                c_part_index(part_index)?,
+                attributes.display_window.as_ptr()
+                    as *const sys::exr_attr_box2i_t,
+                attributes.data_window.as_ptr() as *const sys::exr_attr_box2i_t,
+                attributes.pixel_aspect_ratio,
+                attributes.screen_window_center.as_ptr()
+                    as *const sys::exr_attr_v2f_t,
+                attributes.screen_window_width,
+                attributes.lineorder.into(),
+                attributes.compression.into(),
             )
-            .ok(part_index as usize)
+            .ok_in(self.inner, ())
+            .map_err(|e| e.with_part(part_index))
         }
     }
+
+    /// Set all the attributes a part is required to have for a `width` by
+    /// `height` image with its origin at 0, using the defaults described in
+    /// [`RequiredAttributes::new`] for the rest
+    ///
+    /// # Errors
+    /// * `[Error::ArgumentOutOfRange]` - If `part_index` does not refer to
+    /// a valid part
+    /// * `[Error::InvalidArgument]` - If `width` or `height` is 0 or too large
+    /// * `[Error::AlreadyWroteAttrs]` - If the header has already been written
+    ///
+    pub fn initialize_required_attributes_simple(
+        &mut self,
+        part_index: usize,
+        width: usize,
+        height: usize,
+        compression: Compression,
+    ) -> Result<()> {
+        let width = i32::try_from(width).map_err(|_| Error::InvalidArgument)?;
+        let height =
+            i32::try_from(height).map_err(|_| Error::InvalidArgument)?;
+        unsafe {
+            sys::exr_initialize_required_attr_simple(
+                self.inner,
+                c_part_index(part_index)?,
+                width,
+                height,
+                compression.into(),
+            )
+            .ok_in(self.inner, ())
+            .map_err(|e| e.with_part(part_index))
+        }
+    }
+
+    /// Set the compression method used for the specified part
+    ///
+    /// # Errors
+    /// * `[Error::ArgumentOutOfRange]` - If `part_index` does not refer to
+    /// a valid part
+    /// * `[Error::AlreadyWroteAttrs]` - If the header has already been written
+    ///
+    pub fn set_compression(
+        &mut self,
+        part_index: usize,
+        compression: Compression,
+    ) -> Result<()> {
+        unsafe {
+            sys::exr_set_compression(
+                self.inner,
+                c_part_index(part_index)?,
+                compression.into(),
+            )
+            .ok_in(self.inner, ())
+            .map_err(|e| e.with_part(part_index))
+        }
+    }
+
+    /// Set the data window for the specified part, i.e. the bounds of the
+    /// pixels that are stored, inclusive of the maximum
+    ///
+    /// # Errors
+    /// * `[Error::ArgumentOutOfRange]` - If `part_index` does not refer to
+    /// a valid part
+    /// * `[Error::InvalidArgument]` - If the minimum is greater than the
+    /// maximum
+    /// * `[Error::AlreadyWroteAttrs]` - If the header has already been written
+    ///
+    pub fn set_data_window<B: Bound2<i32>>(
+        &mut self,
+        part_index: usize,
+        data_window: &B,
+    ) -> Result<()> {
+        unsafe {
+            sys::exr_set_data_window(
+                self.inner,
+                c_part_index(part_index)?,
+                data_window.as_slice().as_ptr() as *const sys::exr_attr_box2i_t,
+            )
+            .ok_in(self.inner, ())
+            .map_err(|e| e.with_part(part_index))
+        }
+    }
+
+    /// Set the display window for the specified part, i.e. the bounds of the
+    /// image as it is meant to be viewed, inclusive of the maximum
+    ///
+    /// # Errors
+    /// * `[Error::ArgumentOutOfRange]` - If `part_index` does not refer to
+    /// a valid part
+    /// * `[Error::InvalidArgument]` - If the minimum is greater than the
+    /// maximum
+    /// * `[Error::AlreadyWroteAttrs]` - If the header has already been written
+    ///
+    pub fn set_display_window<B: Bound2<i32>>(
+        &mut self,
+        part_index: usize,
+        display_window: &B,
+    ) -> Result<()> {
+        unsafe {
+            sys::exr_set_display_window(
+                self.inner,
+                c_part_index(part_index)?,
+                display_window.as_slice().as_ptr()
+                    as *const sys::exr_attr_box2i_t,
+            )
+            .ok_in(self.inner, ())
+            .map_err(|e| e.with_part(part_index))
+        }
+    }
+
+    /// Set the order in which the chunks of the specified part are written
+    ///
+    /// # Errors
+    /// * `[Error::ArgumentOutOfRange]` - If `part_index` does not refer to
+    /// a valid part
+    /// * `[Error::AlreadyWroteAttrs]` - If the header has already been written
+    ///
+    pub fn set_lineorder(
+        &mut self,
+        part_index: usize,
+        lineorder: LineOrder,
+    ) -> Result<()> {
+        unsafe {
+            sys::exr_set_lineorder(
+                self.inner,
+                c_part_index(part_index)?,
+                lineorder.into(),
+            )
+            .ok_in(self.inner, ())
+            .map_err(|e| e.with_part(part_index))
+        }
+    }
+
+    /// Set the pixel aspect ratio for the specified part
+    ///
+    /// # Errors
+    /// * `[Error::ArgumentOutOfRange]` - If `part_index` does not refer to
+    /// a valid part
+    /// * `[Error::AlreadyWroteAttrs]` - If the header has already been written
+    ///
+    pub fn set_pixel_aspect_ratio(
+        &mut self,
+        part_index: usize,
+        pixel_aspect_ratio: f32,
+    ) -> Result<()> {
+        unsafe {
+            sys::exr_set_pixel_aspect_ratio(
+                self.inner,
+                c_part_index(part_index)?,
+                pixel_aspect_ratio,
+            )
+            .ok_in(self.inner, ())
+            .map_err(|e| e.with_part(part_index))
+        }
+    }
+
+    /// Set the screen window center for the specified part
+    ///
+    /// # Errors
+    /// * `[Error::ArgumentOutOfRange]` - If `part_index` does not refer to
+    /// a valid part
+    /// * `[Error::AlreadyWroteAttrs]` - If the header has already been written
+    ///
+    pub fn set_screen_window_center<V: Vec2<f32>>(
+        &mut self,
+        part_index: usize,
+        screen_window_center: &V,
+    ) -> Result<()> {
+        unsafe {
+            sys::exr_set_screen_window_center(
+                self.inner,
+                c_part_index(part_index)?,
+                screen_window_center.as_slice().as_ptr()
+                    as *const sys::exr_attr_v2f_t,
+            )
+            .ok_in(self.inner, ())
+            .map_err(|e| e.with_part(part_index))
+        }
+    }
+
+    /// Set the screen window width for the specified part
+    ///
+    /// # Errors
+    /// * `[Error::ArgumentOutOfRange]` - If `part_index` does not refer to
+    /// a valid part
+    /// * `[Error::AlreadyWroteAttrs]` - If the header has already been written
+    ///
+    pub fn set_screen_window_width(
+        &mut self,
+        part_index: usize,
+        screen_window_width: f32,
+    ) -> Result<()> {
+        unsafe {
+            sys::exr_set_screen_window_width(
+                self.inner,
+                c_part_index(part_index)?,
+                screen_window_width,
+            )
+            .ok_in(self.inner, ())
+            .map_err(|e| e.with_part(part_index))
+        }
+    }
+
+    /// Set the tile size and level mode of the specified part, which must
+    /// have tiled storage
+    ///
+    /// # Errors
+    /// * `[Error::ArgumentOutOfRange]` - If `part_index` does not refer to
+    /// a valid part
+    /// * `[Error::TileScanMixedApi]` - If the part is not tiled
+    /// * `[Error::AlreadyWroteAttrs]` - If the header has already been written
+    ///
+    pub fn set_tile_descriptor(
+        &mut self,
+        part_index: usize,
+        tile_descriptor: &TileDescriptor,
+    ) -> Result<()> {
+        unsafe {
+            sys::exr_set_tile_descriptor(
+                self.inner,
+                c_part_index(part_index)?,
+                tile_descriptor.x_size,
+                tile_descriptor.y_size,
+                tile_descriptor.level_mode.into(),
+                tile_descriptor.round_mode.into(),
+            )
+            .ok_in(self.inner, ())
+            .map_err(|e| e.with_part(part_index))
+        }
+    }
+
+    /// Add a channel to the specified part
+    ///
+    /// Channels are kept sorted by name, so the order they are added in does
+    /// not matter. `x_sampling` and `y_sampling` are 1 for a full-resolution
+    /// channel, and `p_linear` marks the channel as perceptually linear,
+    /// which lossy compression takes into account.
+    ///
+    /// # Errors
+    /// * `[Error::ArgumentOutOfRange]` - If `part_index` does not refer to
+    /// a valid part
+    /// * `[Error::InvalidArgument]` - If `name` is empty or contains internal
+    /// null bytes, the part already has a channel called `name`, or either
+    /// sampling is less than 1
+    /// * `[Error::NameTooLong]` - If `name` is longer than the current name
+    /// length limit
+    /// * `[Error::AlreadyWroteAttrs]` - If the header has already been written
+    ///
+    pub fn add_channel(
+        &mut self,
+        part_index: usize,
+        name: &str,
+        pixel_type: PixelType,
+        p_linear: bool,
+        x_sampling: i32,
+        y_sampling: i32,
+    ) -> Result<()> {
+        let c_name = CString::new(name).map_err(|_| Error::InvalidArgument)?;
+        let percept = if p_linear {
+            sys::exr_perceptual_treatment_t::EXR_PERCEPTUALLY_LINEAR
+        } else {
+            sys::exr_perceptual_treatment_t::EXR_PERCEPTUALLY_LOGARITHMIC
+        };
+        unsafe {
+            sys::exr_add_channel(
+                self.inner,
+                c_part_index(part_index)?,
+                c_name.as_ptr(),
+                pixel_type.into(),
+                percept,
+                x_sampling,
+                y_sampling,
+            )
+            .ok_in(self.inner, ())
+            .map_err(|e| e.with_part(part_index))
+        }
+    }
+
+    /// Add each of `channels` to the specified part, as with
+    /// [`add_channel`](Self::add_channel)
+    ///
+    /// # Errors
+    /// * `[Error::ArgumentOutOfRange]` - If `part_index` does not refer to
+    /// a valid part
+    /// * `[Error::InvalidArgument]` - If a channel's name is empty or
+    /// contains internal null bytes, the part already has a channel with that
+    /// name, or either sampling is less than 1
+    /// * `[Error::NameTooLong]` - If a channel's name is longer than the
+    /// current name length limit
+    /// * `[Error::AlreadyWroteAttrs]` - If the header has already been written
+    ///
+    pub fn add_channels(
+        &mut self,
+        part_index: usize,
+        channels: &[ChannelDesc],
+    ) -> Result<()> {
+        for c in channels {
+            self.add_channel(
+                part_index,
+                &c.name,
+                c.pixel_type,
+                c.p_linear,
+                c.sampling.0,
+                c.sampling.1,
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Replace the channels of the specified part with those in `channels`
+    ///
+    /// # Errors
+    /// * `[Error::ArgumentOutOfRange]` - If `part_index` does not refer to
+    /// a valid part
+    /// * `[Error::NameTooLong]` - If a channel's name is longer than the
+    /// current name length limit
+    /// * `[Error::AlreadyWroteAttrs]` - If the header has already been written
+    ///
+    pub fn set_channels(
+        &mut self,
+        part_index: usize,
+        channels: &ChannelListBuilder,
+    ) -> Result<()> {
+        with_c_channel_list(channels.channels(), |list| unsafe {
+            sys::exr_set_channels(self.inner, c_part_index(part_index)?, list)
+                .ok_in(self.inner, ())
+                .map_err(|e| e.with_part(part_index))
+        })
+    }
+
+    /// Add a new part that is a copy of the header of part `src_part_index` in
+    /// `source`, optionally giving it a new name.
+    ///
+    /// If `part_name` is `None` the new part keeps the source part's name, or
+    /// is named after its index in `source` if the source part is unnamed.
+    /// All other attributes of the source part are copied across, so this is
+    /// the starting point for merging several files into one multi-part file.
+    ///
+    /// # Returns
+    /// * `Ok(part_index)` - the index of the new part on success
+    /// * `Err(Error)`  - otherwise
+    ///
+    /// # Panics
+    /// * If `part_name` contains internal null bytes
+    ///
+    pub fn add_part_copy<S: ContextState>(
+        &mut self,
+        source: &Context<S>,
+        src_part_index: usize,
+        part_name: Option<&str>,
+    ) -> Result<usize> {
+        let part_name = match part_name {
+            Some(name) => name.to_string(),
+            None => source
+                .name(src_part_index)?
+                .map(|n| n.to_string())
+                .unwrap_or_else(|| src_part_index.to_string()),
+        };
+        let storage = source.storage(src_part_index)?;
+
+        let part_index = self.add_part(&part_name, storage)?;
+        self.copy_attributes_from(source, src_part_index, part_index)?;
+        Ok(part_index)
+    }
+
+    /// Add a new, empty part with name `part_name`
+    ///
+    /// The part has no attributes until they are set, e.g. with
+    /// [`Self::initialize_required_attributes`]. An empty `part_name` adds
+    /// the part without a name, which only single-part files of flat images
+    /// may have.
+    ///
+    /// # Returns
+    /// * `Ok(part_index)` - the index of the new part on success
+    /// * `Err(Error)`  - otherwise
+    ///
+    /// # Panics
+    /// * If `part_name` contains internal null bytes
+    ///
+    pub fn add_part(
+        &mut self,
+        part_name: &str,
+        storage: Storage,
+    ) -> Result<usize> {
+        let c_part_name =
+            CString::new(part_name).expect("invalid bytes in part_name");
+        let mut part_index = 0;
+        unsafe {
+            sys::exr_add_part(
+                self.inner,
+                c_part_name.as_ptr(),
+                storage.into(),
+                &mut part_index,
+            )
+            .ok_in(self.inner, part_index as usize)
+        }
+    }
+
+    /// Copy every attribute of part `src_part_index` in `source` to part
+    /// `part_index`, including those of types the library does not know
+    /// about, skipping any that are already set on `part_index`
+    ///
+    /// Set any attributes that should differ from the source's before
+    /// calling this, as existing attributes are left untouched.
+    ///
+    /// # Errors
+    /// * `[Error::ArgumentOutOfRange]` - If either part does not exist
+    /// * `[Error::AlreadyWroteAttrs]` - If the header has already been written
+    ///
+    pub fn copy_attributes_from<S: ContextState>(
+        &mut self,
+        source: &Context<S>,
+        src_part_index: usize,
+        part_index: usize,
+    ) -> Result<()> {
+        unsafe {
+            sys::exr_copy_unset_attributes(
+                self.inner,
+                c_part_index(part_index)?,
+                source.inner,
+                c_part_index(src_part_index)?,
+            )
+            .ok_in(self.inner, ())
+            .map_err(|e| e.with_part(part_index))
+        }
+    }
+
+    /// Copy the attributes of part `src_part_index` in `source` to part
+    /// `part_index` as [`copy_attributes_from`](Self::copy_attributes_from)
+    /// does, except for those named in `skip`
+    ///
+    /// This is for copying a header to a part laid out differently, e.g. to
+    /// leave out the source's `tiles` when the copy is stored as scanlines.
+    ///
+    /// # Errors
+    /// * `[Error::ArgumentOutOfRange]` - If either part does not exist
+    /// * `[Error::AttrTypeMismatch]` - If an attribute of a built-in type
+    /// holds a value the library could not interpret, so can't be copied
+    /// * `[Error::AlreadyWroteAttrs]` - If the header has already been written
+    ///
+    pub fn copy_attributes_except<S: ContextState>(
+        &mut self,
+        source: &Context<S>,
+        src_part_index: usize,
+        part_index: usize,
+        skip: &[&str],
+    ) -> Result<()> {
+        let existing = self
+            .attribute_list(part_index, AttrListAccessMode::FileOrder)?
+            .iter()
+            .map(|attr| attr.name().to_string())
+            .collect::<Vec<_>>();
+        for attr in source
+            .attribute_list(src_part_index, AttrListAccessMode::FileOrder)?
+        {
+            let name = attr.name();
+            if skip.contains(&name) || existing.iter().any(|e| e == name) {
+                continue;
+            }
+            self.set_attribute(part_index, name, &attr.value())?;
+        }
+        Ok(())
+    }
+
+    /// Set the attribute `name` on the given part, creating it if it does not
+    /// already exist
+    ///
+    /// # Errors
+    /// * `[Error::AttrTypeMismatch]` - If an attribute called `name` already
+    /// exists with a different type
+    /// * `[Error::AlreadyWroteAttrs]` - If the header has already been written
+    ///
+    pub fn set_attribute<Attr: AttributeWrite>(
+        &mut self,
+        part_index: usize,
+        name: &str,
+        value: &Attr,
+    ) -> Result<()> {
+        <Attr as AttributeWrite>::set(self, part_index, name, value)
+            .map_err(|e| e.with_part(part_index).with_attribute(name))
+    }
+
+    /// Set the `framesPerSecond` attribute of the specified part
+    ///
+    /// # Errors
+    /// * `[Error::AttrTypeMismatch]` - If `framesPerSecond` already exists
+    /// with a different type
+    /// * `[Error::AlreadyWroteAttrs]` - If the header has already been written
+    ///
+    pub fn set_frames_per_second(
+        &mut self,
+        part_index: usize,
+        fps: &AttrRational,
+    ) -> Result<()> {
+        self.set_attribute(part_index, "framesPerSecond", fps)
+    }
+
+    /// Set the `preview` attribute of the specified part to a `width` by
+    /// `height` thumbnail of the image
+    ///
+    /// See [`make_preview`](crate::rgba::make_preview) for generating a
+    /// thumbnail from the full-resolution pixels.
+    ///
+    /// # Errors
+    /// * `[Error::InvalidArgument]` - If `pixels` does not hold exactly
+    /// `width * height` pixels
+    /// * `[Error::AttrTypeMismatch]` - If `preview` already exists with a
+    /// different type
+    /// * `[Error::AlreadyWroteAttrs]` - If the header has already been written
+    ///
+    pub fn set_preview_from_image(
+        &mut self,
+        part_index: usize,
+        width: usize,
+        height: usize,
+        pixels: &[Rgba<u8>],
+    ) -> Result<()> {
+        let width = u32::try_from(width).map_err(|_| Error::InvalidArgument)?;
+        let height =
+            u32::try_from(height).map_err(|_| Error::InvalidArgument)?;
+        let mut rgba = Vec::with_capacity(pixels.len() * 4);
+        for p in pixels {
+            rgba.extend_from_slice(&[p.r, p.g, p.b, p.a]);
+        }
+
+        self.set_attribute(
+            part_index,
+            "preview",
+            &Preview {
+                width,
+                height,
+                rgba: &rgba,
+            },
+        )
+    }
+
+    /// Declare an attribute called `name` with the type named `type_name`,
+    /// without setting its value
+    ///
+    /// `type_name` may be one of the built-in types, e.g. "v3f", or any other
+    /// name, such as a studio-specific "cameraRig", in which case the
+    /// attribute is opaque. Declaring an attribute that already exists with
+    /// the same type does nothing.
+    ///
+    /// # Returns
+    /// * `Ok(kind)` - the type of the attribute, which is
+    /// [`AttrType::Opaque`] for type names the library does not know
+    ///
+    /// # Errors
+    /// * `[Error::AttrTypeMismatch]` - If an attribute called `name` already
+    /// exists with a different type
+    /// * `[Error::InvalidArgument]` - If `name` or `type_name` is empty
+    /// * `[Error::AlreadyWroteAttrs]` - If the header has already been written
+    ///
+    /// # Panics
+    /// * If `name` or `type_name` contains internal null bytes
+    ///
+    pub fn declare_attribute(
+        &mut self,
+        part_index: usize,
+        name: &str,
+        type_name: &str,
+    ) -> Result<AttrType> {
+        let c_name = CString::new(name).expect("invalid bytes in name");
+        let c_type =
+            CString::new(type_name).expect("invalid bytes in type_name");
+        let mut attr = std::ptr::null_mut();
+        unsafe {
+            sys::exr_attr_declare_by_type(
+                self.inner,
+                c_part_index(part_index)?,
+                c_name.as_ptr(),
+                c_type.as_ptr(),
+                &mut attr,
+            )
+            .ok_in(self.inner, ())
+            .map_err(|e| e.with_part(part_index).with_attribute(name))?;
+            if attr.is_null() {
+                return Err(Error::InvalidAttr.with_attribute(name));
+            }
+            Ok((*attr).type_.into())
+        }
+    }
+
+    /// Set the attribute `name` to `value`, first declaring it with the type
+    /// named `type_name` if it does not already exist
+    ///
+    /// This is [`set_attribute`](WriteHeaderContext::set_attribute) with the
+    /// type given explicitly, so `type_name` must name the built-in type
+    /// `value` is written as.
+    ///
+    /// # Errors
+    /// * `[Error::AttrTypeMismatch]` - If `type_name` is not the type of
+    /// `value`, or the attribute already exists with a different type
+    /// * `[Error::AlreadyWroteAttrs]` - If the header has already been written
+    ///
+    pub fn set_attribute_by_type<Attr: AttributeWrite>(
+        &mut self,
+        part_index: usize,
+        name: &str,
+        type_name: &str,
+        value: &Attr,
+    ) -> Result<()> {
+        self.declare_attribute(part_index, name, type_name)?;
+        self.set_attribute(part_index, name, value)
+    }
+
+    /// Set the attribute `name` to the packed bytes `data`, declaring it with
+    /// the type named `type_name` if it does not already exist
+    ///
+    /// This is how attributes of types the library does not know about are
+    /// written. `data` is written to the file as-is, so must already be in
+    /// the type's packed, little-endian form.
+    ///
+    /// # Errors
+    /// * `[Error::AttrTypeMismatch]` - If the attribute already exists with a
+    /// different type, or `type_name` is one of the built-in types
+    /// * `[Error::ArgumentOutOfRange]` - If `data` is too large for an
+    /// attribute
+    /// * `[Error::AlreadyWroteAttrs]` - If the header has already been written
+    ///
+    /// # Panics
+    /// * If `name` or `type_name` contains internal null bytes
+    ///
+    pub fn set_attribute_bytes(
+        &mut self,
+        part_index: usize,
+        name: &str,
+        type_name: &str,
+        data: &[u8],
+    ) -> Result<()> {
+        set_user(self, part_index, name, type_name, data)
+            .map_err(|e| e.with_part(part_index).with_attribute(name))
+    }
+
+    /// Set an attribute of a type the library does not know about to a copy
+    /// of `attr`, which may have been read from another file, keeping its
+    /// name, type name and packed bytes
+    ///
+    /// # Errors
+    /// * `[Error::AttrTypeMismatch]` - If `attr` is of a known type, or an
+    /// attribute with the same name already exists with a different type
+    /// * `[Error::AlreadyWroteAttrs]` - If the header has already been written
+    ///
+    pub fn set_opaque_attribute(
+        &mut self,
+        part_index: usize,
+        attr: &Attribute,
+    ) -> Result<()> {
+        let data = attr.opaque_data().ok_or_else(|| {
+            Error::AttrTypeMismatch
+                .with_part(part_index)
+                .with_attribute(attr.name())
+        })?;
+        self.set_attribute_bytes(
+            part_index,
+            attr.name(),
+            attr.type_name(),
+            data,
+        )
+    }
+
+    /// Set the attribute `name` to `value`, packed as the custom type `T`
+    ///
+    /// # Errors
+    /// * `[Error::AttrTypeMismatch]` - If an attribute called `name` already
+    /// exists with a different type
+    /// * `[Error::AlreadyWroteAttrs]` - If the header has already been written
+    ///
+    pub fn set_custom_attribute<T: CustomAttribute>(
+        &mut self,
+        part_index: usize,
+        name: &str,
+        value: &T,
+    ) -> Result<()> {
+        self.set_attribute_bytes(part_index, name, T::TYPE_NAME, &value.pack())
+    }
+
+    /// Set a 3x3 float matrix attribute from any type implementing
+    /// [`Matrix33<f32>`]
+    ///
+    pub fn set_attribute_m33f<M: Matrix33<f32>>(
+        &mut self,
+        part_index: usize,
+        name: &str,
+        value: &M,
+    ) -> Result<()> {
+        self.set_attribute(part_index, name, value.as_slice())
+    }
+
+    /// Set a 3x3 double matrix attribute from any type implementing
+    /// [`Matrix33<f64>`]
+    ///
+    pub fn set_attribute_m33d<M: Matrix33<f64>>(
+        &mut self,
+        part_index: usize,
+        name: &str,
+        value: &M,
+    ) -> Result<()> {
+        self.set_attribute(part_index, name, value.as_slice())
+    }
+
+    /// Set a 4x4 float matrix attribute, such as `worldToCamera`, from any
+    /// type implementing [`Matrix44<f32>`]
+    ///
+    pub fn set_attribute_m44f<M: Matrix44<f32>>(
+        &mut self,
+        part_index: usize,
+        name: &str,
+        value: &M,
+    ) -> Result<()> {
+        self.set_attribute(part_index, name, value.as_slice())
+    }
+
+    /// Set a 4x4 double matrix attribute from any type implementing
+    /// [`Matrix44<f64>`]
+    ///
+    pub fn set_attribute_m44d<M: Matrix44<f64>>(
+        &mut self,
+        part_index: usize,
+        name: &str,
+        value: &M,
+    ) -> Result<()> {
+        self.set_attribute(part_index, name, value.as_slice())
+    }
+
+    /// Set a 2-element integer vector attribute from any type implementing
+    /// [`Vec2<i32>`]
+    ///
+    pub fn set_attribute_v2i<V: Vec2<i32>>(
+        &mut self,
+        part_index: usize,
+        name: &str,
+        value: &V,
+    ) -> Result<()> {
+        self.set_attribute(part_index, name, value.as_slice())
+    }
+
+    /// Set a 2-element float vector attribute from any type implementing
+    /// [`Vec2<f32>`]
+    ///
+    pub fn set_attribute_v2f<V: Vec2<f32>>(
+        &mut self,
+        part_index: usize,
+        name: &str,
+        value: &V,
+    ) -> Result<()> {
+        self.set_attribute(part_index, name, value.as_slice())
+    }
+
+    /// Set a 2-element double vector attribute from any type implementing
+    /// [`Vec2<f64>`]
+    ///
+    pub fn set_attribute_v2d<V: Vec2<f64>>(
+        &mut self,
+        part_index: usize,
+        name: &str,
+        value: &V,
+    ) -> Result<()> {
+        self.set_attribute(part_index, name, value.as_slice())
+    }
+
+    /// Set a 3-element integer vector attribute from any type implementing
+    /// [`Vec3<i32>`]
+    ///
+    pub fn set_attribute_v3i<V: Vec3<i32>>(
+        &mut self,
+        part_index: usize,
+        name: &str,
+        value: &V,
+    ) -> Result<()> {
+        self.set_attribute(part_index, name, value.as_slice())
+    }
+
+    /// Set a 3-element float vector attribute from any type implementing
+    /// [`Vec3<f32>`]
+    ///
+    pub fn set_attribute_v3f<V: Vec3<f32>>(
+        &mut self,
+        part_index: usize,
+        name: &str,
+        value: &V,
+    ) -> Result<()> {
+        self.set_attribute(part_index, name, value.as_slice())
+    }
+
+    /// Set a 3-element double vector attribute from any type implementing
+    /// [`Vec3<f64>`]
+    ///
+    pub fn set_attribute_v3d<V: Vec3<f64>>(
+        &mut self,
+        part_index: usize,
+        name: &str,
+        value: &V,
+    ) -> Result<()> {
+        self.set_attribute(part_index, name, value.as_slice())
+    }
+
+    /// Set an integer box attribute from any type implementing
+    /// [`Bound2<i32>`]
+    ///
+    pub fn set_attribute_box2i<B: Bound2<i32>>(
+        &mut self,
+        part_index: usize,
+        name: &str,
+        value: &B,
+    ) -> Result<()> {
+        self.set_attribute(part_index, name, value.as_slice())
+    }
+
+    /// Set a float box attribute from any type implementing
+    /// [`Bound2<f32>`]
+    ///
+    pub fn set_attribute_box2f<B: Bound2<f32>>(
+        &mut self,
+        part_index: usize,
+        name: &str,
+        value: &B,
+    ) -> Result<()> {
+        self.set_attribute(part_index, name, value.as_slice())
+    }
 }
@@ -0,0 +1,204 @@
+//! Re-using scratch buffers between chunks, and between threads.
+//!
+//! Decoding or encoding a chunk at a time needs somewhere to put each chunk's
+//! data, and allocating a fresh buffer for every chunk adds up in tight loops.
+//! A [`BufferPool`] keeps the buffers that have been handed back, ready to be
+//! handed out again, so a loop only allocates as many as it has in use at
+//! once.
+//!
+use crate::context::{Context, ContextState};
+use crate::error::Error;
+use std::ops::{Deref, DerefMut};
+use std::sync::{Arc, Mutex};
+
+type Result<T, E = Error> = std::result::Result<T, E>;
+
+struct PoolInner {
+    buffer_size: usize,
+    free: Mutex<Vec<Vec<u8>>>,
+}
+
+/// A pool of byte buffers that are returned to it when dropped
+///
+/// Cloning a pool gives another handle to the same buffers, so a pool can be
+/// shared between threads, and buffers taken on one thread can be dropped on
+/// another.
+///
+#[derive(Clone)]
+pub struct BufferPool {
+    inner: Arc<PoolInner>,
+}
+
+impl BufferPool {
+    /// Create an empty pool whose buffers are allocated to hold at least
+    /// `buffer_size` bytes
+    ///
+    pub fn new(buffer_size: usize) -> BufferPool {
+        BufferPool {
+            inner: Arc::new(PoolInner {
+                buffer_size,
+                free: Mutex::new(Vec::new()),
+            }),
+        }
+    }
+
+    /// Create an empty pool whose buffers can hold any chunk of the part
+    /// `part_index` of `ctx`, either packed or unpacked, as given by
+    /// [`Context::chunk_unpacked_size`]
+    ///
+    /// # Errors
+    /// * `[Error::ArgumentOutOfRange]` - If `part_index` is not a valid part
+    ///
+    pub fn for_part<S: ContextState>(
+        ctx: &Context<S>,
+        part_index: usize,
+    ) -> Result<BufferPool> {
+        Ok(BufferPool::new(ctx.chunk_unpacked_size(part_index)?))
+    }
+
+    /// The number of bytes each buffer is allocated to hold
+    ///
+    pub fn buffer_size(&self) -> usize {
+        self.inner.buffer_size
+    }
+
+    /// The number of buffers waiting in the pool to be handed out again
+    ///
+    pub fn idle(&self) -> usize {
+        self.inner.free.lock().map(|free| free.len()).unwrap_or(0)
+    }
+
+    /// Take a buffer of `len` zeroed bytes from the pool, allocating a new
+    /// one if there are none left
+    ///
+    /// Buffers longer than [`BufferPool::buffer_size`] are grown as needed,
+    /// and keep their larger allocation when they go back to the pool.
+    ///
+    pub fn take(&self, len: usize) -> PooledBuffer {
+        let mut buffer = self
+            .inner
+            .free
+            .lock()
+            .ok()
+            .and_then(|mut free| free.pop())
+            .unwrap_or_else(|| Vec::with_capacity(self.inner.buffer_size));
+        buffer.clear();
+        buffer.resize(len, 0);
+
+        PooledBuffer {
+            buffer,
+            pool: Arc::clone(&self.inner),
+        }
+    }
+}
+
+impl std::fmt::Debug for BufferPool {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("BufferPool")
+            .field("buffer_size", &self.buffer_size())
+            .field("idle", &self.idle())
+            .finish()
+    }
+}
+
+/// A buffer taken from a [`BufferPool`], which goes back to the pool when it
+/// is dropped
+///
+pub struct PooledBuffer {
+    buffer: Vec<u8>,
+    pool: Arc<PoolInner>,
+}
+
+impl PooledBuffer {
+    /// Keep the buffer, rather than returning it to the pool
+    ///
+    pub fn into_vec(mut self) -> Vec<u8> {
+        std::mem::take(&mut self.buffer)
+    }
+}
+
+impl Deref for PooledBuffer {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.buffer
+    }
+}
+
+impl DerefMut for PooledBuffer {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        &mut self.buffer
+    }
+}
+
+impl std::fmt::Debug for PooledBuffer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PooledBuffer")
+            .field("len", &self.buffer.len())
+            .finish()
+    }
+}
+
+impl Drop for PooledBuffer {
+    fn drop(&mut self) {
+        // buffers taken with into_vec leave nothing worth keeping behind
+        if self.buffer.capacity() == 0 {
+            return;
+        }
+        if let Ok(mut free) = self.pool.free.lock() {
+            free.push(std::mem::take(&mut self.buffer));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate as exr;
+    use exr::context::ReadContext;
+    use exr::pool::BufferPool;
+    use std::path::Path;
+
+    #[test]
+    fn buffer_pool() -> Result<(), exr::Error> {
+        let path_ferris = Path::new(
+            &std::env::var("CARGO_MANIFEST_DIR")
+                .expect("CARGO_MANIFEST_DIR not set"),
+        )
+        .join("images")
+        .join("ferris.exr");
+        let ctx = ReadContext::new(&path_ferris)?;
+
+        let pool = BufferPool::for_part(&ctx, 0)?;
+        assert_eq!(pool.buffer_size(), ctx.chunk_unpacked_size(0)?);
+        assert_eq!(pool.idle(), 0);
+
+        let mut buffer = pool.take(16);
+        assert_eq!(buffer.len(), 16);
+        buffer[0] = 1;
+        let address = buffer.as_ptr();
+        drop(buffer);
+        assert_eq!(pool.idle(), 1);
+
+        // the same allocation comes back, zeroed
+        let buffer = pool.take(pool.buffer_size());
+        assert_eq!(buffer.as_ptr(), address);
+        assert!(buffer.iter().all(|b| *b == 0));
+        assert_eq!(pool.idle(), 0);
+
+        // buffers can be dropped on other threads
+        let other = pool.clone();
+        std::thread::spawn(move || {
+            let buffers = (0..4).map(|_| other.take(8)).collect::<Vec<_>>();
+            drop(buffers);
+        })
+        .join()
+        .expect("thread panicked");
+        assert_eq!(pool.idle(), 4);
+
+        let owned = buffer.into_vec();
+        assert_eq!(owned.len(), pool.buffer_size());
+        assert_eq!(pool.idle(), 4);
+
+        Ok(())
+    }
+}
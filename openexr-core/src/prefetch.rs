@@ -0,0 +1,247 @@
+//! Reading chunks ahead of the decoder on a background thread.
+//!
+//! [`ChunkPrefetcher`] reads the packed data of a list of chunks on a thread
+//! of its own, staying up to a fixed number of chunks ahead of whatever is
+//! consuming them, so that waiting on the file overlaps with decompressing
+//! the chunks already read. This helps most where each read takes a long
+//! time to start, such as on spinning disks and network filesystems. The
+//! packed chunks are decoded with [`DecodePipeline::run_packed`].
+//!
+use crate::chunkio::ChunkInfo;
+use crate::coding::ChannelElement;
+use crate::context::ReadContext;
+use crate::decode::{DecodePipeline, Runnable};
+use crate::error::Error;
+use crate::read::{decode_chunk_into, part_chunks, ImageBuffer};
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+
+type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// The packed data of a chunk, exactly as it is stored in the file
+///
+#[derive(Debug, Clone)]
+pub struct PackedChunk {
+    /// The position of the chunk in the list the prefetcher was created with
+    pub index: usize,
+    pub chunk_info: ChunkInfo,
+    /// The chunk's `packed_size` bytes of pixel data
+    pub packed_data: Vec<u8>,
+    /// The chunk's `sample_count_table_size` bytes of packed sample counts,
+    /// which is empty unless the part is deep
+    pub sample_counts: Vec<u8>,
+}
+
+/// Reads the packed data of chunks on a background thread, handing them out
+/// in order as an iterator
+///
+/// At most `depth` chunks are read ahead of the iterator. If a chunk can't be
+/// read, its error is the last item. Dropping the prefetcher stops the thread
+/// after the read it is making, if any, has finished.
+///
+pub struct ChunkPrefetcher {
+    receiver: Option<Receiver<Result<PackedChunk>>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl ChunkPrefetcher {
+    /// Start reading the chunks in `chunks`, all of which are in the part
+    /// `part_index` of `ctx`, keeping up to `depth` of them waiting to be
+    /// taken, or one if `depth` is 0
+    ///
+    pub fn new(
+        ctx: Arc<ReadContext>,
+        part_index: usize,
+        chunks: Vec<ChunkInfo>,
+        depth: usize,
+    ) -> ChunkPrefetcher {
+        let (sender, receiver) = sync_channel(depth.max(1));
+        let thread = std::thread::spawn(move || {
+            prefetch(&ctx, part_index, chunks, sender)
+        });
+
+        ChunkPrefetcher {
+            receiver: Some(receiver),
+            thread: Some(thread),
+        }
+    }
+}
+
+impl Iterator for ChunkPrefetcher {
+    type Item = Result<PackedChunk>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let received = self.receiver.as_ref()?.recv();
+        match received {
+            Ok(chunk) => Some(chunk),
+            Err(_) => {
+                // every chunk has been read, unless the thread panicked
+                self.receiver = None;
+                if let Some(Err(panic)) = self.thread.take().map(|t| t.join()) {
+                    std::panic::resume_unwind(panic);
+                }
+                None
+            }
+        }
+    }
+}
+
+impl Drop for ChunkPrefetcher {
+    fn drop(&mut self) {
+        // closing the channel makes the thread stop at its next send
+        self.receiver = None;
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Read each chunk in turn and send it down `sender`, until every chunk has
+/// been sent, one can't be read or the receiver goes away
+///
+fn prefetch(
+    ctx: &ReadContext,
+    part_index: usize,
+    chunks: Vec<ChunkInfo>,
+    sender: SyncSender<Result<PackedChunk>>,
+) {
+    for (index, chunk_info) in chunks.into_iter().enumerate() {
+        let chunk = read_packed(ctx, part_index, index, chunk_info);
+        let failed = chunk.is_err();
+        if sender.send(chunk).is_err() || failed {
+            return;
+        }
+    }
+}
+
+fn read_packed(
+    ctx: &ReadContext,
+    part_index: usize,
+    index: usize,
+    chunk_info: ChunkInfo,
+) -> Result<PackedChunk> {
+    if chunk_info.sample_count_table_size == 0 {
+        return Ok(PackedChunk {
+            index,
+            packed_data: ctx.read_chunk_vec(part_index, &chunk_info)?,
+            chunk_info,
+            sample_counts: Vec::new(),
+        });
+    }
+
+    let mut packed_data = vec![0u8; chunk_info.packed_size as usize];
+    let mut sample_counts =
+        vec![0u8; chunk_info.sample_count_table_size as usize];
+    ctx.read_deep_chunk(
+        part_index,
+        &chunk_info,
+        Some(&mut packed_data),
+        Some(&mut sample_counts),
+    )?;
+
+    Ok(PackedChunk {
+        index,
+        chunk_info,
+        packed_data,
+        sample_counts,
+    })
+}
+
+/// Decode the whole of a part into an interleaved buffer of the channels
+/// named in `channels`, converted to `T`, as for [`ReadContext::read_image`],
+/// while a [`ChunkPrefetcher`] reads up to `depth` chunks ahead
+///
+/// For tiled parts, this reads the highest-resolution level.
+///
+/// # Errors
+/// * `[Error::ReadIo]` - If reading a chunk fails
+/// * `[Error::FeatureNotImplemented]` - If the part holds deep data
+///
+pub fn read_image<T: ChannelElement>(
+    ctx: &Arc<ReadContext>,
+    part_index: usize,
+    channels: &[&str],
+    depth: usize,
+) -> Result<ImageBuffer<T>> {
+    let dw = ctx.data_window::<[i32; 4]>(part_index)?;
+    let width = (dw[2] - dw[0] + 1).max(0) as usize;
+    let height = (dw[3] - dw[1] + 1).max(0) as usize;
+    let (chunks, origins): (Vec<_>, Vec<_>) =
+        part_chunks(ctx, part_index)?.into_iter().unzip();
+
+    let mut image = ImageBuffer {
+        width,
+        height,
+        data_window: dw,
+        channels: channels.iter().map(|c| c.to_string()).collect(),
+        pixels: vec![T::default(); width * height * channels.len()],
+    };
+
+    let mut pipeline: Option<DecodePipeline<Runnable>> = None;
+    let prefetcher =
+        ChunkPrefetcher::new(Arc::clone(ctx), part_index, chunks, depth);
+    for chunk in prefetcher {
+        let mut chunk = chunk?;
+        decode_chunk_into(
+            ctx,
+            &mut pipeline,
+            part_index,
+            &chunk.chunk_info,
+            Some(&mut chunk.packed_data),
+            channels,
+            &mut image,
+            origins[chunk.index],
+        )?;
+    }
+
+    Ok(image)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate as exr;
+    use exr::context::ReadContext;
+    use exr::prefetch::{self, ChunkPrefetcher};
+    use imath_traits::f16;
+    use std::path::Path;
+    use std::sync::Arc;
+
+    #[test]
+    fn prefetch_chunks() -> Result<(), exr::Error> {
+        let images = Path::new(
+            &std::env::var("CARGO_MANIFEST_DIR")
+                .expect("CARGO_MANIFEST_DIR not set"),
+        )
+        .join("images");
+        let channels = ["R", "G", "B", "A"];
+
+        for name in &["ferris.exr", "ferris-tiled.exr"] {
+            let ctx = Arc::new(ReadContext::new(images.join(name))?);
+            let image = ctx.read_image::<f16>(0, &channels)?;
+            for depth in &[0, 1, 16] {
+                let prefetched =
+                    prefetch::read_image::<f16>(&ctx, 0, &channels, *depth)?;
+                assert_eq!(prefetched, image);
+            }
+        }
+
+        // stopping part way through leaves the thread to finish cleanly
+        let ctx = Arc::new(ReadContext::new(images.join("ferris.exr"))?);
+        let chunks = exr::read::part_chunks(&ctx, 0)?
+            .into_iter()
+            .map(|(chunk_info, _)| chunk_info)
+            .collect::<Vec<_>>();
+        assert!(chunks.len() > 2);
+        let mut prefetcher =
+            ChunkPrefetcher::new(Arc::clone(&ctx), 0, chunks.clone(), 1);
+        let first = prefetcher.next().expect("no chunks prefetched")?;
+        assert_eq!(first.index, 0);
+        assert_eq!(first.chunk_info.idx, chunks[0].idx);
+        assert_eq!(first.packed_data.len() as u64, chunks[0].packed_size);
+        assert!(first.sample_counts.is_empty());
+        drop(prefetcher);
+
+        Ok(())
+    }
+}
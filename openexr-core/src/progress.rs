@@ -0,0 +1,150 @@
+//! Reporting how far through a long operation is, e.g. to show a progress
+//! bar, and cancelling it part way.
+//!
+//! The helpers that read, write or transcode whole images have variants
+//! taking a callback, which is called with a [`Progress`] each time a chunk
+//! is finished. Returning [`ControlFlow::Break`] from the callback stops the
+//! operation, which then fails with [`Error::Cancelled`].
+//!
+use crate::error::Error;
+use std::ops::ControlFlow;
+
+type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// How far through an operation is, in chunks
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Progress {
+    /// The number of chunks finished so far
+    pub chunks_done: usize,
+    /// The number of chunks the operation will process in all
+    pub chunks_total: usize,
+}
+
+impl Progress {
+    /// The fraction of the operation that is finished, from 0 to 1
+    ///
+    pub fn fraction(&self) -> f64 {
+        if self.chunks_total == 0 {
+            1.0
+        } else {
+            self.chunks_done as f64 / self.chunks_total as f64
+        }
+    }
+}
+
+/// Counts the chunks an operation has finished, passing each new count on to
+/// the operation's callback
+///
+pub(crate) struct Tracker<'a> {
+    callback: &'a mut dyn FnMut(Progress) -> ControlFlow<()>,
+    progress: Progress,
+}
+
+impl<'a> Tracker<'a> {
+    pub(crate) fn new(
+        callback: &'a mut dyn FnMut(Progress) -> ControlFlow<()>,
+        chunks_total: usize,
+    ) -> Tracker<'a> {
+        Tracker {
+            callback,
+            progress: Progress {
+                chunks_done: 0,
+                chunks_total,
+            },
+        }
+    }
+
+    /// Record that another chunk has been finished
+    ///
+    /// # Errors
+    /// * `[Error::Cancelled]` - If the callback asked to stop
+    ///
+    pub(crate) fn chunk_done(&mut self) -> Result<()> {
+        self.progress.chunks_done += 1;
+        match (self.callback)(self.progress) {
+            ControlFlow::Continue(()) => Ok(()),
+            ControlFlow::Break(()) => Err(Error::Cancelled),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate as exr;
+    use exr::context::ReadContext;
+    use exr::progress::Progress;
+    use exr::transcode::{transcode_with_progress, TranscodeOptions};
+    use imath_traits::f16;
+    use std::ops::ControlFlow;
+    use std::path::Path;
+
+    #[test]
+    fn progress() -> Result<(), exr::Error> {
+        let images = Path::new(
+            &std::env::var("CARGO_MANIFEST_DIR")
+                .expect("CARGO_MANIFEST_DIR not set"),
+        )
+        .join("images");
+        let channels = ["R", "G", "B", "A"];
+
+        for name in &["ferris.exr", "ferris-tiled.exr"] {
+            let ctx = ReadContext::new(images.join(name))?;
+            let mut reports = Vec::new();
+            let image = ctx.read_image_with_progress::<f16, _>(
+                0,
+                &channels,
+                |progress| {
+                    reports.push(progress);
+                    ControlFlow::Continue(())
+                },
+            )?;
+            assert_eq!(image, ctx.read_image::<f16>(0, &channels)?);
+
+            let total = reports[0].chunks_total;
+            assert!(total > 1);
+            assert_eq!(reports.len(), total);
+            assert!(reports.iter().enumerate().all(|(i, p)| p.chunks_done
+                == i + 1
+                && p.chunks_total == total));
+            assert_eq!(reports[total - 1].fraction(), 1.0);
+
+            let err = ctx
+                .read_image_with_progress::<f16, _>(0, &channels, |progress| {
+                    if progress.chunks_done == 2 {
+                        ControlFlow::Break(())
+                    } else {
+                        ControlFlow::Continue(())
+                    }
+                })
+                .expect_err("read continued after being cancelled");
+            assert_eq!(err.kind(), exr::ErrorKind::Cancelled);
+        }
+
+        let path = std::env::temp_dir().join("progress.exr");
+        let mut last = None;
+        transcode_with_progress(
+            images.join("ferris.exr"),
+            &path,
+            TranscodeOptions::default(),
+            |progress: Progress| {
+                last = Some(progress);
+                ControlFlow::Continue(())
+            },
+        )?;
+        let last = last.expect("no progress reported");
+        assert_eq!(last.chunks_done, last.chunks_total);
+        assert_eq!(last.chunks_total, ReadContext::new(&path)?.chunk_count(0)?);
+
+        let err = transcode_with_progress(
+            images.join("ferris.exr"),
+            &path,
+            TranscodeOptions::default(),
+            |_| ControlFlow::Break(()),
+        )
+        .expect_err("transcode continued after being cancelled");
+        assert_eq!(err.kind(), exr::ErrorKind::Cancelled);
+
+        Ok(())
+    }
+}
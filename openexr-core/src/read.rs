@@ -0,0 +1,894 @@
+//! Higher-level conveniences for reading pixels without having to manage
+//! chunks and decode pipelines by hand.
+//!
+use crate::attr::{split_layer, Storage};
+use crate::chunkio::ChunkInfo;
+use crate::coding::ChannelElement;
+use crate::context::ReadContext;
+use crate::decode::{DecodePipeline, Runnable};
+use crate::error::Error;
+use crate::progress::{Progress, Tracker};
+use std::ops::ControlFlow;
+
+type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// A single decoded scanline
+///
+#[derive(Debug, Clone, PartialEq)]
+pub struct Row<T> {
+    /// The y coordinate of the line, in the part's data window
+    pub y: i32,
+    /// The requested channels, interleaved in the order they were requested
+    pub pixels: Vec<T>,
+}
+
+/// Iterator over the decoded scanlines of a part, created with
+/// [`ReadContext::scanline_rows`]
+///
+/// Each chunk is decoded in turn into an internal buffer, re-using the same
+/// decode pipeline, and its lines handed out one at a time. As the standard
+/// [`Iterator`] trait cannot lend out borrows of the iterator itself, each
+/// [`Row`] owns its pixels.
+///
+pub struct ScanlineRows<'c, T: ChannelElement> {
+    ctx: &'c ReadContext,
+    part_index: usize,
+    channels: Vec<String>,
+    pipeline: Option<DecodePipeline<'c, Runnable>>,
+    chunk: Vec<T>,
+    chunk_start_y: i32,
+    chunk_height: i32,
+    row_in_chunk: i32,
+    width: usize,
+    next_chunk_y: i32,
+    end_y: i32,
+    done: bool,
+}
+
+impl<'c, T: ChannelElement> ScanlineRows<'c, T> {
+    /// Decode the chunk containing line `next_chunk_y` into the buffer
+    ///
+    fn decode_next_chunk(&mut self) -> Result<()> {
+        let chunk_info = self
+            .ctx
+            .read_scanline_chunk_info(self.part_index, self.next_chunk_y)?;
+
+        let mut pipeline = match self.pipeline.take() {
+            Some(p) => p.update(&chunk_info)?,
+            None => {
+                self.ctx.decoding_initialize(self.part_index, &chunk_info)?
+            }
+        };
+
+        let num_pixels = chunk_info.width.max(0) as usize
+            * chunk_info.height.max(0) as usize;
+        self.chunk.clear();
+        self.chunk
+            .resize(num_pixels * self.channels.len(), T::default());
+
+        let names =
+            self.channels.iter().map(|c| c.as_str()).collect::<Vec<_>>();
+        pipeline.select_interleaved(&names, &mut self.chunk)?;
+        let mut pipeline = pipeline.choose_default_routines()?;
+        pipeline.run_checked(&mut self.chunk)?;
+        self.pipeline = Some(pipeline);
+
+        self.chunk_start_y = chunk_info.start_y;
+        self.chunk_height = chunk_info.height;
+        self.row_in_chunk = (self.next_chunk_y - chunk_info.start_y).max(0);
+        self.next_chunk_y = chunk_info.start_y + chunk_info.height;
+        Ok(())
+    }
+}
+
+impl<'c, T: ChannelElement> Iterator for ScanlineRows<'c, T> {
+    type Item = Result<Row<T>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+
+        if self.row_in_chunk >= self.chunk_height {
+            if self.next_chunk_y > self.end_y {
+                self.done = true;
+                return None;
+            }
+
+            if let Err(e) = self.decode_next_chunk() {
+                self.done = true;
+                return Some(Err(e));
+            }
+        }
+
+        let row_len = self.width * self.channels.len();
+        let start = self.row_in_chunk as usize * row_len;
+        let row = Row {
+            y: self.chunk_start_y + self.row_in_chunk,
+            pixels: self.chunk[start..start + row_len].to_vec(),
+        };
+        self.row_in_chunk += 1;
+
+        Some(Ok(row))
+    }
+}
+
+/// A single decoded tile
+///
+#[derive(Debug, Clone, PartialEq)]
+pub struct Tile<T> {
+    /// Index of the tile in the x direction in its level
+    pub x: i32,
+    /// Index of the tile in the y direction in its level
+    pub y: i32,
+    /// Width of the tile in pixels. This is smaller than the part's tile size
+    /// for tiles at the right-hand edge of a level
+    pub width: i32,
+    /// Height of the tile in pixels. This is smaller than the part's tile
+    /// size for tiles at the bottom edge of a level
+    pub height: i32,
+    /// The requested channels, interleaved in the order they were requested
+    pub pixels: Vec<T>,
+}
+
+/// Iterator over the decoded tiles of one level of a tiled part, created with
+/// [`ReadContext::tiles`]
+///
+/// Tiles are visited a row at a time, re-using the same decode pipeline.
+///
+pub struct Tiles<'c, T: ChannelElement> {
+    ctx: &'c ReadContext,
+    part_index: usize,
+    channels: Vec<String>,
+    pipeline: Option<DecodePipeline<'c, Runnable>>,
+    level_x: i32,
+    level_y: i32,
+    tiles_x: i32,
+    tiles_y: i32,
+    next_x: i32,
+    next_y: i32,
+    done: bool,
+    marker: std::marker::PhantomData<T>,
+}
+
+impl<'c, T: ChannelElement> Tiles<'c, T> {
+    fn decode_tile(&mut self, tile_x: i32, tile_y: i32) -> Result<Tile<T>> {
+        let chunk_info = self.ctx.read_tile_chunk_info(
+            self.part_index,
+            tile_x,
+            tile_y,
+            self.level_x,
+            self.level_y,
+        )?;
+
+        let mut pipeline = match self.pipeline.take() {
+            Some(p) => p.update(&chunk_info)?,
+            None => {
+                self.ctx.decoding_initialize(self.part_index, &chunk_info)?
+            }
+        };
+
+        let num_pixels = chunk_info.width.max(0) as usize
+            * chunk_info.height.max(0) as usize;
+        let mut pixels = vec![T::default(); num_pixels * self.channels.len()];
+
+        let names =
+            self.channels.iter().map(|c| c.as_str()).collect::<Vec<_>>();
+        pipeline.select_interleaved(&names, &mut pixels)?;
+        let mut pipeline = pipeline.choose_default_routines()?;
+        pipeline.run_checked(&mut pixels)?;
+        self.pipeline = Some(pipeline);
+
+        Ok(Tile {
+            x: tile_x,
+            y: tile_y,
+            width: chunk_info.width,
+            height: chunk_info.height,
+            pixels,
+        })
+    }
+}
+
+impl<'c, T: ChannelElement> Iterator for Tiles<'c, T> {
+    type Item = Result<Tile<T>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done || self.next_y >= self.tiles_y {
+            return None;
+        }
+
+        let (tile_x, tile_y) = (self.next_x, self.next_y);
+        self.next_x += 1;
+        if self.next_x >= self.tiles_x {
+            self.next_x = 0;
+            self.next_y += 1;
+        }
+
+        let result = self.decode_tile(tile_x, tile_y);
+        if result.is_err() {
+            self.done = true;
+        }
+        Some(result)
+    }
+}
+
+/// A whole part decoded into a single interleaved buffer, created with
+/// [`ReadContext::read_image`]
+///
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImageBuffer<T> {
+    /// Width of the image in pixels
+    pub width: usize,
+    /// Height of the image in pixels
+    pub height: usize,
+    /// The part's data window, i.e. the coordinates of the first and last
+    /// pixels in the buffer
+    pub data_window: [i32; 4],
+    /// The names of the channels in the buffer, in order
+    pub channels: Vec<String>,
+    /// The pixels, a line at a time from the top of the data window, with the
+    /// channels interleaved
+    pub pixels: Vec<T>,
+}
+
+impl<T: ChannelElement> ImageBuffer<T> {
+    /// The interleaved channel values of the pixel at (`x`, `y`), relative to
+    /// the top-left corner of the data window
+    ///
+    /// # Panics
+    /// * If `x` or `y` are outside the image
+    ///
+    pub fn pixel(&self, x: usize, y: usize) -> &[T] {
+        assert!(x < self.width && y < self.height, "pixel out of bounds");
+        let n = self.channels.len();
+        let start = (y * self.width + x) * n;
+        &self.pixels[start..start + n]
+    }
+}
+
+/// The channels of a layer decoded into a separate buffer per channel,
+/// created with [`ReadContext::read_layer`]
+///
+#[derive(Debug, Clone, PartialEq)]
+pub struct LayerBuffer<T> {
+    /// Width of the image in pixels
+    pub width: usize,
+    /// Height of the image in pixels
+    pub height: usize,
+    /// The part's data window, i.e. the coordinates of the first and last
+    /// pixels in each plane
+    pub data_window: [i32; 4],
+    /// The full names of the channels in the layer, e.g. "diffuse.R", in the
+    /// same order as `planes`
+    pub channels: Vec<String>,
+    /// The pixels of each channel, a line at a time from the top of the data
+    /// window
+    pub planes: Vec<Vec<T>>,
+}
+
+impl<T: ChannelElement> LayerBuffer<T> {
+    /// The plane for the channel `name`, which may be either the full name,
+    /// e.g. "diffuse.R", or the name within the layer, e.g. "R"
+    ///
+    pub fn plane(&self, name: &str) -> Option<&[T]> {
+        self.channels
+            .iter()
+            .position(|c| c == name || split_layer(c).1 == name)
+            .map(|i| &self.planes[i][..])
+    }
+}
+
+/// The result of [`ReadContext::read_image_tolerant`]
+///
+#[derive(Debug, Clone, PartialEq)]
+pub struct TolerantImage<T> {
+    /// The decoded image, with the pixels of any chunks that could not be
+    /// decoded set to the fill value
+    pub image: ImageBuffer<T>,
+    /// The indices in the part's chunk table of the chunks that could not be
+    /// read or decoded
+    pub failed_chunks: Vec<usize>,
+}
+
+impl ReadContext {
+    /// Iterate over the lines of a scanline part, decoding the channels named
+    /// in `channels`, interleaved in that order and converted to `T`
+    ///
+    /// Requested channels that do not exist in the part are left at
+    /// `T::default()`.
+    ///
+    /// # Errors
+    /// * `[Error::TileScanMixedApi]` - If the part is not a scanline part
+    ///
+    pub fn scanline_rows<T: ChannelElement>(
+        &self,
+        part_index: usize,
+        channels: &[&str],
+    ) -> Result<ScanlineRows<'_, T>> {
+        if self.storage(part_index)? != Storage::Scanline {
+            return Err(Error::TileScanMixedApi);
+        }
+
+        let dw = self.data_window::<[i32; 4]>(part_index)?;
+
+        Ok(ScanlineRows {
+            ctx: self,
+            part_index,
+            channels: channels.iter().map(|c| c.to_string()).collect(),
+            pipeline: None,
+            chunk: Vec::new(),
+            chunk_start_y: dw[1],
+            chunk_height: 0,
+            row_in_chunk: 0,
+            width: (dw[2] - dw[0] + 1).max(0) as usize,
+            next_chunk_y: dw[1],
+            end_y: dw[3],
+            done: false,
+        })
+    }
+
+    /// Iterate over the tiles in level (`level_x`, `level_y`) of a tiled part,
+    /// decoding the channels named in `channels`, interleaved in that order
+    /// and converted to `T`
+    ///
+    /// Requested channels that do not exist in the part are left at
+    /// `T::default()`.
+    ///
+    /// # Errors
+    /// * `[Error::ScanTileMixedApi]` - If the part is not a tiled part
+    /// * `[Error::ArgumentOutOfRange]` - If the level does not exist
+    ///
+    pub fn tiles<T: ChannelElement>(
+        &self,
+        part_index: usize,
+        level_x: usize,
+        level_y: usize,
+        channels: &[&str],
+    ) -> Result<Tiles<'_, T>> {
+        if self.storage(part_index)? != Storage::Tiled {
+            return Err(Error::ScanTileMixedApi);
+        }
+
+        let (level_width, level_height) =
+            self.level_sizes(part_index, level_x, level_y)?;
+        let (tile_width, tile_height) =
+            self.tile_sizes(part_index, level_x, level_y)?;
+        if tile_width == 0 || tile_height == 0 {
+            return Err(Error::MissingReqAttr);
+        }
+
+        Ok(Tiles {
+            ctx: self,
+            part_index,
+            channels: channels.iter().map(|c| c.to_string()).collect(),
+            pipeline: None,
+            level_x: level_x as i32,
+            level_y: level_y as i32,
+            tiles_x: num_tiles(level_width, tile_width),
+            tiles_y: num_tiles(level_height, tile_height),
+            next_x: 0,
+            next_y: 0,
+            done: false,
+            marker: std::marker::PhantomData,
+        })
+    }
+
+    /// Decode the whole of a part into an interleaved buffer of the channels
+    /// named in `channels`, converted to `T`
+    ///
+    /// For tiled parts, this reads the highest-resolution level. Requested
+    /// channels that do not exist in the part are left at `T::default()`.
+    ///
+    /// # Errors
+    /// * `[Error::FeatureNotImplemented]` - If the part holds deep data
+    ///
+    pub fn read_image<T: ChannelElement>(
+        &self,
+        part_index: usize,
+        channels: &[&str],
+    ) -> Result<ImageBuffer<T>> {
+        self.read_image_with_progress(part_index, channels, |_| {
+            ControlFlow::Continue(())
+        })
+    }
+
+    /// Decode the whole of a part as for [`ReadContext::read_image`], calling
+    /// `progress` each time a chunk has been decoded
+    ///
+    /// # Errors
+    /// * `[Error::FeatureNotImplemented]` - If the part holds deep data
+    /// * `[Error::Cancelled]` - If `progress` returned
+    /// [`ControlFlow::Break`]
+    ///
+    pub fn read_image_with_progress<T, F>(
+        &self,
+        part_index: usize,
+        channels: &[&str],
+        mut progress: F,
+    ) -> Result<ImageBuffer<T>>
+    where
+        T: ChannelElement,
+        F: FnMut(Progress) -> ControlFlow<()>,
+    {
+        let dw = self.data_window::<[i32; 4]>(part_index)?;
+        let width = (dw[2] - dw[0] + 1).max(0) as usize;
+        let height = (dw[3] - dw[1] + 1).max(0) as usize;
+        let num_channels = channels.len();
+
+        let mut image = ImageBuffer {
+            width,
+            height,
+            data_window: dw,
+            channels: channels.iter().map(|c| c.to_string()).collect(),
+            pixels: vec![T::default(); width * height * num_channels],
+        };
+
+        match self.storage(part_index)? {
+            Storage::Scanline => {
+                let lines = self.scanlines_per_chunk(part_index)?.max(1);
+                let chunks_total = num_tiles(height, lines) as usize;
+                let mut tracker = Tracker::new(&mut progress, chunks_total);
+                self.read_scanlines_into(
+                    part_index,
+                    channels,
+                    &mut image,
+                    &mut tracker,
+                )?
+            }
+            Storage::Tiled => {
+                let line_len = width * num_channels;
+                let (tile_width, tile_height) =
+                    self.tile_sizes(part_index, 0, 0)?;
+                let chunks_total = num_tiles(width, tile_width) as usize
+                    * num_tiles(height, tile_height) as usize;
+                let mut tracker = Tracker::new(&mut progress, chunks_total);
+                for tile in self.tiles::<T>(part_index, 0, 0, channels)? {
+                    let tile = tile?;
+                    let x = tile.x as usize * tile_width * num_channels;
+                    let tile_line_len = tile.width as usize * num_channels;
+                    if tile_line_len == 0 {
+                        continue;
+                    }
+                    for (row, src) in
+                        tile.pixels.chunks_exact(tile_line_len).enumerate()
+                    {
+                        let y = tile.y as usize * tile_height + row;
+                        let start = y * line_len + x;
+                        image.pixels[start..start + tile_line_len]
+                            .copy_from_slice(src);
+                    }
+                    tracker.chunk_done()?;
+                }
+            }
+            Storage::DeepScanline | Storage::DeepTiled => {
+                return Err(Error::FeatureNotImplemented)
+            }
+        }
+
+        Ok(image)
+    }
+
+    /// Decode every channel in the layer `layer` of a part, e.g. "diffuse"
+    /// for "diffuse.R", "diffuse.G" and so on, into a separate buffer per
+    /// channel, converted to `T`
+    ///
+    /// An empty `layer` reads the channels with no layer prefix. For tiled
+    /// parts, this reads the highest-resolution level.
+    ///
+    /// # Errors
+    /// * `[Error::NoAttrByName]` - If there are no channels in the layer
+    /// * `[Error::FeatureNotImplemented]` - If the part holds deep data
+    ///
+    pub fn read_layer<T: ChannelElement>(
+        &self,
+        part_index: usize,
+        layer: &str,
+    ) -> Result<LayerBuffer<T>> {
+        let wanted = if layer.is_empty() { None } else { Some(layer) };
+        let names = self
+            .channels(part_index)?
+            .descs()?
+            .into_iter()
+            .map(|c| c.name)
+            .filter(|n| split_layer(n).0 == wanted)
+            .collect::<Vec<_>>();
+        if names.is_empty() {
+            return Err(Error::NoAttrByName);
+        }
+
+        let channels = names.iter().map(|n| n.as_str()).collect::<Vec<_>>();
+        let image = self.read_image::<T>(part_index, &channels)?;
+
+        let num_channels = names.len();
+        let planes = (0..num_channels)
+            .map(|c| {
+                image.pixels[c..]
+                    .iter()
+                    .step_by(num_channels)
+                    .copied()
+                    .collect()
+            })
+            .collect();
+
+        Ok(LayerBuffer {
+            width: image.width,
+            height: image.height,
+            data_window: image.data_window,
+            channels: names,
+            planes,
+        })
+    }
+
+    /// Decode as much of a part as possible, as for
+    /// [`read_image`](ReadContext::read_image), skipping any chunks that are
+    /// missing or corrupt
+    ///
+    /// The pixels of chunks that can't be decoded are set to `fill` in every
+    /// channel, and the chunks are listed in the result. For tiled parts,
+    /// this reads the highest-resolution level.
+    ///
+    /// # Errors
+    /// Problems with individual chunks are not errors. An error is only
+    /// returned if the part's header can't be queried.
+    /// * `[Error::FeatureNotImplemented]` - If the part holds deep data
+    ///
+    pub fn read_image_tolerant<T: ChannelElement>(
+        &self,
+        part_index: usize,
+        channels: &[&str],
+        fill: T,
+    ) -> Result<TolerantImage<T>> {
+        let dw = self.data_window::<[i32; 4]>(part_index)?;
+        let width = (dw[2] - dw[0] + 1).max(0) as usize;
+        let height = (dw[3] - dw[1] + 1).max(0) as usize;
+
+        // the region of the image each chunk covers, as x, y, width, height,
+        // and how to find it
+        let mut chunks = Vec::new();
+        match self.storage(part_index)? {
+            Storage::Scanline => {
+                let lines = self.scanlines_per_chunk(part_index)?.max(1);
+                for y in (0..height).step_by(lines) {
+                    let region = (0, y, width, lines.min(height - y));
+                    chunks.push((region, (dw[1] + y as i32, 0)));
+                }
+            }
+            Storage::Tiled => {
+                let (tile_width, tile_height) =
+                    self.tile_sizes(part_index, 0, 0)?;
+                for tile_y in 0..num_tiles(height, tile_height) {
+                    for tile_x in 0..num_tiles(width, tile_width) {
+                        let x = tile_x as usize * tile_width;
+                        let y = tile_y as usize * tile_height;
+                        let region = (
+                            x,
+                            y,
+                            tile_width.min(width - x),
+                            tile_height.min(height - y),
+                        );
+                        chunks.push((region, (tile_x, tile_y)));
+                    }
+                }
+            }
+            Storage::DeepScanline | Storage::DeepTiled => {
+                return Err(Error::FeatureNotImplemented)
+            }
+        }
+        let tiled = self.storage(part_index)? == Storage::Tiled;
+
+        let mut result = TolerantImage {
+            image: ImageBuffer {
+                width,
+                height,
+                data_window: dw,
+                channels: channels.iter().map(|c| c.to_string()).collect(),
+                pixels: vec![T::default(); width * height * channels.len()],
+            },
+            failed_chunks: Vec::new(),
+        };
+
+        let mut pipeline = None;
+        for (index, ((x, y, w, h), (a, b))) in chunks.into_iter().enumerate() {
+            let chunk_info = if tiled {
+                self.read_tile_chunk_info(part_index, a, b, 0, 0)
+            } else {
+                self.read_scanline_chunk_info(part_index, a)
+            };
+            let decoded = chunk_info.and_then(|chunk_info: ChunkInfo| {
+                decode_chunk_into(
+                    self,
+                    &mut pipeline,
+                    part_index,
+                    &chunk_info,
+                    None,
+                    channels,
+                    &mut result.image,
+                    (x, y),
+                )
+            });
+
+            if decoded.is_err() {
+                // start again with a fresh pipeline, in case the failure left
+                // the old one in a bad state
+                pipeline = None;
+                result.failed_chunks.push(index);
+
+                let line_len = width * channels.len();
+                for row in y..y + h {
+                    let start = row * line_len + x * channels.len();
+                    result.image.pixels[start..start + w * channels.len()]
+                        .iter_mut()
+                        .for_each(|v| *v = fill);
+                }
+            }
+        }
+
+        Ok(result)
+    }
+
+    /// Decode each chunk of a scanline part straight into its lines in
+    /// `image`
+    ///
+    fn read_scanlines_into<T: ChannelElement>(
+        &self,
+        part_index: usize,
+        channels: &[&str],
+        image: &mut ImageBuffer<T>,
+        tracker: &mut Tracker,
+    ) -> Result<()> {
+        let line_len = image.width * channels.len();
+        let mut pipeline: Option<DecodePipeline<Runnable>> = None;
+        let mut y = image.data_window[1];
+
+        while y <= image.data_window[3] {
+            let chunk_info = self.read_scanline_chunk_info(part_index, y)?;
+
+            let mut ready = match pipeline.take() {
+                Some(p) => p.update(&chunk_info)?,
+                None => self.decoding_initialize(part_index, &chunk_info)?,
+            };
+
+            let start = (chunk_info.start_y - image.data_window[1]).max(0)
+                as usize
+                * line_len;
+            let end = start + chunk_info.height.max(0) as usize * line_len;
+            let dest = image
+                .pixels
+                .get_mut(start..end)
+                .ok_or(Error::CorruptChunk)?;
+
+            ready.select_interleaved(channels, dest)?;
+            let mut runnable = ready.choose_default_routines()?;
+            runnable.run_checked(dest)?;
+            pipeline = Some(runnable);
+            tracker.chunk_done()?;
+
+            y = chunk_info.start_y + chunk_info.height;
+        }
+
+        Ok(())
+    }
+}
+
+/// Decode the chunk described by `chunk_info` into `image`, with its top-left
+/// pixel at `origin`, from `packed_data` if it has already been read
+///
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn decode_chunk_into<'c, T: ChannelElement>(
+    ctx: &'c ReadContext,
+    pipeline: &mut Option<DecodePipeline<'c, Runnable>>,
+    part_index: usize,
+    chunk_info: &ChunkInfo,
+    packed_data: Option<&mut [u8]>,
+    channels: &[&str],
+    image: &mut ImageBuffer<T>,
+    origin: (usize, usize),
+) -> Result<()> {
+    let num_channels = channels.len();
+    let width = chunk_info.width.max(0) as usize;
+    let height = chunk_info.height.max(0) as usize;
+    let mut pixels = vec![T::default(); width * height * num_channels];
+
+    let mut ready = match pipeline.take() {
+        Some(p) => p.update(chunk_info)?,
+        None => ctx.decoding_initialize(part_index, chunk_info)?,
+    };
+    ready.select_interleaved(channels, &mut pixels)?;
+    let mut runnable = ready.choose_default_routines()?;
+    match packed_data {
+        Some(packed_data) => {
+            runnable.run_packed_checked(packed_data, &mut [], &mut pixels)?
+        }
+        None => runnable.run_checked(&mut pixels)?,
+    }
+    *pipeline = Some(runnable);
+
+    let line_len = image.width * num_channels;
+    let chunk_line_len = width * num_channels;
+    if chunk_line_len == 0 {
+        return Ok(());
+    }
+    for (row, src) in pixels.chunks_exact(chunk_line_len).enumerate() {
+        let start = (origin.1 + row) * line_len + origin.0 * num_channels;
+        image
+            .pixels
+            .get_mut(start..start + chunk_line_len)
+            .ok_or(Error::CorruptChunk)?
+            .copy_from_slice(src);
+    }
+
+    Ok(())
+}
+
+/// Every chunk of the highest-resolution level of a flat part, along with the
+/// position of its top-left pixel relative to the data window
+///
+/// # Errors
+/// * `[Error::FeatureNotImplemented]` - If the part holds deep data
+///
+pub(crate) fn part_chunks(
+    ctx: &ReadContext,
+    part_index: usize,
+) -> Result<Vec<(ChunkInfo, (usize, usize))>> {
+    let dw = ctx.data_window::<[i32; 4]>(part_index)?;
+    let width = (dw[2] - dw[0] + 1).max(0) as usize;
+    let height = (dw[3] - dw[1] + 1).max(0) as usize;
+
+    let mut chunks = Vec::new();
+    match ctx.storage(part_index)? {
+        Storage::Scanline => {
+            let lines = ctx.scanlines_per_chunk(part_index)?.max(1);
+            for y in (dw[1]..=dw[3]).step_by(lines) {
+                let chunk_info = ctx.read_scanline_chunk_info(part_index, y)?;
+                let y = (chunk_info.start_y - dw[1]) as usize;
+                chunks.push((chunk_info, (0, y)));
+            }
+        }
+        Storage::Tiled => {
+            let (tile_width, tile_height) = ctx.tile_sizes(part_index, 0, 0)?;
+            for tile_y in 0..num_tiles(height, tile_height) {
+                for tile_x in 0..num_tiles(width, tile_width) {
+                    let chunk_info = ctx.read_tile_chunk_info(
+                        part_index, tile_x, tile_y, 0, 0,
+                    )?;
+                    chunks.push((
+                        chunk_info,
+                        (
+                            tile_x as usize * tile_width,
+                            tile_y as usize * tile_height,
+                        ),
+                    ));
+                }
+            }
+        }
+        Storage::DeepScanline | Storage::DeepTiled => {
+            return Err(Error::FeatureNotImplemented)
+        }
+    }
+
+    Ok(chunks)
+}
+
+/// Number of tiles of size `tile_size` needed to cover `level_size` pixels
+///
+pub(crate) fn num_tiles(level_size: usize, tile_size: usize) -> i32 {
+    (level_size / tile_size + usize::from(level_size % tile_size != 0)) as i32
+}
+
+#[cfg(test)]
+mod tests {
+    use crate as exr;
+    use imath_traits::f16;
+    use std::path::Path;
+
+    #[test]
+    fn scanline_rows() -> Result<(), exr::Error> {
+        let path_ferris = Path::new(
+            &std::env::var("CARGO_MANIFEST_DIR")
+                .expect("CARGO_MANIFEST_DIR not set"),
+        )
+        .join("images")
+        .join("ferris.exr");
+
+        let ctx = exr::context::ReadContext::new(&path_ferris)?;
+
+        let mut num_rows = 0;
+        for (i, row) in ctx
+            .scanline_rows::<f16>(0, &["R", "G", "B", "A"])?
+            .enumerate()
+        {
+            let row = row?;
+            assert_eq!(row.y, i as i32);
+            assert_eq!(row.pixels.len(), 1200 * 4);
+            num_rows += 1;
+        }
+        assert_eq!(num_rows, 800);
+
+        Ok(())
+    }
+
+    #[test]
+    fn read_image() -> Result<(), exr::Error> {
+        let path_ferris = Path::new(
+            &std::env::var("CARGO_MANIFEST_DIR")
+                .expect("CARGO_MANIFEST_DIR not set"),
+        )
+        .join("images")
+        .join("ferris.exr");
+
+        let ctx = exr::context::ReadContext::new(&path_ferris)?;
+
+        let image = ctx.read_image::<f16>(0, &["R", "G", "B", "A"])?;
+        assert_eq!(image.width, 1200);
+        assert_eq!(image.height, 800);
+        assert_eq!(image.data_window, [0, 0, 1199, 799]);
+        assert_eq!(image.pixels.len(), 1200 * 800 * 4);
+
+        let row = ctx
+            .scanline_rows::<f16>(0, &["R", "G", "B", "A"])?
+            .nth(400)
+            .unwrap()?;
+        assert_eq!(
+            row.pixels[..],
+            image.pixels[400 * 1200 * 4..401 * 1200 * 4]
+        );
+        assert_eq!(image.pixel(3, 400), &row.pixels[12..16]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn read_layer() -> Result<(), exr::Error> {
+        let path_ferris = Path::new(
+            &std::env::var("CARGO_MANIFEST_DIR")
+                .expect("CARGO_MANIFEST_DIR not set"),
+        )
+        .join("images")
+        .join("ferris.exr");
+
+        let ctx = exr::context::ReadContext::new(&path_ferris)?;
+
+        let layer = ctx.read_layer::<f16>(0, "")?;
+        assert_eq!(layer.channels, ["A", "B", "G", "R"]);
+        assert_eq!(layer.planes.len(), 4);
+
+        let image = ctx.read_image::<f16>(0, &["R"])?;
+        assert_eq!(layer.plane("R"), Some(&image.pixels[..]));
+
+        assert_eq!(
+            ctx.read_layer::<f16>(0, "diffuse"),
+            Err(exr::Error::NoAttrByName)
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn read_image_tolerant() -> Result<(), exr::Error> {
+        let path_ferris = Path::new(
+            &std::env::var("CARGO_MANIFEST_DIR")
+                .expect("CARGO_MANIFEST_DIR not set"),
+        )
+        .join("images")
+        .join("ferris.exr");
+
+        let ctx = exr::context::ReadContext::new(&path_ferris)?;
+        let channels = ["R", "G", "B", "A"];
+        let fill = f16::from_f32(-1.0);
+
+        let result = ctx.read_image_tolerant(0, &channels, fill)?;
+        assert!(result.failed_chunks.is_empty());
+        assert_eq!(result.image, ctx.read_image(0, &channels)?);
+
+        // chop the end off the file, so the last chunks are missing
+        let mut bytes = std::fs::read(&path_ferris).unwrap();
+        bytes.truncate(bytes.len() - 1000);
+        let ctx = exr::context::ReadContext::from_memory(bytes)?;
+
+        let result = ctx.read_image_tolerant(0, &channels, fill)?;
+        assert!(!result.failed_chunks.is_empty());
+        assert_eq!(result.image.pixel(0, 799), &[fill; 4]);
+
+        Ok(())
+    }
+}
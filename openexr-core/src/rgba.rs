@@ -0,0 +1,662 @@
+//! Simple reading and writing of RGBA images, in the spirit of the C++
+//! library's `RgbaInputFile` and `RgbaOutputFile`.
+//!
+//! These cover the common case of wanting the colour of an image without
+//! having to deal with parts, chunks or decode pipelines.
+//!
+use crate::attr::{split_layer, Compression, PixelType, Preview, Storage};
+use crate::context::{
+    Context, ContextState, DefaultWriteMode, ReadContext, WriteHeaderContext,
+};
+use crate::encode::EncodePipeline;
+use crate::error::{Error, ErrorKind};
+use std::convert::TryInto;
+use std::io::{Seek, Write};
+use std::path::Path;
+
+use imath_traits::f16;
+
+type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// A single RGBA pixel
+///
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub struct Rgba<T> {
+    pub r: T,
+    pub g: T,
+    pub b: T,
+    pub a: T,
+}
+
+impl<T> Rgba<T> {
+    pub fn new(r: T, g: T, b: T, a: T) -> Rgba<T> {
+        Rgba { r, g, b, a }
+    }
+}
+
+/// An image with 8 bits per channel, such as a preview thumbnail, ready to be
+/// displayed
+///
+#[derive(Debug, Clone, PartialEq)]
+pub struct RgbaImage8 {
+    pub width: usize,
+    pub height: usize,
+    /// The pixels, a line at a time from the top of the image
+    pub pixels: Vec<Rgba<u8>>,
+}
+
+impl RgbaImage8 {
+    /// The pixel at (`x`, `y`)
+    ///
+    /// # Panics
+    /// * If `x` or `y` are outside the image
+    ///
+    pub fn pixel(&self, x: usize, y: usize) -> Rgba<u8> {
+        assert!(x < self.width && y < self.height, "pixel out of bounds");
+        self.pixels[y * self.width + x]
+    }
+}
+
+impl<'a> From<Preview<'a>> for RgbaImage8 {
+    fn from(preview: Preview<'a>) -> RgbaImage8 {
+        RgbaImage8 {
+            width: preview.width as usize,
+            height: preview.height as usize,
+            pixels: preview
+                .rgba
+                .chunks_exact(4)
+                .map(|p| Rgba::new(p[0], p[1], p[2], p[3]))
+                .collect(),
+        }
+    }
+}
+
+impl<S: ContextState> Context<S> {
+    /// Get a copy of the preview thumbnail of the specified part, or `None` if
+    /// it doesn't have one
+    ///
+    /// # Errors
+    /// * `[Error::AttrTypeMismatch]` - If the `preview` attribute is not a
+    /// preview
+    ///
+    pub fn preview_image(
+        &self,
+        part_index: usize,
+    ) -> Result<Option<RgbaImage8>> {
+        match self.preview(part_index) {
+            Ok(preview) => Ok(Some(preview.into())),
+            Err(e) if e.kind() == ErrorKind::NoAttrByName => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+/// Reads the R, G, B and A channels of the first part of a file
+///
+pub struct RgbaReader {
+    ctx: ReadContext,
+    part_index: usize,
+    layer: Option<String>,
+    // full names of the R, G, B and A channels, in that order, if present
+    channels: [Option<String>; 4],
+}
+
+impl RgbaReader {
+    /// Open the file at `path` for reading its RGBA channels
+    ///
+    /// The unprefixed R, G, B and A channels are used if the file has any of
+    /// them, otherwise those of the first layer that does, e.g. "beauty.R".
+    ///
+    /// # Errors
+    /// * `[Error::NoAttrByName]` - If no R, G or B channels could be found
+    ///
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<RgbaReader> {
+        let ctx = ReadContext::new(path)?;
+        let names = ctx
+            .channels(0)?
+            .descs()?
+            .into_iter()
+            .map(|c| c.name)
+            .collect::<Vec<_>>();
+
+        let layer = if names.iter().any(|n| is_rgb(n, None)) {
+            None
+        } else {
+            names.iter().find_map(|n| {
+                let (layer, _) = split_layer(n);
+                layer.filter(|l| is_rgb(n, Some(l))).map(|l| l.to_string())
+            })
+        };
+
+        Self::with_layer(ctx, &names, layer)
+    }
+
+    /// Open the file at `path` for reading the RGBA channels of `layer`, e.g.
+    /// "diffuse" for the channels "diffuse.R", "diffuse.G" etc.
+    ///
+    /// # Errors
+    /// * `[Error::NoAttrByName]` - If the layer has no R, G or B channels
+    ///
+    pub fn open_layer<P: AsRef<Path>>(
+        path: P,
+        layer: &str,
+    ) -> Result<RgbaReader> {
+        let ctx = ReadContext::new(path)?;
+        let names = ctx
+            .channels(0)?
+            .descs()?
+            .into_iter()
+            .map(|c| c.name)
+            .collect::<Vec<_>>();
+
+        Self::with_layer(ctx, &names, Some(layer.to_string()))
+    }
+
+    fn with_layer(
+        ctx: ReadContext,
+        names: &[String],
+        layer: Option<String>,
+    ) -> Result<RgbaReader> {
+        let find = |base: &str| {
+            let full = match &layer {
+                Some(l) => format!("{}.{}", l, base),
+                None => base.to_string(),
+            };
+            names.iter().find(|n| **n == full).cloned()
+        };
+        let channels = [find("R"), find("G"), find("B"), find("A")];
+
+        if channels[..3].iter().all(|c| c.is_none()) {
+            return Err(Error::NoAttrByName);
+        }
+
+        Ok(RgbaReader {
+            ctx,
+            part_index: 0,
+            layer,
+            channels,
+        })
+    }
+
+    /// The layer the channels are read from, or `None` for the unprefixed
+    /// channels
+    ///
+    pub fn layer(&self) -> Option<&str> {
+        self.layer.as_deref()
+    }
+
+    /// Does the image have an alpha channel? If not, alpha is read as 1.0
+    ///
+    pub fn has_alpha(&self) -> bool {
+        self.channels[3].is_some()
+    }
+
+    /// The data window of the image
+    ///
+    pub fn data_window(&self) -> Result<[i32; 4]> {
+        self.ctx.data_window(self.part_index)
+    }
+
+    /// The underlying context, for reading anything else from the file
+    ///
+    pub fn context(&self) -> &ReadContext {
+        &self.ctx
+    }
+
+    /// Read the whole image, a line at a time from the top of the data window
+    ///
+    /// Missing colour channels are read as 0.0 and missing alpha as 1.0
+    ///
+    pub fn read(&self) -> Result<Vec<Rgba<f16>>> {
+        // Channels that aren't present are given a name that can't match
+        // anything, so that they are left at the default value
+        let names = self
+            .channels
+            .iter()
+            .map(|c| c.as_deref().unwrap_or(""))
+            .collect::<Vec<_>>();
+
+        let image = self.ctx.read_image::<f16>(self.part_index, &names)?;
+        let alpha = if self.has_alpha() {
+            None
+        } else {
+            Some(f16::from_f32(1.0))
+        };
+
+        Ok(image
+            .pixels
+            .chunks_exact(4)
+            .map(|p| Rgba::new(p[0], p[1], p[2], alpha.unwrap_or(p[3])))
+            .collect())
+    }
+}
+
+/// Settings for writing with [`RgbaWriter::write_with_options`]
+///
+#[derive(Debug, Clone, PartialEq)]
+pub struct RgbaWriteOptions {
+    pub compression: Compression,
+    /// Generate a preview thumbnail of the image no larger than this in
+    /// either dimension, or `None` to not write a preview
+    pub preview_size: Option<usize>,
+}
+
+impl RgbaWriteOptions {
+    pub fn new(compression: Compression) -> RgbaWriteOptions {
+        RgbaWriteOptions {
+            compression,
+            preview_size: None,
+        }
+    }
+
+    /// Write a preview thumbnail no larger than `max_size` in either
+    /// dimension, generated from the pixels with [`make_preview`]
+    ///
+    pub fn preview(mut self, max_size: usize) -> RgbaWriteOptions {
+        self.preview_size = Some(max_size);
+        self
+    }
+}
+
+/// Writes RGBA images to single-part scanline files
+///
+pub struct RgbaWriter;
+
+impl RgbaWriter {
+    /// Write `pixels`, a line at a time from the top of the image, as a
+    /// `width` by `height` image with half-float R, G, B and A channels
+    ///
+    /// # Errors
+    /// * `[Error::InvalidArgument]` - If `pixels` does not hold exactly
+    /// `width * height` pixels
+    ///
+    /// # Panics
+    /// * If `path` is not valid UTF-8 or contains null bytes
+    ///
+    pub fn write<P: AsRef<Path>>(
+        path: P,
+        width: usize,
+        height: usize,
+        pixels: &[Rgba<f16>],
+        compression: Compression,
+    ) -> Result<()> {
+        Self::write_with_options(
+            path,
+            width,
+            height,
+            pixels,
+            &RgbaWriteOptions::new(compression),
+        )
+    }
+
+    /// Write `pixels` as for [`RgbaWriter::write`], with the settings in
+    /// `options`
+    ///
+    /// # Errors
+    /// * `[Error::InvalidArgument]` - If `pixels` does not hold exactly
+    /// `width * height` pixels, or the preview size is 0
+    ///
+    /// # Panics
+    /// * If `path` is not valid UTF-8 or contains null bytes
+    ///
+    pub fn write_with_options<P: AsRef<Path>>(
+        path: P,
+        width: usize,
+        height: usize,
+        pixels: &[Rgba<f16>],
+        options: &RgbaWriteOptions,
+    ) -> Result<()> {
+        check_size(width, height, pixels)?;
+        let header_ctx =
+            WriteHeaderContext::new(path, DefaultWriteMode::WriteFileDirectly)?;
+        Self::write_with(header_ctx, width, height, pixels, options)
+    }
+
+    /// Write `pixels` to `writer`, as for [`RgbaWriter::write`]
+    ///
+    /// # Errors
+    /// * `[Error::InvalidArgument]` - If `pixels` does not hold exactly
+    /// `width * height` pixels
+    /// * `[Error::WriteIo]` - If the writer fails
+    ///
+    pub fn write_to<W: Write + Seek + Send + 'static>(
+        writer: W,
+        width: usize,
+        height: usize,
+        pixels: &[Rgba<f16>],
+        compression: Compression,
+    ) -> Result<()> {
+        Self::write_to_with_options(
+            writer,
+            width,
+            height,
+            pixels,
+            &RgbaWriteOptions::new(compression),
+        )
+    }
+
+    /// Write `pixels` to `writer` as for [`RgbaWriter::write`], with the
+    /// settings in `options`
+    ///
+    /// # Errors
+    /// * `[Error::InvalidArgument]` - If `pixels` does not hold exactly
+    /// `width * height` pixels, or the preview size is 0
+    /// * `[Error::WriteIo]` - If the writer fails
+    ///
+    pub fn write_to_with_options<W: Write + Seek + Send + 'static>(
+        writer: W,
+        width: usize,
+        height: usize,
+        pixels: &[Rgba<f16>],
+        options: &RgbaWriteOptions,
+    ) -> Result<()> {
+        check_size(width, height, pixels)?;
+        let header_ctx = WriteHeaderContext::from_writer(writer)?;
+        Self::write_with(header_ctx, width, height, pixels, options)
+    }
+
+    fn write_with(
+        mut header_ctx: WriteHeaderContext,
+        width: usize,
+        height: usize,
+        pixels: &[Rgba<f16>],
+        options: &RgbaWriteOptions,
+    ) -> Result<()> {
+        let h: i32 = height.try_into().map_err(|_| Error::InvalidArgument)?;
+
+        let part_index = header_ctx.add_part("", Storage::Scanline)?;
+        header_ctx.initialize_required_attributes_simple(
+            part_index,
+            width,
+            height,
+            options.compression,
+        )?;
+        for name in &["R", "G", "B", "A"] {
+            header_ctx.add_channel(
+                part_index,
+                name,
+                PixelType::Half,
+                false,
+                1,
+                1,
+            )?;
+        }
+
+        if let Some(max_size) = options.preview_size {
+            let preview = make_preview(width, height, pixels, max_size)?;
+            header_ctx.set_preview_from_image(
+                part_index,
+                preview.width,
+                preview.height,
+                &preview.pixels,
+            )?;
+        }
+
+        let ctx = header_ctx.write_header()?;
+
+        let mut encoder = EncodePipeline::default();
+        let mut initialized = false;
+        let mut y = 0;
+        while y < h {
+            let chunk_info = ctx.write_scanline_chunk_info(part_index, y)?;
+            if initialized {
+                ctx.encoding_update(part_index, &chunk_info, &mut encoder)?;
+            } else {
+                ctx.encoding_initialize(part_index, &chunk_info, &mut encoder)?;
+                initialized = true;
+            }
+
+            // the chunk's lines are always within the image, so the
+            // pointers set up here stay in bounds of pixels
+            let first_pixel = chunk_info.start_y as usize * width;
+            let chunk_pixels = &pixels
+                [first_pixel..first_pixel + chunk_info.height as usize * width];
+            for channel in encoder.channels_mut() {
+                let channel_ptr = match channel.name() {
+                    "R" => &chunk_pixels[0].r,
+                    "G" => &chunk_pixels[0].g,
+                    "B" => &chunk_pixels[0].b,
+                    _ => &chunk_pixels[0].a,
+                } as *const f16 as *const u8;
+                unsafe { channel.set_encode_from(channel_ptr) };
+                channel.set_user_data_type(PixelType::Half);
+                channel.set_user_bytes_per_element(2);
+                channel.set_user_pixel_stride(std::mem::size_of::<Rgba<f16>>());
+                channel.set_user_line_stride(
+                    std::mem::size_of::<Rgba<f16>>() * width,
+                );
+            }
+
+            ctx.encoding_choose_default_routines(part_index, &mut encoder)?;
+            unsafe { ctx.encoding_run(part_index, &mut encoder)? };
+
+            y = chunk_info.start_y + chunk_info.height;
+        }
+
+        ctx.encoding_destroy(encoder)?;
+        ctx.close()
+    }
+}
+
+/// Generate an 8-bit preview thumbnail, no larger than `max_size` in either
+/// dimension, of the `width` by `height` image in `pixels`
+///
+/// Each preview pixel is the average of the pixels it covers, tone mapped as
+/// the `exrmakepreview` tool does, with a gamma of 2.2 and a soft knee to
+/// bring values above 1.0 into range.
+///
+/// # Errors
+/// * `[Error::InvalidArgument]` - If `pixels` does not hold exactly
+/// `width * height` pixels, or `max_size` is 0
+///
+pub fn make_preview(
+    width: usize,
+    height: usize,
+    pixels: &[Rgba<f16>],
+    max_size: usize,
+) -> Result<RgbaImage8> {
+    check_size(width, height, pixels)?;
+    if max_size == 0 {
+        return Err(Error::InvalidArgument);
+    }
+
+    // keep the aspect ratio, scaling only ever down
+    let scale = (max_size as f64 / width.max(height) as f64).min(1.0);
+    let preview_width = ((width as f64 * scale).round() as usize).max(1);
+    let preview_height = ((height as f64 * scale).round() as usize).max(1);
+
+    let mut preview = Vec::with_capacity(preview_width * preview_height);
+    for py in 0..preview_height {
+        let y0 = py * height / preview_height;
+        let y1 = ((py + 1) * height / preview_height).max(y0 + 1);
+        for px in 0..preview_width {
+            let x0 = px * width / preview_width;
+            let x1 = ((px + 1) * width / preview_width).max(x0 + 1);
+
+            let mut sum = [0.0f32; 4];
+            for row in pixels[y0 * width..y1 * width].chunks_exact(width) {
+                for p in &row[x0..x1] {
+                    sum[0] += p.r.to_f32();
+                    sum[1] += p.g.to_f32();
+                    sum[2] += p.b.to_f32();
+                    sum[3] += p.a.to_f32();
+                }
+            }
+            let count = ((x1 - x0) * (y1 - y0)) as f32;
+
+            preview.push(Rgba::new(
+                tone_map(sum[0] / count),
+                tone_map(sum[1] / count),
+                tone_map(sum[2] / count),
+                (sum[3] / count * 255.0).round().clamp(0.0, 255.0) as u8,
+            ));
+        }
+    }
+
+    Ok(RgbaImage8 {
+        width: preview_width,
+        height: preview_height,
+        pixels: preview,
+    })
+}
+
+/// Map a linear value to an 8-bit display value, as `exrmakepreview` does
+/// with an exposure of 0
+///
+fn tone_map(value: f32) -> u8 {
+    // 2^2.47393
+    const EXPOSURE_SCALE: f32 = 5.554_57;
+    const KNEE: f32 = 0.184_874;
+
+    // NaNs are treated as 0 by the max
+    let mut x = (value * EXPOSURE_SCALE).max(0.0);
+    if x > 1.0 {
+        x = 1.0 + (((x - 1.0) * KNEE) + 1.0).ln() / KNEE;
+    }
+    (x.powf(0.4545) * 84.66).clamp(0.0, 255.0) as u8
+}
+
+/// Check `pixels` holds a whole `width` by `height` image, before we create
+/// anything to write it to
+///
+fn check_size(width: usize, height: usize, pixels: &[Rgba<f16>]) -> Result<()> {
+    if width == 0 || height == 0 || pixels.len() != width * height {
+        return Err(Error::InvalidArgument);
+    }
+    Ok(())
+}
+
+/// Is `name` one of the R, G or B channels of `layer`?
+///
+fn is_rgb(name: &str, layer: Option<&str>) -> bool {
+    let (l, base) = split_layer(name);
+    l == layer && matches!(base, "R" | "G" | "B")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate as exr;
+    use exr::rgba::{Rgba, RgbaReader, RgbaWriter};
+    use imath_traits::f16;
+    use std::path::Path;
+
+    #[test]
+    fn rgba_reader() -> Result<(), exr::Error> {
+        let path_ferris = Path::new(
+            &std::env::var("CARGO_MANIFEST_DIR")
+                .expect("CARGO_MANIFEST_DIR not set"),
+        )
+        .join("images")
+        .join("ferris.exr");
+
+        let reader = RgbaReader::open(&path_ferris)?;
+        assert_eq!(reader.layer(), None);
+        assert!(reader.has_alpha());
+        assert_eq!(reader.data_window()?, [0, 0, 1199, 799]);
+
+        let pixels = reader.read()?;
+        assert_eq!(pixels.len(), 1200 * 800);
+
+        Ok(())
+    }
+
+    #[test]
+    fn rgba_roundtrip() -> Result<(), exr::Error> {
+        let (width, height) = (64, 48);
+        let pixels = (0..width * height)
+            .map(|i| {
+                let v = f16::from_f32(i as f32 / (width * height) as f32);
+                Rgba::new(v, v, v, f16::from_f32(1.0))
+            })
+            .collect::<Vec<_>>();
+
+        let path = std::env::temp_dir().join("rgba_roundtrip.exr");
+        RgbaWriter::write(
+            &path,
+            width,
+            height,
+            &pixels,
+            exr::attr::Compression::Zip,
+        )?;
+
+        let reader = RgbaReader::open(&path)?;
+        assert_eq!(reader.data_window()?, [0, 0, 63, 47]);
+        assert_eq!(reader.read()?, pixels);
+
+        Ok(())
+    }
+
+    #[test]
+    fn rgba_preview() -> Result<(), exr::Error> {
+        use exr::rgba::{make_preview, RgbaWriteOptions};
+
+        let (width, height) = (64, 48);
+        let mut pixels = vec![
+            Rgba::new(
+                f16::from_f32(0.0),
+                f16::from_f32(0.0),
+                f16::from_f32(0.0),
+                f16::from_f32(1.0)
+            );
+            width * height
+        ];
+        // make the right half bright
+        for row in pixels.chunks_exact_mut(width) {
+            for p in &mut row[width / 2..] {
+                *p = Rgba::new(p.a, p.a, p.a, p.a);
+            }
+        }
+
+        let preview = make_preview(width, height, &pixels, 16)?;
+        assert_eq!((preview.width, preview.height), (16, 12));
+        assert_eq!(preview.pixel(0, 0), Rgba::new(0, 0, 0, 255));
+        let bright = preview.pixel(15, 11);
+        assert!(bright.r > 128 && bright.r == bright.g && bright.a == 255);
+        assert_eq!(
+            make_preview(width, height, &pixels, 0),
+            Err(exr::Error::InvalidArgument)
+        );
+
+        let path = std::env::temp_dir().join("rgba_preview.exr");
+        RgbaWriter::write_with_options(
+            &path,
+            width,
+            height,
+            &pixels,
+            &RgbaWriteOptions::new(exr::attr::Compression::Zip).preview(16),
+        )?;
+
+        let reader = RgbaReader::open(&path)?;
+        assert_eq!(reader.context().preview_image(0)?, Some(preview));
+        assert_eq!(reader.read()?, pixels);
+
+        Ok(())
+    }
+
+    #[test]
+    fn rgba_write_to() -> Result<(), exr::Error> {
+        let (width, height) = (32, 16);
+        let pixels = (0..width * height)
+            .map(|i| {
+                let v = f16::from_f32(i as f32 / (width * height) as f32);
+                Rgba::new(v, v, v, v)
+            })
+            .collect::<Vec<_>>();
+
+        let path = std::env::temp_dir().join("rgba_write_to.exr");
+        let file = std::fs::File::create(&path).unwrap();
+        RgbaWriter::write_to(
+            file,
+            width,
+            height,
+            &pixels,
+            exr::attr::Compression::Piz,
+        )?;
+
+        let reader = RgbaReader::open(&path)?;
+        assert_eq!(reader.read()?, pixels);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,405 @@
+//! Typed access to the optional attributes defined by the OpenEXR
+//! specification, so their names and types don't need to be spelled out by
+//! hand.
+//!
+//! ```no_run
+//! # fn main() -> Result<(), openexr_core::Error> {
+//! let ctx = openexr_core::context::ReadContext::new("image.exr")?;
+//! if let Some(owner) = ctx.standard_attributes(0).owner()? {
+//!     println!("owned by {}", owner);
+//! }
+//! # Ok(())
+//! # }
+//! ```
+//!
+use crate::attr::{
+    AttrChromaticities, AttrKeycode, AttrRational, AttrTimecode, AttributeRead,
+    AttributeWrite, Envmap,
+};
+use crate::context::{Context, ContextState, WriteHeaderContext};
+use crate::error::{Error, ErrorKind};
+use imath_traits::{Matrix44, Vec2};
+
+type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// Reads the standard attributes of a part
+///
+/// Every getter returns `Ok(None)` if the part does not have the attribute.
+///
+pub struct StandardAttributes<'a, S: ContextState> {
+    ctx: &'a Context<S>,
+    part_index: usize,
+}
+
+/// Sets the standard attributes of a part of a header being written
+///
+pub struct StandardAttributesMut<'a> {
+    ctx: &'a mut WriteHeaderContext,
+    part_index: usize,
+}
+
+impl<S: ContextState> Context<S> {
+    /// Get typed access to the standard attributes of the specified part
+    ///
+    pub fn standard_attributes(
+        &self,
+        part_index: usize,
+    ) -> StandardAttributes<'_, S> {
+        StandardAttributes {
+            ctx: self,
+            part_index,
+        }
+    }
+}
+
+impl WriteHeaderContext {
+    /// Get typed access to set the standard attributes of the specified part
+    ///
+    pub fn standard_attributes_mut(
+        &mut self,
+        part_index: usize,
+    ) -> StandardAttributesMut<'_> {
+        StandardAttributesMut {
+            ctx: self,
+            part_index,
+        }
+    }
+}
+
+impl<'a, S: ContextState> StandardAttributes<'a, S> {
+    fn get<T: AttributeRead>(&self, name: &str) -> Result<Option<T>> {
+        match self.ctx.get_attribute(self.part_index, name) {
+            Ok(value) => Ok(Some(value)),
+            Err(e) if e.kind() == ErrorKind::NoAttrByName => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// The white point of the scene, given as xy chromaticity coordinates,
+    /// that the image's colours are relative to
+    ///
+    pub fn adopted_neutral<V: Vec2<f32>>(&self) -> Result<Option<V>> {
+        self.get::<[f32; 2]>("adoptedNeutral")
+            .map(|v| v.map(|v| V::from_slice(&v)))
+    }
+
+    /// The transform from world space to the camera's space
+    ///
+    pub fn world_to_camera<M: Matrix44<f32>>(&self) -> Result<Option<M>> {
+        self.get::<[f32; 16]>("worldToCamera")
+            .map(|m| m.map(|m| M::from_slice(&m)))
+    }
+
+    /// The transform from world space to the camera's normalized device
+    /// coordinates
+    ///
+    pub fn world_to_ndc<M: Matrix44<f32>>(&self) -> Result<Option<M>> {
+        self.get::<[f32; 16]>("worldToNDC")
+            .map(|m| m.map(|m| M::from_slice(&m)))
+    }
+}
+
+impl<'a> StandardAttributesMut<'a> {
+    fn set<T: AttributeWrite>(&mut self, name: &str, value: &T) -> Result<()> {
+        self.ctx.set_attribute(self.part_index, name, value)
+    }
+
+    /// Set the white point of the scene, given as xy chromaticity
+    /// coordinates, that the image's colours are relative to
+    ///
+    pub fn set_adopted_neutral<V: Vec2<f32>>(
+        &mut self,
+        value: &V,
+    ) -> Result<()> {
+        self.set("adoptedNeutral", value.as_slice())
+    }
+
+    /// Set the transform from world space to the camera's space
+    ///
+    pub fn set_world_to_camera<M: Matrix44<f32>>(
+        &mut self,
+        value: &M,
+    ) -> Result<()> {
+        self.set("worldToCamera", value.as_slice())
+    }
+
+    /// Set the transform from world space to the camera's normalized device
+    /// coordinates
+    ///
+    pub fn set_world_to_ndc<M: Matrix44<f32>>(
+        &mut self,
+        value: &M,
+    ) -> Result<()> {
+        self.set("worldToNDC", value.as_slice())
+    }
+}
+
+/// Generate a getter on [`StandardAttributes`] and a setter on
+/// [`StandardAttributesMut`] for the attribute `$name`, which is read as
+/// `$ty` and set from `$arg`
+///
+macro_rules! standard_attribute {
+    (
+        $(#[$doc:meta])*
+        $get:ident, $set:ident, $name:literal, $ty:ty, $arg:ty
+    ) => {
+        impl<'a, S: ContextState> StandardAttributes<'a, S> {
+            $(#[$doc])*
+            pub fn $get(&self) -> Result<Option<$ty>> {
+                self.get($name)
+            }
+        }
+
+        impl<'a> StandardAttributesMut<'a> {
+            $(#[$doc])*
+            pub fn $set(&mut self, value: $arg) -> Result<()> {
+                self.set($name, &value)
+            }
+        }
+    };
+}
+
+standard_attribute!(
+    /// The CIE xy chromaticities of the image's RGB primaries and white
+    /// point
+    ///
+    chromaticities,
+    set_chromaticities,
+    "chromaticities",
+    AttrChromaticities,
+    AttrChromaticities
+);
+standard_attribute!(
+    /// The luminance, in nits, of the RGB value (1, 1, 1)
+    ///
+    white_luminance,
+    set_white_luminance,
+    "whiteLuminance",
+    f32,
+    f32
+);
+standard_attribute!(
+    /// The name of the transform from scene-referred to display-referred
+    /// values
+    ///
+    rendering_transform,
+    set_rendering_transform,
+    "renderingTransform",
+    String,
+    &str
+);
+standard_attribute!(
+    /// The name of the look modification transform applied to the image
+    ///
+    look_mod_transform,
+    set_look_mod_transform,
+    "lookModTransform",
+    String,
+    &str
+);
+standard_attribute!(
+    /// The horizontal output density, in pixels per inch
+    ///
+    x_density,
+    set_x_density,
+    "xDensity",
+    f32,
+    f32
+);
+standard_attribute!(
+    /// The name of the owner of the image
+    ///
+    owner,
+    set_owner,
+    "owner",
+    String,
+    &str
+);
+standard_attribute!(
+    /// Free-form notes about the image
+    ///
+    comments,
+    set_comments,
+    "comments",
+    String,
+    &str
+);
+standard_attribute!(
+    /// The date the image was captured or created, as local time in the
+    /// form `"YYYY:MM:DD hh:mm:ss"`
+    ///
+    cap_date,
+    set_cap_date,
+    "capDate",
+    String,
+    &str
+);
+standard_attribute!(
+    /// The offset of the local time in `capDate` from UTC, in seconds
+    ///
+    utc_offset,
+    set_utc_offset,
+    "utcOffset",
+    f32,
+    f32
+);
+standard_attribute!(
+    /// The longitude where the image was captured, in degrees east of
+    /// Greenwich
+    ///
+    longitude,
+    set_longitude,
+    "longitude",
+    f32,
+    f32
+);
+standard_attribute!(
+    /// The latitude where the image was captured, in degrees north of the
+    /// equator
+    ///
+    latitude,
+    set_latitude,
+    "latitude",
+    f32,
+    f32
+);
+standard_attribute!(
+    /// The altitude where the image was captured, in metres above sea level
+    ///
+    altitude,
+    set_altitude,
+    "altitude",
+    f32,
+    f32
+);
+standard_attribute!(
+    /// The distance the camera was focused at, in metres
+    ///
+    focus,
+    set_focus,
+    "focus",
+    f32,
+    f32
+);
+standard_attribute!(
+    /// The exposure time, in seconds
+    ///
+    exp_time,
+    set_exp_time,
+    "expTime",
+    f32,
+    f32
+);
+standard_attribute!(
+    /// The lens aperture, as an f-number
+    ///
+    aperture,
+    set_aperture,
+    "aperture",
+    f32,
+    f32
+);
+standard_attribute!(
+    /// The ISO speed of the film or sensor
+    ///
+    iso_speed,
+    set_iso_speed,
+    "isoSpeed",
+    f32,
+    f32
+);
+standard_attribute!(
+    /// How the image is laid out if it is an environment map
+    ///
+    envmap,
+    set_envmap,
+    "envmap",
+    Envmap,
+    Envmap
+);
+standard_attribute!(
+    /// The film keycode of the frame the image was scanned from
+    ///
+    key_code,
+    set_key_code,
+    "keyCode",
+    AttrKeycode,
+    AttrKeycode
+);
+standard_attribute!(
+    /// The SMPTE timecode of the frame
+    ///
+    time_code,
+    set_time_code,
+    "timeCode",
+    AttrTimecode,
+    AttrTimecode
+);
+standard_attribute!(
+    /// How texture lookups outside the image should wrap, e.g. `"periodic"`
+    /// or `"clamp,mirror"`
+    ///
+    wrapmodes,
+    set_wrapmodes,
+    "wrapmodes",
+    String,
+    &str
+);
+standard_attribute!(
+    /// The playback rate of the image sequence the image belongs to
+    ///
+    frames_per_second,
+    set_frames_per_second,
+    "framesPerSecond",
+    AttrRational,
+    AttrRational
+);
+
+#[cfg(test)]
+mod tests {
+    use crate as exr;
+    use exr::attr::AttrRational;
+    use exr::context::{DefaultWriteMode, ReadContext, WriteHeaderContext};
+    use std::path::Path;
+
+    #[test]
+    fn standard_attributes() -> Result<(), exr::Error> {
+        let path_ferris = Path::new(
+            &std::env::var("CARGO_MANIFEST_DIR")
+                .expect("CARGO_MANIFEST_DIR not set"),
+        )
+        .join("images")
+        .join("custom_attributes.exr");
+
+        let src = ReadContext::new(&path_ferris)?;
+        let attrs = src.standard_attributes(0);
+        assert_eq!(
+            attrs.comments()?.as_deref(),
+            Some("this is an awesome image of Ferris")
+        );
+        assert_eq!(attrs.owner()?, None);
+
+        let path = std::env::temp_dir().join("standard_attributes.exr");
+        let mut header = WriteHeaderContext::new(
+            &path,
+            DefaultWriteMode::WriteFileDirectly,
+        )?;
+        header.add_part_copy(&src, 0, None)?;
+        {
+            let mut attrs = header.standard_attributes_mut(0);
+            attrs.set_owner("Ferris")?;
+            attrs.set_iso_speed(800.0)?;
+            attrs.set_adopted_neutral(&[0.3127f32, 0.329])?;
+            attrs.set_frames_per_second(AttrRational::new(24, 1))?;
+        }
+
+        let attrs = header.standard_attributes(0);
+        assert_eq!(attrs.owner()?.as_deref(), Some("Ferris"));
+        assert_eq!(attrs.iso_speed()?, Some(800.0));
+        assert_eq!(attrs.adopted_neutral::<[f32; 2]>()?, Some([0.3127, 0.329]));
+        assert_eq!(attrs.frames_per_second()?, Some(AttrRational::new(24, 1)));
+        assert!(attrs.focus()?.is_none());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,452 @@
+//! Reading from and writing to sources other than files, using the C core's
+//! custom stream callbacks.
+//!
+use crate::context::{Context, ErrorHandler, ReadContext, WriteHeaderContext};
+use crate::error::{Error, ErrorKind};
+use openexr_core_sys as sys;
+use std::ffi::{c_void, CStr, CString};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::marker::PhantomData;
+use std::os::raw::c_char;
+use std::sync::Mutex;
+
+type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// What the C core is given as the user data of a context with custom
+/// callbacks
+///
+/// The error handler comes first so that [`handle_error`] can find it without
+/// knowing the type of the stream.
+///
+#[repr(C)]
+struct UserData<S> {
+    error_handler: Option<ErrorHandler>,
+    stream: S,
+}
+
+/// Get the stream from the user data passed to a callback
+///
+/// # Safety
+/// `userdata` must have been created by [`StreamCallbacks::new`] with a stream
+/// of type `S`
+///
+pub(crate) unsafe fn stream<'a, S>(userdata: *mut c_void) -> &'a S {
+    &(*(userdata as *const UserData<S>)).stream
+}
+
+/// Stream callbacks, and the user data they are passed, to set up a context
+/// with
+///
+pub(crate) struct StreamCallbacks {
+    pub(crate) user_data: *mut c_void,
+    pub(crate) destroy_fn: sys::exr_destroy_stream_func_ptr_t,
+    pub(crate) has_error_handler: bool,
+    pub(crate) read_fn: sys::exr_read_func_ptr_t,
+    pub(crate) size_fn: sys::exr_query_size_func_ptr_t,
+    pub(crate) write_fn: sys::exr_write_func_ptr_t,
+    pub(crate) flags: i32,
+    /// Width and height limits for images and tiles, where 0 uses the
+    /// library's default
+    pub(crate) max_image_size: (i32, i32),
+    pub(crate) max_tile_size: (i32, i32),
+}
+
+impl StreamCallbacks {
+    /// Set up user data holding `stream` and `error_handler`, which is freed
+    /// by the C core when the context is finished. Set the callbacks that
+    /// read or write the stream with [`stream`] after this
+    ///
+    pub(crate) fn new<S>(
+        stream: S,
+        error_handler: Option<ErrorHandler>,
+    ) -> StreamCallbacks {
+        let has_error_handler = error_handler.is_some();
+        let user_data = Box::new(UserData {
+            error_handler,
+            stream,
+        });
+
+        StreamCallbacks {
+            user_data: Box::into_raw(user_data) as *mut c_void,
+            destroy_fn: Some(destroy_user_data::<S>),
+            has_error_handler,
+            read_fn: None,
+            size_fn: None,
+            write_fn: None,
+            flags: 0,
+            max_image_size: (0, 0),
+            max_tile_size: (0, 0),
+        }
+    }
+
+    /// Callbacks reading from `reader`
+    ///
+    pub(crate) fn reader<R: Read + Seek + Send + 'static>(
+        reader: R,
+        error_handler: Option<ErrorHandler>,
+    ) -> StreamCallbacks {
+        StreamCallbacks {
+            read_fn: Some(reader_read::<R>),
+            size_fn: Some(reader_size::<R>),
+            ..StreamCallbacks::new(Mutex::new(reader), error_handler)
+        }
+    }
+
+    /// Build the initializer the C core is passed, with everything other than
+    /// the stream callbacks, error handler, flags and size limits left at
+    /// their defaults
+    ///
+    pub(crate) fn initializer(&self) -> sys::exr_context_initializer_t {
+        let mut init = default_initializer();
+        init.user_data = self.user_data;
+        init.read_fn = self.read_fn;
+        init.size_fn = self.size_fn;
+        init.write_fn = self.write_fn;
+        init.destroy_fn = self.destroy_fn;
+        if self.has_error_handler {
+            init.error_handler_fn = Some(handle_error);
+        }
+        init.flags = self.flags;
+        init.max_image_width = self.max_image_size.0;
+        init.max_image_height = self.max_image_size.1;
+        init.max_tile_width = self.max_tile_size.0;
+        init.max_tile_height = self.max_tile_size.1;
+        init
+    }
+}
+
+/// The initializer the C core uses when it is passed null, i.e. its
+/// `EXR_DEFAULT_CONTEXT_INITIALIZER`, except that error messages are captured
+/// by [`sys::capture_error_message`], and those that aren't attached to an
+/// error are logged as warnings
+///
+pub(crate) fn default_initializer() -> sys::exr_context_initializer_t {
+    // every context is created from this, so the allocator mustn't change
+    // from now on
+    crate::defaults::lock_allocator();
+    crate::trace::install_warning_handler();
+
+    // Safety: the initializer is plain data, for which zero means "use the
+    // default" for everything but the size, zip level and DWA quality, which
+    // are set to their defaults here
+    let mut init: sys::exr_context_initializer_t =
+        unsafe { std::mem::zeroed() };
+    init.size = std::mem::size_of::<sys::exr_context_initializer_t>() as _;
+    init.zip_level = -2;
+    init.dwa_quality = -1.0;
+    // keep error messages to attach to the errors we return, rather than
+    // printing them
+    init.error_handler_fn = Some(sys::capture_error_message);
+    init
+}
+
+impl ReadContext {
+    /// Create a context reading from the bytes of an EXR file in memory
+    ///
+    /// `data` can be anything that holds the bytes, such as a `Vec<u8>`, an
+    /// `Arc<Vec<u8>>` or a `&'static [u8]`, and is dropped along with the
+    /// context. To read from a borrowed slice, copy it with `to_vec()` first.
+    ///
+    /// # Errors
+    /// * `[Error::FileBadHeader]` - If the data does not hold a valid header
+    ///
+    pub fn from_memory<D>(data: D) -> Result<ReadContext>
+    where
+        D: AsRef<[u8]> + Send + Sync + 'static,
+    {
+        let callbacks = StreamCallbacks {
+            read_fn: Some(memory_read::<D>),
+            size_fn: Some(memory_size::<D>),
+            ..StreamCallbacks::new(data, None)
+        };
+
+        // Safety: the callbacks only ever treat the user data as a D
+        unsafe { ReadContext::from_stream("<memory>", callbacks) }
+    }
+
+    /// Create a context reading from `reader`, which is dropped along with
+    /// the context
+    ///
+    /// The reader is seeked to each chunk as it is read, so wrap it in a
+    /// `BufReader` if reads are expensive. Errors from the reader are
+    /// reported as `[Error::ReadIo]`.
+    ///
+    /// # Errors
+    /// * `[Error::ReadIo]` - If the reader fails while reading the header
+    /// * `[Error::FileBadHeader]` - If the data does not hold a valid header
+    ///
+    pub fn from_reader<R>(reader: R) -> Result<ReadContext>
+    where
+        R: Read + Seek + Send + 'static,
+    {
+        let callbacks = StreamCallbacks::reader(reader, None);
+
+        // Safety: the callbacks only ever treat the user data as a Mutex<R>
+        unsafe { ReadContext::from_stream("<reader>", callbacks) }
+    }
+
+    /// Create a context reading through `callbacks`
+    ///
+    /// # Safety
+    /// The callbacks must be valid to call with the user data until its
+    /// destroy function is called, which the C core does both when the
+    /// context is finished and when it fails to start, so the user data must
+    /// not be freed by the caller.
+    ///
+    pub(crate) unsafe fn from_stream(
+        name: &str,
+        callbacks: StreamCallbacks,
+    ) -> Result<ReadContext> {
+        let c_name = CString::new(name).expect("Internal null bytes in name");
+        let init = callbacks.initializer();
+
+        let mut inner = std::ptr::null_mut();
+        sys::exr_start_read(&mut inner, c_name.as_ptr(), &init)
+            .ok_started(inner, ())?;
+
+        Ok(Context {
+            inner,
+            marker: PhantomData,
+        })
+    }
+}
+
+impl WriteHeaderContext {
+    /// Create a context writing to `writer`, which is dropped along with the
+    /// context
+    ///
+    /// The writer is seeked to wherever the C core writes, which is mostly
+    /// sequential, apart from going back to fill in the chunk table when the
+    /// file is finished. Errors from the writer are reported as
+    /// `[Error::WriteIo]`.
+    ///
+    /// Any error flushing the writer when it is dropped is lost, so if it
+    /// buffers, e.g. a `BufWriter`, make sure it is flushed some other way.
+    ///
+    pub fn from_writer<W>(writer: W) -> Result<WriteHeaderContext>
+    where
+        W: Write + Seek + Send + 'static,
+    {
+        let callbacks = StreamCallbacks {
+            write_fn: Some(writer_write::<W>),
+            ..StreamCallbacks::new(Mutex::new(writer), None)
+        };
+
+        let c_name = CString::new("<writer>").unwrap();
+        let init = callbacks.initializer();
+
+        let mut inner = std::ptr::null_mut();
+        // Safety: the callbacks only ever treat the user data as a
+        // Mutex<W>, and the C core frees it with the destroy function
+        unsafe {
+            sys::exr_start_write(
+                &mut inner,
+                c_name.as_ptr(),
+                sys::exr_default_write_mode::EXR_WRITE_FILE_DIRECTLY,
+                &init,
+            )
+            .ok_started(inner, ())?;
+        }
+
+        Ok(Context {
+            inner,
+            marker: PhantomData,
+        })
+    }
+}
+
+unsafe extern "C" fn memory_read<D: AsRef<[u8]>>(
+    _ctxt: sys::exr_const_context_t,
+    userdata: *mut c_void,
+    buffer: *mut c_void,
+    sz: u64,
+    offset: u64,
+    _error_cb: sys::exr_stream_error_func_ptr_t,
+) -> i64 {
+    let data = stream::<D>(userdata).as_ref();
+    if offset > data.len() as u64 {
+        return -1;
+    }
+
+    // reads past the end are short rather than failing, as for a file
+    let start = offset as usize;
+    let len = (data.len() - start).min(sz.min(usize::MAX as u64) as usize);
+    std::ptr::copy_nonoverlapping(
+        data.as_ptr().add(start),
+        buffer as *mut u8,
+        len,
+    );
+    len as i64
+}
+
+unsafe extern "C" fn memory_size<D: AsRef<[u8]>>(
+    _ctxt: sys::exr_const_context_t,
+    userdata: *mut c_void,
+) -> i64 {
+    stream::<D>(userdata).as_ref().len() as i64
+}
+
+unsafe extern "C" fn reader_read<R: Read + Seek>(
+    _ctxt: sys::exr_const_context_t,
+    userdata: *mut c_void,
+    buffer: *mut c_void,
+    sz: u64,
+    offset: u64,
+    _error_cb: sys::exr_stream_error_func_ptr_t,
+) -> i64 {
+    let reader = stream::<Mutex<R>>(userdata);
+    let buffer = std::slice::from_raw_parts_mut(buffer as *mut u8, sz as usize);
+
+    // don't let a panic in the reader unwind into C
+    std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        let mut reader = reader.lock().ok()?;
+        reader.seek(SeekFrom::Start(offset)).ok()?;
+
+        // keep reading until the buffer is full or we hit the end
+        let mut len = 0;
+        while len < buffer.len() {
+            match reader.read(&mut buffer[len..]) {
+                Ok(0) => break,
+                Ok(n) => len += n,
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => (),
+                Err(_) => return None,
+            }
+        }
+        Some(len as i64)
+    }))
+    .ok()
+    .flatten()
+    .unwrap_or(-1)
+}
+
+unsafe extern "C" fn reader_size<R: Read + Seek>(
+    _ctxt: sys::exr_const_context_t,
+    userdata: *mut c_void,
+) -> i64 {
+    let reader = stream::<Mutex<R>>(userdata);
+    std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        let mut reader = reader.lock().ok()?;
+        let size = reader.seek(SeekFrom::End(0)).ok()?;
+        Some(size as i64)
+    }))
+    .ok()
+    .flatten()
+    .unwrap_or(-1)
+}
+
+unsafe extern "C" fn writer_write<W: Write + Seek>(
+    _ctxt: sys::exr_const_context_t,
+    userdata: *mut c_void,
+    buffer: *const c_void,
+    sz: u64,
+    offset: u64,
+    _error_cb: sys::exr_stream_error_func_ptr_t,
+) -> i64 {
+    let writer = stream::<Mutex<W>>(userdata);
+    let buffer = std::slice::from_raw_parts(buffer as *const u8, sz as usize);
+
+    // don't let a panic in the writer unwind into C
+    std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        let mut writer = writer.lock().ok()?;
+        writer.seek(SeekFrom::Start(offset)).ok()?;
+        writer.write_all(buffer).ok()?;
+        Some(buffer.len() as i64)
+    }))
+    .ok()
+    .flatten()
+    .unwrap_or(-1)
+}
+
+unsafe extern "C" fn destroy_user_data<S>(
+    _ctxt: sys::exr_const_context_t,
+    userdata: *mut c_void,
+    _failed: std::os::raw::c_int,
+) {
+    drop(Box::from_raw(userdata as *mut UserData<S>));
+}
+
+/// Pass an error from the C core on to the error handler in the context's
+/// user data, and keep its message to attach to the error that is returned
+///
+unsafe extern "C" fn handle_error(
+    ctxt: sys::exr_const_context_t,
+    code: sys::exr_result_t,
+    msg: *const c_char,
+) {
+    let msg = if msg.is_null() {
+        Default::default()
+    } else {
+        CStr::from_ptr(msg).to_string_lossy()
+    };
+    let kind = code.ok(()).err().map_or(ErrorKind::Unknown, |e| e.kind());
+    sys::record_error_message(ctxt, &code, &msg);
+
+    let mut userdata = std::ptr::null_mut();
+    if sys::exr_get_user_data(ctxt, &mut userdata).ok(()).is_err()
+        || userdata.is_null()
+    {
+        return;
+    }
+
+    // Safety: the error handler is the first field of every UserData<S>
+    let handler = &*(userdata as *const Option<ErrorHandler>);
+    if let Some(handler) = handler {
+        // don't let a panic in the handler unwind into C
+        let _ = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            handler(Error::new(kind, Some(msg.to_string())), &msg)
+        }));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate as exr;
+    use imath_traits::f16;
+    use std::path::Path;
+
+    #[test]
+    fn from_memory() -> Result<(), exr::Error> {
+        let path_ferris = Path::new(
+            &std::env::var("CARGO_MANIFEST_DIR")
+                .expect("CARGO_MANIFEST_DIR not set"),
+        )
+        .join("images")
+        .join("ferris.exr");
+
+        let bytes = std::fs::read(&path_ferris).unwrap();
+        let ctx = exr::context::ReadContext::from_memory(bytes)?;
+        assert_eq!(ctx.data_window::<[i32; 4]>(0)?, [0, 0, 1199, 799]);
+
+        let file_ctx = exr::context::ReadContext::new(&path_ferris)?;
+        assert_eq!(
+            ctx.read_image::<f16>(0, &["R", "G", "B", "A"])?,
+            file_ctx.read_image::<f16>(0, &["R", "G", "B", "A"])?
+        );
+
+        assert!(exr::context::ReadContext::from_memory(vec![0u8; 16]).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn from_reader() -> Result<(), exr::Error> {
+        let path_ferris = Path::new(
+            &std::env::var("CARGO_MANIFEST_DIR")
+                .expect("CARGO_MANIFEST_DIR not set"),
+        )
+        .join("images")
+        .join("ferris.exr");
+
+        let file =
+            std::io::BufReader::new(std::fs::File::open(&path_ferris).unwrap());
+        let ctx = exr::context::ReadContext::from_reader(file)?;
+
+        let file_ctx = exr::context::ReadContext::new(&path_ferris)?;
+        assert_eq!(
+            ctx.read_image::<f16>(0, &["R", "G", "B", "A"])?,
+            file_ctx.read_image::<f16>(0, &["R", "G", "B", "A"])?
+        );
+
+        Ok(())
+    }
+}
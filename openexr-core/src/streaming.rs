@@ -0,0 +1,447 @@
+//! A ready-made pipeline for ingesting the chunks of a part, e.g. for
+//! playback, enabled with the `streaming` feature.
+//!
+//! [`ChunkStream`] reads the packed chunks of a part on one thread and hands
+//! them out to a number of worker threads, each of which decodes its chunks
+//! with a pipeline of its own. The decoded chunks are delivered over a
+//! crossbeam channel, either in the order they are stored in the part or as
+//! soon as each one is ready. The channels between the stages are bounded,
+//! so no stage gets more than a fixed number of chunks ahead of the next, and
+//! memory use stays the same however large the part is.
+//!
+use crate::chunkio::ChunkInfo;
+use crate::coding::ChannelElement;
+use crate::context::ReadContext;
+use crate::decode::{DecodePipeline, Runnable};
+use crate::error::Error;
+use crate::pool::{BufferPool, PooledBuffer};
+use crate::read::part_chunks;
+use crossbeam_channel::{bounded, never, Receiver, Sender};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+
+type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// How a [`ChunkStream`] is run
+///
+#[derive(Debug, Clone, PartialEq)]
+pub struct StreamOptions {
+    /// The number of threads decoding chunks, at least one. Defaults to the
+    /// number of threads the machine can run at once.
+    pub workers: usize,
+    /// The number of chunks each stage may get ahead of the next, at least
+    /// one. Defaults to twice the number of workers.
+    pub depth: usize,
+    /// Deliver the chunks in the order they are stored in the part, rather
+    /// than as soon as each has been decoded. Defaults to `true`.
+    pub ordered: bool,
+}
+
+impl Default for StreamOptions {
+    fn default() -> StreamOptions {
+        let workers = std::thread::available_parallelism()
+            .map(|n| n.get())
+            .unwrap_or(1);
+        StreamOptions {
+            workers,
+            depth: workers * 2,
+            ordered: true,
+        }
+    }
+}
+
+/// A chunk decoded by a [`ChunkStream`]
+///
+#[derive(Debug, Clone, PartialEq)]
+pub struct DecodedChunk<T> {
+    /// The position of the chunk in the order it is stored in the part
+    pub index: usize,
+    /// The x coordinate of the chunk's top-left pixel, relative to the left
+    /// of the data window
+    pub x: usize,
+    /// The y coordinate of the chunk's top-left pixel, relative to the top
+    /// of the data window
+    pub y: usize,
+    /// Width of the chunk in pixels
+    pub width: usize,
+    /// Height of the chunk in pixels
+    pub height: usize,
+    /// The requested channels, interleaved in the order they were requested,
+    /// a line at a time
+    pub pixels: Vec<T>,
+}
+
+/// A chunk the reader has read, waiting for a worker to decode it, and where
+/// to send the result
+///
+struct Job<T> {
+    index: usize,
+    chunk_info: ChunkInfo,
+    origin: (usize, usize),
+    packed_data: PooledBuffer,
+    reply: Sender<Result<DecodedChunk<T>>>,
+}
+
+/// Where the reader tells the workers to send each decoded chunk
+///
+enum Replies<T> {
+    /// Straight to the output
+    Unordered(Sender<Result<DecodedChunk<T>>>),
+    /// To a channel for the chunk alone, which is queued here so the chunks
+    /// can be forwarded to the output in order
+    Ordered(Sender<Receiver<Result<DecodedChunk<T>>>>),
+}
+
+/// Decodes every chunk of a part on a set of background threads, delivering
+/// them over a channel
+///
+/// The chunks can be taken either from [`ChunkStream::receiver`], e.g. to
+/// wait on them alongside other channels, or by iterating over the stream.
+/// If a chunk can't be read, its error is the last item, while a chunk that
+/// can't be decoded is reported in its place and the rest carry on.
+///
+/// Dropping the stream stops the threads once each has finished the chunk it
+/// is working on.
+///
+pub struct ChunkStream<T> {
+    receiver: Receiver<Result<DecodedChunk<T>>>,
+    stop: Arc<AtomicBool>,
+    threads: Vec<JoinHandle<()>>,
+}
+
+impl<T: ChannelElement + Send + 'static> ChunkStream<T> {
+    /// Start decoding the chunks of the part `part_index` of `ctx`, for the
+    /// channels named in `channels`, interleaved in that order and converted
+    /// to `T`
+    ///
+    /// Requested channels that do not exist in the part are left at
+    /// `T::default()`. For tiled parts, this reads the highest-resolution
+    /// level.
+    ///
+    /// # Errors
+    /// * `[Error::ArgumentOutOfRange]` - If `part_index` is not a valid part
+    /// * `[Error::FeatureNotImplemented]` - If the part holds deep data
+    ///
+    pub fn new(
+        ctx: Arc<ReadContext>,
+        part_index: usize,
+        channels: &[&str],
+        options: StreamOptions,
+    ) -> Result<ChunkStream<T>> {
+        let chunks = part_chunks(&ctx, part_index)?;
+        let pool = BufferPool::for_part(&ctx, part_index)?;
+        let channels =
+            channels.iter().map(|c| c.to_string()).collect::<Vec<_>>();
+        let workers = options.workers.max(1);
+        let depth = options.depth.max(1);
+        let stop = Arc::new(AtomicBool::new(false));
+
+        let (output, receiver) = bounded(depth);
+        let (jobs, queue) = bounded::<Job<T>>(depth);
+        let mut threads = Vec::with_capacity(workers + 2);
+
+        for _ in 0..workers {
+            let ctx = Arc::clone(&ctx);
+            let queue = queue.clone();
+            let channels = channels.clone();
+            let stop = Arc::clone(&stop);
+            threads.push(std::thread::spawn(move || {
+                decode_jobs(&ctx, part_index, &channels, queue, &stop)
+            }));
+        }
+
+        let replies = if options.ordered {
+            let (slots, waiting) = bounded(depth);
+            threads.push(std::thread::spawn(move || {
+                forward_in_order(waiting, output)
+            }));
+            Replies::Ordered(slots)
+        } else {
+            Replies::Unordered(output)
+        };
+
+        let reader_stop = Arc::clone(&stop);
+        threads.push(std::thread::spawn(move || {
+            read_jobs(
+                &ctx,
+                part_index,
+                chunks,
+                &pool,
+                jobs,
+                replies,
+                &reader_stop,
+            )
+        }));
+
+        Ok(ChunkStream {
+            receiver,
+            stop,
+            threads,
+        })
+    }
+}
+
+impl<T> ChunkStream<T> {
+    /// The channel the decoded chunks are delivered on
+    ///
+    pub fn receiver(&self) -> &Receiver<Result<DecodedChunk<T>>> {
+        &self.receiver
+    }
+
+    /// Wait for the threads to finish, passing on any panic
+    ///
+    fn join(&mut self) {
+        for thread in self.threads.drain(..) {
+            if let Err(panic) = thread.join() {
+                std::panic::resume_unwind(panic);
+            }
+        }
+    }
+}
+
+impl<T> Iterator for ChunkStream<T> {
+    type Item = Result<DecodedChunk<T>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.receiver.recv() {
+            Ok(chunk) => Some(chunk),
+            Err(_) => {
+                // every chunk has been delivered, unless a thread panicked
+                self.join();
+                None
+            }
+        }
+    }
+}
+
+impl<T> Drop for ChunkStream<T> {
+    fn drop(&mut self) {
+        // closing the output makes any thread waiting to send to it stop,
+        // and those behind it stop in turn as their channels close
+        self.stop.store(true, Ordering::Relaxed);
+        drop(std::mem::replace(&mut self.receiver, never()));
+        for thread in self.threads.drain(..) {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Read each chunk in turn and queue it for the workers, until every chunk
+/// has been queued, one can't be read or the stream is dropped
+///
+fn read_jobs<T>(
+    ctx: &ReadContext,
+    part_index: usize,
+    chunks: Vec<(ChunkInfo, (usize, usize))>,
+    pool: &BufferPool,
+    jobs: Sender<Job<T>>,
+    replies: Replies<T>,
+    stop: &AtomicBool,
+) {
+    for (index, (chunk_info, origin)) in chunks.into_iter().enumerate() {
+        if stop.load(Ordering::Relaxed) {
+            return;
+        }
+
+        let reply = match &replies {
+            Replies::Unordered(output) => output.clone(),
+            Replies::Ordered(slots) => {
+                let (reply, slot) = bounded(1);
+                if slots.send(slot).is_err() {
+                    return;
+                }
+                reply
+            }
+        };
+
+        let mut packed_data = pool.take(chunk_info.packed_size as usize);
+        // Safety: packed_data holds exactly packed_size bytes
+        let read = unsafe {
+            ctx.read_chunk(part_index, &chunk_info, &mut packed_data)
+        };
+        if let Err(e) = read {
+            let _ = reply.send(Err(e));
+            return;
+        }
+
+        let job = Job {
+            index,
+            chunk_info,
+            origin,
+            packed_data,
+            reply,
+        };
+        if jobs.send(job).is_err() {
+            return;
+        }
+    }
+}
+
+/// Decode the chunks from `queue` with the worker's own pipeline, sending
+/// each one on to where the reader asked for it to go
+///
+fn decode_jobs<T: ChannelElement>(
+    ctx: &ReadContext,
+    part_index: usize,
+    channels: &[String],
+    queue: Receiver<Job<T>>,
+    stop: &AtomicBool,
+) {
+    let channels = channels.iter().map(|c| c.as_str()).collect::<Vec<_>>();
+    let mut pipeline: Option<DecodePipeline<Runnable>> = None;
+
+    for mut job in queue {
+        if stop.load(Ordering::Relaxed) {
+            return;
+        }
+
+        let decoded =
+            decode_job(ctx, &mut pipeline, part_index, &channels, &mut job)
+                .map_err(|e| {
+                    e.with_part(part_index)
+                        .with_chunk(job.chunk_info.idx as usize)
+                });
+        if decoded.is_err() {
+            // start again with a fresh pipeline, in case the failure left
+            // the old one in a bad state
+            pipeline = None;
+        }
+        if job.reply.send(decoded).is_err() {
+            return;
+        }
+    }
+}
+
+fn decode_job<'c, T: ChannelElement>(
+    ctx: &'c ReadContext,
+    pipeline: &mut Option<DecodePipeline<'c, Runnable>>,
+    part_index: usize,
+    channels: &[&str],
+    job: &mut Job<T>,
+) -> Result<DecodedChunk<T>> {
+    let width = job.chunk_info.width.max(0) as usize;
+    let height = job.chunk_info.height.max(0) as usize;
+    let mut pixels = vec![T::default(); width * height * channels.len()];
+
+    let mut ready = match pipeline.take() {
+        Some(p) => p.update(&job.chunk_info)?,
+        None => ctx.decoding_initialize(part_index, &job.chunk_info)?,
+    };
+    ready.select_interleaved(channels, &mut pixels)?;
+    let mut runnable = ready.choose_default_routines()?;
+    runnable.run_packed_checked(&mut job.packed_data, &mut [], &mut pixels)?;
+    *pipeline = Some(runnable);
+
+    Ok(DecodedChunk {
+        index: job.index,
+        x: job.origin.0,
+        y: job.origin.1,
+        width,
+        height,
+        pixels,
+    })
+}
+
+/// Wait for each chunk's result in the order the reader queued them, and
+/// send it on to the output
+///
+fn forward_in_order<T>(
+    waiting: Receiver<Receiver<Result<DecodedChunk<T>>>>,
+    output: Sender<Result<DecodedChunk<T>>>,
+) {
+    for slot in waiting {
+        // the slot closes without a result if the stream is being dropped
+        let chunk = match slot.recv() {
+            Ok(chunk) => chunk,
+            Err(_) => return,
+        };
+        if output.send(chunk).is_err() {
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate as exr;
+    use exr::context::ReadContext;
+    use exr::streaming::{ChunkStream, StreamOptions};
+    use imath_traits::f16;
+    use std::path::Path;
+    use std::sync::Arc;
+
+    #[test]
+    fn stream_chunks() -> Result<(), exr::Error> {
+        let images = Path::new(
+            &std::env::var("CARGO_MANIFEST_DIR")
+                .expect("CARGO_MANIFEST_DIR not set"),
+        )
+        .join("images");
+        let channels = ["R", "G", "B", "A"];
+
+        for name in &["ferris.exr", "ferris-tiled.exr"] {
+            let ctx = Arc::new(ReadContext::new(images.join(name))?);
+            let image = ctx.read_image::<f16>(0, &channels)?;
+            let line_len = image.width * channels.len();
+
+            for ordered in &[true, false] {
+                let options = StreamOptions {
+                    workers: 3,
+                    depth: 2,
+                    ordered: *ordered,
+                };
+                let stream = ChunkStream::<f16>::new(
+                    Arc::clone(&ctx),
+                    0,
+                    &channels,
+                    options,
+                )?;
+
+                let mut pixels = vec![f16::from_f32(-1.0); image.pixels.len()];
+                let mut indices = Vec::new();
+                for chunk in stream {
+                    let chunk = chunk?;
+                    indices.push(chunk.index);
+                    let chunk_line_len = chunk.width * channels.len();
+                    for (row, src) in
+                        chunk.pixels.chunks_exact(chunk_line_len).enumerate()
+                    {
+                        let start = (chunk.y + row) * line_len
+                            + chunk.x * channels.len();
+                        pixels[start..start + chunk_line_len]
+                            .copy_from_slice(src);
+                    }
+                }
+                assert_eq!(pixels, image.pixels);
+
+                if *ordered {
+                    assert!(indices.iter().enumerate().all(|(i, c)| i == *c));
+                } else {
+                    indices.sort_unstable();
+                    indices.dedup();
+                    assert_eq!(indices.len(), ctx.chunk_count(0)?);
+                }
+            }
+        }
+
+        // dropping the stream part way through stops the threads
+        let ctx = Arc::new(ReadContext::new(images.join("ferris.exr"))?);
+        let mut stream = ChunkStream::<f16>::new(
+            Arc::clone(&ctx),
+            0,
+            &channels,
+            StreamOptions {
+                workers: 2,
+                depth: 1,
+                ordered: true,
+            },
+        )?;
+        let first = stream.receiver().recv().expect("no chunks decoded")?;
+        assert_eq!(first.index, 0);
+        assert!(stream.next().is_some());
+        drop(stream);
+
+        Ok(())
+    }
+}
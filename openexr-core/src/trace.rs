@@ -0,0 +1,348 @@
+//! Running decode and encode pipelines, instrumented with `tracing` spans and
+//! events when the `tracing` feature is enabled, and logging the warnings the
+//! C core reports.
+//!
+//! Each chunk is run inside a `decode_chunk` or `encode_chunk` span at debug
+//! level, recording its part, index and sizes. The stages the C core runs for
+//! the chunk, i.e. reading, decompressing and converting when decoding, or
+//! converting, compressing and writing when encoding, are wrapped so each
+//! runs in a span of its own at trace level, and ends with an event giving
+//! the bytes it handled and how long it took, in microseconds.
+//!
+//! The stages are wrapped by swapping the pipeline's stage functions for ones
+//! that look up the originals in a thread local while the pipeline runs,
+//! which is safe as the C core runs them on the calling thread before
+//! returning.
+//!
+//! Messages the C core reports that no error is returned for, such as those
+//! for out-of-spec header values it tolerates, are logged as warnings with
+//! `tracing::warn!` when the `tracing` feature is enabled, or `log::warn!`
+//! otherwise.
+//!
+use openexr_core_sys as sys;
+use std::os::raw::c_int;
+use std::sync::Once;
+
+#[cfg(feature = "tracing")]
+use std::cell::Cell;
+#[cfg(feature = "tracing")]
+use std::time::Instant;
+
+/// Pass the C core's warnings on to [`log_warning`], the first time a context
+/// is created
+///
+pub(crate) fn install_warning_handler() {
+    static INSTALL: Once = Once::new();
+    INSTALL.call_once(|| sys::set_warning_handler(Some(log_warning)));
+}
+
+#[cfg(not(feature = "tracing"))]
+fn log_warning(msg: &str) {
+    log::warn!("{}", msg);
+}
+
+#[cfg(feature = "tracing")]
+fn log_warning(msg: &str) {
+    tracing::warn!("{}", msg);
+}
+
+/// Run a decode pipeline, as `exr_decoding_run`
+///
+/// # Safety
+/// As for `exr_decoding_run`
+///
+#[cfg(not(feature = "tracing"))]
+pub(crate) unsafe fn decoding_run(
+    ctx: sys::exr_const_context_t,
+    part_index: c_int,
+    decode: *mut sys::exr_decode_pipeline_t,
+) -> sys::exr_result_t {
+    sys::exr_decoding_run(ctx, part_index, decode)
+}
+
+/// Run an encode pipeline, as `exr_encoding_run`
+///
+/// # Safety
+/// As for `exr_encoding_run`
+///
+#[cfg(not(feature = "tracing"))]
+pub(crate) unsafe fn encoding_run(
+    ctx: sys::exr_const_context_t,
+    part_index: c_int,
+    encode: *mut sys::exr_encode_pipeline_t,
+) -> sys::exr_result_t {
+    sys::exr_encoding_run(ctx, part_index, encode)
+}
+
+#[cfg(feature = "tracing")]
+type DecodeFn =
+    unsafe extern "C" fn(*mut sys::exr_decode_pipeline_t) -> sys::exr_result_t;
+#[cfg(feature = "tracing")]
+type EncodeFn =
+    unsafe extern "C" fn(*mut sys::exr_encode_pipeline_t) -> sys::exr_result_t;
+
+/// The stage functions of the decode pipeline being run on this thread
+///
+#[cfg(feature = "tracing")]
+#[derive(Clone, Copy, Default)]
+struct DecodeStages {
+    read: Option<DecodeFn>,
+    decompress: Option<DecodeFn>,
+    unpack_and_convert: Option<DecodeFn>,
+}
+
+/// The stage functions of the encode pipeline being run on this thread
+///
+#[cfg(feature = "tracing")]
+#[derive(Clone, Copy, Default)]
+struct EncodeStages {
+    convert_and_pack: Option<EncodeFn>,
+    compress: Option<EncodeFn>,
+    write: Option<EncodeFn>,
+}
+
+#[cfg(feature = "tracing")]
+thread_local! {
+    static DECODE_STAGES: Cell<DecodeStages> =
+        Cell::new(DecodeStages::default());
+    static ENCODE_STAGES: Cell<EncodeStages> =
+        Cell::new(EncodeStages::default());
+}
+
+#[cfg(feature = "tracing")]
+pub(crate) unsafe fn decoding_run(
+    ctx: sys::exr_const_context_t,
+    part_index: c_int,
+    decode: *mut sys::exr_decode_pipeline_t,
+) -> sys::exr_result_t {
+    let pipeline = &mut *decode;
+    let _span = tracing::debug_span!(
+        "decode_chunk",
+        part = part_index,
+        chunk = pipeline.chunk.idx,
+        packed_size = pipeline.chunk.packed_size,
+        unpacked_size = pipeline.chunk.unpacked_size,
+    )
+    .entered();
+
+    let stages = DecodeStages {
+        read: pipeline.read_fn,
+        decompress: pipeline.decompress_fn,
+        unpack_and_convert: pipeline.unpack_and_convert_fn,
+    };
+    pipeline.read_fn = stages.read.and(Some(traced_read));
+    pipeline.decompress_fn = stages.decompress.and(Some(traced_decompress));
+    pipeline.unpack_and_convert_fn = stages
+        .unpack_and_convert
+        .and(Some(traced_unpack_and_convert));
+    let outer = DECODE_STAGES.with(|s| s.replace(stages));
+
+    let result = sys::exr_decoding_run(ctx, part_index, decode);
+
+    DECODE_STAGES.with(|s| s.set(outer));
+    let pipeline = &mut *decode;
+    pipeline.read_fn = stages.read;
+    pipeline.decompress_fn = stages.decompress;
+    pipeline.unpack_and_convert_fn = stages.unpack_and_convert;
+    result
+}
+
+#[cfg(feature = "tracing")]
+pub(crate) unsafe fn encoding_run(
+    ctx: sys::exr_const_context_t,
+    part_index: c_int,
+    encode: *mut sys::exr_encode_pipeline_t,
+) -> sys::exr_result_t {
+    let pipeline = &mut *encode;
+    let _span = tracing::debug_span!(
+        "encode_chunk",
+        part = part_index,
+        chunk = pipeline.chunk.idx,
+        unpacked_size = pipeline.chunk.unpacked_size,
+    )
+    .entered();
+
+    let stages = EncodeStages {
+        convert_and_pack: pipeline.convert_and_pack_fn,
+        compress: pipeline.compress_fn,
+        write: pipeline.write_fn,
+    };
+    pipeline.convert_and_pack_fn =
+        stages.convert_and_pack.and(Some(traced_convert_and_pack));
+    pipeline.compress_fn = stages.compress.and(Some(traced_compress));
+    pipeline.write_fn = stages.write.and(Some(traced_write));
+    let outer = ENCODE_STAGES.with(|s| s.replace(stages));
+
+    let result = sys::exr_encoding_run(ctx, part_index, encode);
+
+    ENCODE_STAGES.with(|s| s.set(outer));
+    let pipeline = &mut *encode;
+    pipeline.convert_and_pack_fn = stages.convert_and_pack;
+    pipeline.compress_fn = stages.compress;
+    pipeline.write_fn = stages.write;
+    result
+}
+
+/// Run `stage` inside a span named `name`, then report how many bytes it
+/// handled, as given by `bytes` once it has run, and how long it took
+///
+#[cfg(feature = "tracing")]
+unsafe fn traced_stage<P>(
+    name: &'static str,
+    stage: Option<unsafe extern "C" fn(*mut P) -> sys::exr_result_t>,
+    pipeline: *mut P,
+    bytes: impl FnOnce(&P) -> u64,
+) -> sys::exr_result_t {
+    let stage = match stage {
+        Some(stage) => stage,
+        None => {
+            return sys::exr_result_t::from_code(
+                sys::exr_error_code_t::EXR_ERR_INVALID_ARGUMENT,
+            )
+        }
+    };
+
+    let _span = tracing::trace_span!("stage", name).entered();
+    let start = Instant::now();
+    let result = stage(pipeline);
+    tracing::trace!(
+        bytes = bytes(&*pipeline),
+        elapsed_us = start.elapsed().as_micros() as u64,
+        "{} finished",
+        name,
+    );
+    result
+}
+
+#[cfg(feature = "tracing")]
+unsafe extern "C" fn traced_read(
+    decode: *mut sys::exr_decode_pipeline_t,
+) -> sys::exr_result_t {
+    let stage = DECODE_STAGES.with(|s| s.get().read);
+    traced_stage("read", stage, decode, |d| {
+        d.chunk.packed_size + d.chunk.sample_count_table_size
+    })
+}
+
+#[cfg(feature = "tracing")]
+unsafe extern "C" fn traced_decompress(
+    decode: *mut sys::exr_decode_pipeline_t,
+) -> sys::exr_result_t {
+    let stage = DECODE_STAGES.with(|s| s.get().decompress);
+    traced_stage("decompress", stage, decode, |d| d.chunk.unpacked_size)
+}
+
+#[cfg(feature = "tracing")]
+unsafe extern "C" fn traced_unpack_and_convert(
+    decode: *mut sys::exr_decode_pipeline_t,
+) -> sys::exr_result_t {
+    let stage = DECODE_STAGES.with(|s| s.get().unpack_and_convert);
+    traced_stage("unpack_and_convert", stage, decode, |d| {
+        d.chunk.unpacked_size
+    })
+}
+
+#[cfg(feature = "tracing")]
+unsafe extern "C" fn traced_convert_and_pack(
+    encode: *mut sys::exr_encode_pipeline_t,
+) -> sys::exr_result_t {
+    let stage = ENCODE_STAGES.with(|s| s.get().convert_and_pack);
+    traced_stage("convert_and_pack", stage, encode, |e| e.packed_bytes)
+}
+
+#[cfg(feature = "tracing")]
+unsafe extern "C" fn traced_compress(
+    encode: *mut sys::exr_encode_pipeline_t,
+) -> sys::exr_result_t {
+    let stage = ENCODE_STAGES.with(|s| s.get().compress);
+    traced_stage("compress", stage, encode, |e| e.compressed_bytes as u64)
+}
+
+#[cfg(feature = "tracing")]
+unsafe extern "C" fn traced_write(
+    encode: *mut sys::exr_encode_pipeline_t,
+) -> sys::exr_result_t {
+    let stage = ENCODE_STAGES.with(|s| s.get().write);
+    traced_stage("write", stage, encode, |e| {
+        if e.compressed_buffer.is_null() {
+            e.packed_bytes
+        } else {
+            e.compressed_bytes as u64
+        }
+    })
+}
+
+#[cfg(all(test, feature = "tracing"))]
+mod tests {
+    use crate as exr;
+    use exr::context::ReadContext;
+    use imath_traits::f16;
+    use std::path::Path;
+    use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use tracing::span::{Attributes, Id, Record};
+    use tracing::{Event, Metadata, Subscriber};
+
+    /// Counts the chunk spans opened and the stage events emitted
+    ///
+    #[derive(Default)]
+    struct Counts {
+        next_id: AtomicU64,
+        chunks: AtomicUsize,
+        stages: AtomicUsize,
+    }
+
+    #[derive(Clone, Default)]
+    struct Counter(Arc<Counts>);
+
+    impl Subscriber for Counter {
+        fn enabled(&self, _: &Metadata<'_>) -> bool {
+            true
+        }
+
+        fn new_span(&self, span: &Attributes<'_>) -> Id {
+            if span.metadata().name() == "decode_chunk" {
+                self.0.chunks.fetch_add(1, Ordering::SeqCst);
+            }
+            Id::from_u64(self.0.next_id.fetch_add(1, Ordering::SeqCst) + 1)
+        }
+
+        fn record(&self, _: &Id, _: &Record<'_>) {}
+
+        fn record_follows_from(&self, _: &Id, _: &Id) {}
+
+        fn event(&self, event: &Event<'_>) {
+            if event.metadata().fields().field("elapsed_us").is_some() {
+                self.0.stages.fetch_add(1, Ordering::SeqCst);
+            }
+        }
+
+        fn enter(&self, _: &Id) {}
+
+        fn exit(&self, _: &Id) {}
+    }
+
+    #[test]
+    fn trace_decode_stages() -> Result<(), exr::Error> {
+        let path_ferris = Path::new(
+            &std::env::var("CARGO_MANIFEST_DIR")
+                .expect("CARGO_MANIFEST_DIR not set"),
+        )
+        .join("images")
+        .join("ferris.exr");
+        let ctx = ReadContext::new(&path_ferris)?;
+
+        let counter = Counter::default();
+        let image = tracing::subscriber::with_default(counter.clone(), || {
+            ctx.read_image::<f16>(0, &["R", "G", "B", "A"])
+        })?;
+        assert_eq!(image.width * image.height * 4, image.pixels.len());
+
+        // every chunk is read, decompressed and converted
+        let chunks = counter.0.chunks.load(Ordering::SeqCst);
+        assert_eq!(chunks, ctx.chunk_count(0)?);
+        assert_eq!(counter.0.stages.load(Ordering::SeqCst), chunks * 3);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,715 @@
+//! Converting a file to a different compression or layout in one call, e.g.
+//! recompressing PIZ files as DWAA.
+//!
+//! ```no_run
+//! # fn main() -> Result<(), openexr_core::Error> {
+//! use openexr_core::attr::Compression;
+//! use openexr_core::transcode::{transcode, TranscodeOptions};
+//!
+//! transcode(
+//!     "beauty.exr",
+//!     "beauty_dwaa.exr",
+//!     TranscodeOptions {
+//!         compression: Some(Compression::Dwaa),
+//!         ..Default::default()
+//!     },
+//! )?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+use crate::attr::{Compression, LineOrder, PixelType, Storage, TileDescriptor};
+use crate::chunkio::ChunkInfo;
+use crate::coding::{sampled_count, ChannelInfo};
+use crate::context::{
+    Context, ContextState, DefaultWriteMode, ReadContext, WriteContext,
+    WriteHeaderContext,
+};
+use crate::decode::{DecodePipeline, Runnable};
+use crate::encode::EncodePipeline;
+use crate::error::Error;
+use crate::progress::{Progress, Tracker};
+use crate::read::num_tiles;
+use crate::validate::{part_levels, ChunkLocation};
+use std::ops::ControlFlow;
+use std::path::Path;
+
+type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// How the pixels of a part are laid out in the file
+///
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Tiling {
+    /// Stored as chunks of scanlines
+    Scanline,
+    /// Stored as tiles with the given sizes and levels
+    Tiled(TileDescriptor),
+}
+
+/// What to change when calling [`transcode`]
+///
+/// The default changes nothing, which just re-encodes every chunk.
+///
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TranscodeOptions {
+    /// The compression to use for the output, or `None` to keep each part's
+    /// compression
+    pub compression: Option<Compression>,
+    /// The layout to use for the output, or `None` to keep each part's layout
+    pub tiling: Option<Tiling>,
+    /// The indices of the parts of the source to write, in order, or `None`
+    /// to write every part
+    pub parts: Option<Vec<usize>>,
+}
+
+/// Copy the file at `src_path` to `dst_path`, re-encoding every chunk with
+/// the compression and layout given in `options`
+///
+/// All the attributes of each part are copied, including those of types the
+/// library does not know about, except that `tiles`, `type` and `chunkCount`
+/// are left for the output to fill in when a part's layout changes. Pixels
+/// are decoded and re-encoded in the type of their channel so are not
+/// converted, though of course lossy compression
+/// will change them.
+///
+/// Deep parts are copied chunk for chunk without being decoded, so keep their
+/// compression and layout whatever `options` says. When changing a tiled part
+/// to scanlines, or to a single level, only its highest-resolution level is
+/// kept.
+///
+/// # Errors
+/// * `[Error::ArgumentOutOfRange]` - If `options.parts` has an index that is
+/// not a part in the source
+/// * `[Error::FeatureNotImplemented]` - If `options.tiling` asks for more
+/// than one level for a part that is not already tiled with the same levels,
+/// as the levels would need to be generated
+///
+pub fn transcode<P: AsRef<Path>, Q: AsRef<Path>>(
+    src_path: P,
+    dst_path: Q,
+    options: TranscodeOptions,
+) -> Result<()> {
+    transcode_with_progress(src_path, dst_path, options, |_| {
+        ControlFlow::Continue(())
+    })
+}
+
+/// Copy the file at `src_path` to `dst_path` as for [`transcode`], calling
+/// `progress` each time a chunk has been written
+///
+/// Cancelling leaves the file at `dst_path` partly written, so it can only
+/// be discarded.
+///
+/// # Errors
+/// * `[Error::ArgumentOutOfRange]` - If `options.parts` has an index that is
+/// not a part in the source
+/// * `[Error::FeatureNotImplemented]` - If `options.tiling` asks for more
+/// than one level for a part that is not already tiled with the same levels
+/// * `[Error::Cancelled]` - If `progress` returned [`ControlFlow::Break`]
+///
+pub fn transcode_with_progress<P, Q, F>(
+    src_path: P,
+    dst_path: Q,
+    options: TranscodeOptions,
+    mut progress: F,
+) -> Result<()>
+where
+    P: AsRef<Path>,
+    Q: AsRef<Path>,
+    F: FnMut(Progress) -> ControlFlow<()>,
+{
+    let src = ReadContext::new(src_path)?;
+    let parts = match &options.parts {
+        Some(parts) => parts.clone(),
+        None => (0..src.count()?).collect(),
+    };
+
+    let mut header =
+        WriteHeaderContext::new(dst_path, DefaultWriteMode::WriteFileDirectly)?;
+    let multipart = parts.len() > 1;
+    for &src_part_index in &parts {
+        add_part(&mut header, &src, src_part_index, multipart, &options)?;
+    }
+
+    let mut dst = header.write_header()?;
+    let chunks_total = (0..parts.len())
+        .map(|part_index| dst.chunk_count(part_index))
+        .sum::<Result<usize>>()?;
+    let mut tracker = Tracker::new(&mut progress, chunks_total);
+
+    for (part_index, &src_part_index) in parts.iter().enumerate() {
+        match src.storage(src_part_index)? {
+            Storage::DeepScanline | Storage::DeepTiled => copy_deep_part(
+                &src,
+                src_part_index,
+                &mut dst,
+                part_index,
+                &mut tracker,
+            )?,
+            Storage::Scanline | Storage::Tiled => transcode_part(
+                &src,
+                src_part_index,
+                &mut dst,
+                part_index,
+                &mut tracker,
+            )?,
+        }
+    }
+
+    dst.close()
+}
+
+/// Add a copy of the header of part `src_part_index` in `src` to `header`,
+/// changed as described by `options`
+///
+/// Unnamed parts are named after their index in `src` when the output is
+/// `multipart`, or holds deep data, as the file format requires a name then,
+/// and are otherwise left unnamed.
+///
+fn add_part(
+    header: &mut WriteHeaderContext,
+    src: &ReadContext,
+    src_part_index: usize,
+    multipart: bool,
+    options: &TranscodeOptions,
+) -> Result<usize> {
+    let src_storage = src.storage(src_part_index)?;
+    let deep =
+        matches!(src_storage, Storage::DeepScanline | Storage::DeepTiled);
+
+    let name = match src.name(src_part_index)? {
+        Some(name) => name.to_string(),
+        None if multipart || deep => src_part_index.to_string(),
+        None => String::new(),
+    };
+    let storage = match options.tiling {
+        _ if deep => src_storage,
+        None => src_storage,
+        Some(Tiling::Scanline) => Storage::Scanline,
+        Some(Tiling::Tiled(_)) => Storage::Tiled,
+    };
+
+    let part_index = header.add_part(&name, storage)?;
+
+    // the attributes we change have to be set before copying the rest, as
+    // attributes that are already set are not copied
+    if !deep {
+        if let Some(compression) = options.compression {
+            header.set_compression(part_index, compression)?;
+        }
+        if let Some(Tiling::Tiled(tiles)) = &options.tiling {
+            header.set_tile_descriptor(part_index, tiles)?;
+        }
+    }
+
+    if storage == src_storage {
+        header.copy_attributes_from(src, src_part_index, part_index)?;
+    } else {
+        // these describe how the source is laid out, so would contradict
+        // the part's own layout
+        header.copy_attributes_except(
+            src,
+            src_part_index,
+            part_index,
+            &["tiles", "type", "chunkCount"],
+        )?;
+    }
+    Ok(part_index)
+}
+
+/// Decode each level of a flat part and encode it into part `part_index` of
+/// `dst`
+///
+fn transcode_part(
+    src: &ReadContext,
+    src_part_index: usize,
+    dst: &mut WriteContext,
+    part_index: usize,
+    tracker: &mut Tracker,
+) -> Result<()> {
+    // levels other than the first can only be copied, not generated, so
+    // have to be the same in both parts
+    let levels = part_levels(dst, part_index)?;
+    if levels.len() > 1 {
+        if src.storage(src_part_index)? != Storage::Tiled {
+            return Err(Error::FeatureNotImplemented);
+        }
+        let src_tiles = src.tile_descriptor(src_part_index)?;
+        let dst_tiles = dst.tile_descriptor(part_index)?;
+        if src_tiles.level_mode != dst_tiles.level_mode
+            || src_tiles.round_mode != dst_tiles.round_mode
+        {
+            return Err(Error::FeatureNotImplemented);
+        }
+    }
+
+    for level in levels {
+        let mut planes = Plane::for_level(src, src_part_index, level)?;
+        decode_level(src, src_part_index, level, &mut planes)?;
+        encode_level(dst, part_index, level, &planes, tracker)?;
+    }
+
+    Ok(())
+}
+
+/// Copy the packed chunks of a deep part as they are into part `part_index`
+/// of `dst`
+///
+fn copy_deep_part(
+    src: &ReadContext,
+    src_part_index: usize,
+    dst: &mut WriteContext,
+    part_index: usize,
+    tracker: &mut Tracker,
+) -> Result<()> {
+    let mut packed_data = Vec::new();
+    let mut sample_counts = Vec::new();
+    for level in part_levels(src, src_part_index)? {
+        for (location, _, _) in level_chunks(src, src_part_index, level)? {
+            let chunk_info = read_chunk_info(src, src_part_index, location)?;
+            packed_data.resize(chunk_info.packed_size as usize, 0);
+            sample_counts
+                .resize(chunk_info.sample_count_table_size as usize, 0);
+            src.read_deep_chunk(
+                src_part_index,
+                &chunk_info,
+                Some(&mut packed_data),
+                Some(&mut sample_counts),
+            )?;
+
+            match location {
+                ChunkLocation::Scanline { y } => dst
+                    .write_deep_scanline_chunk(
+                        part_index,
+                        y,
+                        &packed_data,
+                        chunk_info.unpacked_size,
+                        &sample_counts,
+                    )?,
+                ChunkLocation::Tile {
+                    x,
+                    y,
+                    level_x,
+                    level_y,
+                } => dst.write_deep_tile_chunk(
+                    part_index,
+                    x,
+                    y,
+                    level_x,
+                    level_y,
+                    &packed_data,
+                    chunk_info.unpacked_size,
+                    &sample_counts,
+                )?,
+            }
+            tracker.chunk_done()?;
+        }
+    }
+
+    Ok(())
+}
+
+/// The values of one channel of a level, in the channel's own type, one line
+/// after another
+///
+struct Plane {
+    name: String,
+    pixel_type: PixelType,
+    bytes_per_element: usize,
+    /// Pixel coordinates of the top-left of the level
+    origin: (i32, i32),
+    sampling: (i32, i32),
+    /// Number of values in a line, once sampling is taken into account
+    width: usize,
+    data: Vec<u8>,
+}
+
+impl Plane {
+    /// Allocate a plane for each channel of the given level of a part
+    ///
+    fn for_level(
+        ctx: &ReadContext,
+        part_index: usize,
+        (level_x, level_y): (usize, usize),
+    ) -> Result<Vec<Plane>> {
+        let dw = ctx.data_window::<[i32; 4]>(part_index)?;
+        let (width, height) = match ctx.storage(part_index)? {
+            Storage::Tiled => ctx.level_sizes(part_index, level_x, level_y)?,
+            _ => (
+                (dw[2] - dw[0] + 1).max(0) as usize,
+                (dw[3] - dw[1] + 1).max(0) as usize,
+            ),
+        };
+
+        ctx.channels(part_index)?
+            .as_slice()
+            .iter()
+            .map(|channel| {
+                let pixel_type = channel.pixel_type()?;
+                let bytes_per_element = match pixel_type {
+                    PixelType::Half => 2,
+                    PixelType::Float | PixelType::Uint => 4,
+                };
+                let sampling = (channel.x_sampling(), channel.y_sampling());
+                let plane_width = sampled_count(dw[0], width, sampling.0);
+                let plane_height = sampled_count(dw[1], height, sampling.1);
+                Ok(Plane {
+                    name: channel.name().to_string(),
+                    pixel_type,
+                    bytes_per_element,
+                    origin: (dw[0], dw[1]),
+                    sampling,
+                    width: plane_width,
+                    data: vec![
+                        0;
+                        plane_width * plane_height * bytes_per_element
+                    ],
+                })
+            })
+            .collect()
+    }
+
+    /// Point `channel` at its values in this plane for the chunk whose
+    /// top-left pixel is at (`x`, `y`) relative to the level, returning the
+    /// offset of the first value
+    ///
+    /// # Errors
+    /// * `[Error::CorruptChunk]` - If the chunk would extend outside the plane
+    ///
+    fn select(
+        &self,
+        channel: &mut ChannelInfo,
+        x: usize,
+        y: usize,
+    ) -> Result<usize> {
+        let column = sampled_count(self.origin.0, x, self.sampling.0);
+        let row = sampled_count(self.origin.1, y, self.sampling.1);
+        let start = (row * self.width + column) * self.bytes_per_element;
+
+        let (width, height) = (channel.width(), channel.height());
+        if width > 0 && height > 0 {
+            let end = start
+                + ((height - 1) * self.width + width) * self.bytes_per_element;
+            if column + width > self.width || end > self.data.len() {
+                return Err(Error::CorruptChunk);
+            }
+        }
+
+        channel.set_user_data_type(self.pixel_type);
+        channel.set_user_bytes_per_element(self.bytes_per_element);
+        channel.set_user_pixel_stride(self.bytes_per_element);
+        channel.set_user_line_stride(self.width * self.bytes_per_element);
+        Ok(start)
+    }
+}
+
+/// Decode every chunk of a level into `planes`
+///
+fn decode_level(
+    ctx: &ReadContext,
+    part_index: usize,
+    level: (usize, usize),
+    planes: &mut [Plane],
+) -> Result<()> {
+    let mut pipeline: Option<DecodePipeline<Runnable>> = None;
+    for (location, x, y) in level_chunks(ctx, part_index, level)? {
+        let chunk_info = read_chunk_info(ctx, part_index, location)?;
+        let mut ready = match pipeline.take() {
+            Some(p) => p.update(&chunk_info)?,
+            None => ctx.decoding_initialize(part_index, &chunk_info)?,
+        };
+
+        for channel in ready.channels_mut() {
+            let plane = planes
+                .iter_mut()
+                .find(|p| p.name == channel.name())
+                .ok_or(Error::CorruptChunk)?;
+            let offset = plane.select(channel, x, y)?;
+            unsafe { channel.set_decode_to(plane.data[offset..].as_mut_ptr()) };
+        }
+
+        let mut runnable = ready.choose_default_routines()?;
+        // Safety: Plane::select has checked every channel is decoded to
+        // within its plane
+        unsafe { runnable.run()? };
+        pipeline = Some(runnable);
+    }
+
+    match pipeline {
+        Some(p) => p.destroy(),
+        None => Ok(()),
+    }
+}
+
+/// Encode and write every chunk of a level from `planes`
+///
+fn encode_level(
+    ctx: &mut WriteContext,
+    part_index: usize,
+    level: (usize, usize),
+    planes: &[Plane],
+    tracker: &mut Tracker,
+) -> Result<()> {
+    let mut encoder = EncodePipeline::default();
+    for (i, (location, x, y)) in level_chunks(ctx, part_index, level)?
+        .into_iter()
+        .enumerate()
+    {
+        let chunk_info = write_chunk_info(ctx, part_index, location)?;
+        if i == 0 {
+            ctx.encoding_initialize(part_index, &chunk_info, &mut encoder)?;
+        } else {
+            ctx.encoding_update(part_index, &chunk_info, &mut encoder)?;
+        }
+
+        for channel in encoder.channels_mut() {
+            let plane = planes
+                .iter()
+                .find(|p| p.name == channel.name())
+                .ok_or(Error::InvalidArgument)?;
+            let offset = plane.select(channel, x, y)?;
+            unsafe { channel.set_encode_from(plane.data[offset..].as_ptr()) };
+        }
+
+        ctx.encoding_choose_default_routines(part_index, &mut encoder)?;
+        // Safety: Plane::select has checked every channel is encoded from
+        // within its plane
+        unsafe { ctx.encoding_run(part_index, &mut encoder)? };
+        tracker.chunk_done()?;
+    }
+
+    ctx.encoding_destroy(encoder)
+}
+
+/// The chunks of a level in the order they must be written, along with the
+/// pixel coordinates of their top-left corners relative to the level
+///
+fn level_chunks<S: ContextState>(
+    ctx: &Context<S>,
+    part_index: usize,
+    (level_x, level_y): (usize, usize),
+) -> Result<Vec<(ChunkLocation, usize, usize)>> {
+    let reverse = ctx.lineorder(part_index)? == LineOrder::DecreasingY;
+
+    let mut chunks = Vec::new();
+    match ctx.storage(part_index)? {
+        Storage::Scanline | Storage::DeepScanline => {
+            let dw = ctx.data_window::<[i32; 4]>(part_index)?;
+            let lines = ctx.scanlines_per_chunk(part_index)?.max(1);
+            for y in (dw[1]..=dw[3]).step_by(lines) {
+                let location = ChunkLocation::Scanline { y };
+                chunks.push((location, 0, (y - dw[1]) as usize));
+            }
+            if reverse {
+                chunks.reverse();
+            }
+        }
+        Storage::Tiled | Storage::DeepTiled => {
+            let (width, height) =
+                ctx.level_sizes(part_index, level_x, level_y)?;
+            let (tile_width, tile_height) =
+                ctx.tile_sizes(part_index, level_x, level_y)?;
+            if tile_width == 0 || tile_height == 0 {
+                return Err(Error::MissingReqAttr);
+            }
+
+            let mut rows =
+                (0..num_tiles(height, tile_height)).collect::<Vec<_>>();
+            if reverse {
+                rows.reverse();
+            }
+            for y in rows {
+                for x in 0..num_tiles(width, tile_width) {
+                    let location = ChunkLocation::Tile {
+                        x,
+                        y,
+                        level_x: level_x as i32,
+                        level_y: level_y as i32,
+                    };
+                    chunks.push((
+                        location,
+                        x as usize * tile_width,
+                        y as usize * tile_height,
+                    ));
+                }
+            }
+        }
+    }
+
+    Ok(chunks)
+}
+
+fn read_chunk_info(
+    ctx: &ReadContext,
+    part_index: usize,
+    location: ChunkLocation,
+) -> Result<ChunkInfo> {
+    match location {
+        ChunkLocation::Scanline { y } => {
+            ctx.read_scanline_chunk_info(part_index, y)
+        }
+        ChunkLocation::Tile {
+            x,
+            y,
+            level_x,
+            level_y,
+        } => ctx.read_tile_chunk_info(part_index, x, y, level_x, level_y),
+    }
+}
+
+fn write_chunk_info(
+    ctx: &WriteContext,
+    part_index: usize,
+    location: ChunkLocation,
+) -> Result<ChunkInfo> {
+    match location {
+        ChunkLocation::Scanline { y } => {
+            ctx.write_scanline_chunk_info(part_index, y)
+        }
+        ChunkLocation::Tile {
+            x,
+            y,
+            level_x,
+            level_y,
+        } => ctx.write_tile_chunk_info(part_index, x, y, level_x, level_y),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate as exr;
+    use exr::attr::{
+        Compression, LevelMode, Storage, TileDescriptor, TileRoundMode,
+    };
+    use exr::context::ReadContext;
+    use exr::transcode::{transcode, Tiling, TranscodeOptions};
+    use std::path::{Path, PathBuf};
+
+    fn image_path(name: &str) -> PathBuf {
+        Path::new(
+            &std::env::var("CARGO_MANIFEST_DIR")
+                .expect("CARGO_MANIFEST_DIR not set"),
+        )
+        .join("images")
+        .join(name)
+    }
+
+    #[test]
+    fn transcode_compression() -> Result<(), exr::Error> {
+        let path_ferris = image_path("ferris.exr");
+        let path = std::env::temp_dir().join("transcode_compression.exr");
+        transcode(
+            &path_ferris,
+            &path,
+            TranscodeOptions {
+                compression: Some(Compression::Zip),
+                ..Default::default()
+            },
+        )?;
+
+        let src = ReadContext::new(&path_ferris)?;
+        let dst = ReadContext::new(&path)?;
+        assert_eq!(dst.compression(0)?, Compression::Zip);
+        assert_eq!(dst.name(0)?, src.name(0)?);
+        assert_eq!(dst.attribute_count(0)?, src.attribute_count(0)?);
+
+        let channels = ["R", "G", "B", "A"];
+        assert_eq!(
+            src.read_image::<f32>(0, &channels)?.pixels,
+            dst.read_image::<f32>(0, &channels)?.pixels
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn transcode_tiling() -> Result<(), exr::Error> {
+        let path_ferris = image_path("ferris.exr");
+        let path = std::env::temp_dir().join("transcode_tiling.exr");
+        transcode(
+            &path_ferris,
+            &path,
+            TranscodeOptions {
+                tiling: Some(Tiling::Tiled(TileDescriptor {
+                    x_size: 32,
+                    y_size: 32,
+                    level_mode: LevelMode::OneLevel,
+                    round_mode: TileRoundMode::RoundDown,
+                })),
+                ..Default::default()
+            },
+        )?;
+
+        let src = ReadContext::new(&path_ferris)?;
+        let dst = ReadContext::new(&path)?;
+        assert_eq!(dst.storage(0)?, Storage::Tiled);
+        assert_eq!(dst.tile_sizes(0, 0, 0)?, (32, 32));
+
+        let channels = ["R", "G", "B", "A"];
+        assert_eq!(
+            src.read_image::<f32>(0, &channels)?.pixels,
+            dst.read_image::<f32>(0, &channels)?.pixels
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn transcode_scanline() -> Result<(), exr::Error> {
+        use exr::part::AttrListAccessMode;
+
+        let path_tiled = image_path("ferris-tiled.exr");
+        let path = std::env::temp_dir().join("transcode_scanline.exr");
+        transcode(
+            &path_tiled,
+            &path,
+            TranscodeOptions {
+                tiling: Some(Tiling::Scanline),
+                ..Default::default()
+            },
+        )?;
+
+        let src = ReadContext::new(&path_tiled)?;
+        let dst = ReadContext::new(&path)?;
+        assert_eq!(dst.storage(0)?, Storage::Scanline);
+        let layout = ["tiles", "type", "chunkCount"];
+        for attr in src.attribute_list(0, AttrListAccessMode::FileOrder)? {
+            if !layout.contains(&attr.name()) {
+                let copied = dst.get_attribute_by_name(0, attr.name())?;
+                assert_eq!(copied.value(), attr.value());
+            }
+        }
+        assert_eq!(
+            dst.get_attribute_by_name(0, "tiles")
+                .err()
+                .map(|e| e.kind()),
+            Some(exr::ErrorKind::NoAttrByName)
+        );
+        if let Ok(count) = dst.get_attribute::<i32>(0, "chunkCount") {
+            assert_eq!(count as usize, dst.chunk_count(0)?);
+        }
+
+        let channels = ["R", "G", "B", "A"];
+        assert_eq!(
+            src.read_image::<f32>(0, &channels)?.pixels,
+            dst.read_image::<f32>(0, &channels)?.pixels
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn transcode_deep() -> Result<(), exr::Error> {
+        let path_deep = image_path("deep_plane.exr");
+        let path = std::env::temp_dir().join("transcode_deep.exr");
+        transcode(&path_deep, &path, TranscodeOptions::default())?;
+
+        let src = ReadContext::new(&path_deep)?;
+        let dst = ReadContext::new(&path)?;
+        assert_eq!(dst.storage(0)?, src.storage(0)?);
+        assert_eq!(dst.chunk_count(0)?, src.chunk_count(0)?);
+        assert!(dst.validate(0)?.is_valid());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,307 @@
+//! Reading chunks with io_uring on Linux, enabled with the `io-uring` feature.
+//!
+//! [`UringReader`] looks up where every chunk of a part is in the file from
+//! its chunk table, then submits batches of reads for them to an io_uring,
+//! decoding each chunk as soon as its read completes. The C core reads each
+//! chunk's data through stream callbacks that hand it the buffer the ring
+//! filled, and anything else, such as the header, is read directly from the
+//! file.
+//!
+use crate::coding::ChannelElement;
+use crate::context::ReadContext;
+use crate::decode::{DecodePipeline, Runnable};
+use crate::error::Error;
+use crate::read::{decode_chunk_into, part_chunks, ImageBuffer};
+use crate::stream::{stream, StreamCallbacks};
+use io_uring::{opcode, types, IoUring};
+use openexr_core_sys as sys;
+use std::collections::HashMap;
+use std::ffi::c_void;
+use std::fs::File;
+use std::os::unix::fs::FileExt;
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// The file being read, and the chunk data the ring has read from it that is
+/// waiting to be decoded, keyed by offset
+///
+struct UringStream {
+    file: File,
+    chunks: Mutex<HashMap<u64, Vec<u8>>>,
+}
+
+/// Reads parts of a file by submitting reads for many chunks at once to an
+/// io_uring
+///
+pub struct UringReader {
+    stream: Arc<UringStream>,
+    ring: IoUring,
+    queue_depth: u32,
+    ctx: ReadContext,
+}
+
+impl UringReader {
+    /// Open the file at `path`, with a ring that has up to `queue_depth`
+    /// chunk reads in flight at once
+    ///
+    /// # Errors
+    /// * `[Error::FileAccess]` - If the file could not be opened
+    /// * `[Error::OutOfMemory]` - If the ring could not be created
+    ///
+    pub fn open<P: AsRef<Path>>(
+        path: P,
+        queue_depth: u32,
+    ) -> Result<UringReader> {
+        let file = File::open(path).map_err(|_| Error::FileAccess)?;
+        let queue_depth = queue_depth.max(1);
+        let ring = IoUring::new(queue_depth).map_err(|_| Error::OutOfMemory)?;
+
+        let stream = Arc::new(UringStream {
+            file,
+            chunks: Mutex::new(HashMap::new()),
+        });
+        let callbacks = StreamCallbacks {
+            read_fn: Some(uring_read),
+            size_fn: Some(uring_size),
+            ..StreamCallbacks::new(stream.clone(), None)
+        };
+
+        // Safety: the callbacks only ever treat the user data as an
+        // Arc<UringStream>
+        let ctx = unsafe { ReadContext::from_stream("<io_uring>", callbacks)? };
+
+        Ok(UringReader {
+            stream,
+            ring,
+            queue_depth,
+            ctx,
+        })
+    }
+
+    /// The underlying context, for querying the header
+    ///
+    pub fn context(&self) -> &ReadContext {
+        &self.ctx
+    }
+
+    /// Decode the whole of a part into an interleaved buffer of the channels
+    /// named in `channels`, converted to `T`, as for
+    /// [`ReadContext::read_image`]
+    ///
+    /// Chunks are decoded in the order their reads complete.
+    ///
+    /// # Errors
+    /// * `[Error::ReadIo]` - If reading a chunk fails
+    /// * `[Error::FeatureNotImplemented]` - If the part holds deep data
+    ///
+    pub fn read_image<T: ChannelElement>(
+        &mut self,
+        part_index: usize,
+        channels: &[&str],
+    ) -> Result<ImageBuffer<T>> {
+        let ctx = &self.ctx;
+        let dw = ctx.data_window::<[i32; 4]>(part_index)?;
+        let width = (dw[2] - dw[0] + 1).max(0) as usize;
+        let height = (dw[3] - dw[1] + 1).max(0) as usize;
+        let chunks = part_chunks(ctx, part_index)?;
+
+        let mut image = ImageBuffer {
+            width,
+            height,
+            data_window: dw,
+            channels: channels.iter().map(|c| c.to_string()).collect(),
+            pixels: vec![T::default(); width * height * channels.len()],
+        };
+
+        let fd = types::Fd(self.stream.file.as_raw_fd());
+        let ring = &mut self.ring;
+        let stream = &self.stream;
+        let queue_depth = self.queue_depth as usize;
+        let mut in_flight: HashMap<u64, Vec<u8>> = HashMap::new();
+        let mut pipeline: Option<DecodePipeline<Runnable>> = None;
+        let mut next = 0;
+
+        let mut run = || -> Result<()> {
+            while next < chunks.len() || !in_flight.is_empty() {
+                // keep the ring full
+                while next < chunks.len() && in_flight.len() < queue_depth {
+                    let chunk_info = &chunks[next].0;
+                    let len = chunk_info.packed_size as usize;
+                    let mut buffer = vec![0u8; len];
+                    let entry =
+                        opcode::Read::new(fd, buffer.as_mut_ptr(), len as u32)
+                            .offset64(chunk_info.data_offset as i64)
+                            .build()
+                            .user_data(next as u64);
+                    // Safety: the buffer is kept in in_flight, so stays alive
+                    // and unmoved until its read completes
+                    unsafe {
+                        ring.submission()
+                            .push(&entry)
+                            .map_err(|_| Error::ReadIo)?;
+                    }
+                    in_flight.insert(next as u64, buffer);
+                    next += 1;
+                }
+
+                ring.submit_and_wait(1).map_err(|_| Error::ReadIo)?;
+
+                // take one completion at a time, so that every read that has
+                // completed is out of in_flight before any error is returned
+                while let Some((index, result)) = ring
+                    .completion()
+                    .next()
+                    .map(|cqe| (cqe.user_data(), cqe.result()))
+                {
+                    let mut buffer =
+                        in_flight.remove(&index).ok_or(Error::ReadIo)?;
+                    if result < 0 {
+                        return Err(Error::ReadIo);
+                    }
+
+                    let (chunk_info, origin) = &chunks[index as usize];
+                    // finish any short read directly
+                    let read = result as usize;
+                    if read < buffer.len() {
+                        stream
+                            .file
+                            .read_exact_at(
+                                &mut buffer[read..],
+                                chunk_info.data_offset + read as u64,
+                            )
+                            .map_err(|_| Error::ReadIo)?;
+                    }
+
+                    let offset = chunk_info.data_offset;
+                    stream.chunks.lock().unwrap().insert(offset, buffer);
+                    let result = decode_chunk_into(
+                        ctx,
+                        &mut pipeline,
+                        part_index,
+                        chunk_info,
+                        None,
+                        channels,
+                        &mut image,
+                        *origin,
+                    );
+                    stream.chunks.lock().unwrap().remove(&offset);
+                    result?;
+                }
+            }
+            Ok(())
+        };
+        let result = run();
+
+        // the kernel may still be writing to the buffers of any reads left
+        // in flight after an error, so wait for them before freeing them
+        while !in_flight.is_empty() {
+            if ring.submit_and_wait(1).is_err() {
+                std::mem::forget(in_flight);
+                break;
+            }
+            for cqe in ring.completion() {
+                in_flight.remove(&cqe.user_data());
+            }
+        }
+
+        result?;
+        Ok(image)
+    }
+}
+
+unsafe extern "C" fn uring_read(
+    _ctxt: sys::exr_const_context_t,
+    userdata: *mut c_void,
+    buffer: *mut c_void,
+    sz: u64,
+    offset: u64,
+    _error_cb: sys::exr_stream_error_func_ptr_t,
+) -> i64 {
+    let stream = stream::<Arc<UringStream>>(userdata);
+    let buffer = std::slice::from_raw_parts_mut(buffer as *mut u8, sz as usize);
+
+    // chunk data the ring has already read
+    if let Ok(chunks) = stream.chunks.lock() {
+        if let Some(data) = chunks.get(&offset) {
+            if data.len() as u64 >= sz {
+                buffer.copy_from_slice(&data[..buffer.len()]);
+                return sz as i64;
+            }
+        }
+    }
+
+    // anything else, such as the header and chunk table
+    let mut len = 0;
+    while len < buffer.len() {
+        match stream.file.read_at(&mut buffer[len..], offset + len as u64) {
+            Ok(0) => break,
+            Ok(n) => len += n,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => (),
+            Err(_) => return -1,
+        }
+    }
+    len as i64
+}
+
+unsafe extern "C" fn uring_size(
+    _ctxt: sys::exr_const_context_t,
+    userdata: *mut c_void,
+) -> i64 {
+    let stream = stream::<Arc<UringStream>>(userdata);
+    stream.file.metadata().map(|m| m.len() as i64).unwrap_or(-1)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate as exr;
+    use exr::uring::UringReader;
+    use imath_traits::f16;
+    use std::path::Path;
+
+    #[test]
+    fn uring_read_image() -> Result<(), exr::Error> {
+        let path_ferris = Path::new(
+            &std::env::var("CARGO_MANIFEST_DIR")
+                .expect("CARGO_MANIFEST_DIR not set"),
+        )
+        .join("images")
+        .join("ferris.exr");
+
+        let mut reader = UringReader::open(&path_ferris, 8)?;
+        let image = reader.read_image::<f16>(0, &["R", "G", "B", "A"])?;
+
+        let ctx = exr::context::ReadContext::new(&path_ferris)?;
+        assert_eq!(image, ctx.read_image(0, &["R", "G", "B", "A"])?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn uring_read_truncated() -> Result<(), exr::Error> {
+        let path_ferris = Path::new(
+            &std::env::var("CARGO_MANIFEST_DIR")
+                .expect("CARGO_MANIFEST_DIR not set"),
+        )
+        .join("images")
+        .join("ferris.exr");
+        let path = std::env::temp_dir().join("uring_read_truncated.exr");
+        std::fs::copy(&path_ferris, &path).unwrap();
+
+        // cut off the last chunks once the header has been read, so some of
+        // the reads in flight fail
+        let mut reader = UringReader::open(&path, 8)?;
+        let len = std::fs::metadata(&path).unwrap().len();
+        std::fs::OpenOptions::new()
+            .write(true)
+            .open(&path)
+            .and_then(|f| f.set_len(len * 3 / 5))
+            .unwrap();
+
+        assert!(reader.read_image::<f16>(0, &["R", "G", "B", "A"]).is_err());
+
+        Ok(())
+    }
+}
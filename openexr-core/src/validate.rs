@@ -0,0 +1,339 @@
+//! Checking that the chunks of a file can be read, in the spirit of the
+//! `exrcheck` tool.
+//!
+use crate::attr::{LevelMode, Storage};
+use crate::chunkio::ChunkInfo;
+use crate::context::{Context, ContextState, ReadContext};
+use crate::error::{Error, ErrorKind};
+
+type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// Where a chunk is in a part
+///
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ChunkLocation {
+    /// The scanline chunk containing line `y`
+    Scanline { y: i32 },
+    /// The tile at (`x`, `y`) in level (`level_x`, `level_y`)
+    Tile {
+        x: i32,
+        y: i32,
+        level_x: i32,
+        level_y: i32,
+    },
+}
+
+/// Something wrong with a chunk
+///
+#[derive(Debug, Clone, PartialEq)]
+pub enum ChunkProblem {
+    /// The chunk's offset or leader could not be read, or doesn't match what
+    /// the header says the chunk should be
+    BadLeader(Error),
+    /// The chunk's data runs past the end of the file
+    Truncated,
+    /// The chunk's data could not be read for some other reason
+    Unreadable(Error),
+    /// The chunk's data overlaps that of the chunk at `other`
+    Overlaps { other: ChunkLocation },
+}
+
+/// A problem with a single chunk
+///
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChunkIssue {
+    pub location: ChunkLocation,
+    pub problem: ChunkProblem,
+}
+
+/// The result of [`ReadContext::validate`]
+///
+#[derive(Debug, Clone, PartialEq)]
+pub struct ValidationReport {
+    pub part_index: usize,
+    /// The number of chunks the header says the part has
+    pub chunk_count: usize,
+    /// The number of chunks that were checked, which differs from
+    /// `chunk_count` if the header is inconsistent
+    pub chunks_checked: usize,
+    pub issues: Vec<ChunkIssue>,
+}
+
+impl ValidationReport {
+    /// Were all the chunks fine?
+    ///
+    pub fn is_valid(&self) -> bool {
+        self.issues.is_empty() && self.chunks_checked == self.chunk_count
+    }
+}
+
+impl ReadContext {
+    /// Check every chunk of a part, reporting any whose leader doesn't match
+    /// the header, that are truncated, or that overlap other chunks
+    ///
+    /// Each chunk's data is read, but not decoded. For deep parts only the
+    /// chunk leaders and offsets are checked.
+    ///
+    /// # Errors
+    /// Problems with chunks are reported in the [`ValidationReport`]. An
+    /// error is only returned if the part's header can't be queried.
+    /// * `[Error::ArgumentOutOfRange]` - If the part does not exist
+    ///
+    pub fn validate(&self, part_index: usize) -> Result<ValidationReport> {
+        let mut report = ValidationReport {
+            part_index,
+            chunk_count: self.chunk_count(part_index)?,
+            chunks_checked: 0,
+            issues: Vec::new(),
+        };
+
+        let deep = matches!(
+            self.storage(part_index)?,
+            Storage::DeepScanline | Storage::DeepTiled
+        );
+        let mut ranges = Vec::new();
+        let mut buffer = Vec::new();
+        for location in self.chunk_locations(part_index)? {
+            report.chunks_checked += 1;
+
+            let chunk_info = match location {
+                ChunkLocation::Scanline { y } => {
+                    self.read_scanline_chunk_info(part_index, y)
+                }
+                ChunkLocation::Tile {
+                    x,
+                    y,
+                    level_x,
+                    level_y,
+                } => self
+                    .read_tile_chunk_info(part_index, x, y, level_x, level_y),
+            };
+            let chunk_info = match chunk_info {
+                Ok(chunk_info) => chunk_info,
+                Err(e) => {
+                    report.issues.push(ChunkIssue {
+                        location,
+                        problem: ChunkProblem::BadLeader(e),
+                    });
+                    continue;
+                }
+            };
+
+            if !deep {
+                if let Err(problem) =
+                    self.check_chunk_data(part_index, &chunk_info, &mut buffer)
+                {
+                    report.issues.push(ChunkIssue { location, problem });
+                    continue;
+                }
+            }
+
+            let start = chunk_info.data_offset;
+            ranges.push((start, start + chunk_info.packed_size, location));
+        }
+
+        // any chunk that starts before the end of the previous one overlaps it
+        ranges.sort_by_key(|(start, _, _)| *start);
+        for pair in ranges.windows(2) {
+            let (_, prev_end, prev) = pair[0];
+            let (start, _, location) = pair[1];
+            if start < prev_end {
+                report.issues.push(ChunkIssue {
+                    location,
+                    problem: ChunkProblem::Overlaps { other: prev },
+                });
+            }
+        }
+
+        Ok(report)
+    }
+
+    /// Read the packed data of a chunk to make sure it's there
+    ///
+    fn check_chunk_data(
+        &self,
+        part_index: usize,
+        chunk_info: &ChunkInfo,
+        buffer: &mut Vec<u8>,
+    ) -> Result<(), ChunkProblem> {
+        buffer.resize(chunk_info.packed_size as usize, 0);
+        // Safety: the buffer has just been sized for the chunk
+        match unsafe { self.read_chunk(part_index, chunk_info, buffer) } {
+            Ok(()) => Ok(()),
+            Err(e) if e.kind() == ErrorKind::ReadIo => {
+                Err(ChunkProblem::Truncated)
+            }
+            Err(e) => Err(ChunkProblem::Unreadable(e)),
+        }
+    }
+
+    /// The locations of every chunk the header says a part should have
+    ///
+    pub(crate) fn chunk_locations(
+        &self,
+        part_index: usize,
+    ) -> Result<Vec<ChunkLocation>> {
+        let mut locations = Vec::new();
+        match self.storage(part_index)? {
+            Storage::Scanline | Storage::DeepScanline => {
+                let dw = self.data_window::<[i32; 4]>(part_index)?;
+                let lines = self.scanlines_per_chunk(part_index)?.max(1);
+                for y in (dw[1]..=dw[3]).step_by(lines) {
+                    locations.push(ChunkLocation::Scanline { y });
+                }
+            }
+            Storage::Tiled | Storage::DeepTiled => {
+                for (level_x, level_y) in part_levels(self, part_index)? {
+                    let (tiles_x, tiles_y) =
+                        self.tile_counts(part_index, level_x, level_y)?;
+                    for y in 0..tiles_y as i32 {
+                        for x in 0..tiles_x as i32 {
+                            locations.push(ChunkLocation::Tile {
+                                x,
+                                y,
+                                level_x: level_x as i32,
+                                level_y: level_y as i32,
+                            });
+                        }
+                    }
+                }
+            }
+        }
+        Ok(locations)
+    }
+}
+
+/// The levels of a part in the order their chunks are stored, which is just
+/// level (0, 0) for scanline parts
+///
+pub(crate) fn part_levels<S: ContextState>(
+    ctx: &Context<S>,
+    part_index: usize,
+) -> Result<Vec<(usize, usize)>> {
+    match ctx.storage(part_index)? {
+        Storage::Scanline | Storage::DeepScanline => Ok(vec![(0, 0)]),
+        Storage::Tiled | Storage::DeepTiled => {
+            let (levels_x, levels_y) = ctx.tile_levels(part_index)?;
+            Ok(match ctx.tile_descriptor(part_index)?.level_mode {
+                LevelMode::OneLevel => vec![(0, 0)],
+                LevelMode::MipmapLevels => {
+                    (0..levels_x).map(|l| (l, l)).collect()
+                }
+                LevelMode::RipmapLevels => (0..levels_y)
+                    .flat_map(|ly| (0..levels_x).map(move |lx| (lx, ly)))
+                    .collect(),
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate as exr;
+    use exr::chunkio::ChunkEntry;
+    use exr::context::ReadContext;
+    use exr::validate::{ChunkIssue, ChunkProblem};
+    use std::path::Path;
+
+    #[test]
+    fn validate() -> Result<(), exr::Error> {
+        let path_ferris = Path::new(
+            &std::env::var("CARGO_MANIFEST_DIR")
+                .expect("CARGO_MANIFEST_DIR not set"),
+        )
+        .join("images")
+        .join("ferris.exr");
+
+        let ctx = ReadContext::new(&path_ferris)?;
+        let report = ctx.validate(0)?;
+        assert!(report.is_valid());
+        assert_eq!(report.chunks_checked, report.chunk_count);
+        assert!(report.issues.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn validate_problems() -> Result<(), exr::Error> {
+        use exr::attr::Compression;
+        use exr::transcode::{transcode, TranscodeOptions};
+
+        let path_ferris = Path::new(
+            &std::env::var("CARGO_MANIFEST_DIR")
+                .expect("CARGO_MANIFEST_DIR not set"),
+        )
+        .join("images")
+        .join("ferris.exr");
+
+        // ZIP chunks are stored at whatever size they compress to, so their
+        // sizes can be changed without contradicting the header
+        let path = std::env::temp_dir().join("validate_problems.exr");
+        transcode(
+            &path_ferris,
+            &path,
+            TranscodeOptions {
+                compression: Some(Compression::Zip),
+                ..Default::default()
+            },
+        )?;
+        let bytes = std::fs::read(&path).unwrap();
+        let mut table = ReadContext::new(&path)?.chunk_table(0)?;
+        table.sort_by_key(|entry| entry.offset);
+
+        // the data of each chunk follows its first line and packed size
+        let patch = |entry: &ChunkEntry, field: usize, value: i32| {
+            let at = entry.offset as usize - 8 + field * 4;
+            let mut bytes = bytes.clone();
+            bytes[at..at + 4].copy_from_slice(&value.to_le_bytes());
+            bytes
+        };
+        let (first, second) = (&table[0], &table[1]);
+
+        // a chunk that says it starts on a different line
+        let ctx = ReadContext::from_memory(patch(first, 0, -1000))?;
+        let report = ctx.validate(0)?;
+        assert!(!report.is_valid());
+        assert_eq!(report.chunks_checked, report.chunk_count);
+        match report.issues.as_slice() {
+            [ChunkIssue {
+                location,
+                problem: ChunkProblem::BadLeader(e),
+            }] => {
+                assert_eq!(*location, first.location);
+                assert_eq!(e.kind(), exr::ErrorKind::BadChunkLeader);
+            }
+            issues => panic!("expected a bad leader, got {:?}", issues),
+        }
+
+        // a chunk that runs into the next one
+        let size = first.packed_size as i32 + 16;
+        let ctx = ReadContext::from_memory(patch(first, 1, size))?;
+        let report = ctx.validate(0)?;
+        assert!(!report.is_valid());
+        assert_eq!(
+            report.issues,
+            [ChunkIssue {
+                location: second.location,
+                problem: ChunkProblem::Overlaps {
+                    other: first.location
+                },
+            }]
+        );
+
+        // a file cut off part way through its last chunk
+        let last = table.last().unwrap();
+        let mut cut = bytes.clone();
+        cut.truncate((last.offset + last.packed_size / 2) as usize);
+        let report = ReadContext::from_memory(cut)?.validate(0)?;
+        assert!(!report.is_valid());
+        assert_eq!(
+            report.issues,
+            [ChunkIssue {
+                location: last.location,
+                problem: ChunkProblem::Truncated,
+            }]
+        );
+
+        Ok(())
+    }
+}
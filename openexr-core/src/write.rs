@@ -0,0 +1,708 @@
+//! Higher-level conveniences for writing pixels without having to manage
+//! chunks and encode pipelines by hand.
+//!
+use crate::attr::{LineOrder, PixelType, Storage};
+use crate::chunkio::ChunkInfo;
+use crate::context::WriteContext;
+use crate::encode::EncodePipeline;
+use crate::error::Error;
+use crate::framebuffer::FrameBuffer;
+use crate::progress::{Progress, Tracker};
+use crate::read::num_tiles;
+use std::ops::ControlFlow;
+
+type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// Describes the layout of an interleaved buffer of pixels passed to
+/// [`WriteContext::write_image`]
+///
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImageDesc {
+    /// Width of the image in pixels. Must match the part's data window
+    pub width: usize,
+    /// Height of the image in pixels. Must match the part's data window
+    pub height: usize,
+    /// The names of the channels in the buffer, in the order they are
+    /// interleaved
+    pub channels: Vec<String>,
+    /// The type of every element in the buffer. This is converted to the
+    /// type of each channel in the part as it is encoded
+    pub pixel_type: PixelType,
+}
+
+impl ImageDesc {
+    pub fn new(
+        width: usize,
+        height: usize,
+        channels: &[&str],
+        pixel_type: PixelType,
+    ) -> ImageDesc {
+        ImageDesc {
+            width,
+            height,
+            channels: channels.iter().map(|c| c.to_string()).collect(),
+            pixel_type,
+        }
+    }
+
+    /// Size in bytes of a single channel value in the buffer
+    ///
+    pub fn bytes_per_element(&self) -> usize {
+        match self.pixel_type {
+            PixelType::Half => 2,
+            PixelType::Float | PixelType::Uint => 4,
+        }
+    }
+
+    /// Size in bytes of a single pixel in the buffer
+    ///
+    pub fn pixel_bytes(&self) -> usize {
+        self.bytes_per_element() * self.channels.len()
+    }
+
+    /// Size in bytes of a single line in the buffer
+    ///
+    pub fn line_bytes(&self) -> usize {
+        self.pixel_bytes() * self.width
+    }
+}
+
+impl WriteContext {
+    /// Encode and write the whole of a part from an interleaved buffer of
+    /// pixels laid out as described by `desc`, a line at a time from the top
+    /// of the data window
+    ///
+    /// Every channel in the part must be present in the buffer. Scanline parts
+    /// are split into chunks, and tiled parts into tiles, which are written
+    /// in the order required by the part's line order.
+    ///
+    /// # Errors
+    /// * `[Error::InvalidArgument]` - If the size of the image or buffer does
+    /// not match the part, or a channel in the part is missing from the buffer
+    /// * `[Error::FeatureNotImplemented]` - If the part holds deep data, or is
+    /// tiled with more than one level
+    ///
+    pub fn write_image(
+        &mut self,
+        part_index: usize,
+        desc: &ImageDesc,
+        pixels: &[u8],
+    ) -> Result<()> {
+        self.write_image_with_progress(part_index, desc, pixels, |_| {
+            ControlFlow::Continue(())
+        })
+    }
+
+    /// Encode and write the whole of a part as for
+    /// [`WriteContext::write_image`], calling `progress` each time a chunk
+    /// has been written
+    ///
+    /// Cancelling leaves the part partly written, so the file can only be
+    /// discarded.
+    ///
+    /// # Errors
+    /// * `[Error::InvalidArgument]` - If the size of the image or buffer does
+    /// not match the part, or a channel in the part is missing from the buffer
+    /// * `[Error::FeatureNotImplemented]` - If the part holds deep data, or is
+    /// tiled with more than one level
+    /// * `[Error::Cancelled]` - If `progress` returned
+    /// [`ControlFlow::Break`]
+    ///
+    pub fn write_image_with_progress<F>(
+        &mut self,
+        part_index: usize,
+        desc: &ImageDesc,
+        pixels: &[u8],
+        mut progress: F,
+    ) -> Result<()>
+    where
+        F: FnMut(Progress) -> ControlFlow<()>,
+    {
+        let chunks = self.image_chunks(part_index, desc, pixels)?;
+        let mut tracker = Tracker::new(&mut progress, chunks.len());
+
+        let mut encoder = EncodePipeline::default();
+        for (i, chunk) in chunks.iter().enumerate() {
+            let chunk_info = &chunk.info;
+            if i == 0 {
+                self.encoding_initialize(part_index, chunk_info, &mut encoder)?;
+            } else {
+                self.encoding_update(part_index, chunk_info, &mut encoder)?;
+            }
+
+            let (x, y) = chunk.origin;
+            set_encode_from(&mut encoder, chunk_info, desc, pixels, x, y)?;
+
+            self.encoding_choose_default_routines(part_index, &mut encoder)?;
+            // Safety: set_encode_from has checked every channel reads from
+            // within pixels
+            unsafe { self.encoding_run(part_index, &mut encoder)? };
+            tracker.chunk_done()?;
+        }
+
+        self.encoding_destroy(encoder)
+    }
+}
+
+/// A chunk of an image being written by [`WriteContext::write_image`]
+///
+pub(crate) struct ImageChunk {
+    pub(crate) info: ChunkInfo,
+    /// The pixel coordinates of the chunk's top-left corner relative to the
+    /// data window
+    pub(crate) origin: (usize, usize),
+}
+
+impl WriteContext {
+    /// Work out the chunks to write to cover the whole of a part with the
+    /// `pixels` of an image described by `desc`, in the order the part's line
+    /// order requires
+    ///
+    /// # Errors
+    /// * `[Error::InvalidArgument]` - If the size of the image or buffer does
+    /// not match the part
+    /// * `[Error::FeatureNotImplemented]` - If the part holds deep data, or is
+    /// tiled with more than one level
+    ///
+    pub(crate) fn image_chunks(
+        &self,
+        part_index: usize,
+        desc: &ImageDesc,
+        pixels: &[u8],
+    ) -> Result<Vec<ImageChunk>> {
+        let dw = self.data_window::<[i32; 4]>(part_index)?;
+        if (dw[2] - dw[0] + 1) as usize != desc.width
+            || (dw[3] - dw[1] + 1) as usize != desc.height
+            || pixels.len() != desc.line_bytes() * desc.height
+        {
+            return Err(Error::InvalidArgument);
+        }
+
+        let reverse = self.lineorder(part_index)? == LineOrder::DecreasingY;
+
+        let chunks = match self.storage(part_index)? {
+            Storage::Scanline => {
+                let mut chunks = Vec::new();
+                let mut y = dw[1];
+                while y <= dw[3] {
+                    let info = self.write_scanline_chunk_info(part_index, y)?;
+                    y = info.start_y + info.height;
+                    let start_y = (info.start_y - dw[1]) as usize;
+                    chunks.push(ImageChunk {
+                        info,
+                        origin: (0, start_y),
+                    });
+                }
+                if reverse {
+                    chunks.reverse();
+                }
+                chunks
+            }
+            Storage::Tiled => {
+                if self.tile_levels(part_index)? != (1, 1) {
+                    return Err(Error::FeatureNotImplemented);
+                }
+                let (tile_width, tile_height) =
+                    self.tile_sizes(part_index, 0, 0)?;
+                let tiles_x = num_tiles(desc.width, tile_width);
+                let tiles_y = num_tiles(desc.height, tile_height);
+
+                let mut rows = (0..tiles_y).collect::<Vec<_>>();
+                if reverse {
+                    rows.reverse();
+                }
+
+                let mut chunks = Vec::new();
+                for tile_y in rows {
+                    for tile_x in 0..tiles_x {
+                        chunks.push(ImageChunk {
+                            info: self.write_tile_chunk_info(
+                                part_index, tile_x, tile_y, 0, 0,
+                            )?,
+                            origin: (
+                                tile_x as usize * tile_width,
+                                tile_y as usize * tile_height,
+                            ),
+                        });
+                    }
+                }
+                chunks
+            }
+            Storage::DeepScanline | Storage::DeepTiled => {
+                return Err(Error::FeatureNotImplemented)
+            }
+        };
+
+        Ok(chunks)
+    }
+}
+
+/// Writes the parts of a file one after the other, as the file format
+/// requires, keeping track of which chunks of the current part have been
+/// written
+///
+/// Every write goes to the current part, so chunks can't be written to the
+/// wrong part, and [`finish_part`](PartWriter::finish_part) moves on to the
+/// next part once every chunk of the current one has been written. The C
+/// core still checks that chunks are written in the order the part's line
+/// order requires.
+///
+pub struct PartWriter<'a> {
+    ctx: &'a mut WriteContext,
+    part_index: usize,
+    written: Vec<bool>,
+    remaining: usize,
+}
+
+impl WriteContext {
+    /// Start writing the parts of the file in order, beginning with the
+    /// first
+    ///
+    /// This must be called before any chunks have been written.
+    ///
+    /// # Errors
+    /// * `[Error::ArgumentOutOfRange]` - If the file has no parts
+    ///
+    pub fn part_writer(&mut self) -> Result<PartWriter<'_>> {
+        PartWriter::new(self, 0)
+    }
+}
+
+impl<'a> PartWriter<'a> {
+    fn new(ctx: &'a mut WriteContext, part_index: usize) -> Result<Self> {
+        let chunk_count = ctx.chunk_count(part_index)?;
+        Ok(PartWriter {
+            ctx,
+            part_index,
+            written: vec![false; chunk_count],
+            remaining: chunk_count,
+        })
+    }
+
+    /// The index of the part being written
+    ///
+    pub fn part_index(&self) -> usize {
+        self.part_index
+    }
+
+    /// The number of chunks of the current part still to be written
+    ///
+    pub fn remaining_chunks(&self) -> usize {
+        self.remaining
+    }
+
+    /// The context being written to, e.g. to query the current part's header
+    ///
+    pub fn context(&self) -> &WriteContext {
+        self.ctx
+    }
+
+    /// Check that the chunk described by `chunk_info` has not been written
+    /// yet
+    ///
+    fn check_unwritten(&self, chunk_info: &ChunkInfo) -> Result<usize> {
+        let idx = chunk_info.idx as usize;
+        match self.written.get(idx) {
+            Some(false) => Ok(idx),
+            _ => Err(Error::IncorrectChunk
+                .with_part(self.part_index)
+                .with_chunk(idx)),
+        }
+    }
+
+    fn mark_written(&mut self, idx: usize) {
+        self.written[idx] = true;
+        self.remaining -= 1;
+    }
+
+    /// Write an already packed and compressed scanline chunk of the current
+    /// part starting at line `y`
+    ///
+    /// # Errors
+    /// * `[Error::IncorrectChunk]` - If the chunk has already been written,
+    /// or can't be written at this point
+    /// * `[Error::UseScanDeepWrite]` - If the part is a deep scanline part
+    ///
+    pub fn write_scanline_chunk(
+        &mut self,
+        y: i32,
+        packed_data: &[u8],
+    ) -> Result<()> {
+        let chunk_info =
+            self.ctx.write_scanline_chunk_info(self.part_index, y)?;
+        let idx = self.check_unwritten(&chunk_info)?;
+        self.ctx
+            .write_scanline_chunk(self.part_index, y, packed_data)?;
+        self.mark_written(idx);
+        Ok(())
+    }
+
+    /// Write an already packed and compressed deep scanline chunk of the
+    /// current part starting at line `y`, as with
+    /// [`WriteContext::write_deep_scanline_chunk`]
+    ///
+    /// # Errors
+    /// * `[Error::IncorrectChunk]` - If the chunk has already been written,
+    /// or can't be written at this point
+    /// * `[Error::InvalidSampleData]` - If `sample_counts` is empty or larger
+    /// than an uncompressed table for the chunk would be
+    /// * `[Error::InvalidArgument]` - If `packed_data` is larger than
+    /// `unpacked_size`
+    /// * `[Error::UseScanNonDeepWrite]` - If the part is not a deep part
+    ///
+    pub fn write_deep_scanline_chunk(
+        &mut self,
+        y: i32,
+        packed_data: &[u8],
+        unpacked_size: u64,
+        sample_counts: &[u8],
+    ) -> Result<()> {
+        let chunk_info =
+            self.ctx.write_scanline_chunk_info(self.part_index, y)?;
+        let idx = self.check_unwritten(&chunk_info)?;
+        self.ctx.write_deep_scanline_chunk(
+            self.part_index,
+            y,
+            packed_data,
+            unpacked_size,
+            sample_counts,
+        )?;
+        self.mark_written(idx);
+        Ok(())
+    }
+
+    /// Write an already packed and compressed tile of the current part
+    ///
+    /// # Errors
+    /// * `[Error::IncorrectChunk]` - If the tile has already been written, or
+    /// can't be written at this point
+    /// * `[Error::UseTileDeepWrite]` - If the part is a deep tiled part
+    /// * `[Error::TileScanMixedApi]` - If the part is not tiled
+    ///
+    pub fn write_tile_chunk(
+        &mut self,
+        tile_x: i32,
+        tile_y: i32,
+        level_x: i32,
+        level_y: i32,
+        packed_data: &[u8],
+    ) -> Result<()> {
+        let chunk_info = self.ctx.write_tile_chunk_info(
+            self.part_index,
+            tile_x,
+            tile_y,
+            level_x,
+            level_y,
+        )?;
+        let idx = self.check_unwritten(&chunk_info)?;
+        self.ctx.write_tile_chunk(
+            self.part_index,
+            tile_x,
+            tile_y,
+            level_x,
+            level_y,
+            packed_data,
+        )?;
+        self.mark_written(idx);
+        Ok(())
+    }
+
+    /// Write an already packed and compressed deep tile of the current part,
+    /// as with [`WriteContext::write_deep_tile_chunk`]
+    ///
+    /// # Errors
+    /// * `[Error::IncorrectChunk]` - If the tile has already been written, or
+    /// can't be written at this point
+    /// * `[Error::InvalidSampleData]` - If `sample_counts` is empty or larger
+    /// than an uncompressed table for the tile would be
+    /// * `[Error::InvalidArgument]` - If `packed_data` is larger than
+    /// `unpacked_size`
+    /// * `[Error::UseTileNonDeepWrite]` - If the part is not a deep part
+    ///
+    #[allow(clippy::too_many_arguments)]
+    pub fn write_deep_tile_chunk(
+        &mut self,
+        tile_x: i32,
+        tile_y: i32,
+        level_x: i32,
+        level_y: i32,
+        packed_data: &[u8],
+        unpacked_size: u64,
+        sample_counts: &[u8],
+    ) -> Result<()> {
+        let chunk_info = self.ctx.write_tile_chunk_info(
+            self.part_index,
+            tile_x,
+            tile_y,
+            level_x,
+            level_y,
+        )?;
+        let idx = self.check_unwritten(&chunk_info)?;
+        self.ctx.write_deep_tile_chunk(
+            self.part_index,
+            tile_x,
+            tile_y,
+            level_x,
+            level_y,
+            packed_data,
+            unpacked_size,
+            sample_counts,
+        )?;
+        self.mark_written(idx);
+        Ok(())
+    }
+
+    /// Encode and write the whole of the current part from an interleaved
+    /// buffer, as with [`WriteContext::write_image`]
+    ///
+    /// # Errors
+    /// * `[Error::IncorrectChunk]` - If some of the part's chunks have
+    /// already been written
+    /// * `[Error::InvalidArgument]` - If the size of the image or buffer does
+    /// not match the part, or a channel in the part is missing from the buffer
+    /// * `[Error::FeatureNotImplemented]` - If the part holds deep data, or is
+    /// tiled with more than one level
+    ///
+    pub fn write_image(
+        &mut self,
+        desc: &ImageDesc,
+        pixels: &[u8],
+    ) -> Result<()> {
+        self.check_none_written()?;
+        self.ctx.write_image(self.part_index, desc, pixels)?;
+        self.mark_all_written();
+        Ok(())
+    }
+
+    /// Encode and write the whole of the current part, which must be a
+    /// scanline part, from the slices of `frame_buffer`, as with
+    /// [`WriteContext::write_pixels`]
+    ///
+    /// # Errors
+    /// * `[Error::IncorrectChunk]` - If some of the part's chunks have
+    /// already been written
+    /// * `[Error::TileScanMixedApi]` - If the part is not a scanline part
+    /// * `[Error::InvalidArgument]` - If a channel of the part has no slice,
+    /// or a slice is too small to hold the image with its strides
+    ///
+    pub fn write_pixels(&mut self, frame_buffer: &FrameBuffer) -> Result<()> {
+        self.check_none_written()?;
+        self.ctx.write_pixels(self.part_index, frame_buffer)?;
+        self.mark_all_written();
+        Ok(())
+    }
+
+    fn check_none_written(&self) -> Result<()> {
+        if self.remaining != self.written.len() {
+            return Err(Error::IncorrectChunk.with_part(self.part_index));
+        }
+        Ok(())
+    }
+
+    fn mark_all_written(&mut self) {
+        self.written.iter_mut().for_each(|w| *w = true);
+        self.remaining = 0;
+    }
+
+    /// Finish the current part, moving on to the next, or returning `None`
+    /// if this was the last part, after which the context can be closed
+    ///
+    /// # Errors
+    /// * `[Error::IncorrectChunk]` - If not every chunk of the current part
+    /// has been written
+    ///
+    pub fn finish_part(self) -> Result<Option<PartWriter<'a>>> {
+        if self.remaining > 0 {
+            return Err(Error::IncorrectChunk.with_part(self.part_index));
+        }
+
+        let next = self.part_index + 1;
+        if next < self.ctx.count()? {
+            PartWriter::new(self.ctx, next).map(Some)
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+/// Point each channel of the pipeline at its values for the chunk whose
+/// top-left pixel is at (`x`, `y`) in `pixels`
+///
+pub(crate) fn set_encode_from(
+    encoder: &mut EncodePipeline,
+    chunk_info: &ChunkInfo,
+    desc: &ImageDesc,
+    pixels: &[u8],
+    x: usize,
+    y: usize,
+) -> Result<()> {
+    let width = chunk_info.width.max(0) as usize;
+    let height = chunk_info.height.max(0) as usize;
+    if width == 0 || height == 0 {
+        return Ok(());
+    }
+
+    // the chunk covers from its top-left pixel to the end of its bottom-right
+    // pixel
+    let chunk_start = y * desc.line_bytes() + x * desc.pixel_bytes();
+    let chunk_end = chunk_start
+        + (height - 1) * desc.line_bytes()
+        + width * desc.pixel_bytes();
+    if chunk_end > pixels.len() {
+        return Err(Error::InvalidArgument);
+    }
+
+    for channel in encoder.channels_mut() {
+        let offset = desc
+            .channels
+            .iter()
+            .position(|c| c == channel.name())
+            .ok_or(Error::InvalidArgument)?;
+
+        let ptr =
+            pixels[chunk_start + offset * desc.bytes_per_element()..].as_ptr();
+        unsafe { channel.set_encode_from(ptr) };
+        channel.set_user_data_type(desc.pixel_type);
+        channel.set_user_bytes_per_element(desc.bytes_per_element());
+        channel.set_user_pixel_stride(desc.pixel_bytes());
+        channel.set_user_line_stride(desc.line_bytes());
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate as exr;
+    use exr::attr::PixelType;
+    use exr::context::{DefaultWriteMode, ReadContext, WriteHeaderContext};
+    use exr::write::ImageDesc;
+    use imath_traits::f16;
+    use std::path::Path;
+
+    #[test]
+    fn part_writer() -> Result<(), exr::Error> {
+        let path_ferris = Path::new(
+            &std::env::var("CARGO_MANIFEST_DIR")
+                .expect("CARGO_MANIFEST_DIR not set"),
+        )
+        .join("images")
+        .join("ferris.exr");
+        let src = ReadContext::new(&path_ferris)?;
+        let channels = ["A", "B", "G", "R"];
+        let image = src.read_image::<f16>(0, &channels)?;
+        let bytes = image
+            .pixels
+            .iter()
+            .flat_map(|p| p.to_le_bytes())
+            .collect::<Vec<_>>();
+        let desc = ImageDesc::new(
+            image.width,
+            image.height,
+            &channels,
+            PixelType::Half,
+        );
+
+        let path = std::env::temp_dir().join("part_writer.exr");
+        let mut header = WriteHeaderContext::new(
+            &path,
+            DefaultWriteMode::WriteFileDirectly,
+        )?;
+        header.add_part_copy(&src, 0, Some("first"))?;
+        header.add_part_copy(&src, 0, Some("second"))?;
+        let mut ctx = header.write_header()?;
+
+        let mut parts = ctx.part_writer()?;
+        assert_eq!(parts.part_index(), 0);
+        assert_eq!(parts.remaining_chunks(), src.chunk_count(0)?);
+        parts.write_image(&desc, &bytes)?;
+        assert_eq!(parts.remaining_chunks(), 0);
+        assert_eq!(
+            parts
+                .write_image(&desc, &bytes)
+                .expect_err("part was already written")
+                .kind(),
+            exr::ErrorKind::IncorrectChunk
+        );
+
+        let mut parts = parts.finish_part()?.expect("there is a second part");
+        assert_eq!(parts.part_index(), 1);
+        parts.write_image(&desc, &bytes)?;
+        assert!(parts.finish_part()?.is_none());
+        ctx.close()?;
+
+        let ctx = ReadContext::new(&path)?;
+        assert_eq!(ctx.count()?, 2);
+        assert_eq!(ctx.read_image::<f16>(1, &channels)?.pixels, image.pixels);
+
+        // a part can't be finished until it has been written
+        let mut header = WriteHeaderContext::new(
+            &path,
+            DefaultWriteMode::WriteFileDirectly,
+        )?;
+        header.add_part_copy(&src, 0, None)?;
+        let mut ctx = header.write_header()?;
+        let parts = ctx.part_writer()?;
+        assert_eq!(
+            parts
+                .finish_part()
+                .err()
+                .expect("part was not written")
+                .kind(),
+            exr::ErrorKind::IncorrectChunk
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn write_image() -> Result<(), exr::Error> {
+        use exr::attr::{LineOrder, Storage};
+
+        let images = Path::new(
+            &std::env::var("CARGO_MANIFEST_DIR")
+                .expect("CARGO_MANIFEST_DIR not set"),
+        )
+        .join("images");
+
+        for (name, storage) in &[
+            ("ferris.exr", Storage::Scanline),
+            ("ferris-tiled.exr", Storage::Tiled),
+        ] {
+            let src = ReadContext::new(images.join(name))?;
+            let channels = ["A", "B", "G", "R"];
+            let image = src.read_image::<f16>(0, &channels)?;
+            let bytes = image
+                .pixels
+                .iter()
+                .flat_map(|p| p.to_le_bytes())
+                .collect::<Vec<_>>();
+            let desc = ImageDesc::new(
+                image.width,
+                image.height,
+                &channels,
+                PixelType::Half,
+            );
+
+            for lineorder in &[LineOrder::IncreasingY, LineOrder::DecreasingY] {
+                let path = std::env::temp_dir().join("write_image.exr");
+                let mut header = WriteHeaderContext::new(
+                    &path,
+                    DefaultWriteMode::WriteFileDirectly,
+                )?;
+                header.add_part_copy(&src, 0, None)?;
+                header.set_lineorder(0, *lineorder)?;
+                let mut ctx = header.write_header()?;
+                ctx.write_image(0, &desc, &bytes)?;
+                ctx.close()?;
+
+                let written = ReadContext::new(&path)?;
+                assert_eq!(written.storage(0)?, *storage);
+                assert_eq!(written.lineorder(0)?, *lineorder);
+                assert_eq!(written.read_image::<f16>(0, &channels)?, image);
+            }
+        }
+
+        Ok(())
+    }
+}